@@ -103,6 +103,19 @@ fn main() {
                 Ok(_) => (),
                 Err(err) => panic!("Failed to download {}. Err: {}", &url, &err),
             }
+            // Catch a model for another chip here rather than at rknn_init.
+            // Platforms guessed from chip names in the file only warn.
+            match rknn_file::RknnFile::load(&file_path) {
+                Ok(model) => match model.check_platform(feat) {
+                    Ok(()) => (),
+                    Err(err) if model.platforms_scanned => println!(
+                        "cargo:warning={} may not match the {} feature: {}",
+                        file_path, feat, err
+                    ),
+                    Err(err) => panic!("{} doesn't match the {} feature: {}", file_path, feat, err),
+                },
+                Err(err) => println!("cargo:warning=Skipped checking {}: {:#}", file_path, err),
+            }
         }
     }
 }
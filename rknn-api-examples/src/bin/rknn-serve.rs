@@ -0,0 +1,14 @@
+use clap::Parser;
+use rknn_api_examples::examples::serve;
+
+fn main() {
+    let options = serve::Example::parse();
+    match options.execute() {
+        Ok(_) => println!("Done!"),
+        Err(err) => {
+            println!("Ooops!");
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use anyhow::{bail, Result};
+use clap::{Parser, ValueEnum};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+use rknn_api_sys::rknn_tensor_attr;
+
+use crate::examples::{
+    common::*,
+    latency::{Latencies, LatencySummary},
+    npy::{is_npy_path, load_inputs, DType},
+    preprocess::Preprocessor,
+    utils::safe_string,
+};
+
+/// Measure a model's latency: set-inputs, run and get-outputs timed
+/// separately with µs percentiles, optionally on every NPU core mask, with
+/// JSON/CSV reports.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The path to the model file (*.rknn)
+    #[arg(short, long)]
+    model_path: String,
+
+    /// The inputs, as for `infer`; zeros when none are given
+    #[arg(short, long, value_parser)]
+    input_paths: Vec<String>,
+
+    /// Iterations run before measuring
+    #[arg(short, long, default_value_t = 10)]
+    warmup: usize,
+
+    /// Measured iterations [default: 100]
+    #[arg(short = 'n', long, conflicts_with = "duration")]
+    iterations: Option<usize>,
+
+    /// Measure for this many seconds instead of a number of iterations
+    #[arg(short, long, value_parser = parse_seconds)]
+    duration: Option<Duration>,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+
+    /// Run once per core mask instead, skipping masks the chip lacks
+    #[arg(long, conflicts_with = "core_mask")]
+    sweep_core_masks: bool,
+
+    /// Fetch the outputs in the model's own types instead of dequantized f32
+    #[arg(long)]
+    raw: bool,
+
+    /// Write the report to this JSON file
+    #[arg(long)]
+    json: Option<String>,
+
+    /// Write one row per core mask and stage to this CSV file
+    #[arg(long)]
+    csv: Option<String>,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+/// The stages timed per iteration, in order, then their sum.
+pub const STAGES: [&str; 4] = ["set_inputs", "run", "get_outputs", "total"];
+
+/// When a run stops.
+#[derive(Clone, Copy, Debug)]
+pub enum RunLength {
+    Iterations(usize),
+    Duration(Duration),
+}
+
+/// Parses a non-negative, finite number of seconds.
+pub fn parse_seconds(text: &str) -> Result<Duration, String> {
+    let secs: f64 = text.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("{} is not a number of seconds", text))
+}
+
+impl RunLength {
+    pub fn done(&self, iterations: usize, elapsed: Duration) -> bool {
+        match self {
+            RunLength::Iterations(n) => iterations >= *n,
+            RunLength::Duration(d) => elapsed >= *d,
+        }
+    }
+}
+
+/// The latencies of one run, per stage of [`STAGES`].
+#[derive(Clone, Debug, Default)]
+pub struct StageLatencies(pub [Latencies; 4]);
+
+impl StageLatencies {
+    pub fn summaries(&self) -> Vec<(&'static str, LatencySummary)> {
+        STAGES
+            .iter()
+            .zip(&self.0)
+            .map(|(stage, latencies)| (*stage, latencies.summary()))
+            .collect()
+    }
+}
+
+/// The inputs to run the model on, prepared once so only `rknn_inputs_set`
+/// is timed: NumPy files, preprocessed images or zeros.
+pub fn bench_inputs(
+    input_attrs: &[rknn_tensor_attr],
+    paths: &[String],
+    preprocess: &Preprocessor,
+) -> Result<Vec<TensorInput>> {
+    if paths.is_empty() {
+        return input_attrs
+            .iter()
+            .map(|attr| {
+                let dims: Vec<usize> = attr.dims[..attr.n_dims as usize]
+                    .iter()
+                    .map(|d| *d as usize)
+                    .collect();
+                let size = DType::from_tensor_type(attr.type_)?.size();
+                Ok(TensorInput {
+                    data: vec![0; dims.iter().product::<usize>() * size],
+                    dims,
+                    type_: attr.type_,
+                })
+            })
+            .collect();
+    }
+    if paths.iter().all(|p| is_npy_path(p)) {
+        let names = input_attrs
+            .iter()
+            .map(|attr| safe_string(&attr.name))
+            .collect::<Result<Vec<_>>>()?;
+        return load_inputs(paths, &names);
+    }
+    if paths.len() != input_attrs.len() {
+        bail!(
+            "{} images given, the model has {} inputs",
+            paths.len(),
+            input_attrs.len()
+        );
+    }
+    paths
+        .iter()
+        .zip(input_attrs)
+        .map(|(path, attr)| {
+            let img = image::ImageReader::open(path)?.decode()?;
+            Ok(preprocess.run(&img, attr)?.0)
+        })
+        .collect()
+}
+
+/// Runs one iteration, returning how long each of [`STAGES`] took.
+pub fn iteration(
+    ctx: &RKNNContext,
+    inputs: &[TensorInput],
+    want_float: bool,
+) -> Result<[Duration; 4]> {
+    let start = Instant::now();
+    ctx.set_tensor_inputs(inputs)?;
+    let set_inputs = start.elapsed();
+    ctx.run()?;
+    let run = start.elapsed() - set_inputs;
+    drop(ctx.fetch_outputs(want_float)?);
+    let total = start.elapsed();
+    Ok([set_inputs, run, total - set_inputs - run, total])
+}
+
+/// Runs `warmup` unmeasured iterations, then measures until `length`.
+/// Returns the latencies and the wall time of the measured part.
+pub fn measure(
+    ctx: &RKNNContext,
+    inputs: &[TensorInput],
+    want_float: bool,
+    warmup: usize,
+    length: RunLength,
+) -> Result<(StageLatencies, Duration)> {
+    for _ in 0..warmup {
+        iteration(ctx, inputs, want_float)?;
+    }
+    let mut latencies = StageLatencies::default();
+    let start = Instant::now();
+    let mut iterations = 0;
+    while !length.done(iterations, start.elapsed()) {
+        let durations = iteration(ctx, inputs, want_float)?;
+        for (latencies, duration) in latencies.0.iter_mut().zip(durations) {
+            latencies.push(duration);
+        }
+        iterations += 1;
+    }
+    Ok((latencies, start.elapsed()))
+}
+
+/// The name a core mask goes by on the command line, e.g. `npu0-1`.
+pub fn core_mask_name(mask: &RknnCoreMask) -> String {
+    mask.to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+struct MaskResult {
+    core_mask: String,
+    latencies: StageLatencies,
+    elapsed: Duration,
+}
+
+impl MaskResult {
+    fn iterations(&self) -> usize {
+        self.latencies.0[0].len()
+    }
+
+    fn fps(&self) -> f64 {
+        self.iterations() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn to_json(&self) -> Value {
+        let mut result = json!({
+            "core_mask": self.core_mask,
+            "iterations": self.iterations(),
+            "elapsed_sec": self.elapsed.as_secs_f64(),
+            "fps": self.fps(),
+        });
+        for (stage, summary) in self.latencies.summaries() {
+            result[stage] = summary.to_json();
+        }
+        result
+    }
+}
+
+impl Example {
+    fn run_length(&self) -> RunLength {
+        match (self.duration, self.iterations) {
+            (Some(duration), _) => RunLength::Duration(duration),
+            (None, iterations) => RunLength::Iterations(iterations.unwrap_or(100)),
+        }
+    }
+
+    fn write_csv(&self, path: &str, results: &[MaskResult]) -> Result<()> {
+        let mut csv = format!("model,core_mask,stage,{}\n", LatencySummary::CSV_HEADER);
+        for result in results {
+            for (stage, summary) in result.latencies.summaries() {
+                csv.push_str(&format!(
+                    "{},{},{},{}\n",
+                    self.model_path,
+                    result.core_mask,
+                    stage,
+                    summary.csv_fields()
+                ));
+            }
+        }
+        std::fs::write(path, csv)?;
+        println!("\x1b[34;4m CSV written to {}\x1b[0m", path);
+        Ok(())
+    }
+
+    pub fn execute(&self) -> Result<()> {
+        let ctx = RKNNContext::load_model(&self.model_path)?;
+        let input_attrs = ctx.use_default_shapes()?;
+        let inputs = bench_inputs(&input_attrs, &self.input_paths, &self.preprocess)?;
+        let masks: Vec<RknnCoreMask> = if self.sweep_core_masks {
+            RknnCoreMask::value_variants()
+                .iter()
+                .filter(|m| !matches!(m, RknnCoreMask::Undefined))
+                .copied()
+                .collect()
+        } else {
+            vec![self.core_mask]
+        };
+
+        let mut results = Vec::new();
+        for mask in &masks {
+            let name = core_mask_name(mask);
+            if let Err(err) = ctx.set_core_mask(mask) {
+                if !self.sweep_core_masks {
+                    return Err(err);
+                }
+                println!(
+                    "\x1b[34;4m {}: skipped, not supported ({})\x1b[0m",
+                    name, err
+                );
+                continue;
+            }
+            let (latencies, elapsed) =
+                measure(&ctx, &inputs, !self.raw, self.warmup, self.run_length())?;
+            let result = MaskResult {
+                core_mask: name,
+                latencies,
+                elapsed,
+            };
+            println!(
+                "\x1b[34;4m {}: {} iterations, {:.2} FPS\x1b[0m",
+                result.core_mask,
+                result.iterations(),
+                result.fps()
+            );
+            for (stage, summary) in result.latencies.summaries() {
+                println!("  {:<12}{}", stage, summary.dump());
+            }
+            results.push(result);
+        }
+
+        if let Some(path) = &self.json {
+            let report = json!({
+                "model": self.model_path,
+                "platform": PLATFORM,
+                "warmup": self.warmup,
+                "raw": self.raw,
+                "results": results.iter().map(MaskResult::to_json).collect::<Vec<_>>(),
+            });
+            std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+            println!("\x1b[34;4m JSON written to {}\x1b[0m", path);
+        }
+        if let Some(path) = &self.csv {
+            self.write_csv(path, &results)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_seconds() {
+        assert_eq!(parse_seconds("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_seconds("0"), Ok(Duration::ZERO));
+        for text in ["-1", "NaN", "inf", "1e30", "ten"] {
+            assert!(parse_seconds(text).is_err(), "{}", text);
+        }
+    }
+}
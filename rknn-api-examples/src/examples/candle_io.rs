@@ -0,0 +1,172 @@
+use anyhow::{bail, Result};
+use candle::{DType, Device, Tensor};
+use half::f16;
+
+use rknn_api_sys::rknn_tensor_attr;
+
+use crate::examples::{
+    common::{Outputs, TensorInput},
+    utils::{dequantize, get_type_string},
+};
+
+fn to_bytes<T: Copy>(values: &[T]) -> Vec<u8> {
+    unsafe { std::slice::from_raw_parts(values.as_ptr() as *const u8, size_of_val(values)) }
+        .to_vec()
+}
+
+impl TensorInput {
+    /// Copies a contiguous u8/f16/f32/i64 tensor into an input buffer,
+    /// checking its shape and type against the model input `attr`.
+    pub fn from_candle(tensor: &Tensor, attr: &rknn_tensor_attr) -> Result<Self> {
+        if !tensor.is_contiguous() {
+            bail!("input tensors must be contiguous, call .contiguous() first");
+        }
+        let flat = tensor.flatten_all()?;
+        let (data, type_) = match tensor.dtype() {
+            DType::U8 => (
+                flat.to_vec1::<u8>()?,
+                rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8,
+            ),
+            DType::F16 => (
+                to_bytes(&flat.to_vec1::<f16>()?),
+                rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT16,
+            ),
+            DType::F32 => (
+                to_bytes(&flat.to_vec1::<f32>()?),
+                rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32,
+            ),
+            DType::I64 => (
+                to_bytes(&flat.to_vec1::<i64>()?),
+                rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT64,
+            ),
+            other => bail!(
+                "{:?} tensors can't be model inputs, convert them with to_dtype",
+                other
+            ),
+        };
+        let input = TensorInput {
+            data,
+            dims: tensor.dims().to_vec(),
+            type_,
+        };
+        input.check(attr)?;
+        Ok(input)
+    }
+}
+
+/// The first `len` values of output `index`, which the runtime may pad.
+fn leading<T>(values: &[T], len: usize, index: usize) -> Result<&[T]> {
+    match values.get(..len) {
+        Some(values) => Ok(values),
+        None => bail!(
+            "output {} holds {} values, expected {}",
+            index,
+            values.len(),
+            len
+        ),
+    }
+}
+
+impl Outputs<'_> {
+    /// Builds one tensor per output, shaped like its attr. f32, f16, u8 and
+    /// i64 outputs keep their type; candle has no i8 or i32, so int8 outputs
+    /// are dequantized to f32 with their zp/scale and int32 ones widened to
+    /// i64.
+    pub fn to_candle(&self, device: &Device) -> Result<Vec<Tensor>> {
+        (0..self.len())
+            .map(|i| self.output_to_candle(i, device))
+            .collect()
+    }
+
+    fn output_to_candle(&self, index: usize, device: &Device) -> Result<Tensor> {
+        let dims = self.dims(index);
+        let len: usize = dims.iter().product();
+        let tensor = match self.tensor_type(index) {
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32 => Tensor::from_slice(
+                leading(self.as_slice::<f32>(index)?, len, index)?,
+                dims,
+                device,
+            )?,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT16 => Tensor::from_slice(
+                leading(self.as_slice::<f16>(index)?, len, index)?,
+                dims,
+                device,
+            )?,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8 => Tensor::from_slice(
+                leading(self.as_slice::<u8>(index)?, len, index)?,
+                dims,
+                device,
+            )?,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT8 => {
+                let attr = &self.attrs()[index];
+                let values: Vec<f32> = leading(self.as_slice::<i8>(index)?, len, index)?
+                    .iter()
+                    .map(|v| dequantize(*v as f32, attr))
+                    .collect();
+                Tensor::from_vec(values, dims, device)?
+            }
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT32 => {
+                let values: Vec<i64> = leading(self.as_slice::<i32>(index)?, len, index)?
+                    .iter()
+                    .map(|v| *v as i64)
+                    .collect();
+                Tensor::from_vec(values, dims, device)?
+            }
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT64 => Tensor::from_slice(
+                leading(self.as_slice::<i64>(index)?, len, index)?,
+                dims,
+                device,
+            )?,
+            other => bail!(
+                "output {} is {}, which has no candle dtype",
+                index,
+                get_type_string(other)
+            ),
+        };
+        Ok(tensor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nhwc_f32_attr(dims: &[u32]) -> rknn_tensor_attr {
+        let mut attr = rknn_tensor_attr {
+            n_dims: dims.len() as u32,
+            fmt: rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC,
+            type_: rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32,
+            ..Default::default()
+        };
+        attr.dims[..dims.len()].copy_from_slice(dims);
+        attr
+    }
+
+    #[test]
+    fn checks_tensors_against_the_input() {
+        let attr = nhwc_f32_attr(&[1, 2, 2, 3]);
+        let nhwc = Tensor::zeros((1, 2, 2, 3), DType::F32, &Device::Cpu).unwrap();
+        let input = TensorInput::from_candle(&nhwc, &attr).unwrap();
+        assert_eq!(input.data.len(), 12 * 4);
+        // NCHW is converted by the runtime, f16 quantized.
+        let nchw = Tensor::zeros((1, 3, 2, 2), DType::F16, &Device::Cpu).unwrap();
+        assert!(TensorInput::from_candle(&nchw, &attr).is_ok());
+
+        let wrong_shape = Tensor::zeros((1, 2, 3, 2), DType::F32, &Device::Cpu).unwrap();
+        assert!(TensorInput::from_candle(&wrong_shape, &attr).is_err());
+        let wrong_type = Tensor::zeros((1, 2, 2, 3), DType::I64, &Device::Cpu).unwrap();
+        assert!(TensorInput::from_candle(&wrong_type, &attr).is_err());
+        let transposed = Tensor::zeros((1, 3, 2, 2), DType::F32, &Device::Cpu)
+            .unwrap()
+            .permute((0, 2, 3, 1))
+            .unwrap();
+        assert!(TensorInput::from_candle(&transposed, &attr).is_err());
+    }
+
+    #[test]
+    fn fails_on_short_outputs() {
+        assert_eq!(leading(&[1, 2, 3], 2, 0).unwrap(), [1, 2]);
+        let err = leading(&[1, 2, 3], 4, 1).unwrap_err();
+        assert_eq!(err.to_string(), "output 1 holds 3 values, expected 4");
+    }
+}
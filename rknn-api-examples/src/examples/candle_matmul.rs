@@ -0,0 +1,275 @@
+use candle::{CpuStorage, CustomOp2, DType, Device, Layout, Shape, Tensor};
+use half::f16;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::examples::{
+    common::RknnCoreMask,
+    matmul::{MatmulContext, MatmulInfo, MatmulType},
+};
+
+/// How [`NpuMatmul`] feeds the NPU. The result always has the inputs' dtype.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NpuPrecision {
+    /// f16 x f16 accumulated in f32; f32 inputs are rounded to f16.
+    Fp16,
+    /// Inputs are quantized per tensor to int8, accumulated in int32.
+    Int8,
+}
+
+/// A candle op computing `lhs x rhs` for 2-D f16/f32 tensors with the
+/// RKNN matmul API. Contexts are created on first use of a shape and kept
+/// for later calls; empty shapes and shapes the runtime rejects are
+/// computed with candle's CPU matmul instead.
+pub struct NpuMatmul {
+    precision: NpuPrecision,
+    core_mask: RknnCoreMask,
+    contexts: Mutex<HashMap<(usize, usize, usize), Option<MatmulContext>>>,
+}
+
+/// The NPU counterpart of [`Tensor::matmul`].
+pub trait NpuMatmulExt {
+    /// Runs on the NPU when both tensors are 2-D f16 or f32 on the CPU,
+    /// falls back to [`Tensor::matmul`] otherwise.
+    fn npu_matmul(&self, rhs: &Tensor, op: &NpuMatmul) -> candle::Result<Tensor>;
+}
+
+impl NpuMatmulExt for Tensor {
+    fn npu_matmul(&self, rhs: &Tensor, op: &NpuMatmul) -> candle::Result<Tensor> {
+        let supported = matches!(self.device(), Device::Cpu)
+            && matches!(rhs.device(), Device::Cpu)
+            && self.rank() == 2
+            && rhs.rank() == 2
+            && self.dtype() == rhs.dtype()
+            && matches!(self.dtype(), DType::F16 | DType::F32);
+        if !supported {
+            return self.matmul(rhs);
+        }
+        self.contiguous()?.apply_op2_no_bwd(&rhs.contiguous()?, op)
+    }
+}
+
+fn contiguous<'a, T>(data: &'a [T], layout: &Layout) -> candle::Result<&'a [T]> {
+    match layout.contiguous_offsets() {
+        Some((start, end)) => Ok(&data[start..end]),
+        None => candle::bail!("npu_matmul expects contiguous tensors"),
+    }
+}
+
+/// Per tensor symmetric quantization, returns the values and their scale.
+fn quantize(values: &[f32]) -> (Vec<i8>, f32) {
+    let max = values.iter().fold(0f32, |max, v| max.max(v.abs()));
+    let scale = if max > 0.0 { max / 127.0 } else { 1.0 };
+    let quantized = values
+        .iter()
+        .map(|v| (v / scale).round().clamp(-127.0, 127.0) as i8)
+        .collect();
+    (quantized, scale)
+}
+
+fn cpu_matmul<T: candle::WithDType>(
+    a: &[T],
+    b: &[T],
+    (m, k, n): (usize, usize, usize),
+) -> candle::Result<Vec<T>> {
+    let a = Tensor::from_slice(a, (m, k), &Device::Cpu)?;
+    let b = Tensor::from_slice(b, (k, n), &Device::Cpu)?;
+    a.matmul(&b)?.flatten_all()?.to_vec1()
+}
+
+impl NpuMatmul {
+    pub fn new(precision: NpuPrecision, core_mask: RknnCoreMask) -> Self {
+        NpuMatmul {
+            precision,
+            core_mask,
+            contexts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `f` with the cached context for `(m, k, n)`. Returns `None` for
+    /// an empty shape or when the runtime cannot create a context for it.
+    fn with_context<R>(
+        &self,
+        (m, k, n): (usize, usize, usize),
+        f: impl FnOnce(&mut MatmulContext) -> anyhow::Result<R>,
+    ) -> candle::Result<Option<R>> {
+        // The runtime has no empty operands.
+        if m == 0 || k == 0 || n == 0 {
+            return Ok(None);
+        }
+        let mut contexts = self.contexts.lock().unwrap();
+        let matmul = contexts.entry((m, k, n)).or_insert_with(|| {
+            let matmul_type = match self.precision {
+                NpuPrecision::Fp16 => MatmulType::Fp16MmFp16ToFp32,
+                NpuPrecision::Int8 => MatmulType::Int8MmInt8ToInt32,
+            };
+            let info = MatmulInfo::new(m as i32, k as i32, n as i32, matmul_type);
+            MatmulContext::new(&info)
+                .and_then(|mut matmul| {
+                    matmul.set_core_mask(&self.core_mask)?;
+                    Ok(matmul)
+                })
+                .ok()
+        });
+        match matmul {
+            Some(matmul) => f(matmul).map(Some).map_err(candle::Error::msg),
+            None => Ok(None),
+        }
+    }
+
+    fn run_f16(
+        &self,
+        a: &[f16],
+        b: &[f16],
+        mkn: (usize, usize, usize),
+    ) -> candle::Result<Option<Vec<f32>>> {
+        self.with_context(mkn, |matmul| {
+            matmul.a_mut::<f16>()?[..a.len()].copy_from_slice(a);
+            matmul.b_mut::<f16>()?[..b.len()].copy_from_slice(b);
+            matmul.run()?;
+            Ok(matmul.c::<f32>()?[..mkn.0 * mkn.2].to_vec())
+        })
+    }
+
+    fn run_int8(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        mkn: (usize, usize, usize),
+    ) -> candle::Result<Option<Vec<f32>>> {
+        let (a, a_scale) = quantize(a);
+        let (b, b_scale) = quantize(b);
+        self.with_context(mkn, |matmul| {
+            matmul.a_mut::<i8>()?[..a.len()].copy_from_slice(&a);
+            matmul.b_mut::<i8>()?[..b.len()].copy_from_slice(&b);
+            matmul.run()?;
+            let c = &matmul.c::<i32>()?[..mkn.0 * mkn.2];
+            Ok(c.iter().map(|v| *v as f32 * a_scale * b_scale).collect())
+        })
+    }
+
+    fn run(&self, a: &[f32], b: &[f32], mkn: (usize, usize, usize)) -> candle::Result<Vec<f32>> {
+        let c = match self.precision {
+            NpuPrecision::Fp16 => {
+                let a: Vec<f16> = a.iter().map(|v| f16::from_f32(*v)).collect();
+                let b: Vec<f16> = b.iter().map(|v| f16::from_f32(*v)).collect();
+                self.run_f16(&a, &b, mkn)?
+            }
+            NpuPrecision::Int8 => self.run_int8(a, b, mkn)?,
+        };
+        match c {
+            Some(c) => Ok(c),
+            None => cpu_matmul(a, b, mkn),
+        }
+    }
+}
+
+impl CustomOp2 for NpuMatmul {
+    fn name(&self) -> &'static str {
+        "rknn-matmul"
+    }
+
+    fn cpu_fwd(
+        &self,
+        s1: &CpuStorage,
+        l1: &Layout,
+        s2: &CpuStorage,
+        l2: &Layout,
+    ) -> candle::Result<(CpuStorage, Shape)> {
+        let (m, k) = l1.shape().dims2()?;
+        let (k2, n) = l2.shape().dims2()?;
+        if k != k2 {
+            candle::bail!(
+                "npu_matmul: incompatible shapes {:?} and {:?}",
+                l1.shape(),
+                l2.shape()
+            );
+        }
+        let mkn = (m, k, n);
+        let storage = match (s1, s2) {
+            (CpuStorage::F16(a), CpuStorage::F16(b)) => {
+                let (a, b) = (contiguous(a, l1)?, contiguous(b, l2)?);
+                let c = match self.precision {
+                    NpuPrecision::Fp16 => self.run_f16(a, b, mkn)?,
+                    NpuPrecision::Int8 => {
+                        let a: Vec<f32> = a.iter().map(|v| v.to_f32()).collect();
+                        let b: Vec<f32> = b.iter().map(|v| v.to_f32()).collect();
+                        self.run_int8(&a, &b, mkn)?
+                    }
+                };
+                match c {
+                    Some(c) => CpuStorage::F16(c.into_iter().map(f16::from_f32).collect()),
+                    None => CpuStorage::F16(cpu_matmul(a, b, mkn)?),
+                }
+            }
+            (CpuStorage::F32(a), CpuStorage::F32(b)) => {
+                let (a, b) = (contiguous(a, l1)?, contiguous(b, l2)?);
+                CpuStorage::F32(self.run(a, b, mkn)?)
+            }
+            _ => candle::bail!("npu_matmul supports f16 and f32 tensors only"),
+        };
+        Ok((storage, Shape::from((m, n))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operands(dtype: DType) -> (Tensor, Tensor) {
+        let a = Tensor::new(&[[1f32, 2., 3.], [4., 5., 6.]], &Device::Cpu).unwrap();
+        let b = Tensor::new(&[[1f32, -1.], [0.5, 2.], [-2., 0.]], &Device::Cpu).unwrap();
+        (a.to_dtype(dtype).unwrap(), b.to_dtype(dtype).unwrap())
+    }
+
+    #[test]
+    fn computes_empty_shapes_on_the_cpu() {
+        for precision in [NpuPrecision::Fp16, NpuPrecision::Int8] {
+            let op = NpuMatmul::new(precision, RknnCoreMask::Npu0);
+            for dtype in [DType::F32, DType::F16] {
+                let a = Tensor::zeros((2, 0), dtype, &Device::Cpu).unwrap();
+                let b = Tensor::zeros((0, 3), dtype, &Device::Cpu).unwrap();
+                let c = a.npu_matmul(&b, &op).unwrap();
+                assert_eq!(c.dtype(), dtype);
+                let c: Vec<Vec<f32>> = c.to_dtype(DType::F32).unwrap().to_vec2().unwrap();
+                assert_eq!(c, [[0.0; 3]; 2]);
+
+                let a = Tensor::zeros((0, 2), dtype, &Device::Cpu).unwrap();
+                let b = Tensor::ones((2, 3), dtype, &Device::Cpu).unwrap();
+                assert_eq!(a.npu_matmul(&b, &op).unwrap().dims(), [0, 3]);
+            }
+            assert!(op.contexts.lock().unwrap().is_empty(), "{:?}", precision);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs an RKNN NPU"]
+    fn runs_on_the_npu() {
+        for precision in [NpuPrecision::Fp16, NpuPrecision::Int8] {
+            let op = NpuMatmul::new(precision, RknnCoreMask::Npu0);
+            for dtype in [DType::F32, DType::F16] {
+                let (a, b) = operands(dtype);
+                let c = a.npu_matmul(&b, &op).unwrap();
+                assert_eq!(c.dtype(), dtype);
+                let c = c.to_dtype(DType::F32).unwrap().flatten_all().unwrap();
+                let c: Vec<f32> = c.to_vec1().unwrap();
+                for (actual, expected) in c.iter().zip([-4., 3., -5.5, 6.]) {
+                    assert!((actual - expected).abs() < 0.1, "{:?}: {:?}", precision, c);
+                }
+            }
+            let contexts = op.contexts.lock().unwrap();
+            assert!(matches!(contexts.get(&(2, 3, 2)), Some(Some(_))));
+        }
+    }
+
+    #[test]
+    fn unsupported_tensors_use_candle() {
+        let op = NpuMatmul::new(NpuPrecision::Fp16, RknnCoreMask::Npu0);
+        let (a, b) = operands(DType::F64);
+        let c: Vec<Vec<f64>> = a.npu_matmul(&b, &op).unwrap().to_vec2().unwrap();
+        assert_eq!(c, [[-4., 3.], [-5.5, 6.]]);
+        assert!(op.contexts.lock().unwrap().is_empty());
+
+        let (a, _) = operands(DType::F32);
+        assert!(a.npu_matmul(&a, &op).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// One of the top scoring classes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Classification {
+    pub index: usize,
+    pub label: Option<String>,
+    /// The softmax probability, or the raw score without softmax.
+    pub prob: f32,
+}
+
+impl Classification {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "label": self.label,
+            "index": self.index,
+            "prob": self.prob,
+        })
+    }
+}
+
+/// Turns a classifier's output scores into its top classes.
+#[derive(Clone, Debug)]
+pub struct ClassifierHead {
+    pub top_k: usize,
+    /// Apply softmax to the scores first, for models outputting logits.
+    pub softmax: bool,
+    labels: Vec<String>,
+}
+
+/// Reads one label per line. Synset files (`n01440764 tench, Tinca tinca`)
+/// have the WordNet id stripped.
+pub fn load_labels<P: AsRef<Path>>(path: P) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read labels from {}", path.display()))?;
+    Ok(text
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+            match line.split_once(' ') {
+                Some((id, label))
+                    if id.len() == 9
+                        && id.starts_with('n')
+                        && id[1..].bytes().all(|b| b.is_ascii_digit()) =>
+                {
+                    label.trim().to_string()
+                }
+                _ => line.to_string(),
+            }
+        })
+        .collect())
+}
+
+/// Numerically stable softmax, in place.
+pub fn softmax(scores: &mut [f32]) {
+    let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut sum = 0.0;
+    for s in scores.iter_mut() {
+        *s = (*s - max).exp();
+        sum += *s;
+    }
+    for s in scores.iter_mut() {
+        *s /= sum;
+    }
+}
+
+/// The `k` highest scores with their indices, highest first. NaN scores
+/// rank last and ties go to the lower index. Only the top `k` are sorted.
+pub fn top_k(scores: &[f32], k: usize) -> Vec<(usize, f32)> {
+    let mut pairs: Vec<(usize, f32)> = scores.iter().copied().enumerate().collect();
+    let k = k.min(pairs.len());
+    let by_score = |a: &(usize, f32), b: &(usize, f32)| {
+        (a.1.is_nan().cmp(&b.1.is_nan()))
+            .then(b.1.total_cmp(&a.1))
+            .then(a.0.cmp(&b.0))
+    };
+    if k > 0 && k < pairs.len() {
+        pairs.select_nth_unstable_by(k - 1, by_score);
+    }
+    pairs.truncate(k);
+    pairs.sort_by(by_score);
+    pairs
+}
+
+impl ClassifierHead {
+    pub fn new(top_k: usize, softmax: bool) -> Self {
+        ClassifierHead {
+            top_k,
+            softmax,
+            labels: Vec::new(),
+        }
+    }
+
+    pub fn with_labels(mut self, labels: Vec<String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// The label of a class. Models with one more class than there are labels
+    /// (e.g. TF mobilenets) are taken to have a leading background class.
+    pub fn label(&self, index: usize, n_classes: usize) -> Option<String> {
+        let offset = (n_classes == self.labels.len() + 1) as usize;
+        index
+            .checked_sub(offset)
+            .and_then(|i| self.labels.get(i))
+            .cloned()
+    }
+
+    pub fn classify(&self, scores: &[f32]) -> Vec<Classification> {
+        let mut scores = scores.to_vec();
+        if self.softmax {
+            softmax(&mut scores);
+        }
+        top_k(&scores, self.top_k)
+            .into_iter()
+            .map(|(index, prob)| Classification {
+                index,
+                label: self.label(index, scores.len()),
+                prob,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn softmax_is_stable_on_large_logits() {
+        let mut scores = [1000.0, 1001.0, 1002.0];
+        softmax(&mut scores);
+        let expected = [0.090_031, 0.244_728, 0.665_241];
+        for (s, e) in scores.iter().zip(expected) {
+            assert!((s - e).abs() < 1e-5, "{:?}", scores);
+        }
+        let mut scores = [-1000.0, 0.0];
+        softmax(&mut scores);
+        assert_eq!(scores, [0.0, 1.0]);
+    }
+
+    #[test]
+    fn ranks_the_top_scores() {
+        let scores = [0.1, 0.5, 0.2, 0.9, 0.3];
+        assert_eq!(top_k(&scores, 3), [(3, 0.9), (1, 0.5), (4, 0.3)]);
+        assert_eq!(top_k(&scores, 0), []);
+        // k past the end sorts everything.
+        let all: Vec<usize> = top_k(&scores, 10).iter().map(|(i, _)| *i).collect();
+        assert_eq!(all, [3, 1, 4, 2, 0]);
+
+        let ties = [0.5, 0.7, 0.5, 0.7, 0.5];
+        assert_eq!(top_k(&ties, 3), [(1, 0.7), (3, 0.7), (0, 0.5)]);
+
+        let nan = [f32::NAN, 0.2, -f32::NAN, f32::NEG_INFINITY];
+        let ranked: Vec<usize> = top_k(&nan, 4).iter().map(|(i, _)| *i).collect();
+        assert_eq!(ranked, [1, 3, 0, 2]);
+        assert_eq!(top_k(&nan, 1), [(1, 0.2)]);
+    }
+
+    #[test]
+    fn strips_synset_ids() {
+        let path = std::env::temp_dir().join(format!("rknn-labels-{}.txt", std::process::id()));
+        let lines = [
+            "n01440764 tench, Tinca tinca",
+            "  cat  ",
+            "n0144 not a synset id",
+            "n0144076x not one either",
+            "dog house",
+        ];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        let labels = load_labels(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            labels.unwrap(),
+            [
+                "tench, Tinca tinca",
+                "cat",
+                "n0144 not a synset id",
+                "n0144076x not one either",
+                "dog house"
+            ]
+        );
+        assert!(load_labels(&path).is_err());
+    }
+
+    #[test]
+    fn offsets_a_background_class() {
+        let head = ClassifierHead::new(2, false).with_labels(vec!["a".into(), "b".into()]);
+        // As many classes as labels.
+        assert_eq!(head.label(0, 2).as_deref(), Some("a"));
+        assert_eq!(head.label(2, 2), None);
+        // One more, like 1001-class models with 1000 labels.
+        assert_eq!(head.label(0, 3), None);
+        assert_eq!(head.label(1, 3).as_deref(), Some("a"));
+        assert_eq!(head.label(2, 3).as_deref(), Some("b"));
+
+        let top = head.classify(&[0.1, 0.2, 0.7]);
+        let found: Vec<_> = top.iter().map(|c| (c.index, c.label.as_deref())).collect();
+        assert_eq!(found, [(2, Some("b")), (1, Some("a"))]);
+    }
+}
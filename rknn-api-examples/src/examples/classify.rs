@@ -0,0 +1,150 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use serde_json::json;
+
+use crate::examples::{
+    classifier::{load_labels, ClassifierHead},
+    common::*,
+    preprocess::Preprocessor,
+    report::{run_record, ResultWriter, Timings},
+};
+
+/// The classification models `build.rs` downloads.
+#[derive(clap::ValueEnum, Copy, Clone, Debug)]
+pub enum ClassifierModel {
+    MobilenetV1,
+    MobilenetV2,
+    Resnet18,
+}
+
+impl ClassifierModel {
+    pub fn path(self) -> String {
+        bundled_model_path(match self {
+            ClassifierModel::MobilenetV1 => "mobilenet_v1",
+            ClassifierModel::MobilenetV2 => "mobilenet_v2",
+            ClassifierModel::Resnet18 => "resnet18",
+        })
+    }
+}
+
+/// Classify images and print the top classes.
+#[derive(Debug, Parser)]
+pub struct Example {
+    #[arg(short, long, value_enum, default_value_t = ClassifierModel::Resnet18)]
+    model: ClassifierModel,
+
+    /// Use this model file (*.rknn) instead of a bundled one
+    #[arg(long)]
+    model_path: Option<String>,
+
+    /// The images to classify, defaults to the downloaded test images
+    #[arg(short, long, value_parser)]
+    input_paths: Vec<String>,
+
+    /// A label file, one label per line (synset ids are stripped)
+    #[arg(long)]
+    labels: Option<String>,
+
+    /// The number of classes to report
+    #[arg(short = 'k', long, default_value_t = 5)]
+    top_k: usize,
+
+    /// Apply softmax to the outputs, for models without a softmax layer
+    #[arg(long)]
+    softmax: bool,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// The directory a JSON record and the raw outputs (.npy) of each input
+    /// are written to
+    #[arg(short, long)]
+    output_dir: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+impl Example {
+    pub fn execute(&self) -> Result<()> {
+        let model_path = self.model_path.clone().unwrap_or_else(|| self.model.path());
+        let ctx = RKNNContext::load_model(&model_path)?;
+        if ctx.n_input != 1 {
+            bail!("{} has {} inputs, expected 1", model_path, ctx.n_input);
+        }
+        ctx.set_core_mask(&self.core_mask)?;
+        let input_attrs = ctx.use_default_shapes()?;
+
+        let mut head = ClassifierHead::new(self.top_k, self.softmax);
+        if let Some(path) = &self.labels {
+            head = head.with_labels(load_labels(path)?);
+        }
+
+        let input_paths = if self.input_paths.is_empty() {
+            [
+                "cat_224x224.jpg",
+                "dog_224x224.jpg",
+                "space_shuttle_224.jpg",
+            ]
+            .iter()
+            .map(|name| format!("{}/test-data/{}", env!("CARGO_MANIFEST_DIR"), name))
+            .collect()
+        } else {
+            self.input_paths.clone()
+        };
+
+        let writer = self
+            .output_dir
+            .as_ref()
+            .map(ResultWriter::new)
+            .transpose()?;
+        let mut results = Vec::with_capacity(input_paths.len());
+        for path in &input_paths {
+            let img = image::ImageReader::open(path)?.decode()?;
+            let mut timings = Timings::default();
+            timings.time("set_inputs", || {
+                ctx.set_inputs(&input_attrs, &[img], &self.preprocess)
+            })?;
+            timings.time("run", || ctx.run())?;
+            let outputs = timings.time("get_outputs", || ctx.fetch_outputs(true))?;
+            let classes = timings.time("postprocess", || -> Result<_> {
+                Ok(head.classify(outputs.as_slice::<f32>(0)?))
+            })?;
+            let top_k = classes.iter().map(|c| c.to_json()).collect::<Vec<_>>();
+
+            if let Some(writer) = &writer {
+                let mut record =
+                    run_record(path, &model_path, &input_attrs, outputs.attrs(), &timings);
+                record["top_k"] = json!(top_k);
+                let views = (0..outputs.len())
+                    .map(|i| outputs.view(i))
+                    .collect::<Result<Vec<_>>>()?;
+                writer.write(&writer.name(path), &record, &views)?;
+            }
+            if self.json {
+                results.push(json!({
+                    "input": path,
+                    "top_k": top_k,
+                }));
+            } else {
+                println!("\x1b[34;4m {}\x1b[0m", path);
+                for c in &classes {
+                    println!(
+                        "  {:>5}: {:.4}  {}",
+                        c.index,
+                        c.prob,
+                        c.label.as_deref().unwrap_or("")
+                    );
+                }
+            }
+        }
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&results)?);
+        }
+        Ok(())
+    }
+}
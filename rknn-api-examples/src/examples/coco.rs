@@ -0,0 +1,465 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::{collections::HashMap, path::Path};
+
+use crate::examples::yolo::BBox;
+
+/// An image of a COCO annotation file.
+#[derive(Clone, Debug)]
+pub struct CocoImage {
+    pub id: u64,
+    pub file_name: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct CocoCategory {
+    pub id: u64,
+    pub name: String,
+}
+
+/// A ground truth box.
+#[derive(Clone, Debug)]
+pub struct CocoAnnotation {
+    pub image_id: u64,
+    pub category_id: u64,
+    pub bbox: BBox,
+    /// Crowd regions are neither missed nor matched, detections on them are
+    /// ignored.
+    pub iscrowd: bool,
+}
+
+/// The parts of an `instances_*.json` file detection evaluation needs.
+#[derive(Clone, Debug)]
+pub struct CocoDataset {
+    pub images: Vec<CocoImage>,
+    /// Sorted by id, which is the order YOLO models number the classes in.
+    pub categories: Vec<CocoCategory>,
+    pub annotations: Vec<CocoAnnotation>,
+}
+
+/// A prediction, in source image pixels.
+#[derive(Clone, Debug)]
+pub struct CocoDetection {
+    pub image_id: u64,
+    pub category_id: u64,
+    pub bbox: BBox,
+    pub score: f32,
+}
+
+impl CocoDetection {
+    /// The entry of a COCO results file, with its box as `[x, y, w, h]`.
+    pub fn to_json(&self) -> Value {
+        let b = &self.bbox;
+        json!({
+            "image_id": self.image_id,
+            "category_id": self.category_id,
+            "bbox": [b.x1, b.y1, b.width(), b.height()],
+            "score": self.score,
+        })
+    }
+}
+
+fn field<'a>(value: &'a Value, key: &str) -> Result<&'a Value> {
+    value
+        .get(key)
+        .with_context(|| format!("missing '{}' in {}", key, value))
+}
+
+fn id(value: &Value, key: &str) -> Result<u64> {
+    field(value, key)?
+        .as_u64()
+        .with_context(|| format!("'{}' is not an id", key))
+}
+
+fn xywh(value: &Value) -> Result<BBox> {
+    let b = field(value, "bbox")?
+        .as_array()
+        .filter(|b| b.len() == 4)
+        .context("'bbox' is not [x, y, w, h]")?;
+    let v = |i: usize| b[i].as_f64().unwrap_or(0.0) as f32;
+    Ok(BBox {
+        x1: v(0),
+        y1: v(1),
+        x2: v(0) + v(2),
+        y2: v(1) + v(3),
+    })
+}
+
+impl CocoDataset {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let doc: Value = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        let list = |key: &str| -> Result<&Vec<Value>> {
+            field(&doc, key)?
+                .as_array()
+                .with_context(|| format!("'{}' is not a list", key))
+        };
+
+        let images = list("images")?
+            .iter()
+            .map(|image| {
+                Ok(CocoImage {
+                    id: id(image, "id")?,
+                    file_name: field(image, "file_name")?
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut categories = list("categories")?
+            .iter()
+            .map(|category| {
+                Ok(CocoCategory {
+                    id: id(category, "id")?,
+                    name: field(category, "name")?
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        categories.sort_by_key(|c| c.id);
+        let annotations = list("annotations")?
+            .iter()
+            .map(|annotation| {
+                Ok(CocoAnnotation {
+                    image_id: id(annotation, "image_id")?,
+                    category_id: id(annotation, "category_id")?,
+                    bbox: xywh(annotation)?,
+                    iscrowd: annotation.get("iscrowd").and_then(Value::as_u64) == Some(1),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(CocoDataset {
+            images,
+            categories,
+            annotations,
+        })
+    }
+}
+
+/// Average precision per IoU threshold and category, COCO style: greedy
+/// matching by score, crowd regions ignored, precision sampled at 101 recall
+/// points, at most `max_detections` per image and category.
+#[derive(Clone, Debug)]
+pub struct CocoEvaluation {
+    pub iou_thresholds: Vec<f32>,
+    pub categories: Vec<u64>,
+    /// `[threshold][category]`, `None` for categories without ground truth.
+    pub ap: Vec<Vec<Option<f64>>>,
+}
+
+impl CocoEvaluation {
+    pub fn new(
+        dataset: &CocoDataset,
+        detections: &[CocoDetection],
+        image_ids: &[u64],
+        max_detections: usize,
+    ) -> Self {
+        let iou_thresholds: Vec<f32> = (0..10).map(|i| 0.5 + 0.05 * i as f32).collect();
+        let categories: Vec<u64> = dataset.categories.iter().map(|c| c.id).collect();
+
+        let mut gts: HashMap<(u64, u64), Vec<&CocoAnnotation>> = HashMap::new();
+        for a in &dataset.annotations {
+            gts.entry((a.image_id, a.category_id)).or_default().push(a);
+        }
+        let mut dts: HashMap<(u64, u64), Vec<&CocoDetection>> = HashMap::new();
+        for d in detections {
+            dts.entry((d.image_id, d.category_id)).or_default().push(d);
+        }
+
+        let mut ap = vec![vec![None; categories.len()]; iou_thresholds.len()];
+        for (c, category) in categories.iter().enumerate() {
+            // Per threshold: (score, matched) of every counted detection.
+            let mut scored: Vec<Vec<(f32, bool)>> = vec![Vec::new(); iou_thresholds.len()];
+            let mut n_gt = 0;
+            for image_id in image_ids {
+                let key = (*image_id, *category);
+                let mut gt: Vec<&CocoAnnotation> = gts.get(&key).cloned().unwrap_or_default();
+                // Crowds last, so real objects are matched first.
+                gt.sort_by_key(|g| g.iscrowd);
+                n_gt += gt.iter().filter(|g| !g.iscrowd).count();
+                let mut dt: Vec<&CocoDetection> = dts.get(&key).cloned().unwrap_or_default();
+                dt.sort_by(|a, b| b.score.total_cmp(&a.score));
+                dt.truncate(max_detections);
+
+                let ious: Vec<Vec<f32>> = dt
+                    .iter()
+                    .map(|d| {
+                        gt.iter()
+                            .map(|g| {
+                                if g.iscrowd {
+                                    // Crowds count the detection's own area.
+                                    let area = d.bbox.area();
+                                    if area > 0.0 {
+                                        d.bbox.intersection(&g.bbox) / area
+                                    } else {
+                                        0.0
+                                    }
+                                } else {
+                                    d.bbox.iou(&g.bbox)
+                                }
+                            })
+                            .collect()
+                    })
+                    .collect();
+
+                for (t, threshold) in iou_thresholds.iter().enumerate() {
+                    let mut gt_matched = vec![false; gt.len()];
+                    for (di, d) in dt.iter().enumerate() {
+                        let mut best = threshold.min(1.0 - 1e-10);
+                        let mut matched = None;
+                        for (gi, g) in gt.iter().enumerate() {
+                            if gt_matched[gi] && !g.iscrowd {
+                                continue;
+                            }
+                            // Past the real objects, a match is final.
+                            if matched.is_some_and(|m: usize| !gt[m].iscrowd) && g.iscrowd {
+                                break;
+                            }
+                            if ious[di][gi] < best {
+                                continue;
+                            }
+                            best = ious[di][gi];
+                            matched = Some(gi);
+                        }
+                        match matched {
+                            Some(gi) if gt[gi].iscrowd => {}
+                            Some(gi) => {
+                                gt_matched[gi] = true;
+                                scored[t].push((d.score, true));
+                            }
+                            None => scored[t].push((d.score, false)),
+                        }
+                    }
+                }
+            }
+            if n_gt == 0 {
+                continue;
+            }
+            for (t, mut scored) in scored.into_iter().enumerate() {
+                scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+                ap[t][c] = Some(average_precision(&scored, n_gt));
+            }
+        }
+        CocoEvaluation {
+            iou_thresholds,
+            categories,
+            ap,
+        }
+    }
+
+    /// The mean over categories with ground truth at threshold `t`.
+    pub fn map_at(&self, t: usize) -> f64 {
+        let aps: Vec<f64> = self.ap[t].iter().flatten().copied().collect();
+        if aps.is_empty() {
+            0.0
+        } else {
+            aps.iter().sum::<f64>() / aps.len() as f64
+        }
+    }
+
+    /// mAP@0.5.
+    pub fn map50(&self) -> f64 {
+        self.map_at(0)
+    }
+
+    /// mAP@0.5:0.95, the mean over the ten thresholds.
+    pub fn map50_95(&self) -> f64 {
+        (0..self.iou_thresholds.len())
+            .map(|t| self.map_at(t))
+            .sum::<f64>()
+            / self.iou_thresholds.len() as f64
+    }
+
+    /// The AP of category `c` over all thresholds.
+    pub fn category_ap(&self, c: usize) -> Option<f64> {
+        let aps: Vec<f64> = self.ap.iter().filter_map(|ap| ap[c]).collect();
+        (!aps.is_empty()).then(|| aps.iter().sum::<f64>() / aps.len() as f64)
+    }
+}
+
+/// 101 point interpolated AP of detections sorted by score, each a true or
+/// false positive.
+fn average_precision(scored: &[(f32, bool)], n_gt: usize) -> f64 {
+    let mut precision = Vec::with_capacity(scored.len());
+    let mut recall = Vec::with_capacity(scored.len());
+    let (mut tp, mut fp) = (0usize, 0usize);
+    for (_, matched) in scored {
+        if *matched {
+            tp += 1;
+        } else {
+            fp += 1;
+        }
+        precision.push(tp as f64 / (tp + fp) as f64);
+        recall.push(tp as f64 / n_gt as f64);
+    }
+    // The best precision at any higher recall.
+    for i in (1..precision.len()).rev() {
+        precision[i - 1] = precision[i - 1].max(precision[i]);
+    }
+    (0..=100)
+        .map(|r| {
+            let r = r as f64 / 100.0;
+            let i = recall.partition_point(|rc| *rc < r);
+            precision.get(i).copied().unwrap_or(0.0)
+        })
+        .sum::<f64>()
+        / 101.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(x1: f32, y1: f32, x2: f32, y2: f32) -> BBox {
+        BBox { x1, y1, x2, y2 }
+    }
+
+    fn gt(image_id: u64, category_id: u64, bbox: BBox, iscrowd: bool) -> CocoAnnotation {
+        CocoAnnotation {
+            image_id,
+            category_id,
+            bbox,
+            iscrowd,
+        }
+    }
+
+    fn dt(image_id: u64, category_id: u64, bbox: BBox, score: f32) -> CocoDetection {
+        CocoDetection {
+            image_id,
+            category_id,
+            bbox,
+            score,
+        }
+    }
+
+    fn dataset(categories: &[u64], annotations: Vec<CocoAnnotation>) -> CocoDataset {
+        CocoDataset {
+            images: vec![
+                CocoImage {
+                    id: 1,
+                    file_name: "1.jpg".to_string(),
+                },
+                CocoImage {
+                    id: 2,
+                    file_name: "2.jpg".to_string(),
+                },
+            ],
+            categories: categories
+                .iter()
+                .map(|id| CocoCategory {
+                    id: *id,
+                    name: id.to_string(),
+                })
+                .collect(),
+            annotations,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn interpolates_precision() {
+        assert_close(average_precision(&[(0.9, true), (0.8, true)], 2), 1.0);
+        // Recall 1 is only reached at precision 1/2.
+        assert_close(average_precision(&[(0.9, false), (0.8, true)], 1), 0.5);
+        // Precision 1 up to recall 0.5 (51 points), then 2/3 (50 points).
+        let scored = [(0.9, true), (0.8, false), (0.7, true)];
+        assert_close(
+            average_precision(&scored, 2),
+            (51.0 + 50.0 * 2.0 / 3.0) / 101.0,
+        );
+        // Half the objects are never found.
+        assert_close(average_precision(&[(0.9, true)], 2), 51.0 / 101.0);
+    }
+
+    #[test]
+    fn perfect_detections_score_one() {
+        let a = bbox(10.0, 10.0, 50.0, 60.0);
+        let b = bbox(100.0, 20.0, 180.0, 90.0);
+        let data = dataset(&[1, 2], vec![gt(1, 1, a, false), gt(2, 2, b, false)]);
+        let eval = CocoEvaluation::new(&data, &[dt(1, 1, a, 0.9), dt(2, 2, b, 0.4)], &[1, 2], 100);
+        assert_eq!(eval.iou_thresholds.len(), 10);
+        assert_close(eval.map50(), 1.0);
+        assert_close(eval.map50_95(), 1.0);
+        assert_close(eval.category_ap(1).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn false_positive_ranked_first_halves_precision() {
+        let a = bbox(10.0, 10.0, 50.0, 60.0);
+        let data = dataset(&[1], vec![gt(1, 1, a, false)]);
+        let detections = [
+            dt(1, 1, bbox(200.0, 200.0, 240.0, 250.0), 0.9),
+            dt(1, 1, a, 0.8),
+        ];
+        let eval = CocoEvaluation::new(&data, &detections, &[1, 2], 100);
+        assert_close(eval.map50(), 0.5);
+        // At most one detection per image keeps only the false positive.
+        let eval = CocoEvaluation::new(&data, &detections, &[1, 2], 1);
+        assert_close(eval.map50(), 0.0);
+    }
+
+    #[test]
+    fn ignores_detections_on_crowds() {
+        let person = bbox(0.0, 0.0, 40.0, 100.0);
+        let crowd = bbox(100.0, 0.0, 300.0, 100.0);
+        let data = dataset(
+            &[1, 2],
+            vec![
+                gt(1, 1, person, false),
+                gt(1, 1, crowd, true),
+                gt(2, 2, crowd, true),
+            ],
+        );
+        let detections = [
+            // Inside the crowd, ranked above the real match: not a false
+            // positive.
+            dt(1, 1, bbox(120.0, 10.0, 160.0, 90.0), 0.9),
+            dt(1, 1, bbox(200.0, 10.0, 240.0, 90.0), 0.85),
+            dt(1, 1, person, 0.8),
+            dt(2, 2, bbox(120.0, 10.0, 160.0, 90.0), 0.9),
+        ];
+        let eval = CocoEvaluation::new(&data, &detections, &[1, 2], 100);
+        assert_close(eval.ap[0][0].unwrap(), 1.0);
+        // Only crowds: nothing to find.
+        assert_eq!(eval.ap[0][1], None);
+        assert_eq!(eval.category_ap(1), None);
+        assert_close(eval.map50(), 1.0);
+    }
+
+    #[test]
+    fn categories_without_ground_truth_have_no_ap() {
+        let a = bbox(10.0, 10.0, 50.0, 60.0);
+        let data = dataset(&[1, 3], vec![gt(1, 1, a, false)]);
+        let eval = CocoEvaluation::new(&data, &[dt(1, 1, a, 0.9), dt(1, 3, a, 0.9)], &[1], 100);
+        assert_eq!(eval.categories, [1, 3]);
+        assert!(eval.ap.iter().all(|ap| ap[1].is_none()));
+        // Left out of the mean rather than counted as 0.
+        assert_close(eval.map50(), 1.0);
+    }
+
+    #[test]
+    fn averages_over_iou_thresholds() {
+        let data = dataset(&[1], vec![gt(1, 1, bbox(0.0, 0.0, 100.0, 100.0), false)]);
+        // IoU 0.72: a match at 0.5 up to 0.7, a miss from 0.75.
+        let detection = dt(1, 1, bbox(0.0, 0.0, 100.0, 72.0), 0.9);
+        let eval = CocoEvaluation::new(&data, &[detection], &[1], 100);
+        let matched: Vec<f64> = eval.ap.iter().map(|ap| ap[0].unwrap()).collect();
+        assert_eq!(matched, [1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_close(eval.map50(), 1.0);
+        assert_close(eval.map50_95(), 0.5);
+    }
+}
@@ -1,14 +1,24 @@
 use anyhow::{bail, Ok, Result};
+use half::f16;
 use image::DynamicImage;
 use rknn_api_sys::{
-    rknn_context, rknn_destroy, rknn_init, rknn_input, rknn_input_output_num, rknn_input_range,
-    rknn_inputs_set, rknn_output, rknn_outputs_get, rknn_outputs_release, rknn_query,
-    rknn_query_cmd, rknn_run, rknn_sdk_version, rknn_set_core_mask, rknn_set_input_shapes,
-    rknn_tensor_attr, RKNN_SUCC,
+    rknn_context, rknn_destroy, rknn_dup_context, rknn_init, rknn_input, rknn_input_output_num,
+    rknn_input_range, rknn_inputs_set, rknn_output, rknn_outputs_get, rknn_outputs_release,
+    rknn_query, rknn_query_cmd, rknn_register_custom_ops, rknn_run, rknn_sdk_version,
+    rknn_set_core_mask, rknn_set_input_shapes, rknn_tensor_attr, RKNN_SUCC,
+};
+use std::{
+    ffi::CString,
+    sync::atomic::{AtomicU64, Ordering},
 };
-use std::ffi::CString;
 
-use crate::examples::utils::safe_string;
+use crate::examples::{
+    custom_op::{self, CustomOp, RegisteredOp},
+    platform,
+    preprocess::{ImageTransform, Preprocessor},
+    trace::{Span, Tracer},
+    utils::{dequantize, get_format_string, get_type_string, safe_string},
+};
 
 #[macro_export]
 macro_rules! call_rknn_api {
@@ -21,10 +31,35 @@ macro_rules! call_rknn_api {
     }};
 }
 
+/// The platform the crate was built for, as used in the names of the models
+/// `build.rs` downloads.
+pub const PLATFORM: &str = if cfg!(feature = "rk3588") {
+    "rk3588"
+} else if cfg!(feature = "rk3576") {
+    "rk3576"
+} else if cfg!(feature = "rk3566_rk3568") {
+    "rk3566_rk3568"
+} else {
+    "rk3562"
+};
+
+/// Path of a model downloaded by `build.rs`, e.g. `bundled_model_path("resnet18")`.
+pub fn bundled_model_path(name: &str) -> String {
+    format!(
+        "{}/models/{}_for_{}.rknn",
+        env!("CARGO_MANIFEST_DIR"),
+        name,
+        PLATFORM
+    )
+}
+
 pub struct RKNNContext {
     ctx: rknn_context,
     pub n_input: u32,
     pub n_output: u32,
+    tracer: Option<Tracer>,
+    frame: AtomicU64,
+    custom_ops: Vec<RegisteredOp>,
 }
 
 #[repr(u32)]
@@ -40,13 +75,335 @@ pub enum RknnCoreMask {
     Undefined = rknn_api_sys::_rknn_core_mask_RKNN_NPU_CORE_UNDEFINED,
 }
 
+/// Rust types with a matching `rknn_tensor_type`.
+pub trait TensorElement: Copy + Default + 'static {
+    const TENSOR_TYPE: rknn_api_sys::rknn_tensor_type;
+}
+
+macro_rules! tensor_element {
+    ( $($t:ty => $tensor_type:ident),* ) => {
+        $(impl TensorElement for $t {
+            const TENSOR_TYPE: rknn_api_sys::rknn_tensor_type = rknn_api_sys::$tensor_type;
+        })*
+    };
+}
+
+tensor_element!(
+    f32 => _rknn_tensor_type_RKNN_TENSOR_FLOAT32,
+    half::f16 => _rknn_tensor_type_RKNN_TENSOR_FLOAT16,
+    i8 => _rknn_tensor_type_RKNN_TENSOR_INT8,
+    u8 => _rknn_tensor_type_RKNN_TENSOR_UINT8,
+    i16 => _rknn_tensor_type_RKNN_TENSOR_INT16,
+    u16 => _rknn_tensor_type_RKNN_TENSOR_UINT16,
+    i32 => _rknn_tensor_type_RKNN_TENSOR_INT32,
+    u32 => _rknn_tensor_type_RKNN_TENSOR_UINT32,
+    i64 => _rknn_tensor_type_RKNN_TENSOR_INT64
+);
+
+/// An owned input buffer in row major order, see
+/// [`RKNNContext::set_tensor_inputs`].
+#[derive(Clone)]
+pub struct TensorInput {
+    pub data: Vec<u8>,
+    pub dims: Vec<usize>,
+    pub type_: rknn_api_sys::rknn_tensor_type,
+}
+
+/// Types `rknn_inputs_set` converts to whatever the model was quantized to.
+const CONVERTIBLE_TYPES: [rknn_api_sys::rknn_tensor_type; 4] = [
+    rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32,
+    rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT16,
+    rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT8,
+    rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8,
+];
+
+impl TensorInput {
+    /// The format to hand to the runtime for this input: the attr's own
+    /// layout, or for 4-D NHWC/NCHW models the other one of the two.
+    fn format_for(&self, attr: &rknn_tensor_attr) -> Result<rknn_api_sys::rknn_tensor_format> {
+        let nchw = rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NCHW;
+        let nhwc = rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC;
+        let dims: Vec<usize> = attr.dims[..attr.n_dims as usize]
+            .iter()
+            .map(|d| *d as usize)
+            .collect();
+        if self.dims == dims {
+            return Ok(attr.fmt);
+        }
+        if dims.len() == 4 {
+            let (n, a, b, c) = (dims[0], dims[1], dims[2], dims[3]);
+            if attr.fmt == nhwc && self.dims == [n, c, a, b] {
+                return Ok(nchw);
+            }
+            if attr.fmt == nchw && self.dims == [n, b, c, a] {
+                return Ok(nhwc);
+            }
+        }
+        bail!(
+            "input {} has shape {:?}, the model expects {:?} ({})",
+            attr.index,
+            self.dims,
+            dims,
+            get_format_string(attr.fmt)
+        )
+    }
+
+    /// Checks the shape and type against the model input `attr`.
+    pub fn check(&self, attr: &rknn_tensor_attr) -> Result<()> {
+        self.check_type(attr)?;
+        self.format_for(attr).map(|_| ())
+    }
+
+    fn check_type(&self, attr: &rknn_tensor_attr) -> Result<()> {
+        let convertible =
+            CONVERTIBLE_TYPES.contains(&self.type_) && CONVERTIBLE_TYPES.contains(&attr.type_);
+        if self.type_ != attr.type_ && !convertible {
+            bail!(
+                "input {} is {}, the model expects {}",
+                attr.index,
+                get_type_string(self.type_),
+                get_type_string(attr.type_)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Outputs fetched with [`RKNNContext::fetch_outputs`]; the runtime owned
+/// buffers are released on drop.
+pub struct Outputs<'a> {
+    ctx: &'a RKNNContext,
+    outputs: Vec<rknn_output>,
+    attrs: Vec<rknn_tensor_attr>,
+    want_float: bool,
+}
+
+impl Outputs<'_> {
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    pub fn attrs(&self) -> &[rknn_tensor_attr] {
+        &self.attrs
+    }
+
+    pub fn dims(&self, index: usize) -> Vec<usize> {
+        let attr = &self.attrs[index];
+        attr.dims[..attr.n_dims as usize]
+            .iter()
+            .map(|d| *d as usize)
+            .collect()
+    }
+
+    /// The element type of output `index`: FP32 when fetched as float,
+    /// the model's own type otherwise.
+    pub fn tensor_type(&self, index: usize) -> rknn_api_sys::rknn_tensor_type {
+        if self.want_float {
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32
+        } else {
+            self.attrs[index].type_
+        }
+    }
+
+    pub fn as_slice<T: TensorElement>(&self, index: usize) -> Result<&[T]> {
+        let tensor_type = self.tensor_type(index);
+        if tensor_type != T::TENSOR_TYPE {
+            bail!(
+                "output {} is {}, not {}",
+                index,
+                get_type_string(tensor_type),
+                get_type_string(T::TENSOR_TYPE)
+            );
+        }
+        let output = &self.outputs[index];
+        let len = output.size as usize / size_of::<T>();
+        Ok(unsafe { std::slice::from_raw_parts(output.buf as *const T, len) })
+    }
+
+    /// Output `index` read as real numbers, whatever its type.
+    pub fn view(&self, index: usize) -> Result<OutputView<'_>> {
+        let data = match self.tensor_type(index) {
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32 => {
+                OutputData::F32(self.as_slice(index)?)
+            }
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT16 => {
+                OutputData::F16(self.as_slice(index)?)
+            }
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT8 => {
+                OutputData::I8(self.as_slice(index)?)
+            }
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8 => {
+                OutputData::U8(self.as_slice(index)?)
+            }
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT32 => {
+                OutputData::I32(self.as_slice(index)?)
+            }
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT64 => {
+                OutputData::I64(self.as_slice(index)?)
+            }
+            other => bail!("output {} is {}", index, get_type_string(other)),
+        };
+        Ok(OutputView::with_layout(
+            data,
+            self.attrs[index],
+            !self.want_float,
+        ))
+    }
+}
+
+/// The elements of an output in its own type.
+#[derive(Copy, Clone, Debug)]
+pub enum OutputData<'a> {
+    F32(&'a [f32]),
+    F16(&'a [f16]),
+    I8(&'a [i8]),
+    U8(&'a [u8]),
+    I32(&'a [i32]),
+    I64(&'a [i64]),
+}
+
+impl OutputData<'_> {
+    pub fn len(&self) -> usize {
+        match self {
+            OutputData::F32(d) => d.len(),
+            OutputData::F16(d) => d.len(),
+            OutputData::I8(d) => d.len(),
+            OutputData::U8(d) => d.len(),
+            OutputData::I32(d) => d.len(),
+            OutputData::I64(d) => d.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// An output read as real numbers: quantized values are dequantized with the
+/// output's zp/scale as they are read.
+#[derive(Copy, Clone, Debug)]
+pub struct OutputView<'a> {
+    pub data: OutputData<'a>,
+    pub attr: rknn_tensor_attr,
+    quantized: bool,
+    /// `chw()` and the layout, worked out once for `at`.
+    chw: (usize, usize, usize),
+    nhwc: bool,
+}
+
+impl<'a> OutputView<'a> {
+    pub fn new(data: OutputData<'a>, attr: rknn_tensor_attr) -> Self {
+        Self::with_layout(data, attr, true)
+    }
+
+    /// A view of values that are real numbers already, e.g. a dequantized
+    /// copy of an output kept after the runtime's buffers are released.
+    pub fn from_f32(data: &'a [f32], attr: rknn_tensor_attr) -> Self {
+        Self::with_layout(OutputData::F32(data), attr, false)
+    }
+
+    fn with_layout(data: OutputData<'a>, attr: rknn_tensor_attr, quantized: bool) -> Self {
+        let nhwc = attr.fmt == rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC;
+        let d = |i: usize| attr.dims[i] as usize;
+        let chw = match attr.n_dims {
+            4 if nhwc => (d(3), d(1), d(2)),
+            4 => (d(1), d(2), d(3)),
+            3 => (d(0), d(1), d(2)),
+            _ => (data.len(), 1, 1),
+        };
+        OutputView {
+            data,
+            attr,
+            quantized,
+            chw,
+            nhwc,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dims(&self) -> Vec<usize> {
+        self.attr.dims[..self.attr.n_dims as usize]
+            .iter()
+            .map(|d| *d as usize)
+            .collect()
+    }
+
+    pub fn get(&self, index: usize) -> f32 {
+        let value = match self.data {
+            OutputData::F32(d) => d[index],
+            OutputData::F16(d) => d[index].to_f32(),
+            OutputData::I8(d) => d[index] as f32,
+            OutputData::U8(d) => d[index] as f32,
+            OutputData::I32(d) => d[index] as f32,
+            OutputData::I64(d) => d[index] as f32,
+        };
+        if self.quantized {
+            dequantize(value, &self.attr)
+        } else {
+            value
+        }
+    }
+
+    pub fn to_vec(self) -> Vec<f32> {
+        (0..self.len()).map(|i| self.get(i)).collect()
+    }
+
+    /// Channels, height and width of a 4-D output.
+    pub fn chw(&self) -> (usize, usize, usize) {
+        self.chw
+    }
+
+    /// The element at channel `c`, row `y`, column `x` of the first batch of
+    /// a 4-D output, in either layout.
+    pub fn at(&self, c: usize, y: usize, x: usize) -> f32 {
+        let (channels, height, width) = self.chw;
+        if self.nhwc {
+            self.get((y * width + x) * channels + c)
+        } else {
+            self.get((c * height + y) * width + x)
+        }
+    }
+}
+
+impl Drop for Outputs<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            rknn_outputs_release(
+                self.ctx.ctx,
+                self.outputs.len() as u32,
+                self.outputs.as_mut_ptr(),
+            );
+        };
+    }
+}
+
 pub struct SdkVersion {
     pub api_verion: String,
     pub driver_verion: String,
 }
 
 impl RKNNContext {
+    /// Fails with a [`platform::PlatformMismatch`] when the model is for
+    /// another chip than this device's.
     pub fn load_model(model_path: &str) -> Result<Self> {
+        Self::load_model_with_flags(model_path, 0)
+    }
+
+    /// [`RKNNContext::load_model`] with `RKNN_FLAG_*` init flags, e.g.
+    /// `RKNN_FLAG_ASYNC_MASK`.
+    pub fn load_model_with_flags(model_path: &str, flags: u32) -> Result<Self> {
+        platform::check_model(model_path, &platform::device_root())?;
         let mut ctx: rknn_context = 0;
         let c_string = CString::new(model_path).expect("CString::new failed");
         let c_string_ptr = c_string.as_ptr() as *mut ::std::os::raw::c_void;
@@ -54,10 +411,22 @@ impl RKNNContext {
             &mut ctx,
             c_string_ptr,
             0,
-            0,
+            flags,
             std::ptr::null_mut()
         ))?;
+        Self::from_handle(ctx)
+    }
+
+    /// A second context running the same model, sharing its weights, e.g.
+    /// for another thread. Custom ops are not carried over.
+    pub fn dup(&self) -> Result<Self> {
+        let mut ctx_in = self.ctx;
+        let mut ctx: rknn_context = 0;
+        call_rknn_api!(rknn_dup_context(&mut ctx_in, &mut ctx))?;
+        Self::from_handle(ctx)
+    }
 
+    fn from_handle(ctx: rknn_context) -> Result<Self> {
         let mut io_num: rknn_input_output_num = rknn_input_output_num::default();
         let io_num_ptr = &mut io_num as *mut rknn_input_output_num as *mut ::std::os::raw::c_void;
         call_rknn_api!(rknn_query(
@@ -70,9 +439,44 @@ impl RKNNContext {
             ctx: ctx,
             n_input: io_num.n_input,
             n_output: io_num.n_output,
+            tracer: None,
+            frame: AtomicU64::new(0),
+            custom_ops: Vec::new(),
         })
     }
 
+    /// Registers `T` as the implementation of the model's `T::OP_TYPE` ops.
+    /// Must be called before the first `run`.
+    pub fn register_custom_op<T: CustomOp>(&mut self) -> Result<()> {
+        let mut registered = custom_op::describe::<T>()?;
+        call_rknn_api!(rknn_register_custom_ops(
+            self.ctx,
+            registered.op.as_mut(),
+            1
+        ))?;
+        self.custom_ops.push(registered);
+        Ok(())
+    }
+
+    /// Records the time spent in `set_inputs`, `run` and `get_outputs`
+    /// (and any span opened with [`RKNNContext::trace`]) into `tracer`.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Starts a new frame; spans recorded from now on are tagged with it.
+    pub fn begin_frame(&self) -> u64 {
+        self.frame.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Opens a span on this context's track for the current frame.
+    /// Returns `None` when no tracer is set.
+    pub fn trace(&self, name: &'static str) -> Option<Span<'_>> {
+        self.tracer
+            .as_ref()
+            .map(|t| t.span(name, self.ctx, self.frame.load(Ordering::Relaxed)))
+    }
+
     pub fn get_sdk_version(&self) -> Result<SdkVersion> {
         let mut sdk_ver: rknn_sdk_version = rknn_sdk_version::default();
         let sdk_ver_ptr = &mut sdk_ver as *mut rknn_sdk_version as *mut ::std::os::raw::c_void;
@@ -88,6 +492,33 @@ impl RKNNContext {
         })
     }
 
+    /// The string given to the toolkit as `custom_string` when converting.
+    pub fn get_custom_string(&self) -> Result<String> {
+        let mut custom = rknn_api_sys::rknn_custom_string::default();
+        let custom_ptr =
+            &mut custom as *mut rknn_api_sys::rknn_custom_string as *mut ::std::os::raw::c_void;
+        call_rknn_api!(rknn_query(
+            self.ctx,
+            rknn_api_sys::_rknn_query_cmd_RKNN_QUERY_CUSTOM_STRING,
+            custom_ptr,
+            std::mem::size_of::<rknn_api_sys::rknn_custom_string>() as u32,
+        ))?;
+        safe_string(&custom.string)
+    }
+
+    pub fn get_mem_size(&self) -> Result<rknn_api_sys::rknn_mem_size> {
+        let mut mem_size = rknn_api_sys::rknn_mem_size::default();
+        let mem_size_ptr =
+            &mut mem_size as *mut rknn_api_sys::rknn_mem_size as *mut ::std::os::raw::c_void;
+        call_rknn_api!(rknn_query(
+            self.ctx,
+            rknn_api_sys::_rknn_query_cmd_RKNN_QUERY_MEM_SIZE,
+            mem_size_ptr,
+            std::mem::size_of::<rknn_api_sys::rknn_mem_size>() as u32,
+        ))?;
+        Ok(mem_size)
+    }
+
     pub fn get_input_attrs(&self) -> Result<Vec<rknn_tensor_attr>> {
         self.get_attrs(
             self.n_input,
@@ -102,6 +533,36 @@ impl RKNNContext {
         )
     }
 
+    /// The input attrs in the layout and type the NPU works in, for zero
+    /// copy inputs.
+    pub fn get_native_input_attrs(&self) -> Result<Vec<rknn_tensor_attr>> {
+        self.get_attrs(
+            self.n_input,
+            rknn_api_sys::_rknn_query_cmd_RKNN_QUERY_NATIVE_INPUT_ATTR,
+        )
+    }
+
+    pub fn get_native_output_attrs(&self) -> Result<Vec<rknn_tensor_attr>> {
+        self.get_attrs(
+            self.n_output,
+            rknn_api_sys::_rknn_query_cmd_RKNN_QUERY_NATIVE_OUTPUT_ATTR,
+        )
+    }
+
+    pub fn get_native_nhwc_input_attrs(&self) -> Result<Vec<rknn_tensor_attr>> {
+        self.get_attrs(
+            self.n_input,
+            rknn_api_sys::_rknn_query_cmd_RKNN_QUERY_NATIVE_NHWC_INPUT_ATTR,
+        )
+    }
+
+    pub fn get_native_nhwc_output_attrs(&self) -> Result<Vec<rknn_tensor_attr>> {
+        self.get_attrs(
+            self.n_output,
+            rknn_api_sys::_rknn_query_cmd_RKNN_QUERY_NATIVE_NHWC_OUTPUT_ATTR,
+        )
+    }
+
     fn get_attrs(&self, num: u32, cmd: rknn_query_cmd) -> Result<Vec<rknn_tensor_attr>> {
         let mut vec = Vec::with_capacity(num as usize);
         for i in 0..num {
@@ -150,48 +611,108 @@ impl RKNNContext {
         Ok(())
     }
 
+    /// Fixes every dynamic input to the first shape of its range so the model
+    /// can run without picking shapes, and returns the current input attrs.
+    pub fn use_default_shapes(&self) -> Result<Vec<rknn_tensor_attr>> {
+        let mut attrs = self.get_input_attrs()?;
+        let ranges = match self.get_input_range() {
+            Result::Ok(ranges) if ranges.iter().any(|r| r.shape_number > 0) => ranges,
+            _ => return Ok(attrs),
+        };
+        for (attr, range) in attrs.iter_mut().zip(&ranges) {
+            let n_dims = attr.n_dims as usize;
+            attr.dims[..n_dims].copy_from_slice(&range.dyn_range[0][..n_dims]);
+        }
+        self.set_input_shapes(&mut attrs)?;
+        self.get_input_attrs()
+    }
+
     pub fn set_core_mask(&self, core_mask: &RknnCoreMask) -> Result<()> {
         call_rknn_api!(rknn_set_core_mask(self.ctx, *core_mask as u32))?;
         Ok(())
     }
 
-    pub(crate) fn set_inputs(
+    /// Preprocesses one image per input and sets them, returning how each
+    /// image maps onto its input.
+    pub fn set_inputs(
         &self,
-        input_attrs: &Vec<rknn_tensor_attr>,
-        resized_images: &Vec<DynamicImage>,
-    ) -> Result<()> {
-        let mut inputs: Vec<rknn_input> = resized_images
+        input_attrs: &[rknn_tensor_attr],
+        images: &[DynamicImage],
+        preprocessor: &Preprocessor,
+    ) -> Result<Vec<ImageTransform>> {
+        let preprocess = self.trace("preprocess");
+        let (inputs, transforms): (Vec<TensorInput>, Vec<ImageTransform>) = images
             .iter()
-            .enumerate()
-            .map(|(i, img)| {
-                let height =
-                    if input_attrs[i].fmt == rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC {
-                        input_attrs[i].dims[1]
-                    } else {
-                        input_attrs[i].dims[2]
-                    };
-                let width =
-                    if input_attrs[i].fmt == rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC {
-                        input_attrs[i].dims[2]
-                    } else {
-                        input_attrs[i].dims[3]
-                    };
-                let mut buf = img
-                    .resize(width, height, image::imageops::FilterType::Nearest)
-                    .to_rgb8()
-                    .into_raw();
-                rknn_input {
-                    index: i as u32,
-                    pass_through: 0,
-                    fmt: rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC,
-                    type_: rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8,
-                    buf: buf.as_mut_ptr() as *mut ::std::os::raw::c_void,
-                    size: buf.len() as u32,
-                }
+            .zip(input_attrs)
+            .map(|(img, attr)| preprocessor.run(img, attr))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        drop(preprocess);
+
+        self.set_tensor_inputs(&inputs)?;
+        Ok(transforms)
+    }
+
+    /// Checks `inputs` against the model's input attrs (count, shape, layout
+    /// and type) and sets them.
+    pub fn set_tensor_inputs(&self, inputs: &[TensorInput]) -> Result<()> {
+        let attrs = self.get_input_attrs()?;
+        if inputs.len() != attrs.len() {
+            bail!(
+                "{} inputs given, the model has {}",
+                inputs.len(),
+                attrs.len()
+            );
+        }
+        let mut raw_inputs = Vec::with_capacity(inputs.len());
+        for (input, attr) in inputs.iter().zip(&attrs) {
+            input.check_type(attr)?;
+            raw_inputs.push(rknn_input {
+                index: attr.index,
+                pass_through: 0,
+                fmt: input.format_for(attr)?,
+                type_: input.type_,
+                buf: input.data.as_ptr() as *mut ::std::os::raw::c_void,
+                size: input.data.len() as u32,
+            });
+        }
+
+        let _span = self.trace("rknn_inputs_set");
+        call_rknn_api!(rknn_inputs_set(
+            self.ctx,
+            self.n_input,
+            raw_inputs.as_mut_ptr()
+        ))?;
+        Ok(())
+    }
+
+    /// Fetches all outputs, converted to f32 when `want_float` is set and in
+    /// the model's output type otherwise.
+    pub fn fetch_outputs(&self, want_float: bool) -> Result<Outputs<'_>> {
+        let attrs = self.get_output_attrs()?;
+        let mut outputs: Vec<rknn_output> = (0..self.n_output)
+            .map(|i| rknn_output {
+                want_float: want_float as u8,
+                is_prealloc: 0,
+                index: i,
+                ..Default::default()
             })
             .collect();
-        call_rknn_api!(rknn_inputs_set(self.ctx, self.n_input, inputs.as_mut_ptr()))?;
-        Ok(())
+
+        let _span = self.trace("rknn_outputs_get");
+        call_rknn_api!(rknn_outputs_get(
+            self.ctx,
+            self.n_output,
+            outputs.as_mut_ptr(),
+            std::ptr::null_mut()
+        ))?;
+        Ok(Outputs {
+            ctx: self,
+            outputs,
+            attrs,
+            want_float,
+        })
     }
 
     pub fn get_outputs(&self) -> Result<Vec<rknn_output>> {
@@ -204,6 +725,7 @@ impl RKNNContext {
             })
             .collect();
 
+        let _span = self.trace("rknn_outputs_get");
         call_rknn_api!(rknn_outputs_get(
             self.ctx,
             self.n_output,
@@ -223,11 +745,17 @@ impl RKNNContext {
         Ok(())
     }
     pub fn run(&self) -> Result<()> {
+        let _span = self.trace("rknn_run");
         call_rknn_api!(rknn_run(self.ctx, std::ptr::null_mut()))?;
         Ok(())
     }
 }
 
+// The runtime doesn't tie a context to the thread that created it, only
+// concurrent calls are unsafe, which the missing `Sync` rules out. The state
+// of registered custom ops moves along with it, hence `CustomOp: Send`.
+unsafe impl Send for RKNNContext {}
+
 impl Drop for RKNNContext {
     fn drop(&mut self) {
         println!("destroying RKNNContext");
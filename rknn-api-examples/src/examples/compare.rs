@@ -0,0 +1,215 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use serde_json::json;
+
+use crate::examples::{
+    common::*,
+    infer::set_inputs_from_paths,
+    metrics::{compare, nhwc_to_nchw, Comparison},
+    npy::{is_npy_path, load_npz, NpyArray},
+    preprocess::Preprocessor,
+    utils::safe_string,
+};
+
+/// Run a model and compare its outputs with reference tensors (e.g. from
+/// ONNX Runtime or the toolkit's simulator), failing below the thresholds.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The path to the model file (*.rknn)
+    #[arg(short, long)]
+    model_path: String,
+
+    /// The inputs: one .npy per model input, .npz archives holding several,
+    /// or one image per input
+    #[arg(short, long, value_parser, required = true)]
+    input_paths: Vec<String>,
+
+    /// The reference outputs: one .npy per model output, in order, or an
+    /// .npz archive (matched to the outputs by name, else in order)
+    #[arg(short, long, value_parser, required = true)]
+    reference_paths: Vec<String>,
+
+    /// The number of top classes compared, per row of each output's last
+    /// axis
+    #[arg(short = 'k', long, default_value_t = 5)]
+    top_k: usize,
+
+    /// Fail if any output's cosine similarity is lower
+    #[arg(long)]
+    min_cosine: Option<f64>,
+
+    /// Fail if any output's max absolute error is higher
+    #[arg(long)]
+    max_abs_error: Option<f64>,
+
+    /// Fail if any output's mean absolute error is higher
+    #[arg(long)]
+    max_mean_abs_error: Option<f64>,
+
+    /// Fail if any output's top-k agreement rate is lower
+    #[arg(long)]
+    min_top_k_agreement: Option<f64>,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+impl Example {
+    /// The reference of each output, by name when an archive names them all.
+    fn load_references(&self, output_names: &[String]) -> Result<Vec<NpyArray>> {
+        let mut arrays = Vec::new();
+        for path in &self.reference_paths {
+            if !is_npy_path(path) {
+                bail!("{} is not a .npy or .npz file", path);
+            }
+            if path.ends_with(".npz") {
+                arrays.extend(load_npz(path)?);
+            } else {
+                arrays.push((String::new(), NpyArray::load(path)?));
+            }
+        }
+        let named = output_names
+            .iter()
+            .all(|name| arrays.iter().any(|(n, _)| n == name));
+        if named && arrays.len() == output_names.len() {
+            arrays.sort_by_key(|(n, _)| output_names.iter().position(|name| name == n));
+        }
+        if arrays.len() != output_names.len() {
+            bail!(
+                "{} references for {} outputs",
+                arrays.len(),
+                output_names.len()
+            );
+        }
+        Ok(arrays.into_iter().map(|(_, a)| a).collect())
+    }
+
+    /// Which thresholds `c` misses.
+    fn failures(&self, c: &Comparison) -> Vec<String> {
+        let mut failures = Vec::new();
+        if let Some(min) = self.min_cosine.filter(|min| c.cosine < *min) {
+            failures.push(format!("cosine {:.6} < {}", c.cosine, min));
+        }
+        if let Some(max) = self.max_abs_error.filter(|max| c.max_abs_error > *max) {
+            failures.push(format!("max abs error {:.6} > {}", c.max_abs_error, max));
+        }
+        if let Some(max) = self
+            .max_mean_abs_error
+            .filter(|max| c.mean_abs_error > *max)
+        {
+            failures.push(format!("mean abs error {:.6} > {}", c.mean_abs_error, max));
+        }
+        if let Some(min) = self
+            .min_top_k_agreement
+            .filter(|min| c.top_k_agreement < *min)
+        {
+            failures.push(format!(
+                "top-{} agreement {:.4} < {}",
+                self.top_k, c.top_k_agreement, min
+            ));
+        }
+        failures
+    }
+
+    pub fn execute(&self) -> Result<()> {
+        let ctx = RKNNContext::load_model(&self.model_path)?;
+        ctx.set_core_mask(&self.core_mask)?;
+        let input_attrs = ctx.use_default_shapes()?;
+        set_inputs_from_paths(&ctx, &input_attrs, &self.input_paths, &self.preprocess)?;
+        ctx.run()?;
+        let outputs = ctx.fetch_outputs(true)?;
+
+        let output_names = outputs
+            .attrs()
+            .iter()
+            .map(|attr| safe_string(&attr.name))
+            .collect::<Result<Vec<_>>>()?;
+        let references = self.load_references(&output_names)?;
+
+        let mut results = Vec::with_capacity(outputs.len());
+        let mut failed = false;
+        for (i, reference) in references.iter().enumerate() {
+            let dims = outputs.dims(i);
+            let n: usize = dims.iter().product();
+            let actual = outputs.as_slice::<f32>(i)?;
+            let Some(actual) = actual.get(..n) else {
+                bail!(
+                    "output {} {:?} holds {} values, expected {}",
+                    i,
+                    dims,
+                    actual.len(),
+                    n
+                );
+            };
+            let is_nhwc =
+                outputs.attrs()[i].fmt == rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC;
+            // References usually come from NCHW frameworks.
+            let actual = match dims[..] {
+                [b, h, w, c]
+                    if is_nhwc && reference.shape == [b, c, h, w] && dims != reference.shape =>
+                {
+                    nhwc_to_nchw(actual, [b, h, w, c])
+                }
+                _ => {
+                    if reference.shape != dims {
+                        eprintln!(
+                            "output {} has shape {:?}, its reference {:?}; comparing flat",
+                            i, dims, reference.shape
+                        );
+                    }
+                    actual.to_vec()
+                }
+            };
+            let row_len = reference.shape.last().copied().unwrap_or(1);
+            let comparison = compare(&actual, &reference.to_f32_vec(), row_len, self.top_k)?;
+            let failures = self.failures(&comparison);
+            failed |= !failures.is_empty();
+
+            if !self.json {
+                println!(
+                    "\x1b[34;4m output {} ({}) {:?}\x1b[0m",
+                    i, output_names[i], dims
+                );
+                println!(
+                    "  cosine {:.6}, max abs error {:.6}, mean abs error {:.6}, top-{} agreement {:.4}",
+                    comparison.cosine,
+                    comparison.max_abs_error,
+                    comparison.mean_abs_error,
+                    self.top_k,
+                    comparison.top_k_agreement
+                );
+                for failure in &failures {
+                    println!("  FAIL: {}", failure);
+                }
+            }
+            let mut result = comparison.to_json();
+            result["index"] = json!(i);
+            result["name"] = json!(output_names[i]);
+            result["failures"] = json!(failures);
+            results.push(result);
+        }
+
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&json!({
+                    "model": self.model_path,
+                    "top_k": self.top_k,
+                    "passed": !failed,
+                    "outputs": results,
+                }))?
+            );
+        }
+        if failed {
+            bail!("outputs below the accuracy thresholds");
+        }
+        Ok(())
+    }
+}
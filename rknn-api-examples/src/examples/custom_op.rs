@@ -0,0 +1,439 @@
+use anyhow::{anyhow, bail, Result};
+use rknn_api_sys::{
+    rknn_custom_op, rknn_custom_op_attr, rknn_custom_op_context, rknn_custom_op_get_op_attr,
+    rknn_custom_op_tensor, rknn_tensor_attr, RKNN_ERR_FAIL, RKNN_SUCC,
+};
+use std::{
+    ffi::{c_void, CString},
+    os::raw::c_int,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::examples::{
+    common::TensorElement,
+    utils::{get_type_string, write_c_string},
+};
+
+/// Where the runtime executes a custom operator.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CustomOpTarget {
+    Cpu = rknn_api_sys::_rknn_target_type_RKNN_TARGET_TYPE_CPU,
+    Gpu = rknn_api_sys::_rknn_target_type_RKNN_TARGET_TYPE_GPU,
+}
+
+/// OpenCL kernel handed to the runtime for [`CustomOpTarget::Gpu`] operators.
+pub struct ClKernel {
+    pub name: &'static str,
+    pub source: &'static str,
+    pub build_options: &'static str,
+}
+
+/// An operator the model uses but the runtime does not implement.
+///
+/// The runtime calls `init` once per op instance in the graph; the returned
+/// value lives until `destroy` and is handed back to `prepare` and `compute`.
+/// Errors and panics are reported to the runtime as `RKNN_ERR_FAIL`, they
+/// never unwind into C. The value moves with its context when that is sent
+/// to another thread.
+pub trait CustomOp: Sized + Send + 'static {
+    /// The op type recorded in the model, e.g. `"cstSoftmax"`.
+    const OP_TYPE: &'static str;
+    const TARGET: CustomOpTarget = CustomOpTarget::Cpu;
+
+    /// Required when `TARGET` is [`CustomOpTarget::Gpu`].
+    fn cl_kernel() -> Option<ClKernel> {
+        None
+    }
+
+    fn init(
+        ctx: &CustomOpContext,
+        inputs: &[CustomOpTensor],
+        outputs: &[CustomOpTensor],
+    ) -> Result<Self>;
+
+    fn prepare(
+        &mut self,
+        _ctx: &CustomOpContext,
+        _inputs: &[CustomOpTensor],
+        _outputs: &mut [CustomOpTensor],
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn compute(
+        &mut self,
+        ctx: &CustomOpContext,
+        inputs: &[CustomOpTensor],
+        outputs: &mut [CustomOpTensor],
+    ) -> Result<()>;
+
+    fn destroy(self, _ctx: &CustomOpContext) {}
+}
+
+/// The runtime's per instance op context.
+pub struct CustomOpContext {
+    raw: *mut rknn_custom_op_context,
+}
+
+impl CustomOpContext {
+    pub fn gpu_context(&self) -> &rknn_api_sys::rknn_gpu_op_context {
+        unsafe { &(*self.raw).gpu_ctx }
+    }
+
+    /// Looks up a node attribute stored in the model, `None` if the node has
+    /// no attribute of that name. Fails unless `T` matches the attribute's
+    /// dtype.
+    pub fn attr<T: TensorElement>(&self, name: &str) -> Result<Option<&[T]>> {
+        let c_name = CString::new(name)?;
+        let mut attr = rknn_custom_op_attr::default();
+        unsafe { rknn_custom_op_get_op_attr(self.raw, c_name.as_ptr(), &mut attr) };
+        if attr.data.is_null() || attr.n_elems == 0 {
+            return Ok(None);
+        }
+        check_element::<T>(&format!("attribute {}", name), attr.dtype, attr.data)?;
+        Ok(Some(unsafe {
+            std::slice::from_raw_parts(attr.data as *const T, attr.n_elems as usize)
+        }))
+    }
+}
+
+/// Checks that runtime memory holding `type_` elements at `ptr` can be read
+/// as `T`.
+fn check_element<T: TensorElement>(
+    what: &str,
+    type_: rknn_api_sys::rknn_tensor_type,
+    ptr: *const c_void,
+) -> Result<()> {
+    if type_ != T::TENSOR_TYPE {
+        bail!(
+            "{} holds {} elements, not {}",
+            what,
+            get_type_string(type_),
+            get_type_string(T::TENSOR_TYPE)
+        );
+    }
+    if !(ptr as usize).is_multiple_of(align_of::<T>()) {
+        bail!(
+            "{} at {:p} is not aligned for {}",
+            what,
+            ptr,
+            get_type_string(T::TENSOR_TYPE)
+        );
+    }
+    Ok(())
+}
+
+/// An input or output of a custom op, backed by runtime allocated memory.
+#[repr(transparent)]
+pub struct CustomOpTensor(rknn_custom_op_tensor);
+
+impl CustomOpTensor {
+    pub fn attr(&self) -> &rknn_tensor_attr {
+        &self.0.attr
+    }
+
+    fn check_len<T: TensorElement>(&self) -> Result<usize> {
+        let len = self.0.attr.n_elems as usize;
+        let available = (self.0.mem.size as usize).saturating_sub(self.0.mem.offset as usize);
+        if self.0.mem.virt_addr.is_null() || len * size_of::<T>() > available {
+            bail!(
+                "tensor {} holds {} bytes, {} elements of {} bytes requested",
+                self.0.attr.index,
+                available,
+                len,
+                size_of::<T>()
+            );
+        }
+        let what = format!("tensor {}", self.0.attr.index);
+        check_element::<T>(&what, self.0.attr.type_, self.data_ptr() as *const c_void)?;
+        Ok(len)
+    }
+
+    fn data_ptr(&self) -> *mut u8 {
+        unsafe { (self.0.mem.virt_addr as *mut u8).add(self.0.mem.offset as usize) }
+    }
+
+    /// The tensor elements, fails unless `T` matches `attr().type_`.
+    pub fn data<T: TensorElement>(&self) -> Result<&[T]> {
+        let len = self.check_len::<T>()?;
+        Ok(unsafe { std::slice::from_raw_parts(self.data_ptr() as *const T, len) })
+    }
+
+    pub fn data_mut<T: TensorElement>(&mut self) -> Result<&mut [T]> {
+        let len = self.check_len::<T>()?;
+        Ok(unsafe { std::slice::from_raw_parts_mut(self.data_ptr() as *mut T, len) })
+    }
+}
+
+/// Keeps the op description (and the kernel source it points to) alive for
+/// as long as the context it was registered with.
+pub(crate) struct RegisteredOp {
+    pub(crate) op: Box<rknn_custom_op>,
+    _cl_kernel_source: Option<CString>,
+}
+
+pub(crate) fn describe<T: CustomOp>() -> Result<RegisteredOp> {
+    let mut op = Box::new(rknn_custom_op {
+        version: 1,
+        target: T::TARGET as u32,
+        init: Some(init_trampoline::<T>),
+        prepare: Some(prepare_trampoline::<T>),
+        compute: Some(compute_trampoline::<T>),
+        destroy: Some(destroy_trampoline::<T>),
+        ..Default::default()
+    });
+    write_c_string(&mut op.op_type, T::OP_TYPE)?;
+
+    let cl_kernel_source = match (T::TARGET, T::cl_kernel()) {
+        (CustomOpTarget::Cpu, _) => None,
+        (CustomOpTarget::Gpu, None) => bail!("GPU op {} has no OpenCL kernel", T::OP_TYPE),
+        (CustomOpTarget::Gpu, Some(kernel)) => {
+            write_c_string(&mut op.cl_kernel_name, kernel.name)?;
+            write_c_string(&mut op.cl_build_options, kernel.build_options)?;
+            let source = CString::new(kernel.source)?;
+            op.cl_kernel_source = source.as_ptr() as *mut _;
+            op.cl_source_size = kernel.source.len() as u64;
+            Some(source)
+        }
+    };
+    Ok(RegisteredOp {
+        op,
+        _cl_kernel_source: cl_kernel_source,
+    })
+}
+
+fn ffi_guard<F: FnOnce() -> Result<()>>(op_type: &str, stage: &str, f: F) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => RKNN_SUCC as c_int,
+        Ok(Err(err)) => {
+            eprintln!("custom op {} failed to {}: {}", op_type, stage, err);
+            RKNN_ERR_FAIL
+        }
+        Err(_) => {
+            eprintln!("custom op {} panicked in {}", op_type, stage);
+            RKNN_ERR_FAIL
+        }
+    }
+}
+
+unsafe fn tensors<'a>(ptr: *mut rknn_custom_op_tensor, n: u32) -> &'a mut [CustomOpTensor] {
+    if ptr.is_null() || n == 0 {
+        &mut []
+    } else {
+        std::slice::from_raw_parts_mut(ptr as *mut CustomOpTensor, n as usize)
+    }
+}
+
+unsafe fn state<'a, T>(op_ctx: *mut rknn_custom_op_context) -> Result<&'a mut T> {
+    ((*op_ctx).priv_data as *mut T)
+        .as_mut()
+        .ok_or_else(|| anyhow!("op was not initialized"))
+}
+
+unsafe extern "C" fn init_trampoline<T: CustomOp>(
+    op_ctx: *mut rknn_custom_op_context,
+    inputs: *mut rknn_custom_op_tensor,
+    n_inputs: u32,
+    outputs: *mut rknn_custom_op_tensor,
+    n_outputs: u32,
+) -> c_int {
+    ffi_guard(T::OP_TYPE, "init", || {
+        let ctx = CustomOpContext { raw: op_ctx };
+        let op = T::init(&ctx, tensors(inputs, n_inputs), tensors(outputs, n_outputs))?;
+        (*op_ctx).priv_data = Box::into_raw(Box::new(op)) as *mut c_void;
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn prepare_trampoline<T: CustomOp>(
+    op_ctx: *mut rknn_custom_op_context,
+    inputs: *mut rknn_custom_op_tensor,
+    n_inputs: u32,
+    outputs: *mut rknn_custom_op_tensor,
+    n_outputs: u32,
+) -> c_int {
+    ffi_guard(T::OP_TYPE, "prepare", || {
+        let ctx = CustomOpContext { raw: op_ctx };
+        state::<T>(op_ctx)?.prepare(&ctx, tensors(inputs, n_inputs), tensors(outputs, n_outputs))
+    })
+}
+
+unsafe extern "C" fn compute_trampoline<T: CustomOp>(
+    op_ctx: *mut rknn_custom_op_context,
+    inputs: *mut rknn_custom_op_tensor,
+    n_inputs: u32,
+    outputs: *mut rknn_custom_op_tensor,
+    n_outputs: u32,
+) -> c_int {
+    ffi_guard(T::OP_TYPE, "compute", || {
+        let ctx = CustomOpContext { raw: op_ctx };
+        state::<T>(op_ctx)?.compute(&ctx, tensors(inputs, n_inputs), tensors(outputs, n_outputs))
+    })
+}
+
+unsafe extern "C" fn destroy_trampoline<T: CustomOp>(op_ctx: *mut rknn_custom_op_context) -> c_int {
+    ffi_guard(T::OP_TYPE, "destroy", || {
+        let ptr = (*op_ctx).priv_data as *mut T;
+        if !ptr.is_null() {
+            (*op_ctx).priv_data = std::ptr::null_mut();
+            Box::from_raw(ptr).destroy(&CustomOpContext { raw: op_ctx });
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rknn_api_sys::{
+        _rknn_tensor_type_RKNN_TENSOR_FLOAT32, _rknn_tensor_type_RKNN_TENSOR_INT8, rknn_tensor_mem,
+    };
+
+    /// Scales its input by a factor counted up on every `prepare`.
+    struct Scale {
+        factor: f32,
+    }
+
+    impl CustomOp for Scale {
+        const OP_TYPE: &'static str = "cstScale";
+
+        fn init(
+            _ctx: &CustomOpContext,
+            inputs: &[CustomOpTensor],
+            _outputs: &[CustomOpTensor],
+        ) -> Result<Self> {
+            if inputs.len() != 1 {
+                bail!("expected 1 input, got {}", inputs.len());
+            }
+            Ok(Scale { factor: 1.0 })
+        }
+
+        fn prepare(
+            &mut self,
+            _ctx: &CustomOpContext,
+            _inputs: &[CustomOpTensor],
+            _outputs: &mut [CustomOpTensor],
+        ) -> Result<()> {
+            self.factor += 1.0;
+            Ok(())
+        }
+
+        fn compute(
+            &mut self,
+            _ctx: &CustomOpContext,
+            inputs: &[CustomOpTensor],
+            outputs: &mut [CustomOpTensor],
+        ) -> Result<()> {
+            let input = inputs[0].data::<f32>()?;
+            if input.iter().any(|v| v.is_nan()) {
+                panic!("NaN input");
+            }
+            for (out, v) in outputs[0].data_mut::<f32>()?.iter_mut().zip(input) {
+                *out = v * self.factor;
+            }
+            Ok(())
+        }
+    }
+
+    fn tensor(index: u32, type_: u32, data: &mut [f32], offset: usize) -> rknn_custom_op_tensor {
+        let mut attr = rknn_tensor_attr {
+            index,
+            n_elems: (data.len() - offset) as u32,
+            type_,
+            ..Default::default()
+        };
+        attr.n_dims = 1;
+        attr.dims[0] = attr.n_elems;
+        rknn_custom_op_tensor {
+            attr,
+            mem: rknn_tensor_mem {
+                virt_addr: data.as_mut_ptr() as *mut c_void,
+                offset: (offset * size_of::<f32>()) as i32,
+                size: size_of_val(data) as u32,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn trampolines_drive_the_op() {
+        let op = describe::<Scale>().unwrap();
+        let (init, prepare, compute, destroy) = (
+            op.op.init.unwrap(),
+            op.op.prepare.unwrap(),
+            op.op.compute.unwrap(),
+            op.op.destroy.unwrap(),
+        );
+        let mut ctx = rknn_custom_op_context::default();
+        let mut input = vec![1.0f32, -2.0, 3.0];
+        let mut output = vec![0.0f32; 3];
+        let mut inputs = [tensor(
+            0,
+            _rknn_tensor_type_RKNN_TENSOR_FLOAT32,
+            &mut input,
+            0,
+        )];
+        let mut outputs = [tensor(
+            0,
+            _rknn_tensor_type_RKNN_TENSOR_FLOAT32,
+            &mut output,
+            0,
+        )];
+        let (ins, outs) = (inputs.as_mut_ptr(), outputs.as_mut_ptr());
+        unsafe {
+            // The op only exists between init and destroy.
+            assert_eq!(compute(&mut ctx, ins, 1, outs, 1), RKNN_ERR_FAIL);
+            assert_eq!(init(&mut ctx, ins, 0, outs, 1), RKNN_ERR_FAIL);
+            assert!(ctx.priv_data.is_null());
+
+            assert_eq!(init(&mut ctx, ins, 1, outs, 1), RKNN_SUCC as c_int);
+            assert!(!ctx.priv_data.is_null());
+            assert_eq!(prepare(&mut ctx, ins, 1, outs, 1), RKNN_SUCC as c_int);
+            assert_eq!(compute(&mut ctx, ins, 1, outs, 1), RKNN_SUCC as c_int);
+            assert_eq!(output, [2.0, -4.0, 6.0]);
+
+            // Panics are caught and the op stays usable.
+            input[1] = f32::NAN;
+            assert_eq!(compute(&mut ctx, ins, 1, outs, 1), RKNN_ERR_FAIL);
+            input[1] = 0.5;
+            assert_eq!(compute(&mut ctx, ins, 1, outs, 1), RKNN_SUCC as c_int);
+            assert_eq!(output, [2.0, 1.0, 6.0]);
+
+            assert_eq!(destroy(&mut ctx), RKNN_SUCC as c_int);
+            assert!(ctx.priv_data.is_null());
+            assert_eq!(destroy(&mut ctx), RKNN_SUCC as c_int);
+        }
+    }
+
+    #[test]
+    fn ffi_guard_reports_errors_and_panics() {
+        assert_eq!(ffi_guard("op", "run", || Ok(())), RKNN_SUCC as c_int);
+        assert_eq!(ffi_guard("op", "run", || bail!("no")), RKNN_ERR_FAIL);
+        assert_eq!(ffi_guard("op", "run", || panic!("no")), RKNN_ERR_FAIL);
+    }
+
+    #[test]
+    fn checks_element_types_lengths_and_alignment() {
+        let mut data = vec![0.0f32; 4];
+        let raw = tensor(3, _rknn_tensor_type_RKNN_TENSOR_FLOAT32, &mut data, 1);
+        let mut t = CustomOpTensor(raw);
+        assert_eq!(t.data::<f32>().unwrap().len(), 3);
+        assert!(t.data_mut::<f32>().is_ok());
+        let err = t.data::<u32>().unwrap_err().to_string();
+        assert!(err.contains("FP32"), "{}", err);
+
+        let mut raw = tensor(3, _rknn_tensor_type_RKNN_TENSOR_FLOAT32, &mut data, 0);
+        raw.attr.n_elems = 5;
+        assert!(CustomOpTensor(raw).data::<f32>().is_err());
+
+        let mut raw = tensor(3, _rknn_tensor_type_RKNN_TENSOR_INT8, &mut data, 0);
+        raw.mem.offset = 1;
+        raw.attr.n_elems = 4;
+        assert_eq!(CustomOpTensor(raw).data::<i8>().unwrap().len(), 4);
+        raw.attr.type_ = _rknn_tensor_type_RKNN_TENSOR_FLOAT32;
+        raw.attr.n_elems = 1;
+        let err = CustomOpTensor(raw).data::<f32>().unwrap_err().to_string();
+        assert!(err.contains("aligned"), "{}", err);
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// An image and the index of its class.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub path: PathBuf,
+    pub label: usize,
+}
+
+/// Labelled images and the names of their classes, indexed by label.
+#[derive(Clone, Debug, Default)]
+pub struct Dataset {
+    pub samples: Vec<Sample>,
+    pub classes: Vec<String>,
+}
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "bmp", "webp"];
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+pub fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read {}", dir.display()))?
+        .map(|e| Ok(e?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+impl Dataset {
+    /// An ImageFolder style tree: one subdirectory per class, classes
+    /// numbered in name order, images anywhere below their class directory.
+    /// Each directory's images come before those of its subdirectories, all
+    /// in name order.
+    pub fn image_folder<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let mut dataset = Dataset::default();
+        for dir in sorted_entries(root.as_ref())? {
+            if !dir.is_dir() {
+                continue;
+            }
+            let label = dataset.classes.len();
+            dataset
+                .classes
+                .push(dir.file_name().unwrap_or_default().to_string_lossy().into());
+            let mut pending = vec![dir];
+            while let Some(dir) = pending.pop() {
+                let (dirs, files): (Vec<_>, Vec<_>) =
+                    sorted_entries(&dir)?.into_iter().partition(|p| p.is_dir());
+                // Popped in name order.
+                pending.extend(dirs.into_iter().rev());
+                for path in files.into_iter().filter(|p| is_image(p)) {
+                    dataset.samples.push(Sample { path, label });
+                }
+            }
+        }
+        if dataset.samples.is_empty() {
+            bail!("no images found under {}", root.as_ref().display());
+        }
+        Ok(dataset)
+    }
+
+    /// A list of `path,label` (or whitespace separated) lines; a header line
+    /// and `#` comments are skipped. Labels are class indices, or class names
+    /// numbered in the order of `classes` when given, else in name order.
+    /// Relative paths are taken from `root`.
+    pub fn from_list<P: AsRef<Path>>(list: P, root: &Path, classes: &[String]) -> Result<Self> {
+        let list = list.as_ref();
+        let text = std::fs::read_to_string(list)
+            .with_context(|| format!("failed to read {}", list.display()))?;
+        let mut rows = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (path, label) = line
+                .rsplit_once(',')
+                .or_else(|| line.rsplit_once(char::is_whitespace))
+                .map(|(path, label)| (path.trim(), label.trim()))
+                .filter(|(path, label)| !path.is_empty() && !label.is_empty())
+                .with_context(|| format!("{}:{}: expected path,label", list.display(), n + 1))?;
+            if rows.is_empty() && ["path", "image", "file", "filename"].contains(&path) {
+                continue;
+            }
+            rows.push((root.join(path), label.to_string()));
+        }
+
+        let by_index = rows.iter().all(|(_, label)| label.parse::<usize>().is_ok());
+        let mut dataset = Dataset::default();
+        if by_index && classes.is_empty() {
+            let n_classes = rows
+                .iter()
+                .map(|(_, l)| l.parse::<usize>().unwrap() + 1)
+                .max()
+                .unwrap_or(0);
+            dataset.classes = (0..n_classes).map(|i| i.to_string()).collect();
+        } else if classes.is_empty() {
+            dataset.classes = rows.iter().map(|(_, l)| l.clone()).collect();
+            dataset.classes.sort();
+            dataset.classes.dedup();
+        } else {
+            dataset.classes = classes.to_vec();
+        }
+        for (path, label) in rows {
+            let index = match dataset.classes.iter().position(|c| *c == label) {
+                Some(index) => index,
+                None if by_index => label.parse()?,
+                None => bail!("unknown class '{}' for {}", label, path.display()),
+            };
+            if index >= dataset.classes.len() {
+                bail!("class {} of {} is out of range", index, path.display());
+            }
+            dataset.samples.push(Sample { path, label: index });
+        }
+        Ok(dataset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rknn-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(root: &Path, files: &[&str]) {
+        for file in files {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+    }
+
+    fn labelled(dataset: &Dataset, root: &Path) -> Vec<(String, usize)> {
+        dataset
+            .samples
+            .iter()
+            .map(|s| {
+                let path = s.path.strip_prefix(root).unwrap_or(&s.path);
+                (path.to_string_lossy().into_owned(), s.label)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walks_image_folders() {
+        let root = temp_dir("image-folder");
+        touch(
+            &root,
+            &[
+                "readme.jpg",
+                "b/1.jpg",
+                "b/notes.txt",
+                "a/x.png",
+                "a/z/deeper/y.jpg",
+                "a/m/y.JPG",
+                "a/w.webp",
+            ],
+        );
+        std::fs::create_dir_all(root.join("c")).unwrap();
+        let dataset = Dataset::image_folder(&root);
+        let empty = temp_dir("image-folder-empty");
+        let no_images = Dataset::image_folder(&empty);
+        std::fs::remove_dir_all(&root).unwrap();
+        std::fs::remove_dir_all(&empty).unwrap();
+
+        let dataset = dataset.unwrap();
+        assert_eq!(dataset.classes, ["a", "b", "c"]);
+        assert_eq!(
+            labelled(&dataset, &root),
+            [
+                ("a/w.webp".into(), 0),
+                ("a/x.png".into(), 0),
+                ("a/m/y.JPG".into(), 0),
+                ("a/z/deeper/y.jpg".into(), 0),
+                ("b/1.jpg".into(), 1),
+            ]
+        );
+        assert!(no_images.is_err());
+    }
+
+    fn from_list(text: &str, classes: &[&str]) -> Result<Dataset> {
+        let dir = temp_dir("list");
+        let list = dir.join("list.csv");
+        std::fs::write(&list, text).unwrap();
+        let classes: Vec<String> = classes.iter().map(|c| c.to_string()).collect();
+        let dataset = Dataset::from_list(&list, Path::new("/data"), &classes);
+        std::fs::remove_dir_all(&dir).unwrap();
+        dataset
+    }
+
+    #[test]
+    fn reads_index_labels() {
+        let text = "path,label\n# a comment\n\n  imgs/a b.jpg, 2\n/abs/b.jpg 0\n";
+        let dataset = from_list(text, &[]).unwrap();
+        assert_eq!(dataset.classes, ["0", "1", "2"]);
+        let root = Path::new("/data");
+        assert_eq!(
+            labelled(&dataset, root),
+            [("imgs/a b.jpg".into(), 2), ("/abs/b.jpg".into(), 0)]
+        );
+
+        // Indices into given class names.
+        let dataset = from_list("a.jpg,1\n", &["cat", "dog"]).unwrap();
+        assert_eq!(dataset.samples[0].label, 1);
+        assert!(from_list("a.jpg,2\n", &["cat", "dog"]).is_err());
+    }
+
+    #[test]
+    fn reads_class_names() {
+        let text = "image,class\na.jpg,dog\nb.jpg,cat\nc.jpg,dog\n";
+        let dataset = from_list(text, &[]).unwrap();
+        assert_eq!(dataset.classes, ["cat", "dog"]);
+        let labels: Vec<usize> = dataset.samples.iter().map(|s| s.label).collect();
+        assert_eq!(labels, [1, 0, 1]);
+
+        let dataset = from_list(text, &["dog", "cat"]).unwrap();
+        let labels: Vec<usize> = dataset.samples.iter().map(|s| s.label).collect();
+        assert_eq!(labels, [0, 1, 0]);
+        assert!(from_list("a.jpg,bird\n", &["dog", "cat"]).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let Err(err) = from_list("a.jpg,0\njust-a-path\n", &[]) else {
+            panic!("a line without a label");
+        };
+        assert!(
+            err.to_string().ends_with("list.csv:2: expected path,label"),
+            "{}",
+            err
+        );
+        assert!(from_list("a.jpg,\n", &[]).is_err());
+        assert!(from_list(",0\n", &[]).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use image::RgbImage;
+use serde_json::{json, Value};
+use std::time::Instant;
+
+use crate::examples::{
+    classifier::load_labels,
+    common::*,
+    draw::{blend_mask, class_color, draw_box, draw_keypoints},
+    instance_seg::SegDecoder,
+    pose::{PoseDecoder, COCO_SKELETON},
+    preprocess::{input_geometry, ImageTransform, Preprocessor},
+    report::{run_record, ResultWriter, Timings},
+    yolo::{Detection, YoloDecoder, YoloVersion},
+};
+
+/// What the model predicts on top of boxes.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DetectTask {
+    Detect,
+    /// YOLOv8-seg instance masks
+    Segment,
+    /// YOLOv8-pose keypoints
+    Pose,
+}
+
+impl DetectTask {
+    /// Decodes the detections of one image, mapped back to the source image,
+    /// and draws masks or keypoints on `canvas`. Also returns what the task
+    /// adds to each detection's JSON.
+    pub fn decode(
+        self,
+        decoder: &YoloDecoder,
+        views: &[OutputView],
+        input_size: (u32, u32),
+        transform: &ImageTransform,
+        canvas: &mut RgbImage,
+    ) -> Result<(Vec<Detection>, Vec<Value>)> {
+        let mut extras: Vec<Value> = Vec::new();
+        let detections: Vec<Detection> = match self {
+            DetectTask::Detect => decoder
+                .decode(views, input_size)?
+                .into_iter()
+                .map(|det| Detection {
+                    bbox: det.bbox.to_source(transform),
+                    ..det
+                })
+                .collect(),
+            DetectTask::Segment => {
+                let results =
+                    SegDecoder::new(decoder.clone()).decode(views, input_size, transform)?;
+                for (det, mask) in &results {
+                    blend_mask(canvas, mask, class_color(det.class), 0.5);
+                    extras.push(json!({ "mask_area": mask.area() }));
+                }
+                results.into_iter().map(|(det, _)| det).collect()
+            }
+            DetectTask::Pose => {
+                let results =
+                    PoseDecoder::new(decoder.clone()).decode(views, input_size, transform)?;
+                for (det, keypoints) in &results {
+                    let color = class_color(det.class);
+                    draw_keypoints(canvas, keypoints, &COCO_SKELETON, 0.5, color);
+                    extras.push(json!({ "keypoints": keypoints.to_json() }));
+                }
+                results.into_iter().map(|(det, _)| det).collect()
+            }
+        };
+        Ok((detections, extras))
+    }
+}
+
+/// The detections' JSON, each with its extra fields from
+/// [`DetectTask::decode`].
+pub fn detections_json(
+    detections: &[Detection],
+    extras: &[Value],
+    labels: &[String],
+) -> Vec<Value> {
+    detections
+        .iter()
+        .enumerate()
+        .map(|(i, det)| {
+            let mut value = det.to_json(labels);
+            if let (Some(value), Some(Value::Object(extra))) =
+                (value.as_object_mut(), extras.get(i))
+            {
+                value.extend(extra.clone());
+            }
+            value
+        })
+        .collect()
+}
+
+pub const COCO_LABELS: [&str; 80] = [
+    "person",
+    "bicycle",
+    "car",
+    "motorcycle",
+    "airplane",
+    "bus",
+    "train",
+    "truck",
+    "boat",
+    "traffic light",
+    "fire hydrant",
+    "stop sign",
+    "parking meter",
+    "bench",
+    "bird",
+    "cat",
+    "dog",
+    "horse",
+    "sheep",
+    "cow",
+    "elephant",
+    "bear",
+    "zebra",
+    "giraffe",
+    "backpack",
+    "umbrella",
+    "handbag",
+    "tie",
+    "suitcase",
+    "frisbee",
+    "skis",
+    "snowboard",
+    "sports ball",
+    "kite",
+    "baseball bat",
+    "baseball glove",
+    "skateboard",
+    "surfboard",
+    "tennis racket",
+    "bottle",
+    "wine glass",
+    "cup",
+    "fork",
+    "knife",
+    "spoon",
+    "bowl",
+    "banana",
+    "apple",
+    "sandwich",
+    "orange",
+    "broccoli",
+    "carrot",
+    "hot dog",
+    "pizza",
+    "donut",
+    "cake",
+    "chair",
+    "couch",
+    "potted plant",
+    "bed",
+    "dining table",
+    "toilet",
+    "tv",
+    "laptop",
+    "mouse",
+    "remote",
+    "keyboard",
+    "cell phone",
+    "microwave",
+    "oven",
+    "toaster",
+    "sink",
+    "refrigerator",
+    "book",
+    "clock",
+    "vase",
+    "scissors",
+    "teddy bear",
+    "hair drier",
+    "toothbrush",
+];
+
+/// Detect objects with a YOLO model and draw the boxes (and masks or
+/// keypoints). Images are always letterboxed.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The path to the model file (*.rknn)
+    #[arg(short, long)]
+    model_path: String,
+
+    /// The images to run detection on
+    #[arg(short, long, value_parser, required = true)]
+    input_paths: Vec<String>,
+
+    #[arg(short = 'y', long, value_enum, default_value_t = YoloVersion::V8)]
+    yolo_version: YoloVersion,
+
+    #[arg(short, long, value_enum, default_value_t = DetectTask::Detect)]
+    task: DetectTask,
+
+    /// A label file, one label per line, defaults to the COCO classes
+    #[arg(long)]
+    labels: Option<String>,
+
+    #[arg(long, default_value_t = 0.25)]
+    conf_threshold: f32,
+
+    #[arg(long, default_value_t = 0.45)]
+    iou_threshold: f32,
+
+    /// Apply sigmoid to the scores, for heads exported without it
+    #[arg(long)]
+    sigmoid: bool,
+
+    /// The directory the annotated images (PNG), a JSON record and the raw
+    /// outputs (.npy) of each input are written to
+    #[arg(short, long)]
+    output_dir: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+impl Example {
+    pub fn decoder(&self) -> YoloDecoder {
+        YoloDecoder {
+            conf_threshold: self.conf_threshold,
+            iou_threshold: self.iou_threshold,
+            sigmoid: self.sigmoid,
+            ..YoloDecoder::new(self.yolo_version)
+        }
+    }
+
+    pub fn execute(&self) -> Result<()> {
+        let ctx = RKNNContext::load_model(&self.model_path)?;
+        if ctx.n_input != 1 {
+            bail!("{} has {} inputs, expected 1", self.model_path, ctx.n_input);
+        }
+        ctx.set_core_mask(&self.core_mask)?;
+        let input_attrs = ctx.use_default_shapes()?;
+        let (height, width, _, _) = input_geometry(&input_attrs[0])?;
+
+        let labels = match &self.labels {
+            Some(path) => load_labels(path)?,
+            None => COCO_LABELS.iter().map(|l| l.to_string()).collect(),
+        };
+        let preprocess = Preprocessor {
+            letterbox: true,
+            ..self.preprocess.clone()
+        };
+        let decoder = self.decoder();
+        let writer = self
+            .output_dir
+            .as_ref()
+            .map(ResultWriter::new)
+            .transpose()?;
+
+        for path in &self.input_paths {
+            let img = image::ImageReader::open(path)?.decode()?;
+            let mut timings = Timings::default();
+            let transforms = timings.time("set_inputs", || {
+                ctx.set_inputs(&input_attrs, std::slice::from_ref(&img), &preprocess)
+            })?;
+            timings.time("run", || ctx.run())?;
+            let outputs = timings.time("get_outputs", || ctx.fetch_outputs(false))?;
+            let views = (0..outputs.len())
+                .map(|i| outputs.view(i))
+                .collect::<Result<Vec<_>>>()?;
+            let transform = &transforms[0];
+            let mut canvas = img.to_rgb8();
+            let postprocess = Instant::now();
+            let (detections, extras) =
+                self.task
+                    .decode(&decoder, &views, (width, height), transform, &mut canvas)?;
+
+            timings.record("postprocess", postprocess.elapsed());
+
+            println!("\x1b[34;4m {}: {} objects\x1b[0m", path, detections.len());
+            for det in &detections {
+                let bbox = det.bbox;
+                let label = labels.get(det.class).map(String::as_str).unwrap_or("?");
+                println!(
+                    "  {} ({}) {:.3} @ ({:.0}, {:.0}, {:.0}, {:.0})",
+                    label, det.class, det.score, bbox.x1, bbox.y1, bbox.x2, bbox.y2
+                );
+                draw_box(&mut canvas, &bbox, class_color(det.class), 2);
+            }
+
+            if let Some(writer) = &writer {
+                let detections = detections_json(&detections, &extras, &labels);
+                let mut record = run_record(
+                    path,
+                    &self.model_path,
+                    &input_attrs,
+                    outputs.attrs(),
+                    &timings,
+                );
+                record["detections"] = json!(detections);
+                let name = writer.name(path);
+                writer.write(&name, &record, &views)?;
+
+                let out_path = writer.path(&format!("{}.png", name));
+                canvas.save(&out_path)?;
+                println!("\x1b[34;4m saved {}\x1b[0m", out_path.display());
+            }
+        }
+        Ok(())
+    }
+}
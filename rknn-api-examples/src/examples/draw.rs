@@ -0,0 +1,108 @@
+use image::{Rgb, RgbImage};
+
+use crate::examples::{instance_seg::Mask, pose::Keypoints, yolo::BBox};
+
+/// A distinct color per class index.
+pub fn class_color(class: usize) -> Rgb<u8> {
+    const PALETTE: [[u8; 3]; 12] = [
+        [255, 56, 56],
+        [255, 157, 151],
+        [255, 112, 31],
+        [255, 178, 29],
+        [207, 210, 49],
+        [72, 249, 10],
+        [26, 147, 52],
+        [0, 212, 187],
+        [0, 194, 255],
+        [52, 69, 147],
+        [100, 115, 255],
+        [203, 56, 255],
+    ];
+    Rgb(PALETTE[class % PALETTE.len()])
+}
+
+/// Fills the pixels of `[x1, x2) x [y1, y2)` that fall inside the image.
+pub fn fill_rect(img: &mut RgbImage, x1: i64, y1: i64, x2: i64, y2: i64, color: Rgb<u8>) {
+    let (w, h) = (img.width() as i64, img.height() as i64);
+    for y in y1.max(0)..y2.min(h) {
+        for x in x1.max(0)..x2.min(w) {
+            img.put_pixel(x as u32, y as u32, color);
+        }
+    }
+}
+
+/// Draws the outline of `bbox`, `thickness` pixels wide, inside the box.
+pub fn draw_box(img: &mut RgbImage, bbox: &BBox, color: Rgb<u8>, thickness: u32) {
+    let t = thickness as i64;
+    let (x1, y1) = (bbox.x1.round() as i64, bbox.y1.round() as i64);
+    let (x2, y2) = (bbox.x2.round() as i64, bbox.y2.round() as i64);
+    fill_rect(img, x1, y1, x2, y1 + t, color);
+    fill_rect(img, x1, y2 - t, x2, y2, color);
+    fill_rect(img, x1, y1, x1 + t, y2, color);
+    fill_rect(img, x2 - t, y1, x2, y2, color);
+}
+
+/// Blends `color` over the pixels of `mask`, `alpha` being the color's weight.
+pub fn blend_mask(img: &mut RgbImage, mask: &Mask, color: Rgb<u8>, alpha: f32) {
+    for y in mask.y..(mask.y + mask.height).min(img.height()) {
+        for x in mask.x..(mask.x + mask.width).min(img.width()) {
+            if mask.contains(x, y) {
+                let p = img.get_pixel_mut(x, y);
+                for c in 0..3 {
+                    p[c] = (p[c] as f32 * (1.0 - alpha) + color[c] as f32 * alpha).round() as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Draws a line as a run of `thickness` sized squares.
+pub fn draw_line(
+    img: &mut RgbImage,
+    from: (f32, f32),
+    to: (f32, f32),
+    color: Rgb<u8>,
+    thickness: u32,
+) {
+    let steps = (to.0 - from.0)
+        .abs()
+        .max((to.1 - from.1).abs())
+        .ceil()
+        .max(1.0) as usize;
+    let t = thickness as i64;
+    for i in 0..=steps {
+        let f = i as f32 / steps as f32;
+        let x = (from.0 + (to.0 - from.0) * f).round() as i64;
+        let y = (from.1 + (to.1 - from.1) * f).round() as i64;
+        fill_rect(
+            img,
+            x - t / 2,
+            y - t / 2,
+            x - t / 2 + t,
+            y - t / 2 + t,
+            color,
+        );
+    }
+}
+
+/// Draws the limbs and joints whose keypoints score at least `threshold`.
+pub fn draw_keypoints(
+    img: &mut RgbImage,
+    keypoints: &Keypoints,
+    skeleton: &[(usize, usize)],
+    threshold: f32,
+    color: Rgb<u8>,
+) {
+    let points = &keypoints.points;
+    for (a, b) in skeleton {
+        if let (Some(a), Some(b)) = (points.get(*a), points.get(*b)) {
+            if a.score >= threshold && b.score >= threshold {
+                draw_line(img, (a.x, a.y), (b.x, b.y), color, 2);
+            }
+        }
+    }
+    for p in points.iter().filter(|p| p.score >= threshold) {
+        let (x, y) = (p.x.round() as i64, p.y.round() as i64);
+        fill_rect(img, x - 2, y - 2, x + 3, y + 3, Rgb([255, 255, 255]));
+    }
+}
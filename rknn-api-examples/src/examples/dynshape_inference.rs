@@ -1,15 +1,32 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use image::DynamicImage;
+use serde_json::json;
+use std::time::{Duration, Instant};
 
 use crate::{
     examples::{
+        classifier::ClassifierHead,
         common::*,
+        preprocess::{input_geometry, Preprocessor},
+        report::{run_record, ResultWriter, Timings},
+        semseg::{LabelMap, Palette},
+        trace::Tracer,
         utils::{DumpStats, DumpVals},
+        yuv::{YuvFormat, YuvFrame},
     },
     time_bench,
 };
 
+/// What the model's first output holds.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputKind {
+    /// Class scores
+    Classify,
+    /// Per pixel class scores, `[1, C, H, W]` or `[1, H, W, C]`
+    Segment,
+}
+
 /// Search for a pattern in a file and display the lines that contain it.
 #[derive(Debug, Parser)]
 pub struct Example {
@@ -28,14 +45,52 @@ pub struct Example {
     #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
     core_mask: RknnCoreMask,
 
-    /// The path to the output image file
+    #[arg(long, value_enum, default_value_t = OutputKind::Classify)]
+    task: OutputKind,
+
+    /// The directory a JSON record and the raw outputs (.npy) of each shape
+    /// are written to, along with segmentation overlays
     #[arg(short, long)]
     output_dir: Option<String>,
+
+    /// A palette file, one r,g,b color per class, defaults to Pascal VOC's
+    #[arg(long)]
+    palette: Option<String>,
+
+    /// The weight of the class colors in the overlays
+    #[arg(long, default_value_t = 0.5)]
+    alpha: f32,
+
+    /// The path to write a Chrome trace (JSON) of the run, viewable in Perfetto
+    #[arg(long)]
+    trace_path: Option<String>,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+
+    /// Read the inputs as raw camera frames in this format instead of images
+    #[arg(long, value_enum, requires = "frame_size")]
+    yuv_format: Option<YuvFormat>,
+
+    /// The size of the raw frames, as WIDTHxHEIGHT
+    #[arg(long, value_parser = parse_frame_size)]
+    frame_size: Option<(u32, u32)>,
+}
+
+fn parse_frame_size(s: &str) -> Result<(u32, u32)> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| anyhow::anyhow!("expected WIDTHxHEIGHT, got {}", s))?;
+    Ok((width.parse()?, height.parse()?))
 }
 
 impl Example {
     pub fn execute(&self) -> Result<()> {
-        let ctx = RKNNContext::load_model(&self.model_path)?;
+        let mut ctx = RKNNContext::load_model(&self.model_path)?;
+        let tracer = self.trace_path.as_ref().map(|_| Tracer::new());
+        if let Some(tracer) = &tracer {
+            ctx.set_tracer(tracer.clone());
+        }
         println!("\x1b[34;4m Load model sucess\x1b[0m");
         let ver = &ctx.get_sdk_version()?;
         println!(
@@ -65,22 +120,57 @@ impl Example {
         }
 
         println!("\x1b[34;4m dynamic inputs shape range:\x1b[0m");
-        let shape_range = ctx.get_input_range()?;
+        let shape_range = ctx.get_input_range().with_context(|| {
+            format!(
+                "{} has no dynamic shapes, run it with `classify` or `segment`",
+                self.model_path
+            )
+        })?;
+        if shape_range.first().is_none_or(|r| r.shape_number == 0) {
+            bail!(
+                "{} has no dynamic shapes, run it with `classify` or `segment`",
+                self.model_path
+            );
+        }
         for range in &shape_range {
             println!("{}", range.dump()?);
         }
 
-        println!("\x1b[34;4m load input images\x1b[0m");
-        let images: Vec<DynamicImage> = self
-            .input_paths
-            .iter()
-            .map(|p| {
-                image::ImageReader::open(p)
-                    .expect("failed to open")
-                    .decode()
-                    .expect("failed to decode")
-            })
-            .collect();
+        let mut images: Vec<DynamicImage> = Vec::new();
+        let mut frames: Vec<Vec<u8>> = Vec::new();
+        if self.yuv_format.is_some() {
+            println!("\x1b[34;4m load input frames\x1b[0m");
+            for p in &self.input_paths {
+                frames.push(std::fs::read(p)?);
+            }
+        } else {
+            println!("\x1b[34;4m load input images\x1b[0m");
+            images = self
+                .input_paths
+                .iter()
+                .map(|p| {
+                    image::ImageReader::open(p)
+                        .expect("failed to open")
+                        .decode()
+                        .expect("failed to decode")
+                })
+                .collect();
+        }
+
+        let palette = match &self.palette {
+            Some(path) => Palette::load(path)?,
+            None => Palette::pascal_voc(256),
+        };
+        let writer = self
+            .output_dir
+            .as_ref()
+            .map(ResultWriter::new)
+            .transpose()?;
+        let stem = writer
+            .as_ref()
+            .zip(self.input_paths.first())
+            .map(|(w, p)| w.name(p))
+            .unwrap_or_default();
 
         for s in 0..shape_range[0].shape_number {
             println!(
@@ -113,23 +203,121 @@ impl Example {
             }
 
             ctx.set_core_mask(&self.core_mask)?;
-            ctx.set_inputs(&cur_input_attrs, &images)?;
+            ctx.begin_frame();
+            let mut timings = Timings::default();
+            let set_inputs = Instant::now();
+            let transforms = match (self.yuv_format, self.frame_size) {
+                (Some(format), Some((width, height))) => {
+                    let preprocess = ctx.trace("preprocess");
+                    let (inputs, transforms): (Vec<_>, Vec<_>) = frames
+                        .iter()
+                        .zip(&cur_input_attrs)
+                        .map(|(data, attr)| {
+                            let frame = YuvFrame::from_contiguous(format, width, height, data)?;
+                            self.preprocess.run_yuv(&frame, attr)
+                        })
+                        .collect::<Result<Vec<_>>>()?
+                        .into_iter()
+                        .unzip();
+                    drop(preprocess);
+                    ctx.set_tensor_inputs(&inputs)?;
+                    transforms
+                }
+                _ => ctx.set_inputs(&cur_input_attrs, &images, &self.preprocess)?,
+            };
+
+            timings.record("set_inputs", set_inputs.elapsed());
 
+            // The mean of the loops, without time_bench!'s printing.
+            let mut run = Duration::ZERO;
+            let mut runs = 0;
             time_bench!(self.loop_count, {
+                // The first run shares the frame of its inputs.
+                if runs > 0 {
+                    ctx.begin_frame();
+                }
+                runs += 1;
+                let start = Instant::now();
                 ctx.run()?;
+                run += start.elapsed();
             });
+            timings.record("run", run / self.loop_count.max(1) as u32);
+
+            let outputs = timings.time("get_outputs", || {
+                ctx.fetch_outputs(self.task == OutputKind::Classify)
+            })?;
+            let postprocess = ctx.trace("postprocess");
+            let postprocess_start = Instant::now();
+            let results = match self.task {
+                OutputKind::Classify => {
+                    println!("\x1b[34;4m --- Top5 ---\x1b[0m");
+                    let classes =
+                        ClassifierHead::new(5, false).classify(outputs.as_slice::<f32>(0)?);
+                    for c in &classes {
+                        println!("{}: {:.4}", c.index, c.prob);
+                    }
+                    (
+                        "top_k",
+                        json!(classes.iter().map(|c| c.to_json()).collect::<Vec<_>>()),
+                    )
+                }
+                OutputKind::Segment => {
+                    let map = LabelMap::argmax(&outputs.view(0)?)?;
+                    println!("\x1b[34;4m --- classes (pixels) ---\x1b[0m");
+                    let histogram = map.histogram();
+                    for (class, count) in &histogram {
+                        println!("{}: {}", class, count);
+                    }
+                    if let Some(writer) = &writer {
+                        let source = match (self.yuv_format, self.frame_size) {
+                            (Some(format), Some((width, height))) => {
+                                YuvFrame::from_contiguous(format, width, height, &frames[0])?
+                                    .to_rgb()
+                            }
+                            _ => images[0].to_rgb8(),
+                        };
+                        let (in_h, in_w, _, _) = input_geometry(&cur_input_attrs[0])?;
+                        let overlay = map
+                            .to_source((in_w, in_h), &transforms[0])
+                            .overlay(&source, &palette, self.alpha)?;
+                        let out_path = writer.path(&format!("{}_shape{}.png", stem, s));
+                        overlay.save(&out_path)?;
+                        println!(
+                            "\x1b[34;4m overlay written to {}\x1b[0m",
+                            out_path.display()
+                        );
+                    }
+                    let pixels = histogram
+                        .iter()
+                        .map(|(class, count)| json!({ "class": class, "pixels": count }))
+                        .collect::<Vec<_>>();
+                    ("classes", json!(pixels))
+                }
+            };
+            timings.record("postprocess", postprocess_start.elapsed());
+            drop(postprocess);
 
-            let outputs = ctx.get_outputs()?;
-            let len = outputs[0].size as usize / size_of::<f32>();
-            let results = unsafe { Vec::from_raw_parts(outputs[0].buf as *mut f32, len, len) };
-            let mut results_pairs: Vec<(usize, f32)> =
-                results.iter().enumerate().map(|(i, f)| (i, *f)).collect();
-            results_pairs.sort_by(|(_, f1), (_, f2)| f2.total_cmp(f1));
-            println!("\x1b[34;4m --- Top5 ---\x1b[0m");
-            for (i, f) in results_pairs.iter().take(5) {
-                println!("{}: {:.2}%", i, f);
+            if let Some(writer) = &writer {
+                let mut record = run_record(
+                    &self.input_paths.join(","),
+                    &self.model_path,
+                    &cur_input_attrs,
+                    outputs.attrs(),
+                    &timings,
+                );
+                record["shape_index"] = json!(s);
+                record[results.0] = results.1;
+                let views = (0..outputs.len())
+                    .map(|i| outputs.view(i))
+                    .collect::<Result<Vec<_>>>()?;
+                writer.write(&format!("{}_shape{}", stem, s), &record, &views)?;
             }
         }
+
+        if let (Some(tracer), Some(path)) = (&tracer, &self.trace_path) {
+            tracer.write_chrome_trace(path)?;
+            println!("\x1b[34;4m trace written to {}\x1b[0m", path);
+        }
         Ok(())
     }
 }
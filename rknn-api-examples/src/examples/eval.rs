@@ -0,0 +1,224 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde_json::json;
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::examples::{
+    classifier::{load_labels, top_k},
+    classify::ClassifierModel,
+    common::*,
+    dataset::Dataset,
+    metrics::ConfusionMatrix,
+    preprocess::Preprocessor,
+};
+
+/// Measure a classifier's accuracy on a labelled dataset: top-1/top-k
+/// accuracy, a confusion matrix and throughput.
+#[derive(Debug, Parser)]
+pub struct Example {
+    #[arg(short, long, value_enum, default_value_t = ClassifierModel::Resnet18)]
+    model: ClassifierModel,
+
+    /// Use this model file (*.rknn) instead of a bundled one
+    #[arg(long)]
+    model_path: Option<String>,
+
+    /// An ImageFolder style directory (one subdirectory per class) or a list
+    /// file of `path,label` lines
+    #[arg(short, long)]
+    dataset: String,
+
+    /// The directory relative paths of a list file start from, defaults to
+    /// the list's own directory
+    #[arg(long)]
+    root: Option<String>,
+
+    /// The model's label file, one label per line (synset ids are stripped).
+    /// Class names of the dataset are looked up in it, otherwise classes are
+    /// taken to be numbered like the model's outputs
+    #[arg(long)]
+    labels: Option<String>,
+
+    /// The k of the top-k accuracy
+    #[arg(short = 'k', long, default_value_t = 5)]
+    top_k: usize,
+
+    /// Only evaluate the first N images
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Write the confusion matrix to this CSV file
+    #[arg(long)]
+    confusion_csv: Option<String>,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+impl Example {
+    fn load_dataset(&self, labels: &[String]) -> Result<Dataset> {
+        let path = Path::new(&self.dataset);
+        if path.is_dir() {
+            return Dataset::image_folder(path);
+        }
+        let root = match &self.root {
+            Some(root) => Path::new(root).to_path_buf(),
+            None => path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        };
+        Dataset::from_list(path, &root, labels)
+    }
+
+    pub fn execute(&self) -> Result<()> {
+        let model_path = self.model_path.clone().unwrap_or_else(|| self.model.path());
+        let ctx = RKNNContext::load_model(&model_path)?;
+        if ctx.n_input != 1 {
+            bail!("{} has {} inputs, expected 1", model_path, ctx.n_input);
+        }
+        ctx.set_core_mask(&self.core_mask)?;
+        let input_attrs = ctx.use_default_shapes()?;
+
+        let labels = match &self.labels {
+            Some(path) => load_labels(path)?,
+            None => Vec::new(),
+        };
+        let mut dataset = self.load_dataset(&labels)?;
+        if let Some(limit) = self.limit {
+            dataset.samples.truncate(limit);
+        }
+        // The model output index of each dataset class.
+        let class_outputs: Vec<usize> = if labels.is_empty() {
+            (0..dataset.classes.len()).collect()
+        } else {
+            dataset
+                .classes
+                .iter()
+                .map(|class| {
+                    labels
+                        .iter()
+                        .position(|l| l == class)
+                        .with_context(|| format!("class '{}' is not in the labels", class))
+                })
+                .collect::<Result<_>>()?
+        };
+        println!(
+            "\x1b[34;4m evaluating {} images of {} classes\x1b[0m",
+            dataset.samples.len(),
+            dataset.classes.len()
+        );
+
+        let mut confusion = ConfusionMatrix::new(dataset.classes.len());
+        let (mut top1, mut topk) = (0usize, 0usize);
+        let mut inference = Duration::ZERO;
+        let start = Instant::now();
+        for sample in &dataset.samples {
+            let img = image::ImageReader::open(&sample.path)?
+                .decode()
+                .with_context(|| format!("failed to decode {}", sample.path.display()))?;
+            ctx.set_inputs(&input_attrs, &[img], &self.preprocess)?;
+            let run = Instant::now();
+            ctx.run()?;
+            inference += run.elapsed();
+            let outputs = ctx.fetch_outputs(true)?;
+            let scores = outputs.as_slice::<f32>(0)?;
+
+            // Models with a background class put it first.
+            let offset = (!labels.is_empty() && scores.len() == labels.len() + 1) as usize;
+            let predicted: Vec<Option<usize>> = top_k(scores, self.top_k)
+                .into_iter()
+                .map(|(index, _)| {
+                    let index = index.checked_sub(offset)?;
+                    class_outputs.iter().position(|c| *c == index)
+                })
+                .collect();
+            confusion.add(sample.label, predicted.first().copied().flatten());
+            if predicted.first() == Some(&Some(sample.label)) {
+                top1 += 1;
+            }
+            if predicted.contains(&Some(sample.label)) {
+                topk += 1;
+            }
+        }
+        let elapsed = start.elapsed();
+
+        let n = dataset.samples.len().max(1) as f64;
+        let (top1_accuracy, topk_accuracy) = (top1 as f64 / n, topk as f64 / n);
+        let images_per_sec = dataset.samples.len() as f64 / elapsed.as_secs_f64();
+        let inference_ms = inference.as_secs_f64() * 1000.0 / n;
+        let recall = confusion.recall();
+
+        if let Some(path) = &self.confusion_csv {
+            std::fs::write(path, confusion.to_csv(&dataset.classes))?;
+        }
+        if self.json {
+            let report = json!({
+                "model": model_path,
+                "dataset": self.dataset,
+                "images": dataset.samples.len(),
+                "top1_accuracy": top1_accuracy,
+                "top_k": self.top_k,
+                "top_k_accuracy": topk_accuracy,
+                "elapsed_sec": elapsed.as_secs_f64(),
+                "images_per_sec": images_per_sec,
+                "inference_ms": inference_ms,
+                "per_class_accuracy": dataset
+                    .classes
+                    .iter()
+                    .zip(&recall)
+                    .map(|(class, r)| json!({ "class": class, "accuracy": r }))
+                    .collect::<Vec<_>>(),
+                "confusion_matrix": confusion.to_json(&dataset.classes),
+            });
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+
+        println!(
+            "\x1b[34;4m top-1 accuracy: {:.4} ({}/{})\x1b[0m",
+            top1_accuracy,
+            top1,
+            dataset.samples.len()
+        );
+        println!(
+            "\x1b[34;4m top-{} accuracy: {:.4} ({}/{})\x1b[0m",
+            self.top_k,
+            topk_accuracy,
+            topk,
+            dataset.samples.len()
+        );
+        println!(
+            "\x1b[34;4m throughput: {:.2} images/s, inference {:.2} ms/image\x1b[0m",
+            images_per_sec, inference_ms
+        );
+        println!("\x1b[34;4m per class accuracy:\x1b[0m");
+        for (c, class) in dataset.classes.iter().enumerate() {
+            let total: usize = confusion.row(c).iter().sum();
+            match recall[c] {
+                Some(r) => println!("  {:>6.4} ({:>5})  {}", r, total, class),
+                None => println!("  {:>6} ({:>5})  {}", "-", total, class),
+            }
+        }
+        // Wider matrices are only readable from the CSV.
+        if dataset.classes.len() <= 20 {
+            println!("\x1b[34;4m confusion matrix (true \\ predicted, last column other):\x1b[0m");
+            for c in 0..dataset.classes.len() {
+                let row: Vec<String> = confusion
+                    .row(c)
+                    .iter()
+                    .map(|n| format!("{:>5}", n))
+                    .collect();
+                println!("  {}", row.join(" "));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use serde_json::{json, Value};
+use std::{path::Path, time::Instant};
+
+use crate::examples::{
+    coco::{CocoDataset, CocoDetection, CocoEvaluation},
+    common::*,
+    preprocess::{input_geometry, Preprocessor},
+    yolo::{YoloDecoder, YoloVersion},
+};
+
+/// Measure a YOLO model's COCO mAP@0.5 and mAP@0.5:0.95 on an annotated
+/// image set, writing the predictions as a COCO results file. Class `i` of
+/// the model is the `i`th category by id. Images are always letterboxed.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The path to the model file (*.rknn)
+    #[arg(short, long)]
+    model_path: String,
+
+    /// The COCO annotation file, e.g. `instances_val2017.json`
+    #[arg(short, long)]
+    annotations: String,
+
+    /// The directory holding the annotated images
+    #[arg(short, long)]
+    image_dir: String,
+
+    #[arg(short = 'y', long, value_enum, default_value_t = YoloVersion::V8)]
+    yolo_version: YoloVersion,
+
+    /// Low, so the precision/recall curves are complete
+    #[arg(long, default_value_t = 0.001)]
+    conf_threshold: f32,
+
+    #[arg(long, default_value_t = 0.7)]
+    iou_threshold: f32,
+
+    /// Apply sigmoid to the scores, for heads exported without it
+    #[arg(long)]
+    sigmoid: bool,
+
+    /// Only evaluate the first N images
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Write the predictions to this COCO results file
+    #[arg(short, long)]
+    results: Option<String>,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+/// Detections per image and category COCO evaluates.
+const MAX_DETECTIONS: usize = 100;
+
+impl Example {
+    pub fn execute(&self) -> Result<()> {
+        let ctx = RKNNContext::load_model(&self.model_path)?;
+        if ctx.n_input != 1 {
+            bail!("{} has {} inputs, expected 1", self.model_path, ctx.n_input);
+        }
+        ctx.set_core_mask(&self.core_mask)?;
+        let input_attrs = ctx.use_default_shapes()?;
+        let (height, width, _, _) = input_geometry(&input_attrs[0])?;
+
+        let mut dataset = CocoDataset::load(&self.annotations)?;
+        if let Some(limit) = self.limit {
+            dataset.images.truncate(limit);
+        }
+        let preprocess = Preprocessor {
+            letterbox: true,
+            ..self.preprocess.clone()
+        };
+        let decoder = YoloDecoder {
+            conf_threshold: self.conf_threshold,
+            iou_threshold: self.iou_threshold,
+            sigmoid: self.sigmoid,
+            ..YoloDecoder::new(self.yolo_version)
+        };
+        println!(
+            "\x1b[34;4m evaluating {} images of {} categories\x1b[0m",
+            dataset.images.len(),
+            dataset.categories.len()
+        );
+
+        let mut detections = Vec::new();
+        let start = Instant::now();
+        for image in &dataset.images {
+            let path = Path::new(&self.image_dir).join(&image.file_name);
+            let img = image::ImageReader::open(&path)?.decode()?;
+            let transforms =
+                ctx.set_inputs(&input_attrs, std::slice::from_ref(&img), &preprocess)?;
+            ctx.run()?;
+            let outputs = ctx.fetch_outputs(false)?;
+            let views = (0..outputs.len())
+                .map(|i| outputs.view(i))
+                .collect::<Result<Vec<_>>>()?;
+            for det in decoder.decode(&views, (width, height))? {
+                let Some(category) = dataset.categories.get(det.class) else {
+                    bail!(
+                        "class {} but the annotations have {} categories",
+                        det.class,
+                        dataset.categories.len()
+                    );
+                };
+                detections.push(CocoDetection {
+                    image_id: image.id,
+                    category_id: category.id,
+                    bbox: det.bbox.to_source(&transforms[0]),
+                    score: det.score,
+                });
+            }
+        }
+        let elapsed = start.elapsed();
+
+        if let Some(path) = &self.results {
+            let results: Vec<Value> = detections.iter().map(|d| d.to_json()).collect();
+            std::fs::write(path, serde_json::to_string(&results)?)?;
+            println!("\x1b[34;4m results written to {}\x1b[0m", path);
+        }
+
+        let image_ids: Vec<u64> = dataset.images.iter().map(|i| i.id).collect();
+        let evaluation = CocoEvaluation::new(&dataset, &detections, &image_ids, MAX_DETECTIONS);
+        let images_per_sec = dataset.images.len() as f64 / elapsed.as_secs_f64();
+        if self.json {
+            let report = json!({
+                "model": self.model_path,
+                "images": dataset.images.len(),
+                "detections": detections.len(),
+                "map50": evaluation.map50(),
+                "map50_95": evaluation.map50_95(),
+                "images_per_sec": images_per_sec,
+                "per_category_ap": dataset
+                    .categories
+                    .iter()
+                    .enumerate()
+                    .map(|(c, category)| json!({
+                        "id": category.id,
+                        "name": category.name,
+                        "ap50": evaluation.ap[0][c],
+                        "ap50_95": evaluation.category_ap(c),
+                    }))
+                    .collect::<Vec<_>>(),
+            });
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+
+        println!("\x1b[34;4m per category AP@0.5 / AP@0.5:0.95:\x1b[0m");
+        for (c, category) in dataset.categories.iter().enumerate() {
+            if let (Some(ap50), Some(ap)) = (evaluation.ap[0][c], evaluation.category_ap(c)) {
+                println!("  {:.4} / {:.4}  {}", ap50, ap, category.name);
+            }
+        }
+        println!("\x1b[34;4m mAP@0.5: {:.4}\x1b[0m", evaluation.map50());
+        println!(
+            "\x1b[34;4m mAP@0.5:0.95: {:.4}\x1b[0m",
+            evaluation.map50_95()
+        );
+        println!(
+            "\x1b[34;4m {} detections, {:.2} images/s\x1b[0m",
+            detections.len(),
+            images_per_sec
+        );
+        Ok(())
+    }
+}
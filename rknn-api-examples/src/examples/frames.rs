@@ -0,0 +1,369 @@
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, ImageFormat, RgbImage};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use crate::examples::{
+    dataset::{is_image, sorted_entries},
+    yuv::{yuv_to_rgb, YuvFormat, YuvFrame},
+};
+
+/// A decoded frame and the name its results are written under: the image's
+/// file name, so `a.jpg` and `a.png` stay apart, or `frame_<index>`.
+pub struct Frame {
+    pub index: usize,
+    pub name: String,
+    pub image: DynamicImage,
+}
+
+/// Where frames come from: a directory of images (in name order), a Motion
+/// JPEG file (concatenated JPEGs) or a YUV4MPEG2 file.
+pub enum FrameSource {
+    Images(std::vec::IntoIter<PathBuf>),
+    Mjpeg(BufReader<File>),
+    Y4m(Y4mReader<BufReader<File>>),
+}
+
+impl FrameSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            let images: Vec<PathBuf> = sorted_entries(path)?
+                .into_iter()
+                .filter(|p| is_image(p))
+                .collect();
+            if images.is_empty() {
+                bail!("no images in {}", path.display());
+            }
+            return Ok(FrameSource::Images(images.into_iter()));
+        }
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let reader = BufReader::new(file);
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "mjpeg" | "mjpg" => Ok(FrameSource::Mjpeg(reader)),
+            "y4m" => Ok(FrameSource::Y4m(Y4mReader::new(reader)?)),
+            _ => bail!("{} is not a directory, .mjpeg or .y4m file", path.display()),
+        }
+    }
+
+    /// The next frame's name and image, `None` at the end.
+    fn next_image(&mut self, index: usize) -> Result<Option<(String, DynamicImage)>> {
+        let video_name = || format!("frame_{:06}", index);
+        Ok(match self {
+            FrameSource::Images(paths) => match paths.next() {
+                Some(path) => {
+                    let image = image::ImageReader::open(&path)?
+                        .decode()
+                        .with_context(|| format!("failed to decode {}", path.display()))?;
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    Some((name.into_owned(), image))
+                }
+                None => None,
+            },
+            FrameSource::Mjpeg(reader) => match next_jpeg(reader)? {
+                Some(jpeg) => {
+                    let image = image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg)
+                        .with_context(|| format!("failed to decode frame {}", index))?;
+                    Some((video_name(), image))
+                }
+                None => None,
+            },
+            FrameSource::Y4m(reader) => reader
+                .next_frame()?
+                .map(|rgb| (video_name(), DynamicImage::ImageRgb8(rgb))),
+        })
+    }
+}
+
+/// Frames are numbered from 0; reading stops at the first error.
+pub struct Frames {
+    source: FrameSource,
+    index: usize,
+    failed: bool,
+}
+
+impl Iterator for Frames {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let index = self.index;
+        match self.source.next_image(index) {
+            Ok(Some((name, image))) => {
+                self.index += 1;
+                Some(Ok(Frame { index, name, image }))
+            }
+            Ok(None) => None,
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl IntoIterator for FrameSource {
+    type Item = Result<Frame>;
+    type IntoIter = Frames;
+
+    fn into_iter(self) -> Frames {
+        Frames {
+            source: self,
+            index: 0,
+            failed: false,
+        }
+    }
+}
+
+fn read_byte(reader: &mut impl BufRead) -> Result<Option<u8>> {
+    let mut byte = [0u8];
+    match reader.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+fn expect_byte(reader: &mut impl BufRead) -> Result<u8> {
+    read_byte(reader)?.context("truncated JPEG frame")
+}
+
+/// The next JPEG of a Motion JPEG stream, `None` at the end. Segments are
+/// walked by their lengths, so JPEG thumbnails in EXIF data don't end the
+/// frame early.
+pub fn next_jpeg(reader: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    // Skip anything up to the start of image marker.
+    let mut previous = 0u8;
+    loop {
+        match read_byte(reader)? {
+            None => return Ok(None),
+            Some(0xD8) if previous == 0xFF => break,
+            Some(byte) => previous = byte,
+        }
+    }
+    let mut jpeg = vec![0xFF, 0xD8];
+    let mut marker = None;
+    loop {
+        let m = match marker.take() {
+            Some(m) => m,
+            None => {
+                if expect_byte(reader)? != 0xFF {
+                    bail!("corrupt JPEG frame, expected a marker");
+                }
+                // Markers may be padded with any number of 0xFF.
+                let mut m = expect_byte(reader)?;
+                while m == 0xFF {
+                    m = expect_byte(reader)?;
+                }
+                m
+            }
+        };
+        jpeg.extend_from_slice(&[0xFF, m]);
+        match m {
+            0xD9 => return Ok(Some(jpeg)),
+            0x01 | 0xD0..=0xD7 => continue,
+            _ => {}
+        }
+        let mut length_bytes = [0u8; 2];
+        reader.read_exact(&mut length_bytes)?;
+        jpeg.extend_from_slice(&length_bytes);
+        let length = u16::from_be_bytes(length_bytes) as usize;
+        if length < 2 {
+            bail!("corrupt JPEG frame, segment length {}", length);
+        }
+        let start = jpeg.len();
+        jpeg.resize(start + length - 2, 0);
+        reader.read_exact(&mut jpeg[start..])?;
+        if m != 0xDA {
+            continue;
+        }
+        // Entropy coded data follows a start of scan, up to the next marker
+        // that isn't a stuffed 0xFF00 or a restart.
+        loop {
+            let byte = expect_byte(reader)?;
+            if byte != 0xFF {
+                jpeg.push(byte);
+                continue;
+            }
+            let mut next = expect_byte(reader)?;
+            while next == 0xFF {
+                next = expect_byte(reader)?;
+            }
+            if next == 0x00 || (0xD0..=0xD7).contains(&next) {
+                jpeg.extend_from_slice(&[0xFF, next]);
+            } else {
+                marker = Some(next);
+                break;
+            }
+        }
+    }
+}
+
+/// The chroma layouts of YUV4MPEG2 this reader converts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Y4mColorspace {
+    C420,
+    C444,
+    Mono,
+}
+
+/// Reads the 8-bit frames of a YUV4MPEG2 stream as RGB, BT.601 limited
+/// range.
+pub struct Y4mReader<R> {
+    reader: R,
+    width: u32,
+    height: u32,
+    colorspace: Y4mColorspace,
+}
+
+impl<R: BufRead> Y4mReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let mut params = header.split_ascii_whitespace();
+        if params.next() != Some("YUV4MPEG2") {
+            bail!("not a YUV4MPEG2 stream");
+        }
+        let (mut width, mut height) = (0, 0);
+        let mut colorspace = Y4mColorspace::C420;
+        for param in params.filter(|p| p.is_char_boundary(1)) {
+            let (key, value) = param.split_at(1);
+            match key {
+                "W" => width = value.parse().context("bad Y4M width")?,
+                "H" => height = value.parse().context("bad Y4M height")?,
+                "C" => {
+                    colorspace = match value {
+                        "420" | "420jpeg" | "420paldv" | "420mpeg2" => Y4mColorspace::C420,
+                        "444" => Y4mColorspace::C444,
+                        "mono" => Y4mColorspace::Mono,
+                        other => bail!("unsupported Y4M colorspace {}", other),
+                    }
+                }
+                _ => {}
+            }
+        }
+        if width == 0 || height == 0 {
+            bail!("Y4M header without a size: {}", header.trim_end());
+        }
+        Ok(Y4mReader {
+            reader,
+            width,
+            height,
+            colorspace,
+        })
+    }
+
+    fn frame_len(&self) -> usize {
+        let (w, h) = (self.width as usize, self.height as usize);
+        match self.colorspace {
+            Y4mColorspace::C420 => w * h + 2 * w.div_ceil(2) * h.div_ceil(2),
+            Y4mColorspace::C444 => 3 * w * h,
+            Y4mColorspace::Mono => w * h,
+        }
+    }
+
+    pub fn next_frame(&mut self) -> Result<Option<RgbImage>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if !line.starts_with("FRAME") {
+            bail!("corrupt Y4M stream, expected FRAME");
+        }
+        let mut data = vec![0u8; self.frame_len()];
+        self.reader
+            .read_exact(&mut data)
+            .context("truncated Y4M frame")?;
+        let (w, h) = (self.width, self.height);
+        let plane = (w * h) as usize;
+        Ok(Some(match self.colorspace {
+            Y4mColorspace::C420 => {
+                YuvFrame::from_contiguous(YuvFormat::I420, w, h, &data)?.to_rgb()
+            }
+            Y4mColorspace::C444 => RgbImage::from_fn(w, h, |x, y| {
+                let i = (y * w + x) as usize;
+                image::Rgb(yuv_to_rgb(data[i], data[plane + i], data[2 * plane + i]))
+            }),
+            Y4mColorspace::Mono => RgbImage::from_fn(w, h, |x, y| {
+                image::Rgb(yuv_to_rgb(data[(y * w + x) as usize], 128, 128))
+            }),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let img = RgbImage::from_pixel(width, height, image::Rgb([200, 30, 30]));
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgb8(img)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn splits_mjpeg() {
+        let (first, second) = (jpeg(16, 8), jpeg(8, 4));
+        let mut stream = b"--boundary\r\n".to_vec();
+        stream.extend_from_slice(&first);
+        stream.extend_from_slice(&second);
+        stream.extend_from_slice(b"\r\n");
+        let mut reader = Cursor::new(stream);
+        assert_eq!(next_jpeg(&mut reader).unwrap(), Some(first));
+        assert_eq!(next_jpeg(&mut reader).unwrap(), Some(second));
+        assert_eq!(next_jpeg(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_y4m() {
+        let mut stream = b"YUV4MPEG2 W4 H2 F30:1 Ip A1:1 C420jpeg\n".to_vec();
+        for luma in [16u8, 235] {
+            stream.extend_from_slice(b"FRAME\n");
+            stream.extend_from_slice(&[luma; 8]);
+            stream.extend_from_slice(&[128; 4]);
+        }
+        let mut reader = Y4mReader::new(Cursor::new(stream)).unwrap();
+        let black = reader.next_frame().unwrap().unwrap();
+        assert_eq!(black.dimensions(), (4, 2));
+        assert_eq!(black.get_pixel(3, 1).0, [0, 0, 0]);
+        let white = reader.next_frame().unwrap().unwrap();
+        assert_eq!(white.get_pixel(0, 0).0, [255, 255, 255]);
+        assert!(reader.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn names_images_by_file_name() {
+        let dir = std::env::temp_dir().join(format!("frames-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let img = DynamicImage::ImageRgb8(RgbImage::new(2, 2));
+        img.save(dir.join("a.png")).unwrap();
+        std::fs::write(dir.join("a.jpg"), jpeg(2, 2)).unwrap();
+        let names: Vec<String> = FrameSource::open(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|frame| frame.unwrap().name)
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(names, ["a.jpg", "a.png"]);
+    }
+
+    #[test]
+    fn rejects_high_bit_depth_y4m() {
+        let header = b"YUV4MPEG2 W4 H2 C420p10\n".to_vec();
+        assert!(Y4mReader::new(Cursor::new(header)).is_err());
+    }
+}
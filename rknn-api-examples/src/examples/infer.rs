@@ -0,0 +1,115 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use serde_json::json;
+
+use crate::examples::{
+    common::*,
+    npy::{is_npy_path, load_inputs},
+    preprocess::Preprocessor,
+    report::{run_record, ResultWriter, Timings},
+    utils::{safe_string, DumpStats, DumpVals},
+};
+
+/// Run a model on NumPy arrays (or images) and write every output as .npy,
+/// for checking a model against the toolkit's own dumps.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The path to the model file (*.rknn)
+    #[arg(short, long)]
+    model_path: String,
+
+    /// The inputs: one .npy per model input, .npz archives holding several
+    /// (matched to the inputs by name, else in order), or one image per input
+    #[arg(short, long, value_parser, required = true)]
+    input_paths: Vec<String>,
+
+    /// The directory the outputs (`<name>_output<i>.npy`) and a JSON record
+    /// are written to
+    #[arg(short, long, default_value = ".")]
+    output_dir: String,
+
+    /// Write the outputs in the model's own types instead of dequantized f32
+    #[arg(long)]
+    raw: bool,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+/// Sets the model inputs from NumPy files (see [`load_inputs`]) or from one
+/// image per input, preprocessed with `preprocess`.
+pub fn set_inputs_from_paths(
+    ctx: &RKNNContext,
+    input_attrs: &[rknn_api_sys::rknn_tensor_attr],
+    paths: &[String],
+    preprocess: &Preprocessor,
+) -> Result<()> {
+    let npy_inputs = paths.iter().filter(|p| is_npy_path(p)).count();
+    if npy_inputs == paths.len() {
+        let names = input_attrs
+            .iter()
+            .map(|attr| safe_string(&attr.name))
+            .collect::<Result<Vec<_>>>()?;
+        ctx.set_tensor_inputs(&load_inputs(paths, &names)?)
+    } else if npy_inputs == 0 {
+        let images = paths
+            .iter()
+            .map(|p| Ok(image::ImageReader::open(p)?.decode()?))
+            .collect::<Result<Vec<_>>>()?;
+        if images.len() != input_attrs.len() {
+            bail!(
+                "{} images given, the model has {} inputs",
+                images.len(),
+                input_attrs.len()
+            );
+        }
+        ctx.set_inputs(input_attrs, &images, preprocess)?;
+        Ok(())
+    } else {
+        bail!("inputs must be all NumPy files or all images");
+    }
+}
+
+impl Example {
+    pub fn execute(&self) -> Result<()> {
+        let ctx = RKNNContext::load_model(&self.model_path)?;
+        ctx.set_core_mask(&self.core_mask)?;
+        let input_attrs = ctx.use_default_shapes()?;
+        println!("\x1b[34;4m input tensors:\x1b[0m");
+        for attr in &input_attrs {
+            println!("{}", attr.dump()?);
+        }
+
+        let mut timings = Timings::default();
+        timings.time("set_inputs", || {
+            set_inputs_from_paths(&ctx, &input_attrs, &self.input_paths, &self.preprocess)
+        })?;
+        timings.time("run", || ctx.run())?;
+        let outputs = timings.time("get_outputs", || ctx.fetch_outputs(!self.raw))?;
+        let views = (0..outputs.len())
+            .map(|i| outputs.view(i))
+            .collect::<Result<Vec<_>>>()?;
+
+        println!("\x1b[34;4m output tensors:\x1b[0m");
+        for (attr, view) in outputs.attrs().iter().zip(&views) {
+            println!("{}", attr.dump()?);
+            let values: Vec<f64> = (0..view.len()).map(|i| view.get(i) as f64).collect();
+            println!("  {}", values.dump_stats(""));
+        }
+
+        let writer = ResultWriter::new(&self.output_dir)?;
+        let mut record = run_record(
+            &self.input_paths.join(","),
+            &self.model_path,
+            &input_attrs,
+            outputs.attrs(),
+            &timings,
+        );
+        record["raw"] = json!(self.raw);
+        writer.write(&writer.name(&self.input_paths[0]), &record, &views)?;
+        Ok(())
+    }
+}
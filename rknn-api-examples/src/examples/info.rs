@@ -0,0 +1,252 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde_json::{json, Value};
+
+use rknn_api_sys::{rknn_input_range, rknn_mem_size, rknn_tensor_attr};
+use rknn_file::RknnFile;
+
+use crate::examples::{
+    common::*,
+    platform::{detect_chip, device_root, matches_build},
+    report::tensor_json,
+    utils::{get_format_string, safe_string, DumpVals},
+};
+
+/// Print what the runtime knows about a model: SDK and driver versions,
+/// custom string, tensor attrs (as given and as the NPU holds them), memory
+/// use and dynamic shape ranges. Nothing is run, so no inputs are needed.
+/// With `--offline`, only what the file itself says is printed.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The path to the model file (*.rknn)
+    #[arg(short, long)]
+    model_path: String,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Only read the file's header and metadata, without the runtime or an
+    /// NPU
+    #[arg(long)]
+    offline: bool,
+
+    /// Fail when the file says the model is built for another platform,
+    /// e.g. `rk3588` or `rk3566_rk3568`
+    #[arg(long)]
+    platform: Option<String>,
+}
+
+/// Everything `info` reports. Queries the runtime or model doesn't support
+/// are `None`.
+pub struct ModelInfo {
+    sdk: SdkVersion,
+    custom_string: Option<String>,
+    inputs: Vec<rknn_tensor_attr>,
+    outputs: Vec<rknn_tensor_attr>,
+    native_inputs: Option<Vec<rknn_tensor_attr>>,
+    native_outputs: Option<Vec<rknn_tensor_attr>>,
+    native_nhwc_inputs: Option<Vec<rknn_tensor_attr>>,
+    native_nhwc_outputs: Option<Vec<rknn_tensor_attr>>,
+    mem_size: Option<rknn_mem_size>,
+    /// Only the inputs with dynamic shapes.
+    input_ranges: Option<Vec<rknn_input_range>>,
+}
+
+impl ModelInfo {
+    pub fn query(ctx: &RKNNContext) -> Result<Self> {
+        Ok(ModelInfo {
+            sdk: ctx.get_sdk_version()?,
+            custom_string: ctx.get_custom_string().ok(),
+            inputs: ctx.get_input_attrs()?,
+            outputs: ctx.get_output_attrs()?,
+            native_inputs: ctx.get_native_input_attrs().ok(),
+            native_outputs: ctx.get_native_output_attrs().ok(),
+            native_nhwc_inputs: ctx.get_native_nhwc_input_attrs().ok(),
+            native_nhwc_outputs: ctx.get_native_nhwc_output_attrs().ok(),
+            mem_size: ctx.get_mem_size().ok(),
+            input_ranges: ctx
+                .get_input_range()
+                .ok()
+                .map(|ranges| ranges.into_iter().filter(|r| r.shape_number > 0).collect()),
+        })
+    }
+
+    /// What the runtime said, as `info --json` prints it.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "api_version": self.sdk.api_verion,
+            "driver_version": self.sdk.driver_verion,
+            "custom_string": self.custom_string,
+            "inputs": self.inputs.iter().map(tensor_json).collect::<Vec<_>>(),
+            "outputs": self.outputs.iter().map(tensor_json).collect::<Vec<_>>(),
+            "native_inputs": attrs_json(&self.native_inputs),
+            "native_outputs": attrs_json(&self.native_outputs),
+            "native_nhwc_inputs": attrs_json(&self.native_nhwc_inputs),
+            "native_nhwc_outputs": attrs_json(&self.native_nhwc_outputs),
+            "mem_size": self.mem_size.as_ref().map(mem_size_json),
+            "dynamic_ranges": self
+                .input_ranges
+                .as_ref()
+                .map(|ranges| ranges.iter().map(range_json).collect::<Vec<_>>()),
+        })
+    }
+}
+
+fn attrs_json(attrs: &Option<Vec<rknn_tensor_attr>>) -> Value {
+    match attrs {
+        Some(attrs) => json!(attrs.iter().map(tensor_json).collect::<Vec<_>>()),
+        None => Value::Null,
+    }
+}
+
+fn mem_size_json(mem: &rknn_mem_size) -> Value {
+    json!({
+        "weight": mem.total_weight_size,
+        "internal": mem.total_internal_size,
+        "dma_allocated": mem.total_dma_allocated_size,
+        "sram_total": mem.total_sram_size,
+        "sram_free": mem.free_sram_size,
+    })
+}
+
+fn range_json(range: &rknn_input_range) -> Value {
+    let n_dims = range.n_dims as usize;
+    json!({
+        "index": range.index,
+        "name": safe_string(&range.name).unwrap_or_default(),
+        "fmt": get_format_string(range.fmt),
+        "shapes": range.dyn_range[..range.shape_number as usize]
+            .iter()
+            .map(|dims| dims[..n_dims].to_vec())
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn print_attrs(title: &str, attrs: &Option<Vec<rknn_tensor_attr>>) -> Result<()> {
+    println!("\x1b[34;4m {}:\x1b[0m", title);
+    match attrs {
+        Some(attrs) => {
+            for attr in attrs {
+                println!("{}", attr.dump()?);
+            }
+        }
+        None => println!("  not available"),
+    }
+    Ok(())
+}
+
+impl Example {
+    pub fn execute(&self) -> Result<()> {
+        // Only --offline and --platform need the file's metadata; the
+        // runtime may still load files it can't be read from.
+        let file = match RknnFile::load(&self.model_path) {
+            Ok(file) => Some(file),
+            Err(e) if self.offline || self.platform.is_some() => return Err(e),
+            Err(_) => None,
+        };
+        if let Some(file) = &file {
+            if let Some(platform) = &self.platform {
+                file.check_platform(platform)
+                    .with_context(|| format!("{} can't run on {}", self.model_path, platform))?;
+            }
+            if self.offline {
+                return file.print(&self.model_path, self.json);
+            }
+        }
+
+        let ctx = RKNNContext::load_model(&self.model_path)?;
+        let info = ModelInfo::query(&ctx)?;
+        let device = detect_chip(&device_root());
+
+        if self.json {
+            let mut report = info.to_json();
+            report["model"] = json!(self.model_path);
+            report["target_platforms"] = json!(file.as_ref().map(|f| &f.target_platforms));
+            report["platforms_scanned"] = json!(file.as_ref().map(|f| f.platforms_scanned));
+            report["bundled_models_platform"] = json!(PLATFORM);
+            report["device"] = json!(device);
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+
+        println!("\x1b[34;4m model: {}\x1b[0m", self.model_path);
+        println!(
+            "\x1b[34;4m target platform: {}\x1b[0m",
+            file.as_ref()
+                .map_or("unknown".to_string(), RknnFile::target_platforms_text)
+        );
+        println!("\x1b[34;4m bundled models built for: {}\x1b[0m", PLATFORM);
+        // The platforms of a model that can't run on this device.
+        let mismatch = device
+            .as_ref()
+            .zip(file.as_ref())
+            .filter(|(chip, file)| file.supports(chip) == Some(false))
+            .map(|(_, file)| file.target_platforms.join(","));
+        match (&device, mismatch) {
+            (Some(chip), Some(platforms)) => println!(
+                "\x1b[34;4m device: {}, but the model is built for {}\x1b[0m",
+                chip, platforms
+            ),
+            (Some(chip), None) if matches_build(chip) => {
+                println!("\x1b[34;4m device: {}\x1b[0m", chip)
+            }
+            (Some(chip), None) => println!(
+                "\x1b[34;4m device: {}, but the bundled models are for {}\x1b[0m",
+                chip, PLATFORM
+            ),
+            (None, _) => println!("\x1b[34;4m device: unknown\x1b[0m"),
+        }
+        println!(
+            "\x1b[34;4m sdk api version: {}, driver version: {}\x1b[0m",
+            info.sdk.api_verion, info.sdk.driver_verion
+        );
+        match &info.custom_string {
+            Some(custom) if !custom.is_empty() => {
+                println!("\x1b[34;4m custom string: {}\x1b[0m", custom)
+            }
+            Some(_) => println!("\x1b[34;4m custom string: (empty)\x1b[0m"),
+            None => println!("\x1b[34;4m custom string: not available\x1b[0m"),
+        }
+        print_attrs("input tensors", &Some(info.inputs))?;
+        print_attrs("output tensors", &Some(info.outputs))?;
+        print_attrs("native input tensors", &info.native_inputs)?;
+        print_attrs("native output tensors", &info.native_outputs)?;
+        print_attrs("native NHWC input tensors", &info.native_nhwc_inputs)?;
+        print_attrs("native NHWC output tensors", &info.native_nhwc_outputs)?;
+
+        println!("\x1b[34;4m memory:\x1b[0m");
+        match &info.mem_size {
+            Some(mem) => {
+                println!("  weight={:.2} MiB", mib(mem.total_weight_size as u64));
+                println!("  internal={:.2} MiB", mib(mem.total_internal_size as u64));
+                println!(
+                    "  dma_allocated={:.2} MiB",
+                    mib(mem.total_dma_allocated_size)
+                );
+                println!(
+                    "  sram total={:.2} MiB, free={:.2} MiB",
+                    mib(mem.total_sram_size as u64),
+                    mib(mem.free_sram_size as u64)
+                );
+            }
+            None => println!("  not available"),
+        }
+
+        println!("\x1b[34;4m dynamic shape ranges:\x1b[0m");
+        match &info.input_ranges {
+            Some(ranges) if ranges.is_empty() => println!("  none, the input shapes are fixed"),
+            Some(ranges) => {
+                for range in ranges {
+                    println!("{}", range.dump()?);
+                }
+            }
+            None => println!("  not available"),
+        }
+        Ok(())
+    }
+}
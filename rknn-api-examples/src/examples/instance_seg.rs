@@ -0,0 +1,404 @@
+use anyhow::{bail, Result};
+
+use crate::examples::{
+    common::OutputView,
+    preprocess::ImageTransform,
+    yolo::{Detection, YoloDecoder},
+};
+
+/// A binary instance mask covering the box of its detection, in source image
+/// pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mask {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Row major, `width * height` values.
+    pub data: Vec<bool>,
+}
+
+impl Mask {
+    /// Whether the image pixel `(x, y)` belongs to the instance.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x
+            && y >= self.y
+            && x < self.x + self.width
+            && y < self.y + self.height
+            && self.data[((y - self.y) * self.width + (x - self.x)) as usize]
+    }
+
+    pub fn area(&self) -> usize {
+        self.data.iter().filter(|m| **m).count()
+    }
+}
+
+/// Decodes YOLOv8-seg outputs: boxes from the detection head and a mask per
+/// box from its coefficients and the mask prototypes.
+///
+/// Two layouts are recognised, both with the prototypes `[1, nm, ph, pw]`
+/// last: one `[1, 4+nc+nm, N]` output with the coefficients after the class
+/// scores, or 12 branch outputs, each branch's DFL boxes, class scores, score
+/// sum and `[1, nm, H, W]` coefficients.
+#[derive(Clone, Debug)]
+pub struct SegDecoder {
+    pub decoder: YoloDecoder,
+    pub mask_threshold: f32,
+}
+
+impl SegDecoder {
+    pub fn new(decoder: YoloDecoder) -> Self {
+        SegDecoder {
+            decoder,
+            mask_threshold: 0.5,
+        }
+    }
+
+    /// Decodes boxes and masks, both mapped back to the source image.
+    pub fn decode(
+        &self,
+        outputs: &[OutputView],
+        input_size: (u32, u32),
+        transform: &ImageTransform,
+    ) -> Result<Vec<(Detection, Mask)>> {
+        let Some((protos, heads)) = outputs.split_last() else {
+            bail!("no outputs");
+        };
+        let (nm, _, _) = protos.chw();
+
+        let (detections, coefficients): (Vec<Detection>, Vec<Vec<f32>>) = match heads.len() {
+            1 => {
+                let (_, attrs, at) = YoloDecoder::rows(&heads[0])?;
+                let n_classes = attrs.checked_sub(4 + nm).filter(|n| *n > 0);
+                let Some(n_classes) = n_classes else {
+                    bail!(
+                        "{} values per box leave no room for {} coefficients",
+                        attrs,
+                        nm
+                    );
+                };
+                let decoder = YoloDecoder {
+                    num_classes: Some(n_classes),
+                    ..self.decoder.clone()
+                };
+                let detections = decoder.decode(heads, input_size)?;
+                let coefficients = detections
+                    .iter()
+                    .map(|d| (0..nm).map(|k| at(d.anchor, 4 + n_classes + k)).collect())
+                    .collect();
+                (detections, coefficients)
+            }
+            12 => {
+                let (det_heads, coef_heads): (Vec<OutputView>, Vec<&OutputView>) = (
+                    heads
+                        .chunks(4)
+                        .flat_map(|b| b[..3].iter().copied())
+                        .collect(),
+                    heads.chunks(4).map(|b| &b[3]).collect(),
+                );
+                let detections = self.decoder.decode(&det_heads, input_size)?;
+                let coefficients = detections
+                    .iter()
+                    .map(|d| branch_values(&coef_heads, d.anchor, nm))
+                    .collect();
+                (detections, coefficients)
+            }
+            n => bail!("no YOLOv8-seg layout with {} outputs", n + 1),
+        };
+
+        Ok(detections
+            .into_iter()
+            .zip(coefficients)
+            .map(|(det, coefficients)| {
+                let mask = self.mask(protos, &coefficients, &det, input_size, transform);
+                let bbox = det.bbox.to_source(transform);
+                (Detection { bbox, ..det }, mask)
+            })
+            .collect())
+    }
+
+    /// Combines the prototypes with `coefficients`, crops to the box and
+    /// upsamples straight to source image pixels.
+    fn mask(
+        &self,
+        protos: &OutputView,
+        coefficients: &[f32],
+        det: &Detection,
+        (in_w, in_h): (u32, u32),
+        transform: &ImageTransform,
+    ) -> Mask {
+        let (_, proto_h, proto_w) = protos.chw();
+        let bbox = det.bbox.to_source(transform);
+        let (x0, y0) = (bbox.x1.floor() as u32, bbox.y1.floor() as u32);
+        let x1 = (bbox.x2.ceil() as u32).min(transform.source_width);
+        let y1 = (bbox.y2.ceil() as u32).min(transform.source_height);
+        let (width, height) = (x1.saturating_sub(x0), y1.saturating_sub(y0));
+
+        // Prototype coordinates of a source pixel center.
+        let to_proto = |x: u32, y: u32| {
+            let mx = (x as f32 + 0.5) * transform.scale_x + transform.offset_x;
+            let my = (y as f32 + 0.5) * transform.scale_y + transform.offset_y;
+            (
+                mx * proto_w as f32 / in_w as f32 - 0.5,
+                my * proto_h as f32 / in_h as f32 - 0.5,
+            )
+        };
+        // Only the prototype cells under the box are combined.
+        let (u0, v0) = to_proto(x0, y0);
+        let (u1, v1) = to_proto(x1.max(x0 + 1) - 1, y1.max(y0 + 1) - 1);
+        let cu0 = (u0.floor().max(0.0) as usize).min(proto_w - 1);
+        let cv0 = (v0.floor().max(0.0) as usize).min(proto_h - 1);
+        let cu1 = (u1.ceil().max(0.0) as usize).min(proto_w - 1);
+        let cv1 = (v1.ceil().max(0.0) as usize).min(proto_h - 1);
+        let cells_w = cu1 - cu0 + 1;
+        let mut logits = vec![0f32; cells_w * (cv1 - cv0 + 1)];
+        for v in cv0..=cv1 {
+            for u in cu0..=cu1 {
+                logits[(v - cv0) * cells_w + (u - cu0)] = coefficients
+                    .iter()
+                    .enumerate()
+                    .map(|(k, c)| c * protos.at(k, v, u))
+                    .sum();
+            }
+        }
+        let logit_at = |u: usize, v: usize| logits[(v - cv0) * cells_w + (u - cu0)];
+
+        // sigmoid(logit) > threshold, without the exp per pixel.
+        let threshold = (self.mask_threshold / (1.0 - self.mask_threshold)).ln();
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in y0..y0 + height {
+            for x in x0..x0 + width {
+                let (u, v) = to_proto(x, y);
+                let u = u.clamp(cu0 as f32, cu1 as f32);
+                let v = v.clamp(cv0 as f32, cv1 as f32);
+                let (ul, vt) = (u.floor() as usize, v.floor() as usize);
+                let (ur, vb) = ((ul + 1).min(cu1), (vt + 1).min(cv1));
+                let (fu, fv) = (u - ul as f32, v - vt as f32);
+                let logit = logit_at(ul, vt) * (1.0 - fu) * (1.0 - fv)
+                    + logit_at(ur, vt) * fu * (1.0 - fv)
+                    + logit_at(ul, vb) * (1.0 - fu) * fv
+                    + logit_at(ur, vb) * fu * fv;
+                data.push(logit > threshold);
+            }
+        }
+        Mask {
+            x: x0,
+            y: y0,
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+/// `n` channels at `anchor` of branch outputs `[1, n, H, W]`, the anchor
+/// counting through the branches' cells in order.
+pub(crate) fn branch_values(branches: &[&OutputView], anchor: usize, n: usize) -> Vec<f32> {
+    let mut anchor = anchor;
+    for branch in branches {
+        let (_, height, width) = branch.chw();
+        if anchor < height * width {
+            let (y, x) = (anchor / width, anchor % width);
+            return (0..n).map(|k| branch.at(k, y, x)).collect();
+        }
+        anchor -= height * width;
+    }
+    vec![0.0; n]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::yolo::YoloVersion;
+    use rknn_api_sys::rknn_tensor_attr;
+
+    fn attr(dims: &[u32]) -> rknn_tensor_attr {
+        let mut attr = rknn_tensor_attr {
+            n_dims: dims.len() as u32,
+            n_elems: dims.iter().product(),
+            ..Default::default()
+        };
+        attr.dims[..dims.len()].copy_from_slice(dims);
+        attr
+    }
+
+    /// An NCHW `[1, C, H, W]` tensor, zero but for `values` at (c, y, x).
+    fn nchw(c: usize, h: usize, w: usize, values: &[((usize, usize, usize), f32)]) -> Vec<f32> {
+        let mut data = vec![0.0; c * h * w];
+        for ((ci, y, x), v) in values {
+            data[(ci * h + y) * w + x] = *v;
+        }
+        data
+    }
+
+    /// Two 4x4 prototypes for a 16x16 input: the first positive on its left
+    /// half and negative on its right, the second positive everywhere.
+    fn protos() -> Vec<f32> {
+        let mut protos = vec![10.0; 32];
+        for v in 0..4 {
+            protos[v * 4 + 2] = -10.0;
+            protos[v * 4 + 3] = -10.0;
+        }
+        protos
+    }
+
+    const IDENTITY: ImageTransform = ImageTransform {
+        scale_x: 1.0,
+        scale_y: 1.0,
+        offset_x: 0.0,
+        offset_y: 0.0,
+        source_width: 16,
+        source_height: 16,
+    };
+
+    /// A 32x16 source letterboxed into the 16x16 input.
+    const LETTERBOX: ImageTransform = ImageTransform {
+        scale_x: 0.5,
+        scale_y: 0.5,
+        offset_x: 0.0,
+        offset_y: 4.0,
+        source_width: 32,
+        source_height: 16,
+    };
+
+    /// Mask columns `x0..split` of rows `y0..y0 + height` set, the rest of
+    /// `x0..x1` clear.
+    fn assert_mask(mask: &Mask, (x0, x1): (u32, u32), split: u32, (y0, height): (u32, u32)) {
+        assert_eq!(
+            (mask.x, mask.y, mask.width, mask.height),
+            (x0, y0, x1 - x0, height)
+        );
+        for y in y0..y0 + height {
+            for x in x0..x1 {
+                assert_eq!(mask.contains(x, y), x < split, "({}, {})", x, y);
+            }
+        }
+        assert!(!mask.contains(x0 - 1, y0) && !mask.contains(x0, y0 + height));
+        assert_eq!(mask.area(), ((split - x0) * height) as usize);
+    }
+
+    #[test]
+    fn decodes_concatenated_masks() {
+        // [1, 4 + 1 class + 2 coefficients, 8 rows], a box (4, 4)-(12, 12)
+        // in row 3 taking the first prototype.
+        let mut head = vec![0.0; 56];
+        for (c, v) in [8.0, 8.0, 8.0, 8.0, 0.9, 1.0, 0.0].into_iter().enumerate() {
+            head[c * 8 + 3] = v;
+        }
+        let protos = protos();
+        let views = [
+            OutputView::from_f32(&head, attr(&[1, 7, 8])),
+            OutputView::from_f32(&protos, attr(&[1, 2, 4, 4])),
+        ];
+        let mut decoder = SegDecoder::new(YoloDecoder::new(YoloVersion::V8));
+        let results = decoder.decode(&views, (16, 16), &IDENTITY).unwrap();
+        assert_eq!(results.len(), 1);
+        let (det, mask) = &results[0];
+        assert_eq!((det.anchor, det.score), (3, 0.9));
+        // The logits cross zero halfway between prototype columns 1 and 2,
+        // at input x = 8.
+        assert_mask(mask, (4, 12), 8, (4, 8));
+
+        // Pixel 7 sits at a logit of 2.5, sigmoid 0.924.
+        decoder.mask_threshold = 0.9;
+        let (_, mask) = &decoder.decode(&views, (16, 16), &IDENTITY).unwrap()[0];
+        assert_mask(mask, (4, 12), 8, (4, 8));
+        decoder.mask_threshold = 0.95;
+        let (_, mask) = &decoder.decode(&views, (16, 16), &IDENTITY).unwrap()[0];
+        assert_mask(mask, (4, 12), 7, (4, 8));
+    }
+
+    #[test]
+    fn decodes_branch_masks_in_source_pixels() {
+        // reg_max 2, 1 class, 2 coefficients: 1x1, 2x2 and 1x1 branches.
+        // The box sits in cell (1, 1) of the 2x2 branch (stride 8), anchor
+        // 1 + 3, distances 1, 1, 0, 0 around (12, 12).
+        let boxes = nchw(
+            8,
+            2,
+            2,
+            &[
+                ((1, 1, 1), 30.0),
+                ((3, 1, 1), 30.0),
+                ((4, 1, 1), 30.0),
+                ((6, 1, 1), 30.0),
+            ],
+        );
+        let scores = nchw(1, 2, 2, &[((0, 1, 1), 0.9)]);
+        let sum = scores.clone();
+        // Every other cell takes the all positive prototype.
+        let coefficients = nchw(
+            2,
+            2,
+            2,
+            &[
+                ((0, 1, 1), 1.0),
+                ((1, 0, 0), 1.0),
+                ((1, 0, 1), 1.0),
+                ((1, 1, 0), 1.0),
+            ],
+        );
+        let (empty_boxes, empty, other) = (vec![0.0; 8], vec![0.0; 1], vec![0.0, 1.0]);
+        let protos = protos();
+        let small = |data| {
+            [
+                OutputView::from_f32(&empty_boxes, attr(&[1, 8, 1, 1])),
+                OutputView::from_f32(&empty, attr(&[1, 1, 1, 1])),
+                OutputView::from_f32(&empty, attr(&[1, 1, 1, 1])),
+                OutputView::from_f32(data, attr(&[1, 2, 1, 1])),
+            ]
+        };
+        let mut views = Vec::new();
+        views.extend(small(&other));
+        views.extend([
+            OutputView::from_f32(&boxes, attr(&[1, 8, 2, 2])),
+            OutputView::from_f32(&scores, attr(&[1, 1, 2, 2])),
+            OutputView::from_f32(&sum, attr(&[1, 1, 2, 2])),
+            OutputView::from_f32(&coefficients, attr(&[1, 2, 2, 2])),
+        ]);
+        views.extend(small(&other));
+        views.push(OutputView::from_f32(&protos, attr(&[1, 2, 4, 4])));
+
+        let mut yolo = YoloDecoder::new(YoloVersion::V8);
+        yolo.reg_max = 2;
+        let decoder = SegDecoder::new(yolo);
+        let results = decoder.decode(&views, (16, 16), &IDENTITY).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.anchor, 4);
+        assert_mask(&results[0].1, (4, 12), 8, (4, 8));
+
+        // Letterboxed, the box covers source (8, 0)-(24, 16) and the
+        // boundary falls at source x = 16.
+        let results = decoder.decode(&views, (16, 16), &LETTERBOX).unwrap();
+        let (det, mask) = &results[0];
+        let bbox = [det.bbox.x1, det.bbox.y1, det.bbox.x2, det.bbox.y2];
+        assert_eq!(bbox, [8.0, 0.0, 24.0, 16.0]);
+        assert_mask(mask, (8, 24), 16, (0, 16));
+    }
+
+    #[test]
+    fn reads_branch_values_across_branches() {
+        let first: Vec<f32> = (0..8).map(|v| v as f32).collect();
+        let second = [100.0, 101.0];
+        let branches = [
+            OutputView::from_f32(&first, attr(&[1, 2, 2, 2])),
+            OutputView::from_f32(&second, attr(&[1, 2, 1, 1])),
+        ];
+        let branches: Vec<&OutputView> = branches.iter().collect();
+        assert_eq!(branch_values(&branches, 1, 2), [1.0, 5.0]);
+        assert_eq!(branch_values(&branches, 4, 2), [100.0, 101.0]);
+        assert_eq!(branch_values(&branches, 5, 2), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn rejects_heads_without_coefficients() {
+        let head = vec![0.0; 48];
+        let protos = protos();
+        let views = [
+            OutputView::from_f32(&head, attr(&[1, 6, 8])),
+            OutputView::from_f32(&protos, attr(&[1, 2, 4, 4])),
+        ];
+        let decoder = SegDecoder::new(YoloDecoder::new(YoloVersion::V8));
+        assert!(decoder.decode(&views, (16, 16), &IDENTITY).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// The latencies of one stage over a run.
+#[derive(Clone, Debug, Default)]
+pub struct Latencies(pub Vec<Duration>);
+
+impl Latencies {
+    pub fn push(&mut self, duration: Duration) {
+        self.0.push(duration);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn extend(&mut self, other: &Latencies) {
+        self.0.extend_from_slice(&other.0);
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        let mut us: Vec<f64> = self.0.iter().map(|d| d.as_nanos() as f64 / 1e3).collect();
+        us.sort_by(f64::total_cmp);
+        let count = us.len();
+        if count == 0 {
+            return LatencySummary::default();
+        }
+        let mean = us.iter().sum::<f64>() / count as f64;
+        let variance = us.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / count as f64;
+        // Nearest rank: the smallest sample with at least p% at or below it.
+        let percentile =
+            |p: f64| us[((p / 100.0 * count as f64).ceil() as usize).clamp(1, count) - 1];
+        LatencySummary {
+            count,
+            mean_us: mean,
+            sd_us: variance.sqrt(),
+            min_us: us[0],
+            p50_us: percentile(50.0),
+            p90_us: percentile(90.0),
+            p99_us: percentile(99.0),
+            max_us: us[count - 1],
+        }
+    }
+}
+
+/// Latency statistics in microseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub mean_us: f64,
+    pub sd_us: f64,
+    pub min_us: f64,
+    pub p50_us: f64,
+    pub p90_us: f64,
+    pub p99_us: f64,
+    pub max_us: f64,
+}
+
+impl LatencySummary {
+    /// The CSV columns of [`LatencySummary::csv_fields`].
+    pub const CSV_HEADER: &'static str = "count,mean_us,sd_us,min_us,p50_us,p90_us,p99_us,max_us";
+
+    pub fn csv_fields(&self) -> String {
+        format!(
+            "{},{:.1},{:.1},{:.1},{:.1},{:.1},{:.1},{:.1}",
+            self.count,
+            self.mean_us,
+            self.sd_us,
+            self.min_us,
+            self.p50_us,
+            self.p90_us,
+            self.p99_us,
+            self.max_us
+        )
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "count": self.count,
+            "mean_us": self.mean_us,
+            "sd_us": self.sd_us,
+            "min_us": self.min_us,
+            "p50_us": self.p50_us,
+            "p90_us": self.p90_us,
+            "p99_us": self.p99_us,
+            "max_us": self.max_us,
+        })
+    }
+
+    /// One line, e.g. for a table row.
+    pub fn dump(&self) -> String {
+        format!(
+            "mean {:>9.1}  p50 {:>9.1}  p90 {:>9.1}  p99 {:>9.1}  min {:>9.1}  max {:>9.1} (us)",
+            self.mean_us, self.p50_us, self.p90_us, self.p99_us, self.min_us, self.max_us
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        // 1..=100 us, shuffled.
+        let mut latencies = Latencies::default();
+        for i in (1..=100u64).rev() {
+            latencies.push(Duration::from_micros((i * 37) % 100 + 1));
+        }
+        let summary = latencies.summary();
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.min_us, 1.0);
+        assert_eq!(summary.p50_us, 50.0);
+        assert_eq!(summary.p90_us, 90.0);
+        assert_eq!(summary.p99_us, 99.0);
+        assert_eq!(summary.max_us, 100.0);
+        assert_eq!(summary.mean_us, 50.5);
+    }
+
+    #[test]
+    fn keeps_sub_millisecond_resolution() {
+        let latencies = Latencies(vec![Duration::from_nanos(250_500)]);
+        let summary = latencies.summary();
+        assert_eq!(summary.p99_us, 250.5);
+        assert_eq!(Latencies::default().summary().count, 0);
+    }
+}
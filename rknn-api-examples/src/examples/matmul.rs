@@ -0,0 +1,451 @@
+use anyhow::{bail, Ok, Result};
+use rknn_api_sys::{
+    rknn_B_normal_layout_to_native_layout, rknn_create_mem, rknn_destroy_mem, rknn_matmul_create,
+    rknn_matmul_create_dyn_shape, rknn_matmul_ctx, rknn_matmul_destroy, rknn_matmul_info,
+    rknn_matmul_io_attr, rknn_matmul_run, rknn_matmul_set_core_mask, rknn_matmul_set_dynamic_shape,
+    rknn_matmul_set_io_mem, rknn_matmul_set_quant_params, rknn_matmul_shape,
+    rknn_matmul_tensor_attr, rknn_quant_params, rknn_tensor_mem, rknn_tensor_type, RKNN_SUCC,
+};
+
+use crate::{
+    call_rknn_api,
+    examples::{
+        common::{RknnCoreMask, TensorElement},
+        utils::get_type_string,
+    },
+};
+
+/// The operand types of `C = A x B`, named after `RKNN_<A>_MM_<B>_TO_<C>`.
+#[repr(u32)]
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MatmulType {
+    Fp16MmFp16ToFp32 = rknn_api_sys::_rknn_matmul_type_RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32,
+    Int8MmInt8ToInt32 = rknn_api_sys::_rknn_matmul_type_RKNN_INT8_MM_INT8_TO_INT32,
+    Int8MmInt8ToInt8 = rknn_api_sys::_rknn_matmul_type_RKNN_INT8_MM_INT8_TO_INT8,
+    Fp16MmFp16ToFp16 = rknn_api_sys::_rknn_matmul_type_RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT16,
+    Fp16MmInt8ToFp32 = rknn_api_sys::_rknn_matmul_type_RKNN_FLOAT16_MM_INT8_TO_FLOAT32,
+    Fp16MmInt8ToFp16 = rknn_api_sys::_rknn_matmul_type_RKNN_FLOAT16_MM_INT8_TO_FLOAT16,
+    Fp16MmInt4ToFp32 = rknn_api_sys::_rknn_matmul_type_RKNN_FLOAT16_MM_INT4_TO_FLOAT32,
+    Fp16MmInt4ToFp16 = rknn_api_sys::_rknn_matmul_type_RKNN_FLOAT16_MM_INT4_TO_FLOAT16,
+    Int8MmInt8ToFp32 = rknn_api_sys::_rknn_matmul_type_RKNN_INT8_MM_INT8_TO_FLOAT32,
+    Int4MmInt4ToInt16 = rknn_api_sys::_rknn_matmul_type_RKNN_INT4_MM_INT4_TO_INT16,
+    Int8MmInt4ToInt32 = rknn_api_sys::_rknn_matmul_type_RKNN_INT8_MM_INT4_TO_INT32,
+}
+
+/// How B is laid out in memory.
+#[repr(i16)]
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BLayout {
+    /// Row major K x N.
+    Normal = 0,
+    /// The NPU's blocked layout, see `rknn_B_normal_layout_to_native_layout`.
+    Native = 1,
+    /// Row major N x K.
+    Transposed = 2,
+}
+
+/// How A and C are laid out in memory.
+#[repr(i16)]
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AcLayout {
+    /// Row major M x K and M x N.
+    Normal = 0,
+    /// The NPU's blocked layout.
+    Native = 1,
+}
+
+/// Granularity of B's quantization parameters.
+#[repr(i16)]
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QuantGranularity {
+    PerLayer = 0,
+    PerChannel = 1,
+    PerGroup = 2,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum MatmulOperand {
+    A,
+    B,
+    C,
+}
+
+/// Describes a `C[M, N] = A[M, K] x B[K, N]` multiplication.
+#[derive(Clone, Debug)]
+pub struct MatmulInfo {
+    pub m: i32,
+    pub k: i32,
+    pub n: i32,
+    pub matmul_type: MatmulType,
+    pub b_layout: BLayout,
+    pub ac_layout: AcLayout,
+    pub b_quant: QuantGranularity,
+    /// Only used with [`QuantGranularity::PerGroup`].
+    pub group_size: i16,
+}
+
+impl MatmulInfo {
+    pub fn new(m: i32, k: i32, n: i32, matmul_type: MatmulType) -> Self {
+        MatmulInfo {
+            m,
+            k,
+            n,
+            matmul_type,
+            b_layout: BLayout::Normal,
+            ac_layout: AcLayout::Normal,
+            b_quant: QuantGranularity::PerLayer,
+            group_size: 0,
+        }
+    }
+
+    pub(crate) fn to_raw(&self) -> rknn_matmul_info {
+        rknn_matmul_info {
+            M: self.m,
+            K: self.k,
+            N: self.n,
+            type_: self.matmul_type as u32,
+            B_layout: self.b_layout as i16,
+            B_quant_type: self.b_quant as i16,
+            AC_layout: self.ac_layout as i16,
+            group_size: self.group_size,
+            ..Default::default()
+        }
+    }
+}
+
+/// Reorders a row major `K x N` matrix into the NPU's native B layout,
+/// `[ceil(N / sub_n), ceil(K / sub_k), sub_n, sub_k]`, zero padding the
+/// partial blocks. The block sizes are the last two dims the runtime
+/// reports for a native B (see [`MatmulContext::native_b_block`]).
+pub fn b_normal_to_native<T: Copy + Default>(
+    b: &[T],
+    k: usize,
+    n: usize,
+    sub_n: usize,
+    sub_k: usize,
+) -> Vec<T> {
+    assert_eq!(b.len(), k * n, "B must hold K x N elements");
+    let n_blocks = n.div_ceil(sub_n);
+    let k_blocks = k.div_ceil(sub_k);
+    let mut native = vec![T::default(); n_blocks * k_blocks * sub_n * sub_k];
+    for (i, block) in native.chunks_exact_mut(sub_n * sub_k).enumerate() {
+        let (n_base, k_base) = (i / k_blocks * sub_n, i % k_blocks * sub_k);
+        for (j, value) in block.iter_mut().enumerate() {
+            let (ni, ki) = (n_base + j / sub_k, k_base + j % sub_k);
+            if ni < n && ki < k {
+                *value = b[ki * n + ni];
+            }
+        }
+    }
+    native
+}
+
+/// A matmul running directly on the NPU, with A, B and C allocated by the
+/// runtime. Fill A and B through [`MatmulContext::a_mut`] and
+/// [`MatmulContext::b_mut`], call [`MatmulContext::run`] and read C.
+pub struct MatmulContext {
+    ctx: rknn_matmul_ctx,
+    info: rknn_matmul_info,
+    io_attr: rknn_matmul_io_attr,
+    mems: [*mut rknn_tensor_mem; 3],
+    /// The shapes a dynamic shape context was created with, empty otherwise.
+    shapes: Vec<(rknn_matmul_shape, rknn_matmul_io_attr)>,
+    /// The current M; A's dims don't give it in the native layout.
+    m: i32,
+}
+
+impl MatmulContext {
+    pub fn new(info: &MatmulInfo) -> Result<Self> {
+        let mut raw_info = info.to_raw();
+        let mut ctx: rknn_matmul_ctx = 0;
+        let mut io_attr = rknn_matmul_io_attr::default();
+        call_rknn_api!(rknn_matmul_create(&mut ctx, &mut raw_info, &mut io_attr))?;
+
+        let mut matmul = MatmulContext {
+            ctx,
+            info: raw_info,
+            io_attr,
+            mems: [std::ptr::null_mut(); 3],
+            shapes: Vec::new(),
+            m: info.m,
+        };
+        for operand in [MatmulOperand::A, MatmulOperand::B, MatmulOperand::C] {
+            matmul.alloc(operand, matmul.attr(operand).size)?;
+            matmul.bind(operand)?;
+        }
+        Ok(matmul)
+    }
+
+    /// Creates a context whose M can be switched between `ms` with
+    /// [`MatmulContext::set_m`], e.g. to follow the number of tokens of a
+    /// transformer decode step. K and N (and so B) are fixed; A and C are
+    /// allocated for the largest M. Starts with the first M.
+    pub fn new_dyn_shape(info: &MatmulInfo, ms: &[i32]) -> Result<Self> {
+        if ms.is_empty() {
+            bail!("at least one M is required");
+        }
+        let mut raw_info = info.to_raw();
+        let mut shapes: Vec<rknn_matmul_shape> = ms
+            .iter()
+            .map(|m| rknn_matmul_shape {
+                M: *m,
+                K: info.k,
+                N: info.n,
+            })
+            .collect();
+        let mut io_attrs = vec![rknn_matmul_io_attr::default(); shapes.len()];
+        let mut ctx: rknn_matmul_ctx = 0;
+        call_rknn_api!(rknn_matmul_create_dyn_shape(
+            &mut ctx,
+            &mut raw_info,
+            shapes.len() as i32,
+            shapes.as_mut_ptr(),
+            io_attrs.as_mut_ptr()
+        ))?;
+
+        let mut matmul = MatmulContext {
+            ctx,
+            info: raw_info,
+            io_attr: io_attrs[0],
+            mems: [std::ptr::null_mut(); 3],
+            shapes: shapes.into_iter().zip(io_attrs).collect(),
+            m: ms[0],
+        };
+        let max_size = |attr: fn(&rknn_matmul_io_attr) -> u32| {
+            matmul
+                .shapes
+                .iter()
+                .map(|(_, io)| attr(io))
+                .max()
+                .unwrap_or(0)
+        };
+        let (a_size, c_size) = (max_size(|io| io.A.size), max_size(|io| io.C.size));
+        matmul.alloc(MatmulOperand::A, a_size)?;
+        matmul.alloc(MatmulOperand::B, matmul.io_attr.B.size)?;
+        matmul.alloc(MatmulOperand::C, c_size)?;
+        // The first shape is set, then bound, like every later one.
+        matmul.set_m(ms[0])?;
+        matmul.bind(MatmulOperand::B)?;
+        Ok(matmul)
+    }
+
+    /// Switches a dynamic shape context to one of the M it was created with.
+    pub fn set_m(&mut self, m: i32) -> Result<()> {
+        let Some(index) = self.shapes.iter().position(|(shape, _)| shape.M == m) else {
+            bail!(
+                "M={} is not one of {:?}",
+                m,
+                self.shapes.iter().map(|(s, _)| s.M).collect::<Vec<_>>()
+            );
+        };
+        call_rknn_api!(rknn_matmul_set_dynamic_shape(
+            self.ctx,
+            &mut self.shapes[index].0
+        ))?;
+        self.io_attr = self.shapes[index].1;
+        self.m = m;
+        self.bind(MatmulOperand::A)?;
+        self.bind(MatmulOperand::C)
+    }
+
+    pub fn m(&self) -> i32 {
+        self.m
+    }
+
+    fn alloc(&mut self, operand: MatmulOperand, size: u32) -> Result<()> {
+        let mem = unsafe { rknn_create_mem(self.ctx, size) };
+        if mem.is_null() {
+            bail!("failed to allocate {} bytes for {:?}", size, operand);
+        }
+        self.mems[operand as usize] = mem;
+        Ok(())
+    }
+
+    fn bind(&mut self, operand: MatmulOperand) -> Result<()> {
+        let mut attr = *self.attr(operand);
+        call_rknn_api!(rknn_matmul_set_io_mem(
+            self.ctx,
+            self.mems[operand as usize],
+            &mut attr
+        ))
+    }
+
+    /// The `(sub_n, sub_k)` block size of a B created with
+    /// [`BLayout::Native`].
+    pub fn native_b_block(&self) -> Result<(usize, usize)> {
+        let b = &self.io_attr.B;
+        if self.info.B_layout != BLayout::Native as i16 || b.n_dims != 4 {
+            bail!("B is not in native layout");
+        }
+        Ok((b.dims[2] as usize, b.dims[3] as usize))
+    }
+
+    /// Converts a row major `K x N` B into the native layout and copies it
+    /// into the context's B memory.
+    pub fn set_b_from_normal<T: TensorElement>(&mut self, b: &[T]) -> Result<()> {
+        let (sub_n, sub_k) = self.native_b_block()?;
+        let (k, n) = (self.info.K as usize, self.info.N as usize);
+        if b.len() != k * n {
+            bail!("B holds {} elements, expected {}x{}", b.len(), k, n);
+        }
+        let native = b_normal_to_native(b, k, n, sub_n, sub_k);
+        let dst = self.b_mut::<T>()?;
+        if dst.len() < native.len() {
+            bail!(
+                "native B needs {} elements, {} allocated",
+                native.len(),
+                dst.len()
+            );
+        }
+        dst[..native.len()].copy_from_slice(&native);
+        Ok(())
+    }
+
+    /// Same as [`MatmulContext::set_b_from_normal`] but lets the runtime do
+    /// the conversion; useful to check [`b_normal_to_native`] on a device.
+    pub fn set_b_from_normal_with_runtime<T: TensorElement>(&mut self, b: &[T]) -> Result<()> {
+        self.native_b_block()?;
+        let mut src = b.to_vec();
+        let dst = self.b_mut::<T>()?.as_mut_ptr();
+        let mut info = self.info;
+        call_rknn_api!(rknn_B_normal_layout_to_native_layout(
+            src.as_mut_ptr() as *mut ::std::os::raw::c_void,
+            dst as *mut ::std::os::raw::c_void,
+            info.K,
+            info.N,
+            &mut info
+        ))
+    }
+
+    pub fn io_attr(&self) -> &rknn_matmul_io_attr {
+        &self.io_attr
+    }
+
+    pub fn attr(&self, operand: MatmulOperand) -> &rknn_matmul_tensor_attr {
+        match operand {
+            MatmulOperand::A => &self.io_attr.A,
+            MatmulOperand::B => &self.io_attr.B,
+            MatmulOperand::C => &self.io_attr.C,
+        }
+    }
+
+    pub fn set_core_mask(&mut self, core_mask: &RknnCoreMask) -> Result<()> {
+        call_rknn_api!(rknn_matmul_set_core_mask(self.ctx, *core_mask as u32))
+    }
+
+    /// Sets the scales and zero points of an int8/int4 operand, one per
+    /// layer, per output channel or per group depending on the
+    /// [`QuantGranularity`] the context was created with.
+    pub fn set_quant_params(
+        &mut self,
+        operand: MatmulOperand,
+        scale: &[f32],
+        zp: &[i32],
+    ) -> Result<()> {
+        let mut params = rknn_quant_params {
+            name: self.attr(operand).name,
+            scale: scale.as_ptr() as *mut f32,
+            scale_len: scale.len() as i32,
+            zp: zp.as_ptr() as *mut i32,
+            zp_len: zp.len() as i32,
+        };
+        call_rknn_api!(rknn_matmul_set_quant_params(self.ctx, &mut params))
+    }
+
+    fn mem(&self, operand: MatmulOperand) -> &rknn_tensor_mem {
+        unsafe { &*self.mems[operand as usize] }
+    }
+
+    /// The raw bytes of an operand, for layouts and types without a Rust
+    /// counterpart (e.g. int4).
+    pub fn bytes_mut(&mut self, operand: MatmulOperand) -> &mut [u8] {
+        let size = self.attr(operand).size as usize;
+        unsafe { std::slice::from_raw_parts_mut(self.mem(operand).virt_addr as *mut u8, size) }
+    }
+
+    fn typed<T: TensorElement>(&self, operand: MatmulOperand) -> Result<(*mut T, usize)> {
+        let tensor_type: rknn_tensor_type = self.attr(operand).type_;
+        if tensor_type != T::TENSOR_TYPE {
+            bail!(
+                "{:?} holds {} elements, not {}",
+                operand,
+                get_type_string(tensor_type),
+                get_type_string(T::TENSOR_TYPE)
+            );
+        }
+        let len = self.attr(operand).size as usize / size_of::<T>();
+        Ok((self.mem(operand).virt_addr as *mut T, len))
+    }
+
+    pub fn a_mut<T: TensorElement>(&mut self) -> Result<&mut [T]> {
+        let (ptr, len) = self.typed(MatmulOperand::A)?;
+        Ok(unsafe { std::slice::from_raw_parts_mut(ptr, len) })
+    }
+
+    pub fn b_mut<T: TensorElement>(&mut self) -> Result<&mut [T]> {
+        let (ptr, len) = self.typed(MatmulOperand::B)?;
+        Ok(unsafe { std::slice::from_raw_parts_mut(ptr, len) })
+    }
+
+    pub fn c<T: TensorElement>(&mut self) -> Result<&[T]> {
+        let (ptr, len) = self.typed(MatmulOperand::C)?;
+        Ok(unsafe { std::slice::from_raw_parts(ptr, len) })
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        call_rknn_api!(rknn_matmul_run(self.ctx))
+    }
+}
+
+// The context handle and the memory it owns are not tied to the thread that
+// created them, and every method calling into the runtime or touching the
+// memory takes `&mut self`, so moving the context to another thread is sound.
+// It isn't `Sync`.
+unsafe impl Send for MatmulContext {}
+
+impl Drop for MatmulContext {
+    fn drop(&mut self) {
+        unsafe {
+            for mem in self.mems.iter().filter(|m| !m.is_null()) {
+                rknn_destroy_mem(self.ctx, *mem);
+            }
+            rknn_matmul_destroy(self.ctx);
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::b_normal_to_native;
+
+    #[test]
+    fn native_layout_blocks_and_pads() {
+        // One 2x2 block holding B transposed.
+        assert_eq!(b_normal_to_native(&[1, 2, 3, 4], 2, 2, 2, 2), [1, 3, 2, 4]);
+
+        // K=3, N=5 with 2x2 blocks, b[k][n] = 10 * (k + 1) + n + 1:
+        //   11 12 13 14 15
+        //   21 22 23 24 25
+        //   31 32 33 34 35
+        // Blocks run along K within each group of 2 columns, each holding
+        // its columns one after the other, padded with zeros.
+        let b: Vec<i32> = (1..=3)
+            .flat_map(|k| (1..=5).map(move |n| 10 * k + n))
+            .collect();
+        #[rustfmt::skip]
+        let expected = [
+            11, 21, 12, 22, /**/ 31, 0, 32, 0,
+            13, 23, 14, 24, /**/ 33, 0, 34, 0,
+            15, 25, 0, 0, /**/ 35, 0, 0, 0,
+        ];
+        assert_eq!(b_normal_to_native(&b, 3, 5, 2, 2), expected);
+
+        // Blocks of 1 x 4: a column per block, K padded to 4.
+        #[rustfmt::skip]
+        let expected = [
+            11, 21, 31, 0, /**/ 12, 22, 32, 0, /**/ 13, 23, 33, 0,
+            14, 24, 34, 0, /**/ 15, 25, 35, 0,
+        ];
+        assert_eq!(b_normal_to_native(&b, 3, 5, 1, 4), expected);
+    }
+}
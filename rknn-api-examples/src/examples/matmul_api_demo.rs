@@ -0,0 +1,152 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use half::f16;
+
+use crate::{
+    examples::{
+        common::RknnCoreMask,
+        matmul::{AcLayout, BLayout, MatmulContext, MatmulInfo, MatmulOperand, MatmulType},
+        utils::{DumpStats, DumpVals},
+    },
+    time_bench,
+};
+
+/// Multiply two generated matrices on the NPU and check C against the CPU.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The number of rows of A and C
+    #[arg(short = 'M', default_value_t = 4)]
+    m: i32,
+
+    /// The number of columns of A and rows of B
+    #[arg(short = 'K', default_value_t = 64)]
+    k: i32,
+
+    /// The number of columns of B and C
+    #[arg(short = 'N', default_value_t = 32)]
+    n: i32,
+
+    /// Run with a dynamic shape context, switching between these M (e.g. 1,2,4)
+    #[arg(long, value_delimiter = ',')]
+    dyn_m: Vec<i32>,
+
+    #[arg(short = 't', long, value_enum, default_value_t = MatmulType::Fp16MmFp16ToFp32)]
+    matmul_type: MatmulType,
+
+    #[arg(long, value_enum, default_value_t = BLayout::Normal)]
+    b_layout: BLayout,
+
+    /// The number of loops
+    #[arg(short, long, default_value_t = 1)]
+    loop_count: u8,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+}
+
+/// Small deterministic values, exactly representable as f16 and i8.
+fn sample(i: usize) -> i8 {
+    (i * 7 % 17) as i8 - 8
+}
+
+impl Example {
+    pub fn execute(&self) -> Result<()> {
+        let info = MatmulInfo {
+            b_layout: self.b_layout,
+            ac_layout: AcLayout::Normal,
+            ..MatmulInfo::new(self.m, self.k, self.n, self.matmul_type)
+        };
+        let mut matmul = if self.dyn_m.is_empty() {
+            MatmulContext::new(&info)?
+        } else {
+            MatmulContext::new_dyn_shape(&info, &self.dyn_m)?
+        };
+        matmul.set_core_mask(&self.core_mask)?;
+
+        let (k, n) = (self.k as usize, self.n as usize);
+        let b: Vec<i8> = (0..k * n).map(|i| sample(i + 3)).collect();
+        self.fill_b(&mut matmul, &b)?;
+
+        let ms = if self.dyn_m.is_empty() {
+            vec![self.m]
+        } else {
+            self.dyn_m.clone()
+        };
+        for m in ms {
+            if !self.dyn_m.is_empty() {
+                println!("\x1b[34;4m setting M={}\x1b[0m", m);
+                matmul.set_m(m)?;
+            }
+            self.run_and_check(&mut matmul, &b)?;
+        }
+        Ok(())
+    }
+
+    fn fill_b(&self, matmul: &mut MatmulContext, b: &[i8]) -> Result<()> {
+        match (self.matmul_type, self.b_layout) {
+            (MatmulType::Fp16MmFp16ToFp32 | MatmulType::Fp16MmFp16ToFp16, layout) => {
+                let b: Vec<f16> = b.iter().map(|v| f16::from_f32(*v as f32)).collect();
+                if layout == BLayout::Native {
+                    matmul.set_b_from_normal(&b)?;
+                } else {
+                    matmul.b_mut::<f16>()?[..b.len()].copy_from_slice(&b);
+                }
+            }
+            (MatmulType::Int8MmInt8ToInt32, BLayout::Native) => matmul.set_b_from_normal(b)?,
+            (MatmulType::Int8MmInt8ToInt32, _) => {
+                matmul.b_mut::<i8>()?[..b.len()].copy_from_slice(b)
+            }
+            (other, _) => bail!("the demo does not generate inputs for {:?}", other),
+        }
+        Ok(())
+    }
+
+    fn run_and_check(&self, matmul: &mut MatmulContext, b: &[i8]) -> Result<()> {
+        println!("\x1b[34;4m matmul tensors:\x1b[0m");
+        for operand in [MatmulOperand::A, MatmulOperand::B, MatmulOperand::C] {
+            println!("{}", matmul.attr(operand).dump()?);
+        }
+
+        let (m, k, n) = (matmul.m() as usize, self.k as usize, self.n as usize);
+        let a: Vec<i8> = (0..m * k).map(sample).collect();
+        // B is generated row major K x N; a transposed B reads the same
+        // values as N x K.
+        let b_at = |row: usize, col: usize| match self.b_layout {
+            BLayout::Transposed => b[col * k + row],
+            _ => b[row * n + col],
+        };
+        let expected: Vec<f64> = (0..m * n)
+            .map(|i| {
+                let (row, col) = (i / n, i % n);
+                (0..k)
+                    .map(|j| a[row * k + j] as f64 * b_at(j, col) as f64)
+                    .sum()
+            })
+            .collect();
+
+        if self.matmul_type == MatmulType::Int8MmInt8ToInt32 {
+            matmul.a_mut::<i8>()?[..a.len()].copy_from_slice(&a);
+        } else {
+            for (d, s) in matmul.a_mut::<f16>()?.iter_mut().zip(&a) {
+                *d = f16::from_f32(*s as f32);
+            }
+        }
+
+        time_bench!(self.loop_count, {
+            matmul.run()?;
+        });
+
+        let actual: Vec<f64> = match self.matmul_type {
+            MatmulType::Fp16MmFp16ToFp32 => matmul.c::<f32>()?.iter().map(|v| *v as f64).collect(),
+            MatmulType::Fp16MmFp16ToFp16 => matmul.c::<f16>()?.iter().map(|v| v.to_f64()).collect(),
+            _ => matmul.c::<i32>()?.iter().map(|v| *v as f64).collect(),
+        };
+        let max_error = expected
+            .iter()
+            .zip(&actual)
+            .map(|(e, a)| (e - a).abs())
+            .fold(0.0, f64::max);
+        println!("\x1b[34;4m max abs error vs CPU: {:.4}\x1b[0m", max_error);
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use crate::examples::classifier::top_k;
+
+/// How closely an output matches its reference.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub cosine: f64,
+    pub max_abs_error: f64,
+    pub mean_abs_error: f64,
+    /// The share of the reference's top-k classes also in the output's
+    /// top-k, averaged over the rows of the last axis.
+    pub top_k_agreement: f64,
+}
+
+impl Comparison {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "cosine": self.cosine,
+            "max_abs_error": self.max_abs_error,
+            "mean_abs_error": self.mean_abs_error,
+            "top_k_agreement": self.top_k_agreement,
+        })
+    }
+}
+
+/// Compares `actual` with `reference`, both row major with rows of `row_len`
+/// values for the top-k agreement.
+pub fn compare(actual: &[f32], reference: &[f32], row_len: usize, k: usize) -> Result<Comparison> {
+    if actual.len() != reference.len() {
+        bail!(
+            "{} values to compare with {} reference values",
+            actual.len(),
+            reference.len()
+        );
+    }
+    if actual.is_empty() {
+        bail!("nothing to compare");
+    }
+    let (mut dot, mut norm_a, mut norm_r) = (0f64, 0f64, 0f64);
+    let (mut max_abs, mut sum_abs) = (0f64, 0f64);
+    for (a, r) in actual.iter().zip(reference) {
+        let (a, r) = (*a as f64, *r as f64);
+        dot += a * r;
+        norm_a += a * a;
+        norm_r += r * r;
+        let err = (a - r).abs();
+        max_abs = max_abs.max(err);
+        sum_abs += err;
+    }
+    let cosine = if norm_a == 0.0 && norm_r == 0.0 {
+        1.0
+    } else if norm_a == 0.0 || norm_r == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a.sqrt() * norm_r.sqrt())
+    };
+
+    let row_len = if row_len == 0 || !actual.len().is_multiple_of(row_len) {
+        actual.len()
+    } else {
+        row_len
+    };
+    let k = k.clamp(1, row_len);
+    let agreement: f64 = actual
+        .chunks(row_len)
+        .zip(reference.chunks(row_len))
+        .map(|(a, r)| {
+            let a: Vec<usize> = top_k(a, k).into_iter().map(|(i, _)| i).collect();
+            let shared = top_k(r, k).iter().filter(|(i, _)| a.contains(i)).count();
+            shared as f64 / k as f64
+        })
+        .sum();
+
+    Ok(Comparison {
+        cosine,
+        max_abs_error: max_abs,
+        mean_abs_error: sum_abs / actual.len() as f64,
+        top_k_agreement: agreement / (actual.len() / row_len) as f64,
+    })
+}
+
+/// Transposes a `[n, h, w, c]` tensor to `[n, c, h, w]`.
+pub fn nhwc_to_nchw(values: &[f32], [n, h, w, c]: [usize; 4]) -> Vec<f32> {
+    let mut out = vec![0f32; values.len()];
+    for b in 0..n {
+        for y in 0..h {
+            for x in 0..w {
+                for ch in 0..c {
+                    out[((b * c + ch) * h + y) * w + x] = values[((b * h + y) * w + x) * c + ch];
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Counts of true class (rows) against predicted class (columns), with a
+/// last column for predictions outside the known classes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfusionMatrix {
+    n_classes: usize,
+    counts: Vec<usize>,
+}
+
+impl ConfusionMatrix {
+    pub fn new(n_classes: usize) -> Self {
+        ConfusionMatrix {
+            n_classes,
+            counts: vec![0; n_classes * (n_classes + 1)],
+        }
+    }
+
+    pub fn add(&mut self, truth: usize, predicted: Option<usize>) {
+        let column = predicted
+            .filter(|p| *p < self.n_classes)
+            .unwrap_or(self.n_classes);
+        self.counts[truth * (self.n_classes + 1) + column] += 1;
+    }
+
+    /// The row of `truth`, the last value counting unknown predictions.
+    pub fn row(&self, truth: usize) -> &[usize] {
+        let width = self.n_classes + 1;
+        &self.counts[truth * width..(truth + 1) * width]
+    }
+
+    /// The share of each class's samples predicted as that class, `None`
+    /// for classes without samples.
+    pub fn recall(&self) -> Vec<Option<f64>> {
+        (0..self.n_classes)
+            .map(|c| {
+                let row = self.row(c);
+                let total: usize = row.iter().sum();
+                (total > 0).then(|| row[c] as f64 / total as f64)
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self, classes: &[String]) -> Value {
+        json!({
+            "classes": classes,
+            "rows": (0..self.n_classes).map(|c| self.row(c)).collect::<Vec<_>>(),
+        })
+    }
+
+    /// A CSV table with a header of predicted classes and a row per class.
+    pub fn to_csv(&self, classes: &[String]) -> String {
+        let field = |s: &str| {
+            if s.contains([',', '"', '\n']) {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            } else {
+                s.to_string()
+            }
+        };
+        let mut csv = String::from("true\\predicted");
+        for class in classes {
+            csv.push(',');
+            csv.push_str(&field(class));
+        }
+        csv.push_str(",other\n");
+        for (c, class) in classes.iter().enumerate() {
+            csv.push_str(&field(class));
+            for count in self.row(c) {
+                csv.push_str(&format!(",{}", count));
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_outputs() {
+        let same = compare(&[1.0, 2.0, 3.0], &[1.0, 2.0, 3.0], 3, 1).unwrap();
+        assert_eq!(same.cosine, 1.0);
+        assert_eq!((same.max_abs_error, same.mean_abs_error), (0.0, 0.0));
+
+        let c = compare(&[1.0, 0.0], &[0.0, 1.0], 2, 1).unwrap();
+        assert_eq!(c.cosine, 0.0);
+        assert_eq!((c.max_abs_error, c.mean_abs_error), (1.0, 1.0));
+        assert_eq!(c.top_k_agreement, 0.0);
+        let opposite = compare(&[-2.0, 4.0], &[1.0, -2.0], 2, 1).unwrap();
+        assert!((opposite.cosine + 1.0).abs() < 1e-12);
+
+        // Zero vectors: two of them agree, one doesn't point anywhere.
+        assert_eq!(compare(&[0.0; 3], &[0.0; 3], 3, 1).unwrap().cosine, 1.0);
+        assert_eq!(compare(&[0.0; 3], &[1.0; 3], 3, 1).unwrap().cosine, 0.0);
+
+        assert!(compare(&[1.0], &[1.0, 2.0], 1, 1).is_err());
+        assert!(compare(&[], &[], 1, 1).is_err());
+    }
+
+    #[test]
+    fn averages_top_k_agreement_over_rows() {
+        let actual = [0.9, 0.1, 0.0, /**/ 0.1, 0.2, 0.7];
+        let reference = [0.8, 0.0, 0.2, /**/ 0.1, 0.8, 0.1];
+        // Row 0 agrees on its top class, row 1 doesn't.
+        assert_eq!(
+            compare(&actual, &reference, 3, 1).unwrap().top_k_agreement,
+            0.5
+        );
+        // Top 2: {0, 1} vs {0, 2} and {2, 1} vs {1, 0}.
+        assert_eq!(
+            compare(&actual, &reference, 3, 2).unwrap().top_k_agreement,
+            0.5
+        );
+        // k is clamped to the row.
+        assert_eq!(
+            compare(&actual, &reference, 3, 9).unwrap().top_k_agreement,
+            1.0
+        );
+        // Rows that don't divide the values compare as one row: the top 1 of
+        // both is 0.9 and 0.8 at index 0.
+        assert_eq!(
+            compare(&actual, &reference, 4, 1).unwrap().top_k_agreement,
+            1.0
+        );
+        assert_eq!(
+            compare(&actual, &reference, 0, 1).unwrap().top_k_agreement,
+            1.0
+        );
+    }
+
+    #[test]
+    fn transposes_nhwc() {
+        // [1, 2, 2, 3] with value = 100 * y + 10 * x + c.
+        let nhwc: Vec<f32> = (0..2)
+            .flat_map(|y| {
+                (0..2).flat_map(move |x| (0..3).map(move |c| (100 * y + 10 * x + c) as f32))
+            })
+            .collect();
+        let nchw = nhwc_to_nchw(&nhwc, [1, 2, 2, 3]);
+        assert_eq!(
+            nchw,
+            [0.0, 10.0, 100.0, 110.0, 1.0, 11.0, 101.0, 111.0, 2.0, 12.0, 102.0, 112.0]
+        );
+        // Batches stay apart.
+        let two = [nhwc.clone(), nhwc.iter().map(|v| v + 1000.0).collect()].concat();
+        let out = nhwc_to_nchw(&two, [2, 2, 2, 3]);
+        assert_eq!(out[..12], nchw[..]);
+        assert_eq!(out[12], 1000.0);
+    }
+
+    #[test]
+    fn counts_confusions() {
+        let mut matrix = ConfusionMatrix::new(2);
+        matrix.add(0, Some(0));
+        matrix.add(0, Some(1));
+        matrix.add(0, Some(0));
+        matrix.add(1, Some(7));
+        matrix.add(1, None);
+        assert_eq!(matrix.row(0), [2, 1, 0]);
+        assert_eq!(matrix.row(1), [0, 0, 2]);
+        assert_eq!(matrix.recall(), [Some(2.0 / 3.0), Some(0.0)]);
+        assert_eq!(ConfusionMatrix::new(1).recall(), [None]);
+
+        let classes = ["cat".to_string(), "big, \"dog\"".to_string()];
+        assert_eq!(
+            matrix.to_csv(&classes),
+            "true\\predicted,cat,\"big, \"\"dog\"\"\",other\n\
+             cat,2,1,0\n\
+             \"big, \"\"dog\"\"\",0,0,2\n"
+        );
+        assert_eq!(
+            matrix.to_json(&classes)["rows"],
+            json!([[2, 1, 0], [0, 0, 2]])
+        );
+    }
+}
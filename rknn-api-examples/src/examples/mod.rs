@@ -1,3 +1,40 @@
-mod common;
+pub mod bench;
+pub mod candle_io;
+pub mod candle_matmul;
+pub mod classifier;
+pub mod classify;
+pub mod coco;
+pub mod common;
+pub mod compare;
+pub mod custom_op;
+pub mod dataset;
+pub mod detect;
+pub mod draw;
 pub mod dynshape_inference;
-mod utils;
+pub mod eval;
+pub mod eval_detect;
+pub mod frames;
+pub mod infer;
+pub mod info;
+pub mod instance_seg;
+pub mod latency;
+pub mod matmul;
+pub mod matmul_api_demo;
+pub mod metrics;
+pub mod npy;
+pub mod ocr;
+pub mod ocr_demo;
+pub mod platform;
+pub mod pool;
+pub mod pose;
+pub mod preprocess;
+pub mod report;
+pub mod segment;
+pub mod semseg;
+pub mod serve;
+pub mod throughput;
+pub mod trace;
+pub mod utils;
+pub mod video;
+pub mod yolo;
+pub mod yuv;
@@ -0,0 +1,567 @@
+use anyhow::{bail, Context, Result};
+use half::f16;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+use rknn_api_sys::rknn_tensor_type;
+
+use crate::examples::{
+    common::{OutputData, OutputView, TensorInput},
+    utils::get_type_string,
+};
+
+/// The element types the codec handles.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DType {
+    F32,
+    F16,
+    I8,
+    U8,
+    I32,
+    I64,
+}
+
+impl DType {
+    pub fn size(self) -> usize {
+        match self {
+            DType::I8 | DType::U8 => 1,
+            DType::F16 => 2,
+            DType::F32 | DType::I32 => 4,
+            DType::I64 => 8,
+        }
+    }
+
+    /// The little endian `descr` of the array header.
+    pub fn descr(self) -> &'static str {
+        match self {
+            DType::F32 => "<f4",
+            DType::F16 => "<f2",
+            DType::I8 => "|i1",
+            DType::U8 => "|u1",
+            DType::I32 => "<i4",
+            DType::I64 => "<i8",
+        }
+    }
+
+    /// Parses a header `descr`. Single byte types may carry any byte order
+    /// mark, wider ones must be little endian.
+    pub fn from_descr(descr: &str) -> Result<Self> {
+        let (order, kind) = descr.split_at(descr.len().min(1));
+        let dtype = match kind {
+            "f4" => DType::F32,
+            "f2" => DType::F16,
+            "i1" => DType::I8,
+            "u1" | "b1" => DType::U8,
+            "i4" => DType::I32,
+            "i8" => DType::I64,
+            _ => bail!("unsupported dtype '{}'", descr),
+        };
+        if dtype.size() > 1 && order == ">" {
+            bail!("big endian dtype '{}' is not supported", descr);
+        }
+        Ok(dtype)
+    }
+
+    pub fn tensor_type(self) -> rknn_tensor_type {
+        match self {
+            DType::F32 => rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32,
+            DType::F16 => rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT16,
+            DType::I8 => rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT8,
+            DType::U8 => rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8,
+            DType::I32 => rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT32,
+            DType::I64 => rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT64,
+        }
+    }
+
+    pub fn from_tensor_type(tensor_type: rknn_tensor_type) -> Result<Self> {
+        Ok(match tensor_type {
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32 => DType::F32,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT16 => DType::F16,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT8 => DType::I8,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8 => DType::U8,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT32 => DType::I32,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT64 => DType::I64,
+            other => bail!("no npy dtype for {}", get_type_string(other)),
+        })
+    }
+}
+
+/// Element types with a NumPy dtype, stored little endian.
+pub trait NpyElement: Copy {
+    const DTYPE: DType;
+
+    fn write_le<W: Write>(&self, w: &mut W) -> std::io::Result<()>;
+
+    /// Reads one element from `DTYPE.size()` bytes.
+    fn from_le(bytes: &[u8]) -> Self;
+}
+
+macro_rules! npy_element {
+    ($t:ty, $dtype:expr) => {
+        impl NpyElement for $t {
+            const DTYPE: DType = $dtype;
+
+            fn write_le<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+                w.write_all(&self.to_le_bytes())
+            }
+
+            fn from_le(bytes: &[u8]) -> Self {
+                <$t>::from_le_bytes(bytes.try_into().unwrap())
+            }
+        }
+    };
+}
+
+npy_element!(f32, DType::F32);
+npy_element!(f16, DType::F16);
+npy_element!(i8, DType::I8);
+npy_element!(u8, DType::U8);
+npy_element!(i32, DType::I32);
+npy_element!(i64, DType::I64);
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+fn write_header<W: Write>(w: &mut W, dtype: DType, shape: &[usize]) -> Result<()> {
+    let shape = match shape {
+        [d] => format!("({},)", d),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        dtype.descr(),
+        shape
+    );
+    // Magic, version and length take 10 bytes; the header ends with a
+    // newline and pads the total to a multiple of 64.
+    let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.extend(std::iter::repeat_n(' ', padding));
+    header.push('\n');
+
+    w.write_all(MAGIC)?;
+    w.write_all(&[1, 0])?;
+    w.write_all(&(header.len() as u16).to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+    Ok(())
+}
+
+/// The value of `key` in the header dict, up to the next top level comma.
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str> {
+    let pattern = format!("'{}':", key);
+    let start = header
+        .find(&pattern)
+        .with_context(|| format!("no '{}' in npy header {}", key, header))?
+        + pattern.len();
+    let rest = header[start..].trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|i| i + 1)
+    } else {
+        rest.find([',', '}'])
+    }
+    .with_context(|| format!("malformed npy header {}", header))?;
+    Ok(rest[..end].trim())
+}
+
+/// The number of elements of `shape`, failing instead of overflowing on
+/// shapes read from untrusted headers.
+pub fn element_count(shape: &[usize]) -> Result<usize> {
+    shape
+        .iter()
+        .try_fold(1usize, |n, d| n.checked_mul(*d))
+        .with_context(|| format!("shape {:?} is too large", shape))
+}
+
+/// Reads the magic, version and header of an `.npy` stream, leaving the
+/// reader at the start of the data.
+pub fn read_header<R: Read>(r: &mut R) -> Result<(DType, Vec<usize>)> {
+    let mut preamble = [0u8; 8];
+    r.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        bail!("not an npy file");
+    }
+    let len = match preamble[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            r.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            r.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        v => bail!("unsupported npy version {}", v),
+    };
+    let mut header = vec![0u8; len];
+    r.read_exact(&mut header)?;
+    let header = String::from_utf8(header)?;
+
+    let dtype = DType::from_descr(header_value(&header, "descr")?.trim_matches('\''))?;
+    if header_value(&header, "fortran_order")? != "False" {
+        bail!("Fortran order arrays are not supported");
+    }
+    let shape = header_value(&header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| {
+            d.parse::<usize>()
+                .with_context(|| format!("bad dim '{}'", d))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((dtype, shape))
+}
+
+/// Writes a C order array in the `.npy` format (version 1.0).
+pub fn write_npy<W: Write, T: NpyElement>(w: &mut W, shape: &[usize], data: &[T]) -> Result<()> {
+    write_header(w, T::DTYPE, shape)?;
+    for value in data {
+        value.write_le(w)?;
+    }
+    Ok(())
+}
+
+/// A C order array with its elements as little endian bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NpyArray {
+    pub dtype: DType,
+    pub shape: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+impl NpyArray {
+    pub fn new<T: NpyElement>(shape: &[usize], values: &[T]) -> Result<Self> {
+        if element_count(shape)? != values.len() {
+            bail!("{} values for shape {:?}", values.len(), shape);
+        }
+        let mut data = Vec::with_capacity(values.len() * T::DTYPE.size());
+        for value in values {
+            value.write_le(&mut data)?;
+        }
+        Ok(NpyArray {
+            dtype: T::DTYPE,
+            shape: shape.to_vec(),
+            data,
+        })
+    }
+
+    /// The number of elements.
+    pub fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn to_vec<T: NpyElement>(&self) -> Result<Vec<T>> {
+        if T::DTYPE != self.dtype {
+            bail!("the array is {:?}, not {:?}", self.dtype, T::DTYPE);
+        }
+        Ok(self
+            .data
+            .chunks_exact(self.dtype.size())
+            .map(T::from_le)
+            .collect())
+    }
+
+    /// The elements converted to f32, whatever the dtype.
+    pub fn to_f32_vec(&self) -> Vec<f32> {
+        let bytes = self.data.chunks_exact(self.dtype.size());
+        match self.dtype {
+            DType::F32 => bytes.map(<f32 as NpyElement>::from_le).collect(),
+            DType::F16 => bytes.map(|b| f16::from_le(b).to_f32()).collect(),
+            DType::I8 => bytes
+                .map(|b| <i8 as NpyElement>::from_le(b) as f32)
+                .collect(),
+            DType::U8 => bytes
+                .map(|b| <u8 as NpyElement>::from_le(b) as f32)
+                .collect(),
+            DType::I32 => bytes
+                .map(|b| <i32 as NpyElement>::from_le(b) as f32)
+                .collect(),
+            DType::I64 => bytes
+                .map(|b| <i64 as NpyElement>::from_le(b) as f32)
+                .collect(),
+        }
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let (dtype, shape) = read_header(r)?;
+        NpyArray::read_data(r, dtype, shape)
+    }
+
+    /// Reads the data following a header read with [`read_header`]. The
+    /// buffer grows with the data that actually arrives, so a header claiming
+    /// more than the stream holds fails rather than allocating it up front.
+    pub fn read_data<R: Read>(r: &mut R, dtype: DType, shape: Vec<usize>) -> Result<Self> {
+        let len = element_count(&shape)?
+            .checked_mul(dtype.size())
+            .with_context(|| format!("shape {:?} is too large", shape))?;
+        let mut data = Vec::new();
+        r.take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len {
+            bail!("truncated npy data, {} of {} bytes", data.len(), len);
+        }
+        Ok(NpyArray { dtype, shape, data })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_header(w, self.dtype, &self.shape)?;
+        w.write_all(&self.data)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut r = BufReader::new(
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?,
+        );
+        NpyArray::read(&mut r).with_context(|| format!("failed to read {}", path.display()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// The array as a model input of the same shape and type. The input
+    /// attrs are checked when it is set.
+    pub fn into_input(self) -> TensorInput {
+        TensorInput {
+            type_: self.dtype.tensor_type(),
+            dims: self.shape,
+            data: self.data,
+        }
+    }
+}
+
+/// Reads the arrays of an `.npz` archive (stored or deflated), in archive
+/// order, named without their `.npy` extension.
+pub fn read_npz<R: Read + Seek>(r: R) -> Result<Vec<(String, NpyArray)>> {
+    let mut archive = zip::ZipArchive::new(r)?;
+    let mut arrays = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let name = entry.name().trim_end_matches(".npy").to_string();
+        let array = NpyArray::read(&mut entry).with_context(|| format!("array '{}'", name))?;
+        arrays.push((name, array));
+    }
+    Ok(arrays)
+}
+
+pub fn load_npz<P: AsRef<Path>>(path: P) -> Result<Vec<(String, NpyArray)>> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    read_npz(BufReader::new(file)).with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Writes an uncompressed archive, like `numpy.savez`.
+pub fn write_npz<W: Write + Seek>(w: W, arrays: &[(String, NpyArray)]) -> Result<()> {
+    let mut archive = zip::ZipWriter::new(w);
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for (name, array) in arrays {
+        archive.start_file(format!("{}.npy", name), options)?;
+        array.write(&mut archive)?;
+    }
+    archive.finish()?;
+    Ok(())
+}
+
+pub fn save_npz<P: AsRef<Path>>(path: P, arrays: &[(String, NpyArray)]) -> Result<()> {
+    write_npz(BufWriter::new(File::create(path)?), arrays)
+}
+
+/// Loads model inputs from `.npy` files, one input each, or `.npz` archives,
+/// one input per array. When the arrays are named after the model's inputs
+/// they are reordered to match, otherwise they are taken in order.
+pub fn load_inputs(paths: &[String], input_names: &[String]) -> Result<Vec<TensorInput>> {
+    let mut arrays = Vec::new();
+    for path in paths {
+        if path.ends_with(".npz") {
+            arrays.extend(load_npz(path)?);
+        } else {
+            arrays.push((String::new(), NpyArray::load(path)?));
+        }
+    }
+    let named = input_names
+        .iter()
+        .all(|name| arrays.iter().any(|(n, _)| n == name));
+    if named && arrays.len() == input_names.len() {
+        arrays.sort_by_key(|(n, _)| input_names.iter().position(|name| name == n));
+    }
+    Ok(arrays.into_iter().map(|(_, a)| a.into_input()).collect())
+}
+
+/// Whether `path` names a NumPy file rather than an image.
+pub fn is_npy_path(path: &str) -> bool {
+    path.ends_with(".npy") || path.ends_with(".npz")
+}
+
+/// Saves an output in its own element type and shape.
+pub fn save_output<P: AsRef<Path>>(path: P, output: &OutputView) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    let shape = output.dims();
+    let n: usize = shape.iter().product();
+    if output.len() < n {
+        bail!("{} values for an output of shape {:?}", output.len(), shape);
+    }
+    // The runtime's buffer may be padded past the last element.
+    match output.data {
+        OutputData::F32(data) => write_npy(&mut w, &shape, &data[..n])?,
+        OutputData::F16(data) => write_npy(&mut w, &shape, &data[..n])?,
+        OutputData::I8(data) => write_npy(&mut w, &shape, &data[..n])?,
+        OutputData::U8(data) => write_npy(&mut w, &shape, &data[..n])?,
+        OutputData::I32(data) => write_npy(&mut w, &shape, &data[..n])?,
+        OutputData::I64(data) => write_npy(&mut w, &shape, &data[..n])?,
+    }
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip<T: NpyElement + PartialEq + std::fmt::Debug>(shape: &[usize], values: &[T]) {
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, shape, values).unwrap();
+
+        let array = NpyArray::read(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(array.dtype, T::DTYPE);
+        assert_eq!(array.shape, shape);
+        assert_eq!(array.to_vec::<T>().unwrap(), values);
+
+        let mut rewritten = Vec::new();
+        array.write(&mut rewritten).unwrap();
+        assert_eq!(rewritten, bytes);
+    }
+
+    #[test]
+    fn round_trips_every_dtype() {
+        round_trip(&[2, 3], &[1.5f32, -2.0, 0.0, 3.25, f32::MAX, f32::MIN]);
+        round_trip(&[3], &[f16::from_f32(0.5), f16::ONE, f16::NEG_ONE]);
+        round_trip(&[1, 2, 2], &[-128i8, -1, 0, 127]);
+        round_trip(&[4, 1], &[0u8, 1, 128, 255]);
+        round_trip(&[2], &[i32::MIN, i32::MAX]);
+        round_trip(&[1, 1, 2], &[i64::MIN, 42]);
+    }
+
+    #[test]
+    fn handles_scalars_and_empty_arrays() {
+        round_trip::<f32>(&[], &[7.0]);
+        round_trip::<u8>(&[0, 3], &[]);
+    }
+
+    #[test]
+    fn writes_numpy_headers() {
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[5], &[0i32; 5]).unwrap();
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '<i4', 'fortran_order': False, 'shape': (5,), }"));
+        assert!(header.ends_with('\n'));
+        assert_eq!((10 + header_len) % 64, 0);
+    }
+
+    #[test]
+    fn reads_headers_numpy_writes_differently() {
+        let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (2,3), }";
+        let mut bytes = b"\x93NUMPY\x02\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend(std::iter::repeat_n(0u8, 24));
+        let array = NpyArray::read(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(array.shape, [2, 3]);
+        assert_eq!(array.to_vec::<f32>().unwrap(), [0.0; 6]);
+    }
+
+    #[test]
+    fn rejects_unsupported_arrays() {
+        for header in [
+            "{'descr': '<f4', 'fortran_order': True, 'shape': (2,), }",
+            "{'descr': '>f4', 'fortran_order': False, 'shape': (2,), }",
+            "{'descr': '<c8', 'fortran_order': False, 'shape': (2,), }",
+        ] {
+            let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+            bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(header.as_bytes());
+            bytes.extend(std::iter::repeat_n(0u8, 16));
+            assert!(
+                NpyArray::read(&mut Cursor::new(&bytes)).is_err(),
+                "{}",
+                header
+            );
+        }
+        let mut truncated = Vec::new();
+        write_npy(&mut truncated, &[4], &[1.0f32; 4]).unwrap();
+        truncated.pop();
+        assert!(NpyArray::read(&mut Cursor::new(&truncated)).is_err());
+    }
+
+    #[test]
+    fn rejects_huge_shapes_without_allocating() {
+        // A few bytes claiming terabytes, or more than usize holds.
+        for shape in ["(1099511627776,)", "(4294967296, 4294967296, 16)"] {
+            let header = format!(
+                "{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}",
+                shape
+            );
+            let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+            bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(header.as_bytes());
+            bytes.extend_from_slice(&[0; 8]);
+            assert!(
+                NpyArray::read(&mut Cursor::new(&bytes)).is_err(),
+                "{}",
+                shape
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_npz_archives() {
+        let arrays = vec![
+            (
+                "images".to_string(),
+                NpyArray::new(&[1, 2], &[0.5f32, 1.5]).unwrap(),
+            ),
+            (
+                "mask".to_string(),
+                NpyArray::new(&[3], &[1u8, 0, 1]).unwrap(),
+            ),
+        ];
+        let mut bytes = Cursor::new(Vec::new());
+        write_npz(&mut bytes, &arrays).unwrap();
+        bytes.set_position(0);
+        assert_eq!(read_npz(bytes).unwrap(), arrays);
+    }
+
+    #[test]
+    fn converts_arrays_to_inputs() {
+        let input = NpyArray::new(&[1, 2, 2, 1], &[1i8, 2, 3, 4])
+            .unwrap()
+            .into_input();
+        assert_eq!(input.dims, [1, 2, 2, 1]);
+        assert_eq!(
+            input.type_,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_INT8
+        );
+        assert_eq!(input.data, [1, 2, 3, 4]);
+        assert!(NpyArray::new(&[3], &[1u8, 2]).is_err());
+    }
+}
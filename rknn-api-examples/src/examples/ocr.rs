@@ -0,0 +1,686 @@
+use anyhow::{bail, Context, Result};
+use image::{Rgb, RgbImage};
+use std::path::Path;
+
+use crate::examples::{
+    common::{OutputView, TensorInput},
+    preprocess::{input_geometry, ImageTransform, Preprocessor},
+};
+
+pub type Point = (f32, f32);
+
+/// A text box as its four corners: top left, top right, bottom right,
+/// bottom left.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RotatedBox {
+    pub points: [Point; 4],
+    /// Mean text probability over the detected region.
+    pub score: f32,
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+impl RotatedBox {
+    pub fn width(&self) -> f32 {
+        let p = &self.points;
+        distance(p[0], p[1]).max(distance(p[3], p[2]))
+    }
+
+    pub fn height(&self) -> f32 {
+        let p = &self.points;
+        distance(p[0], p[3]).max(distance(p[1], p[2]))
+    }
+
+    pub fn map(&self, f: impl Fn(Point) -> Point) -> RotatedBox {
+        RotatedBox {
+            points: self.points.map(f),
+            score: self.score,
+        }
+    }
+}
+
+/// DBNet post-processing: turns a text probability map into text boxes.
+#[derive(Clone, Debug)]
+pub struct DbPostProcess {
+    /// Pixels above this probability are text.
+    pub threshold: f32,
+    /// Regions with a lower mean probability are dropped.
+    pub box_threshold: f32,
+    /// How far the shrunk regions DBNet predicts are grown back, relative to
+    /// their area over perimeter.
+    pub unclip_ratio: f32,
+    /// Boxes with a shorter side are dropped.
+    pub min_size: f32,
+    pub max_candidates: usize,
+}
+
+impl Default for DbPostProcess {
+    fn default() -> Self {
+        DbPostProcess {
+            threshold: 0.3,
+            box_threshold: 0.6,
+            unclip_ratio: 1.5,
+            min_size: 3.0,
+            max_candidates: 1000,
+        }
+    }
+}
+
+/// Monotone chain convex hull, counter clockwise.
+fn convex_hull(mut points: Vec<Point>) -> Vec<Point> {
+    points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let cross =
+        |o: Point, a: Point, b: Point| (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0);
+    let mut hull: Vec<Point> = Vec::with_capacity(points.len() * 2);
+    for pass in 0..2 {
+        let start = hull.len();
+        let iter: Box<dyn Iterator<Item = &Point>> = if pass == 0 {
+            Box::new(points.iter())
+        } else {
+            Box::new(points.iter().rev())
+        };
+        for p in iter {
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], *p) <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(*p);
+        }
+        hull.pop();
+    }
+    hull
+}
+
+/// The smallest rectangle (any rotation) around `points`, as its center,
+/// unit axis, and half extents along the axis and its normal.
+fn min_area_rect(points: Vec<Point>) -> (Point, Point, f32, f32) {
+    let hull = convex_hull(points);
+    let mut best = ((0.0, 0.0), (1.0, 0.0), 0.0, 0.0);
+    let mut best_area = f32::INFINITY;
+    for i in 0..hull.len() {
+        let (a, b) = (hull[i], hull[(i + 1) % hull.len()]);
+        let len = distance(a, b);
+        let axis = if len > 0.0 {
+            ((b.0 - a.0) / len, (b.1 - a.1) / len)
+        } else {
+            (1.0, 0.0)
+        };
+        let normal = (-axis.1, axis.0);
+        let (mut min_u, mut max_u, mut min_v, mut max_v) = (
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+        );
+        for p in &hull {
+            let u = p.0 * axis.0 + p.1 * axis.1;
+            let v = p.0 * normal.0 + p.1 * normal.1;
+            (min_u, max_u, min_v, max_v) = (min_u.min(u), max_u.max(u), min_v.min(v), max_v.max(v));
+        }
+        let area = (max_u - min_u) * (max_v - min_v);
+        if area < best_area {
+            best_area = area;
+            let (cu, cv) = ((min_u + max_u) / 2.0, (min_v + max_v) / 2.0);
+            let center = (cu * axis.0 + cv * normal.0, cu * axis.1 + cv * normal.1);
+            best = (center, axis, (max_u - min_u) / 2.0, (max_v - min_v) / 2.0);
+        }
+    }
+    best
+}
+
+/// The corners of a rectangle, ordered top left, top right, bottom right,
+/// bottom left: clockwise by angle around the center, starting from the
+/// corner nearest the origin (the upper one of a rectangle at 45°).
+fn rect_corners(center: Point, axis: Point, half_u: f32, half_v: f32) -> [Point; 4] {
+    let normal = (-axis.1, axis.0);
+    let corner = |su: f32, sv: f32| {
+        (
+            center.0 + su * half_u * axis.0 + sv * half_v * normal.0,
+            center.1 + su * half_u * axis.1 + sv * half_v * normal.1,
+        )
+    };
+    let mut corners = [
+        corner(-1.0, -1.0),
+        corner(1.0, -1.0),
+        corner(1.0, 1.0),
+        corner(-1.0, 1.0),
+    ];
+    // With y pointing down, increasing angles run clockwise.
+    let angle = |p: &Point| (p.1 - center.1).atan2(p.0 - center.0);
+    corners.sort_by(|a, b| angle(a).total_cmp(&angle(b)));
+    let first = (0..4)
+        .min_by(|&i, &j| {
+            let (a, b) = (corners[i], corners[j]);
+            let (sa, sb) = (a.0 + a.1, b.0 + b.1);
+            if (sa - sb).abs() < 1e-3 {
+                a.1.total_cmp(&b.1)
+            } else {
+                sa.total_cmp(&sb)
+            }
+        })
+        .unwrap_or(0);
+    corners.rotate_left(first);
+    corners
+}
+
+impl DbPostProcess {
+    /// Finds the text regions of a `[1, 1, H, W]` probability map and
+    /// returns their boxes in map pixels.
+    pub fn boxes(&self, map: &OutputView) -> Result<Vec<RotatedBox>> {
+        let (channels, height, width) = map.chw();
+        if channels != 1 {
+            bail!(
+                "expected a one channel probability map, got {:?}",
+                map.dims()
+            );
+        }
+        let probs: Vec<f32> = (0..height * width)
+            .map(|i| map.at(0, i / width, i % width))
+            .collect();
+        let mut visited: Vec<bool> = probs.iter().map(|p| *p <= self.threshold).collect();
+
+        let mut boxes = Vec::new();
+        let mut stack = Vec::new();
+        for start in 0..probs.len() {
+            if visited[start] {
+                continue;
+            }
+            // Flood fill one 8-connected region.
+            visited[start] = true;
+            stack.push(start);
+            let mut points = Vec::new();
+            let mut score = 0.0;
+            while let Some(i) = stack.pop() {
+                let (x, y) = ((i % width) as i64, (i / width) as i64);
+                points.push((x as f32, y as f32));
+                score += probs[i];
+                for (dx, dy) in [
+                    (-1, -1),
+                    (0, -1),
+                    (1, -1),
+                    (-1, 0),
+                    (1, 0),
+                    (-1, 1),
+                    (0, 1),
+                    (1, 1),
+                ] {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    let n = ny as usize * width + nx as usize;
+                    if !visited[n] {
+                        visited[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+            let score = score / points.len() as f32;
+            if score < self.box_threshold || points.len() < 4 {
+                continue;
+            }
+
+            let (center, axis, half_u, half_v) = min_area_rect(points);
+            // Pixel centers were used, the region reaches half a pixel out.
+            let (w, h) = (2.0 * half_u + 1.0, 2.0 * half_v + 1.0);
+            if w.min(h) < self.min_size {
+                continue;
+            }
+            // Offsetting a rectangle's sides by d grows it by 2d each way.
+            let d = w * h * self.unclip_ratio / (2.0 * (w + h));
+            let (w, h) = (w + 2.0 * d, h + 2.0 * d);
+            if w.min(h) < self.min_size + 2.0 {
+                continue;
+            }
+            boxes.push(RotatedBox {
+                points: rect_corners(center, axis, w / 2.0, h / 2.0),
+                score,
+            });
+            if boxes.len() >= self.max_candidates {
+                break;
+            }
+        }
+        // Reading order: top to bottom, then left to right.
+        boxes.sort_by(|a, b| {
+            let (ay, by) = (a.points[0].1, b.points[0].1);
+            if (ay - by).abs() < 10.0 {
+                a.points[0].0.total_cmp(&b.points[0].0)
+            } else {
+                ay.total_cmp(&by)
+            }
+        });
+        Ok(boxes)
+    }
+
+    /// [`DbPostProcess::boxes`] mapped back to the source image, for a map
+    /// covering the model input of `input_size` (width, height).
+    pub fn source_boxes(
+        &self,
+        map: &OutputView,
+        (in_w, in_h): (u32, u32),
+        transform: &ImageTransform,
+    ) -> Result<Vec<RotatedBox>> {
+        let (_, height, width) = map.chw();
+        let (sx, sy) = (in_w as f32 / width as f32, in_h as f32 / height as f32);
+        let (src_w, src_h) = (
+            transform.source_width as f32,
+            transform.source_height as f32,
+        );
+        Ok(self
+            .boxes(map)?
+            .into_iter()
+            .map(|b| {
+                b.map(|(x, y)| {
+                    let (x, y) = transform.to_source(x * sx, y * sy);
+                    (x.clamp(0.0, src_w - 1.0), y.clamp(0.0, src_h - 1.0))
+                })
+            })
+            .collect())
+    }
+}
+
+/// Solves for the homography taking `from[i]` to `to[i]`.
+fn homography(from: [Point; 4], to: [Point; 4]) -> Option<[f32; 9]> {
+    // h33 = 1, two equations per correspondence.
+    let mut a = [[0f64; 9]; 8];
+    for i in 0..4 {
+        let ((x, y), (u, v)) = (
+            (from[i].0 as f64, from[i].1 as f64),
+            (to[i].0 as f64, to[i].1 as f64),
+        );
+        a[2 * i] = [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, u];
+        a[2 * i + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, v];
+    }
+    for col in 0..8 {
+        let pivot = (col..8).max_by(|r, s| a[*r][col].abs().total_cmp(&a[*s][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        let pivot_row = a[col];
+        for (r, row) in a.iter_mut().enumerate() {
+            if r != col {
+                let f = row[col] / pivot_row[col];
+                for (value, p) in row.iter_mut().zip(pivot_row).skip(col) {
+                    *value -= f * p;
+                }
+            }
+        }
+    }
+    let mut h = [1f32; 9];
+    for i in 0..8 {
+        h[i] = (a[i][8] / a[i][i]) as f32;
+    }
+    Some(h)
+}
+
+fn sample_bilinear(img: &RgbImage, x: f32, y: f32) -> Rgb<u8> {
+    let (w, h) = (img.width() as f32, img.height() as f32);
+    let x = x.clamp(0.0, w - 1.0);
+    let y = y.clamp(0.0, h - 1.0);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = (
+        (x0 + 1).min(img.width() - 1),
+        (y0 + 1).min(img.height() - 1),
+    );
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let mut out = [0u8; 3];
+    for (c, o) in out.iter_mut().enumerate() {
+        let v = img.get_pixel(x0, y0)[c] as f32 * (1.0 - fx) * (1.0 - fy)
+            + img.get_pixel(x1, y0)[c] as f32 * fx * (1.0 - fy)
+            + img.get_pixel(x0, y1)[c] as f32 * (1.0 - fx) * fy
+            + img.get_pixel(x1, y1)[c] as f32 * fx * fy;
+        *o = v.round() as u8;
+    }
+    Rgb(out)
+}
+
+/// Warps the quadrilateral `text_box` of `img` to an upright rectangle.
+/// Boxes much taller than wide are taken to be vertical text and rotated to
+/// read left to right.
+pub fn crop_box(img: &RgbImage, text_box: &RotatedBox) -> RgbImage {
+    let width = text_box.width().round().max(1.0) as u32;
+    let height = text_box.height().round().max(1.0) as u32;
+    let (w, h) = (width as f32, height as f32);
+    let rect = [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)];
+    let crop = match homography(rect, text_box.points) {
+        Some(m) => RgbImage::from_fn(width, height, |x, y| {
+            let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+            let z = m[6] * x + m[7] * y + m[8];
+            let sx = (m[0] * x + m[1] * y + m[2]) / z - 0.5;
+            let sy = (m[3] * x + m[4] * y + m[5]) / z - 0.5;
+            sample_bilinear(img, sx, sy)
+        }),
+        None => RgbImage::new(width, height),
+    };
+    if h / w >= 1.5 {
+        image::imageops::rotate270(&crop)
+    } else {
+        crop
+    }
+}
+
+/// Maps CTC output classes to text. Class 0 is the blank, class `i` the
+/// `i`th dictionary entry.
+#[derive(Clone, Debug)]
+pub struct CtcDecoder {
+    pub charset: Vec<String>,
+}
+
+/// Recognized text and its mean character probability.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recognition {
+    pub text: String,
+    pub score: f32,
+}
+
+impl CtcDecoder {
+    /// Loads a PaddleOCR style dictionary, one character per line, with a
+    /// space appended when `use_space` is set.
+    pub fn load<P: AsRef<Path>>(path: P, use_space: bool) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read dictionary {}", path.display()))?;
+        let mut charset: Vec<String> = text
+            .lines()
+            .map(|l| l.trim_end_matches('\r').to_string())
+            .collect();
+        if use_space {
+            charset.push(" ".to_string());
+        }
+        Ok(CtcDecoder { charset })
+    }
+
+    fn text(&self, classes: &[usize]) -> String {
+        classes
+            .iter()
+            .filter_map(|c| c.checked_sub(1).and_then(|i| self.charset.get(i)))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// The `[T, C]` probabilities of a `[1, T, C]` (or any shape with ones
+    /// around it) recognition output.
+    pub fn steps(output: &OutputView) -> Result<(usize, usize)> {
+        let dims: Vec<usize> = output.dims().into_iter().filter(|d| *d != 1).collect();
+        match dims[..] {
+            [steps, classes] => Ok((steps, classes)),
+            _ => bail!("expected a [1, T, C] output, got {:?}", output.dims()),
+        }
+    }
+
+    /// Best class per step, repeats merged and blanks dropped.
+    pub fn greedy(&self, output: &OutputView) -> Result<Recognition> {
+        let (steps, classes) = Self::steps(output)?;
+        let mut chars = Vec::new();
+        let mut probs = Vec::new();
+        let mut previous = 0;
+        for t in 0..steps {
+            let (best, prob) = (0..classes).map(|c| (c, output.get(t * classes + c))).fold(
+                (0, f32::NEG_INFINITY),
+                |b, cur| if cur.1 > b.1 { cur } else { b },
+            );
+            if best != 0 && best != previous {
+                chars.push(best);
+                probs.push(prob);
+            }
+            previous = best;
+        }
+        let score = if probs.is_empty() {
+            0.0
+        } else {
+            probs.iter().sum::<f32>() / probs.len() as f32
+        };
+        Ok(Recognition {
+            text: self.text(&chars),
+            score,
+        })
+    }
+
+    /// CTC prefix beam search keeping `beam_width` prefixes, each extended
+    /// with the `beam_width` most likely classes of every step. Expects
+    /// softmax probabilities.
+    pub fn beam_search(&self, output: &OutputView, beam_width: usize) -> Result<Recognition> {
+        let (steps, classes) = Self::steps(output)?;
+        let beam_width = beam_width.max(1);
+        // prefix -> (p ending in blank, p ending in its last class)
+        let mut beams: Vec<(Vec<usize>, f64, f64)> = vec![(Vec::new(), 1.0, 0.0)];
+        for t in 0..steps {
+            let probs: Vec<f64> = (0..classes)
+                .map(|c| output.get(t * classes + c) as f64)
+                .collect();
+            let mut candidates: Vec<usize> = (0..classes).collect();
+            candidates.sort_by(|a, b| probs[*b].total_cmp(&probs[*a]));
+            candidates.truncate(beam_width);
+
+            let mut next: std::collections::HashMap<Vec<usize>, (f64, f64)> =
+                std::collections::HashMap::new();
+            for (prefix, p_blank, p_last) in &beams {
+                let total = p_blank + p_last;
+                for &c in &candidates {
+                    let p = probs[c];
+                    if c == 0 {
+                        next.entry(prefix.clone()).or_default().0 += total * p;
+                        continue;
+                    }
+                    let last = prefix.last().copied();
+                    let mut extended = prefix.clone();
+                    extended.push(c);
+                    if last == Some(c) {
+                        // A repeat only extends after a blank.
+                        next.entry(extended).or_default().1 += p_blank * p;
+                        next.entry(prefix.clone()).or_default().1 += p_last * p;
+                    } else {
+                        next.entry(extended).or_default().1 += total * p;
+                    }
+                }
+            }
+            let mut next: Vec<(Vec<usize>, f64, f64)> =
+                next.into_iter().map(|(k, (b, l))| (k, b, l)).collect();
+            next.sort_by(|a, b| (b.1 + b.2).total_cmp(&(a.1 + a.2)));
+            next.truncate(beam_width);
+            beams = next;
+        }
+        let (prefix, p_blank, p_last) = beams.into_iter().next().unwrap_or_default();
+        let score = if prefix.is_empty() {
+            0.0
+        } else {
+            ((p_blank + p_last).powf(1.0 / steps as f64)) as f32
+        };
+        Ok(Recognition {
+            text: self.text(&prefix),
+            score,
+        })
+    }
+}
+
+/// Builds the recognition input from a text crop: resized to the input
+/// height keeping its aspect ratio (squeezed if too wide) and padded on the
+/// right with the pad color.
+pub fn recognition_input(
+    crop: &RgbImage,
+    attr: &rknn_api_sys::rknn_tensor_attr,
+    preprocess: &Preprocessor,
+) -> Result<TensorInput> {
+    let (height, width, channels, _) = input_geometry(attr)?;
+    let ratio = crop.width() as f32 / crop.height().max(1) as f32;
+    let resized_w = ((height as f32 * ratio).ceil() as u32).clamp(1, width);
+    let resized = image::imageops::resize(crop, resized_w, height, preprocess.resize_filter.into());
+    let mut canvas = RgbImage::from_pixel(width, height, preprocess.pad_rgb());
+    image::imageops::replace(&mut canvas, &resized, 0, 0);
+    preprocess.to_input(preprocess.pixels(&canvas, channels as usize)?, attr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rknn_api_sys::rknn_tensor_attr;
+
+    fn attr(dims: &[u32]) -> rknn_tensor_attr {
+        let mut attr = rknn_tensor_attr {
+            n_dims: dims.len() as u32,
+            n_elems: dims.iter().product(),
+            ..Default::default()
+        };
+        attr.dims[..dims.len()].copy_from_slice(dims);
+        attr
+    }
+
+    fn assert_points(actual: &[Point], expected: &[Point]) {
+        assert_eq!(
+            actual.len(),
+            expected.len(),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a.0 - e.0).abs() < 1e-3 && (a.1 - e.1).abs() < 1e-3,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn finds_hull_and_min_area_rect() {
+        let square = vec![
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (1.0, 1.0),
+            (2.0, 2.0),
+            (0.0, 2.0),
+            (0.0, 0.0),
+        ];
+        assert_points(
+            &convex_hull(square),
+            &[(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)],
+        );
+
+        // A 4x2 rectangle turned by 45° around (10, 10).
+        let (c, s) = (0.5f32.sqrt(), 0.5f32.sqrt());
+        let points: Vec<Point> = [
+            (-2.0, -1.0),
+            (2.0, -1.0),
+            (2.0, 1.0),
+            (-2.0, 1.0),
+            (0.5, 0.0),
+        ]
+        .iter()
+        .map(|(u, v)| (10.0 + u * c - v * s, 10.0 + u * s + v * c))
+        .collect();
+        let (center, axis, half_u, half_v) = min_area_rect(points);
+        assert_points(&[center], &[(10.0, 10.0)]);
+        assert!((axis.0.abs() - c).abs() < 1e-3 && (axis.1.abs() - s).abs() < 1e-3);
+        let mut halves = [half_u, half_v];
+        halves.sort_by(f32::total_cmp);
+        assert_points(&[(halves[0], halves[1])], &[(1.0, 2.0)]);
+    }
+
+    #[test]
+    fn orders_corners_by_angle() {
+        let upright = rect_corners((5.0, 5.0), (1.0, 0.0), 2.0, 1.0);
+        assert_points(&upright, &[(3.0, 4.0), (7.0, 4.0), (7.0, 6.0), (3.0, 6.0)]);
+        // Either axis direction gives the same order.
+        let flipped = rect_corners((5.0, 5.0), (-1.0, 0.0), 2.0, 1.0);
+        assert_points(&flipped, &upright);
+        let along_y = rect_corners((5.0, 5.0), (0.0, 1.0), 1.0, 2.0);
+        assert_points(&along_y, &upright);
+
+        // At 45° the corner sums tie: the top corner comes first, and no
+        // corner is picked twice.
+        let r = 0.5f32.sqrt();
+        let diamond = rect_corners((0.0, 0.0), (r, r), 1.0, 1.0);
+        let d = 2.0f32.sqrt();
+        assert_points(&diamond, &[(0.0, -d), (d, 0.0), (0.0, d), (-d, 0.0)]);
+    }
+
+    #[test]
+    fn boxes_a_rectangle_of_text() {
+        let (height, width) = (20, 30);
+        let mut probs = vec![0.0f32; height * width];
+        for y in 8..12 {
+            for x in 5..15 {
+                probs[y * width + x] = 0.9;
+            }
+        }
+        // Too small to keep.
+        for (x, y) in [(25, 2), (26, 2), (25, 3), (26, 3)] {
+            probs[y * width + x] = 0.9;
+        }
+        let map = OutputView::from_f32(&probs, attr(&[1, 1, height as u32, width as u32]));
+        let boxes = DbPostProcess::default().boxes(&map).unwrap();
+        assert_eq!(boxes.len(), 1);
+        assert!((boxes[0].score - 0.9).abs() < 1e-6);
+        // A 10x4 region grown by 10 * 4 * 1.5 / (2 * 14) on each side.
+        let d = 60.0 / 28.0;
+        let (w, h) = (5.0 + d, 2.0 + d);
+        assert_points(
+            &boxes[0].points,
+            &[
+                (9.5 - w, 9.5 - h),
+                (9.5 + w, 9.5 - h),
+                (9.5 + w, 9.5 + h),
+                (9.5 - w, 9.5 + h),
+            ],
+        );
+    }
+
+    #[test]
+    fn solves_homographies() {
+        let m = [1.0, 0.2, 3.0, 0.1, 1.0, 5.0, 0.001, 0.002, 1.0];
+        let apply = |(x, y): Point| {
+            let z = m[6] * x + m[7] * y + m[8];
+            (
+                (m[0] * x + m[1] * y + m[2]) / z,
+                (m[3] * x + m[4] * y + m[5]) / z,
+            )
+        };
+        let from = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let h = homography(from, from.map(apply)).unwrap();
+        for (a, e) in h.iter().zip(m) {
+            assert!((a - e).abs() < 1e-4, "{:?} != {:?}", h, m);
+        }
+        let collinear = [(0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (3.0, 0.0)];
+        assert_eq!(homography(collinear, from), None);
+    }
+
+    /// `[1, T, 4]` probabilities peaked at `classes`, blank being 0.
+    fn peaked(classes: &[usize]) -> Vec<f32> {
+        classes
+            .iter()
+            .flat_map(|c| (0..4).map(move |i| if i == *c { 0.91 } else { 0.03 }))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_ctc_greedily() {
+        let decoder = CtcDecoder {
+            charset: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+        };
+        // a a - a b b - - c: repeats merge unless a blank separates them.
+        let probs = peaked(&[1, 1, 0, 1, 2, 2, 0, 0, 3]);
+        let output = OutputView::from_f32(&probs, attr(&[1, 9, 4]));
+        let greedy = decoder.greedy(&output).unwrap();
+        assert_eq!(greedy.text, "aabc");
+        assert!((greedy.score - 0.91).abs() < 1e-6);
+
+        let beam = decoder.beam_search(&output, 5).unwrap();
+        assert_eq!(beam.text, greedy.text);
+        assert!(beam.score > 0.0 && beam.score <= 1.0);
+
+        let blanks = peaked(&[0, 0, 0]);
+        let output = OutputView::from_f32(&blanks, attr(&[1, 3, 4]));
+        assert_eq!(decoder.greedy(&output).unwrap().text, "");
+        assert_eq!(decoder.beam_search(&output, 3).unwrap().text, "");
+    }
+}
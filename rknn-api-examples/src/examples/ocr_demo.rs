@@ -0,0 +1,180 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+use crate::examples::{
+    common::*,
+    draw::{class_color, draw_line},
+    ocr::{crop_box, recognition_input, CtcDecoder, DbPostProcess},
+    preprocess::{input_geometry, Preprocessor},
+    report::{run_record, ResultWriter, Timings},
+};
+
+/// Read text with PP-OCR models: a DBNet detection model finds the text
+/// boxes, each box is cropped upright and a CTC recognition model reads it.
+/// The preprocessing options apply to both models.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The path to the text detection model (*.rknn)
+    #[arg(long)]
+    det_model: String,
+
+    /// The path to the text recognition model (*.rknn)
+    #[arg(long)]
+    rec_model: String,
+
+    /// The recognition model's character dictionary, one character per line
+    #[arg(long)]
+    dict: String,
+
+    /// Don't append a space to the dictionary
+    #[arg(long)]
+    no_space: bool,
+
+    /// The images to read
+    #[arg(short, long, value_parser, required = true)]
+    input_paths: Vec<String>,
+
+    #[arg(long, default_value_t = 0.3)]
+    det_threshold: f32,
+
+    #[arg(long, default_value_t = 0.6)]
+    box_threshold: f32,
+
+    #[arg(long, default_value_t = 1.5)]
+    unclip_ratio: f32,
+
+    /// Use CTC beam search with this many beams, greedy decoding if 1
+    #[arg(long, default_value_t = 1)]
+    beam_width: usize,
+
+    /// Drop texts recognized with a lower score
+    #[arg(long, default_value_t = 0.5)]
+    rec_threshold: f32,
+
+    /// The directory the images with their text boxes (PNG), a JSON record
+    /// and the raw outputs (.npy) of each input are written to, the
+    /// recognition outputs as `<input>_rec<box>_output0.npy`
+    #[arg(short, long)]
+    output_dir: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+impl Example {
+    fn load(&self, path: &str) -> Result<(RKNNContext, Vec<rknn_api_sys::rknn_tensor_attr>)> {
+        let ctx = RKNNContext::load_model(path)?;
+        if ctx.n_input != 1 {
+            bail!("{} has {} inputs, expected 1", path, ctx.n_input);
+        }
+        ctx.set_core_mask(&self.core_mask)?;
+        let input_attrs = ctx.use_default_shapes()?;
+        Ok((ctx, input_attrs))
+    }
+
+    pub fn execute(&self) -> Result<()> {
+        let (det_ctx, det_attrs) = self.load(&self.det_model)?;
+        let (rec_ctx, rec_attrs) = self.load(&self.rec_model)?;
+        let (det_h, det_w, _, _) = input_geometry(&det_attrs[0])?;
+
+        let post_process = DbPostProcess {
+            threshold: self.det_threshold,
+            box_threshold: self.box_threshold,
+            unclip_ratio: self.unclip_ratio,
+            ..Default::default()
+        };
+        let decoder = CtcDecoder::load(&self.dict, !self.no_space)?;
+        let writer = self
+            .output_dir
+            .as_ref()
+            .map(ResultWriter::new)
+            .transpose()?;
+
+        for path in &self.input_paths {
+            let img = image::ImageReader::open(path)?.decode()?;
+            let stem = writer.as_ref().map(|w| w.name(path)).unwrap_or_default();
+            let mut timings = Timings::default();
+            let transforms = timings.time("set_inputs", || {
+                det_ctx.set_inputs(&det_attrs, std::slice::from_ref(&img), &self.preprocess)
+            })?;
+            timings.time("run", || det_ctx.run())?;
+            let outputs = timings.time("get_outputs", || det_ctx.fetch_outputs(true))?;
+            let det_output = outputs.view(0)?;
+            let boxes = timings.time("postprocess", || {
+                post_process.source_boxes(&det_output, (det_w, det_h), &transforms[0])
+            })?;
+
+            let rgb = img.to_rgb8();
+            let mut canvas = rgb.clone();
+            let mut texts = Vec::new();
+            // All recognitions of the input together.
+            let mut recognize = Duration::ZERO;
+            println!("\x1b[34;4m {}: {} text boxes\x1b[0m", path, boxes.len());
+            for (i, text_box) in boxes.iter().enumerate() {
+                let start = Instant::now();
+                let crop = crop_box(&rgb, text_box);
+                let input = recognition_input(&crop, &rec_attrs[0], &self.preprocess)?;
+                rec_ctx.set_tensor_inputs(std::slice::from_ref(&input))?;
+                rec_ctx.run()?;
+                let rec_outputs = rec_ctx.fetch_outputs(true)?;
+                let view = rec_outputs.view(0)?;
+                let recognition = if self.beam_width > 1 {
+                    decoder.beam_search(&view, self.beam_width)?
+                } else {
+                    decoder.greedy(&view)?
+                };
+                recognize += start.elapsed();
+                if let Some(writer) = &writer {
+                    writer.write_outputs(&format!("{}_rec{}", stem, i), &[view])?;
+                }
+                if recognition.score < self.rec_threshold {
+                    continue;
+                }
+                texts.push(json!({
+                    "text": recognition.text,
+                    "score": recognition.score,
+                    "box": text_box.points.map(|(x, y)| [x, y]),
+                    "box_score": text_box.score,
+                }));
+
+                let p = &text_box.points;
+                println!(
+                    "  {:?} {:.3} @ ({:.0}, {:.0}) ({:.0}, {:.0}) ({:.0}, {:.0}) ({:.0}, {:.0})",
+                    recognition.text,
+                    recognition.score,
+                    p[0].0,
+                    p[0].1,
+                    p[1].0,
+                    p[1].1,
+                    p[2].0,
+                    p[2].1,
+                    p[3].0,
+                    p[3].1
+                );
+                for k in 0..4 {
+                    draw_line(&mut canvas, p[k], p[(k + 1) % 4], class_color(i), 2);
+                }
+            }
+
+            timings.record("recognize", recognize);
+
+            if let Some(writer) = &writer {
+                let mut record =
+                    run_record(path, &self.det_model, &det_attrs, outputs.attrs(), &timings);
+                record["rec_model"] = json!(self.rec_model);
+                record["texts"] = json!(texts);
+                writer.write(&stem, &record, &[det_output])?;
+
+                let out_path = writer.path(&format!("{}.png", stem));
+                canvas.save(&out_path)?;
+                println!("\x1b[34;4m saved {}\x1b[0m", out_path.display());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::Result;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use rknn_file::{npu_family, platform_chips, RknnFile};
+
+/// Overrides the root `/proc/device-tree` is read under, e.g. to test with
+/// another board's device tree.
+pub const DEVICE_ROOT_ENV: &str = "RKNN_DEVICE_ROOT";
+
+/// A model built for one chip loaded on another. `rknn_init` only reports
+/// these as a failed call, so they're caught before it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlatformMismatch {
+    pub model_path: String,
+    /// The platforms the model is built for.
+    pub model: Vec<String>,
+    /// The chip of this device.
+    pub device: String,
+}
+
+impl fmt::Display for PlatformMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is built for {}, but this device is an {}",
+            self.model_path,
+            self.model.join(","),
+            self.device
+        )
+    }
+}
+
+impl std::error::Error for PlatformMismatch {}
+
+/// The root the device tree is read under, `/` unless [`DEVICE_ROOT_ENV`]
+/// is set.
+pub fn device_root() -> PathBuf {
+    std::env::var_os(DEVICE_ROOT_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/"))
+}
+
+/// The NPU family (see [`npu_family`]) of the Rockchip chip named by
+/// `<root>/proc/device-tree/compatible`, e.g. `rk3588` or `rk3566_rk3568`,
+/// or `None` off Rockchip boards.
+pub fn detect_chip(root: &Path) -> Option<String> {
+    let compatible = std::fs::read(root.join("proc/device-tree/compatible")).ok()?;
+    // NUL separated, most specific first: the board, then the SoC.
+    compatible
+        .split(|b| *b == 0)
+        .filter_map(|entry| std::str::from_utf8(entry).ok())
+        .filter_map(|entry| entry.strip_prefix("rockchip,"))
+        .find(|chip| chip.starts_with("rk") || chip.starts_with("rv"))
+        .map(npu_family)
+}
+
+/// Fails with a [`PlatformMismatch`] when the model at `model_path` names
+/// its platform and the device under `root` isn't one of them. Models the
+/// reader can't make sense of, and devices that aren't Rockchip boards, are
+/// left for `rknn_init` to judge.
+pub fn check_model(model_path: &str, root: &Path) -> Result<()> {
+    let Some(device) = detect_chip(root) else {
+        return Ok(());
+    };
+    let Ok(file) = RknnFile::load(model_path) else {
+        return Ok(());
+    };
+    if file.supports(&device) == Some(false) {
+        return Err(PlatformMismatch {
+            model_path: model_path.to_string(),
+            model: file.target_platforms,
+            device,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Whether this build's models (see the cargo features) are for `chip`.
+pub fn matches_build(chip: &str) -> bool {
+    let family = npu_family(chip);
+    platform_chips(crate::examples::common::PLATFORM)
+        .iter()
+        .any(|c| npu_family(c) == family)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory holding a device tree whose `compatible` is `entries`.
+    fn device(name: &str, entries: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rknn-{}-{}", name, std::process::id()));
+        let tree = root.join("proc/device-tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        let mut compatible = entries.join("\0");
+        compatible.push('\0');
+        std::fs::write(tree.join("compatible"), compatible).unwrap();
+        root
+    }
+
+    fn model(name: &str, platform: &str) -> String {
+        let metadata = format!(r#"{{"target_platform":["{}"]}}"#, platform);
+        let mut bytes = b"RKNN\0\0\0\0".to_vec();
+        bytes.extend_from_slice(&6u64.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&(metadata.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(metadata.as_bytes());
+        let path = std::env::temp_dir().join(format!("{}-{}.rknn", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn detects_chip() {
+        let rock5b = device("rock5b", &["radxa,rock-5b", "rockchip,rk3588"]);
+        assert_eq!(detect_chip(&rock5b).as_deref(), Some("rk3588"));
+        let orangepi = device("opi5", &["xunlong,orangepi-5", "rockchip,rk3588s"]);
+        assert_eq!(detect_chip(&orangepi).as_deref(), Some("rk3588"));
+        let rock3a = device("rock3a", &["radxa,rock3a", "rockchip,rk3568"]);
+        assert_eq!(detect_chip(&rock3a).as_deref(), Some("rk3566_rk3568"));
+        let luckfox = device("luckfox", &["luckfox,pico-max", "rockchip,rv1106"]);
+        assert_eq!(detect_chip(&luckfox).as_deref(), Some("rv1103_rv1106"));
+        let pi = device("rpi", &["raspberrypi,5-model-b", "brcm,bcm2712"]);
+        assert_eq!(detect_chip(&pi), None);
+        assert_eq!(detect_chip(Path::new("/nonexistent")), None);
+    }
+
+    #[test]
+    fn rejects_models_for_other_chips() {
+        let root = device("rk3588", &["rockchip,rk3588"]);
+        assert!(check_model(&model("ok", "rk3588"), &root).is_ok());
+
+        let path = model("mismatch", "rk3566");
+        let err = check_model(&path, &root).unwrap_err();
+        let mismatch = err.downcast_ref::<PlatformMismatch>().unwrap();
+        assert_eq!(mismatch.model, ["rk3566"]);
+        assert_eq!(mismatch.device, "rk3588");
+        assert!(err.to_string().contains("built for rk3566"));
+
+        // An rk3566 and an rk3568 share an NPU.
+        let rock3a = device("rock3a-check", &["rockchip,rk3568"]);
+        assert!(check_model(&path, &rock3a).is_ok());
+
+        let pi = device("rpi-check", &["brcm,bcm2712"]);
+        assert!(check_model(&path, &pi).is_ok());
+    }
+}
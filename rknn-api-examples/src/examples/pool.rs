@@ -0,0 +1,399 @@
+use anyhow::{anyhow, bail, Result};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{
+            channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError,
+        },
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::examples::common::*;
+
+/// What a [`Pool`] needs from a runtime. Implemented by [`RKNNContext`],
+/// and by stubs so the pool and the server run without an NPU.
+pub trait Runtime: Send {
+    /// Runs the model once, returning each output dequantized to f32.
+    fn infer(&mut self, inputs: &[TensorInput]) -> Result<Vec<Vec<f32>>>;
+}
+
+impl Runtime for RKNNContext {
+    fn infer(&mut self, inputs: &[TensorInput]) -> Result<Vec<Vec<f32>>> {
+        self.set_tensor_inputs(inputs)?;
+        self.run()?;
+        let outputs = self.fetch_outputs(true)?;
+        (0..outputs.len())
+            .map(|i| Ok(outputs.view(i)?.to_vec()))
+            .collect()
+    }
+}
+
+/// The outputs of one request and how it was served.
+#[derive(Debug)]
+pub struct Reply {
+    pub outputs: Vec<Vec<f32>>,
+    /// The number of requests run together with this one (itself included).
+    pub batch: usize,
+    /// From submitting to the batch starting.
+    pub queued: Duration,
+    /// The batch's inference, merging and splitting included.
+    pub infer: Duration,
+}
+
+/// Why a request wasn't queued.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubmitError {
+    /// The queue is full, the caller should back off.
+    Full,
+    /// The pool is shutting down.
+    Closed,
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Full => write!(f, "too many queued requests"),
+            SubmitError::Closed => write!(f, "shutting down"),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
+
+struct Job {
+    inputs: Vec<TensorInput>,
+    submitted: Instant,
+    reply: Sender<Result<Reply>>,
+}
+
+#[derive(Debug, Default)]
+pub struct PoolStats {
+    pub requests: AtomicUsize,
+    pub batches: AtomicUsize,
+}
+
+/// Runs requests on a set of runtimes (usually contexts of one model on
+/// different core masks) fed from a bounded queue.
+///
+/// Models with a batch dimension (the leading dim of every input and output,
+/// when above 1) get requests of one sample each merged: a free runtime takes
+/// the first request, waits up to `batch_wait` for more of the same shape,
+/// pads the batch with zeros and splits the outputs back. Other requests run
+/// as they are.
+///
+/// Dropping the pool stops taking requests, runs the queued ones and joins
+/// the workers.
+pub struct Pool {
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    batch_size: usize,
+    stats: Arc<PoolStats>,
+}
+
+impl Pool {
+    pub fn new(
+        runtimes: Vec<Box<dyn Runtime>>,
+        batch_size: usize,
+        queue_depth: usize,
+        batch_wait: Duration,
+    ) -> Self {
+        let (sender, receiver) = sync_channel::<Job>(queue_depth.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        let stats = Arc::new(PoolStats::default());
+        let batch_size = batch_size.max(1);
+        let workers = runtimes
+            .into_iter()
+            .map(|runtime| {
+                let (jobs, stats) = (receiver.clone(), stats.clone());
+                thread::spawn(move || worker(runtime, &jobs, batch_size, batch_wait, &stats))
+            })
+            .collect();
+        Pool {
+            sender: Some(sender),
+            workers,
+            batch_size,
+            stats,
+        }
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn stats(&self) -> &PoolStats {
+        &self.stats
+    }
+
+    /// Queues a request without waiting, the reply arrives on the returned
+    /// receiver.
+    pub fn submit(
+        &self,
+        inputs: Vec<TensorInput>,
+    ) -> std::result::Result<Receiver<Result<Reply>>, SubmitError> {
+        let sender = self.sender.as_ref().ok_or(SubmitError::Closed)?;
+        let (reply, receiver) = channel();
+        let job = Job {
+            inputs,
+            submitted: Instant::now(),
+            reply,
+        };
+        match sender.try_send(job) {
+            Ok(()) => Ok(receiver),
+            Err(TrySendError::Full(_)) => Err(SubmitError::Full),
+            Err(TrySendError::Disconnected(_)) => Err(SubmitError::Closed),
+        }
+    }
+
+    /// Queues a request and waits for its reply.
+    pub fn infer(&self, inputs: Vec<TensorInput>) -> Result<Reply> {
+        self.submit(inputs)?
+            .recv()
+            .map_err(|_| anyhow!("the runtime stopped"))?
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // The workers run what is queued, then see the queue closed.
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Whether `job` is one sample of a model batching `batch_size`.
+fn batchable(job: &Job, batch_size: usize) -> bool {
+    batch_size > 1
+        && job
+            .inputs
+            .iter()
+            .all(|input| input.dims.first() == Some(&1))
+}
+
+fn same_shapes(a: &Job, b: &Job) -> bool {
+    a.inputs.len() == b.inputs.len()
+        && a.inputs
+            .iter()
+            .zip(&b.inputs)
+            .all(|(a, b)| a.dims == b.dims && a.type_ == b.type_)
+}
+
+/// Takes the next requests off the queue, `None` once it is closed and
+/// empty. A request that can't join the batch is returned as a batch of its
+/// own after it.
+fn next_batches(
+    jobs: &Receiver<Job>,
+    batch_size: usize,
+    batch_wait: Duration,
+) -> Option<Vec<Vec<Job>>> {
+    let first = jobs.recv().ok()?;
+    if !batchable(&first, batch_size) {
+        return Some(vec![vec![first]]);
+    }
+    let deadline = Instant::now() + batch_wait;
+    let mut batch = vec![first];
+    while batch.len() < batch_size {
+        let job = match jobs.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(job) => job,
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+        };
+        if batchable(&job, batch_size) && same_shapes(&batch[0], &job) {
+            batch.push(job);
+        } else {
+            return Some(vec![batch, vec![job]]);
+        }
+    }
+    Some(vec![batch])
+}
+
+fn worker(
+    mut runtime: Box<dyn Runtime>,
+    jobs: &Mutex<Receiver<Job>>,
+    batch_size: usize,
+    batch_wait: Duration,
+    stats: &PoolStats,
+) {
+    loop {
+        // Held while a batch fills, the other workers would only wait for
+        // the same requests.
+        let batches = match jobs.lock() {
+            Ok(jobs) => next_batches(&jobs, batch_size, batch_wait),
+            Err(_) => None,
+        };
+        let Some(batches) = batches else {
+            return;
+        };
+        for batch in batches {
+            run_batch(runtime.as_mut(), batch, batch_size, stats);
+        }
+    }
+}
+
+/// Joins one sample inputs into batches of `batch_size`, padded with zeros.
+fn merge_inputs(batch: &[Job], batch_size: usize) -> Vec<TensorInput> {
+    (0..batch[0].inputs.len())
+        .map(|i| {
+            let first = &batch[0].inputs[i];
+            let mut data = Vec::with_capacity(first.data.len() * batch_size);
+            for job in batch {
+                data.extend_from_slice(&job.inputs[i].data);
+            }
+            data.resize(first.data.len() * batch_size, 0);
+            let mut dims = first.dims.clone();
+            dims[0] = batch_size;
+            TensorInput {
+                data,
+                dims,
+                type_: first.type_,
+            }
+        })
+        .collect()
+}
+
+/// Splits batched outputs into one set per request, dropping the padding.
+fn split_outputs(
+    outputs: Vec<Vec<f32>>,
+    requests: usize,
+    batch_size: usize,
+) -> Result<Vec<Vec<Vec<f32>>>> {
+    let mut split = vec![Vec::with_capacity(outputs.len()); requests];
+    for (i, output) in outputs.iter().enumerate() {
+        if output.len() % batch_size != 0 {
+            bail!(
+                "output {} has {} values, not a multiple of the batch size {}",
+                i,
+                output.len(),
+                batch_size
+            );
+        }
+        for (request, sample) in split
+            .iter_mut()
+            .zip(output.chunks(output.len() / batch_size))
+        {
+            request.push(sample.to_vec());
+        }
+    }
+    Ok(split)
+}
+
+fn run_batch(runtime: &mut dyn Runtime, batch: Vec<Job>, batch_size: usize, stats: &PoolStats) {
+    let start = Instant::now();
+    let merged = batchable(&batch[0], batch_size);
+    let outputs = if merged {
+        runtime
+            .infer(&merge_inputs(&batch, batch_size))
+            .and_then(|outputs| split_outputs(outputs, batch.len(), batch_size))
+    } else {
+        runtime.infer(&batch[0].inputs).map(|outputs| vec![outputs])
+    };
+    let infer = start.elapsed();
+    stats.requests.fetch_add(batch.len(), Ordering::Relaxed);
+    stats.batches.fetch_add(1, Ordering::Relaxed);
+
+    let size = batch.len();
+    match outputs {
+        Ok(outputs) => {
+            for (job, outputs) in batch.into_iter().zip(outputs) {
+                let reply = Reply {
+                    outputs,
+                    batch: size,
+                    queued: start - job.submitted,
+                    infer,
+                };
+                // The client may have gone away.
+                let _ = job.reply.send(Ok(reply));
+            }
+        }
+        Err(err) => {
+            let message = format!("{:#}", err);
+            for job in batch {
+                let _ = job.reply.send(Err(anyhow!(message.clone())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Doubles its only f32 input, recording the batch dim of each run.
+    struct Doubler {
+        runs: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl Runtime for Doubler {
+        fn infer(&mut self, inputs: &[TensorInput]) -> Result<Vec<Vec<f32>>> {
+            self.runs.lock().unwrap().push(inputs[0].dims[0]);
+            let values = inputs[0]
+                .data
+                .chunks_exact(4)
+                .map(|b| 2.0 * f32::from_le_bytes(b.try_into().unwrap()));
+            Ok(vec![values.collect()])
+        }
+    }
+
+    fn input(dims: &[usize], values: &[f32]) -> Vec<TensorInput> {
+        vec![TensorInput {
+            data: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            dims: dims.to_vec(),
+            type_: rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32,
+        }]
+    }
+
+    fn pool(batch_size: usize, batch_wait: Duration) -> (Pool, Arc<Mutex<Vec<usize>>>) {
+        let runs = Arc::new(Mutex::new(Vec::new()));
+        let doubler = Doubler { runs: runs.clone() };
+        (
+            Pool::new(vec![Box::new(doubler)], batch_size, 8, batch_wait),
+            runs,
+        )
+    }
+
+    #[test]
+    fn merges_requests_into_batches() {
+        let (pool, runs) = pool(4, Duration::from_millis(500));
+        let replies: Vec<_> = (0..3)
+            .map(|i| pool.submit(input(&[1, 2], &[i as f32, 1.0])).unwrap())
+            .collect();
+        for (i, reply) in replies.into_iter().enumerate() {
+            let reply = reply.recv().unwrap().unwrap();
+            assert_eq!(reply.outputs, vec![vec![2.0 * i as f32, 2.0]]);
+            assert_eq!(reply.batch, 3);
+        }
+        // One run, padded to the model's batch.
+        assert_eq!(*runs.lock().unwrap(), [4]);
+        assert_eq!(pool.stats().batches.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn runs_other_shapes_alone() {
+        let (pool, runs) = pool(4, Duration::from_millis(500));
+        let full = pool.submit(input(&[4, 1], &[1.0, 2.0, 3.0, 4.0])).unwrap();
+        let reply = full.recv().unwrap().unwrap();
+        assert_eq!(reply.outputs, vec![vec![2.0, 4.0, 6.0, 8.0]]);
+        assert_eq!(reply.batch, 1);
+        assert_eq!(*runs.lock().unwrap(), [4]);
+    }
+
+    #[test]
+    fn drains_the_queue_on_drop() {
+        let (pool, runs) = pool(1, Duration::ZERO);
+        let replies: Vec<_> = (0..5)
+            .map(|i| pool.submit(input(&[1], &[i as f32])).unwrap())
+            .collect();
+        drop(pool);
+        for (i, reply) in replies.into_iter().enumerate() {
+            assert_eq!(
+                reply.recv().unwrap().unwrap().outputs,
+                vec![vec![2.0 * i as f32]]
+            );
+        }
+        assert_eq!(runs.lock().unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use crate::examples::{
+    common::OutputView,
+    preprocess::ImageTransform,
+    yolo::{Detection, YoloDecoder},
+};
+
+/// The limbs between COCO keypoints, for drawing.
+pub const COCO_SKELETON: [(usize, usize); 19] = [
+    (15, 13),
+    (13, 11),
+    (16, 14),
+    (14, 12),
+    (11, 12),
+    (5, 11),
+    (6, 12),
+    (5, 6),
+    (5, 7),
+    (6, 8),
+    (7, 9),
+    (8, 10),
+    (1, 2),
+    (0, 1),
+    (0, 2),
+    (1, 3),
+    (2, 4),
+    (3, 5),
+    (4, 6),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keypoint {
+    pub x: f32,
+    pub y: f32,
+    /// Visibility confidence.
+    pub score: f32,
+}
+
+/// The keypoints of one detected instance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keypoints {
+    pub points: Vec<Keypoint>,
+}
+
+impl Keypoints {
+    pub fn to_source(&self, transform: &ImageTransform) -> Keypoints {
+        let points = self
+            .points
+            .iter()
+            .map(|p| {
+                let (x, y) = transform.to_source(p.x, p.y);
+                Keypoint { x, y, ..*p }
+            })
+            .collect();
+        Keypoints { points }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!(self
+            .points
+            .iter()
+            .map(|p| [p.x, p.y, p.score])
+            .collect::<Vec<_>>())
+    }
+}
+
+/// Decodes YOLOv8-pose outputs: boxes from the detection head and their
+/// keypoints, already decoded to input pixels by the model.
+///
+/// Two layouts are recognised: one `[1, 4+nc+3*K, N]` output with the
+/// keypoints after the class scores, or 3 branch outputs
+/// `[1, 4*reg_max+nc, H, W]` followed by the keypoints `[1, K, 3, N]`.
+#[derive(Clone, Debug)]
+pub struct PoseDecoder {
+    pub decoder: YoloDecoder,
+    pub num_keypoints: usize,
+}
+
+impl PoseDecoder {
+    pub fn new(decoder: YoloDecoder) -> Self {
+        PoseDecoder {
+            decoder,
+            num_keypoints: 17,
+        }
+    }
+
+    fn score(&self, value: f32) -> f32 {
+        if self.decoder.sigmoid {
+            1.0 / (1.0 + (-value).exp())
+        } else {
+            value
+        }
+    }
+
+    /// Decodes boxes and keypoints, both mapped back to the source image.
+    pub fn decode(
+        &self,
+        outputs: &[OutputView],
+        input_size: (u32, u32),
+        transform: &ImageTransform,
+    ) -> Result<Vec<(Detection, Keypoints)>> {
+        let k = self.num_keypoints;
+        let results: Vec<(Detection, Keypoints)> = match outputs.len() {
+            1 => {
+                let (_, attrs, at) = YoloDecoder::rows(&outputs[0])?;
+                let Some(n_classes) = attrs.checked_sub(4 + 3 * k).filter(|n| *n > 0) else {
+                    bail!("{} values per box leave no room for {} keypoints", attrs, k);
+                };
+                let decoder = YoloDecoder {
+                    num_classes: Some(n_classes),
+                    ..self.decoder.clone()
+                };
+                decoder
+                    .decode(outputs, input_size)?
+                    .into_iter()
+                    .map(|det| {
+                        let base = 4 + n_classes;
+                        let points = (0..k)
+                            .map(|j| Keypoint {
+                                x: at(det.anchor, base + 3 * j),
+                                y: at(det.anchor, base + 3 * j + 1),
+                                score: self.score(at(det.anchor, base + 3 * j + 2)),
+                            })
+                            .collect();
+                        (det, Keypoints { points })
+                    })
+                    .collect()
+            }
+            4 => {
+                let keypoints = &outputs[3];
+                let anchors = keypoints.len() / (k * 3);
+                if keypoints.len() != anchors * k * 3 || anchors == 0 {
+                    bail!("keypoint output {:?} for {} keypoints", keypoints.dims(), k);
+                }
+                let at = |anchor: usize, j: usize, c: usize| {
+                    keypoints.get((j * 3 + c) * anchors + anchor)
+                };
+                self.decoder
+                    .decode(&outputs[..3], input_size)?
+                    .into_iter()
+                    .map(|det| {
+                        let points = (0..k)
+                            .map(|j| Keypoint {
+                                x: at(det.anchor, j, 0),
+                                y: at(det.anchor, j, 1),
+                                score: self.score(at(det.anchor, j, 2)),
+                            })
+                            .collect();
+                        (det, Keypoints { points })
+                    })
+                    .collect()
+            }
+            n => bail!("no YOLOv8-pose layout with {} outputs", n),
+        };
+
+        Ok(results
+            .into_iter()
+            .map(|(det, keypoints)| {
+                let bbox = det.bbox.to_source(transform);
+                (Detection { bbox, ..det }, keypoints.to_source(transform))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::yolo::YoloVersion;
+    use rknn_api_sys::rknn_tensor_attr;
+
+    fn attr(dims: &[u32]) -> rknn_tensor_attr {
+        let mut attr = rknn_tensor_attr {
+            n_dims: dims.len() as u32,
+            n_elems: dims.iter().product(),
+            ..Default::default()
+        };
+        attr.dims[..dims.len()].copy_from_slice(dims);
+        attr
+    }
+
+    /// A 32x16 source letterboxed into a 16x16 input.
+    const LETTERBOX: ImageTransform = ImageTransform {
+        scale_x: 0.5,
+        scale_y: 0.5,
+        offset_x: 0.0,
+        offset_y: 4.0,
+        source_width: 32,
+        source_height: 16,
+    };
+
+    fn points(keypoints: &Keypoints) -> Vec<[f32; 3]> {
+        keypoints
+            .points
+            .iter()
+            .map(|p| [p.x, p.y, p.score])
+            .collect()
+    }
+
+    #[test]
+    fn decodes_concatenated_keypoints() {
+        // [1, 4 + 1 class + 3 * 2 keypoints, 12 rows], a box (4, 4)-(12, 12)
+        // with keypoints (2, 6) and (4, 10) in row 5.
+        let mut head = vec![0.0; 132];
+        let row = [8.0, 8.0, 8.0, 8.0, 0.9, 2.0, 6.0, 0.8, 4.0, 10.0, 0.1];
+        for (c, v) in row.into_iter().enumerate() {
+            head[c * 12 + 5] = v;
+        }
+        let views = [OutputView::from_f32(&head, attr(&[1, 11, 12]))];
+        let mut decoder = PoseDecoder::new(YoloDecoder::new(YoloVersion::V8));
+        decoder.num_keypoints = 2;
+        let results = decoder.decode(&views, (16, 16), &LETTERBOX).unwrap();
+        assert_eq!(results.len(), 1);
+        let (det, keypoints) = &results[0];
+        assert_eq!((det.anchor, det.score), (5, 0.9));
+        let bbox = [det.bbox.x1, det.bbox.y1, det.bbox.x2, det.bbox.y2];
+        assert_eq!(bbox, [8.0, 0.0, 24.0, 16.0]);
+        assert_eq!(points(keypoints), [[4.0, 4.0, 0.8], [8.0, 12.0, 0.1]]);
+
+        decoder.num_keypoints = 3;
+        assert!(decoder.decode(&views, (16, 16), &LETTERBOX).is_err());
+    }
+
+    #[test]
+    fn decodes_branch_keypoints() {
+        // reg_max 2, 1 class: 1x1, 2x2 and 1x1 branches, 6 anchors. The box
+        // sits in cell (1, 1) of the 2x2 branch (stride 8), anchor 1 + 3,
+        // distances 1, 1, 0, 0 around (12, 12).
+        let mut fine = vec![0.0; 9 * 4];
+        for c in [1, 3, 4, 6] {
+            fine[c * 4 + 3] = 30.0;
+        }
+        // A class logit of 3, -10 elsewhere, and 2 keypoints [1, 2, 3, 6],
+        // anchors last.
+        fine[8 * 4..].copy_from_slice(&[-10.0, -10.0, -10.0, 3.0]);
+        let mut keypoints = vec![-1.0; 36];
+        for (i, v) in [2.0, 6.0, 0.0, 4.0, 10.0, 2.0].into_iter().enumerate() {
+            keypoints[i * 6 + 4] = v;
+        }
+        let coarse = vec![-10.0; 9];
+        let views = [
+            OutputView::from_f32(&coarse, attr(&[1, 9, 1, 1])),
+            OutputView::from_f32(&fine, attr(&[1, 9, 2, 2])),
+            OutputView::from_f32(&coarse, attr(&[1, 9, 1, 1])),
+            OutputView::from_f32(&keypoints, attr(&[1, 2, 3, 6])),
+        ];
+        let mut yolo = YoloDecoder::new(YoloVersion::V8);
+        yolo.reg_max = 2;
+        yolo.sigmoid = true;
+        let mut decoder = PoseDecoder::new(yolo);
+        decoder.num_keypoints = 2;
+        let results = decoder.decode(&views, (16, 16), &LETTERBOX).unwrap();
+        assert_eq!(results.len(), 1);
+        let (det, keypoints) = &results[0];
+        assert_eq!(det.anchor, 4);
+        assert!((det.score - 0.952_574).abs() < 1e-5);
+        let bbox = [det.bbox.x1, det.bbox.y1, det.bbox.x2, det.bbox.y2];
+        assert_eq!(bbox, [8.0, 0.0, 24.0, 16.0]);
+        let points = points(keypoints);
+        assert_eq!(points[0], [4.0, 4.0, 0.5]);
+        assert_eq!(points[1][..2], [8.0, 12.0]);
+        assert!((points[1][2] - 0.880_797).abs() < 1e-5);
+    }
+}
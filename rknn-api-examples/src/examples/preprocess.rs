@@ -0,0 +1,481 @@
+use anyhow::{bail, Result};
+use image::{imageops, DynamicImage, Rgb, RgbImage};
+use rknn_api_sys::rknn_tensor_attr;
+
+use crate::examples::common::TensorInput;
+
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for imageops::FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => imageops::FilterType::Nearest,
+            ResizeFilter::Triangle => imageops::FilterType::Triangle,
+            ResizeFilter::CatmullRom => imageops::FilterType::CatmullRom,
+            ResizeFilter::Gaussian => imageops::FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => imageops::FilterType::Lanczos3,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorOrder {
+    Rgb,
+    Bgr,
+    Gray,
+}
+
+/// Maps model input coordinates back to the source image:
+/// `model = source * scale + offset`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImageTransform {
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub source_width: u32,
+    pub source_height: u32,
+}
+
+impl ImageTransform {
+    pub fn to_source(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            (x - self.offset_x) / self.scale_x,
+            (y - self.offset_y) / self.scale_y,
+        )
+    }
+}
+
+/// Turns an image into the input tensor a model expects.
+#[derive(Debug, Clone, clap::Args)]
+pub struct Preprocessor {
+    /// The filter used to resize images
+    #[arg(long, value_enum, default_value_t = ResizeFilter::Triangle)]
+    pub resize_filter: ResizeFilter,
+
+    /// Keep the aspect ratio when resizing, padding with --pad-color
+    #[arg(long)]
+    pub letterbox: bool,
+
+    /// The letterbox padding color as R,G,B
+    #[arg(long, value_delimiter = ',', num_args = 3, default_values_t = [114, 114, 114])]
+    pub pad_color: Vec<u8>,
+
+    /// Crop the center of the image, this fraction of the largest region
+    /// with the model's aspect ratio, before resizing (e.g. 0.875)
+    #[arg(long)]
+    pub center_crop: Option<f32>,
+
+    #[arg(long, value_enum, default_value_t = ColorOrder::Rgb)]
+    pub color: ColorOrder,
+
+    /// Per channel mean subtracted from the 0-255 pixel values. Without it
+    /// (and --std) the raw u8 pixels are handed to the runtime, which applies
+    /// the normalization baked into the model. The runtime applies that in
+    /// any case, so only use this with models converted without
+    /// mean_values/std_values
+    #[arg(long, value_delimiter = ',')]
+    pub mean: Vec<f32>,
+
+    /// Per channel value the pixels are divided by after the mean
+    #[arg(long, value_delimiter = ',')]
+    pub std: Vec<f32>,
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Preprocessor {
+            resize_filter: ResizeFilter::Triangle,
+            letterbox: false,
+            pad_color: vec![114, 114, 114],
+            center_crop: None,
+            color: ColorOrder::Rgb,
+            mean: Vec::new(),
+            std: Vec::new(),
+        }
+    }
+}
+
+/// Height, width and channels of an NHWC or NCHW input, and whether it is
+/// NCHW.
+pub fn input_geometry(attr: &rknn_tensor_attr) -> Result<(u32, u32, u32, bool)> {
+    if attr.n_dims != 4 {
+        bail!(
+            "input {} is not an image, dims={:?}",
+            attr.index,
+            &attr.dims[..attr.n_dims as usize]
+        );
+    }
+    let d = &attr.dims;
+    Ok(
+        if attr.fmt == rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NCHW {
+            (d[2], d[3], d[1], true)
+        } else {
+            (d[1], d[2], d[3], false)
+        },
+    )
+}
+
+fn per_channel(values: &[f32], channels: usize, default: f32, name: &str) -> Result<Vec<f32>> {
+    match values.len() {
+        0 => Ok(vec![default; channels]),
+        1 => Ok(vec![values[0]; channels]),
+        n if n == channels => Ok(values.to_vec()),
+        n => bail!(
+            "{} has {} values, the model has {} channels",
+            name,
+            n,
+            channels
+        ),
+    }
+}
+
+fn to_bytes<T: Copy>(values: &[T]) -> Vec<u8> {
+    unsafe { std::slice::from_raw_parts(values.as_ptr() as *const u8, size_of_val(values)) }
+        .to_vec()
+}
+
+/// Where the (cropped) source lands in the model input.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Placement {
+    /// x, y, width, height of the region taken from the source
+    pub crop: (u32, u32, u32, u32),
+    /// x, y, width, height of the region it is resized to
+    pub dest: (u32, u32, u32, u32),
+}
+
+impl Preprocessor {
+    /// Works out the crop and the letterboxed destination for a
+    /// `src_w` x `src_h` image resized to `width` x `height`.
+    pub fn place(
+        &self,
+        src_w: u32,
+        src_h: u32,
+        width: u32,
+        height: u32,
+    ) -> (Placement, ImageTransform) {
+        let (mut crop_x, mut crop_y, mut crop_w, mut crop_h) = (0, 0, src_w, src_h);
+        if let Some(fraction) = self.center_crop {
+            let fit = (src_w as f32 / width as f32).min(src_h as f32 / height as f32);
+            crop_w = ((width as f32 * fit * fraction).round() as u32).clamp(1, src_w);
+            crop_h = ((height as f32 * fit * fraction).round() as u32).clamp(1, src_h);
+            crop_x = (src_w - crop_w) / 2;
+            crop_y = (src_h - crop_h) / 2;
+        }
+
+        let (dest_w, dest_h) = if self.letterbox {
+            let scale = (width as f32 / crop_w as f32).min(height as f32 / crop_h as f32);
+            (
+                ((crop_w as f32 * scale).round() as u32).clamp(1, width),
+                ((crop_h as f32 * scale).round() as u32).clamp(1, height),
+            )
+        } else {
+            (width, height)
+        };
+        let (dest_x, dest_y) = ((width - dest_w) / 2, (height - dest_h) / 2);
+
+        let (scale_x, scale_y) = (dest_w as f32 / crop_w as f32, dest_h as f32 / crop_h as f32);
+        let transform = ImageTransform {
+            scale_x,
+            scale_y,
+            offset_x: dest_x as f32 - crop_x as f32 * scale_x,
+            offset_y: dest_y as f32 - crop_y as f32 * scale_y,
+            source_width: src_w,
+            source_height: src_h,
+        };
+        let placement = Placement {
+            crop: (crop_x, crop_y, crop_w, crop_h),
+            dest: (dest_x, dest_y, dest_w, dest_h),
+        };
+        (placement, transform)
+    }
+
+    /// Crops and resizes `img` to `width` x `height`, returning the RGB pixels
+    /// and where they came from.
+    pub fn resize(
+        &self,
+        img: &DynamicImage,
+        width: u32,
+        height: u32,
+    ) -> (RgbImage, ImageTransform) {
+        let (placement, transform) = self.place(img.width(), img.height(), width, height);
+        let (crop_x, crop_y, crop_w, crop_h) = placement.crop;
+        let (dest_x, dest_y, dest_w, dest_h) = placement.dest;
+        let resized = img
+            .crop_imm(crop_x, crop_y, crop_w, crop_h)
+            .resize_exact(dest_w, dest_h, self.resize_filter.into())
+            .to_rgb8();
+        if (dest_w, dest_h) == (width, height) {
+            return (resized, transform);
+        }
+        let mut canvas = RgbImage::from_pixel(width, height, self.pad_rgb());
+        imageops::replace(&mut canvas, &resized, dest_x as i64, dest_y as i64);
+        (canvas, transform)
+    }
+
+    pub fn pad_rgb(&self) -> Rgb<u8> {
+        Rgb([self.pad_color[0], self.pad_color[1], self.pad_color[2]])
+    }
+
+    /// Checks the configured color order against the model's channels.
+    pub fn check_channels(&self, channels: usize) -> Result<()> {
+        let expected = if self.color == ColorOrder::Gray { 1 } else { 3 };
+        if channels != expected {
+            bail!(
+                "the model takes {} channels, {:?} has {}",
+                channels,
+                self.color,
+                expected
+            );
+        }
+        Ok(())
+    }
+
+    /// Appends one RGB pixel in the configured color order.
+    pub fn push_pixel(&self, rgb: [u8; 3], out: &mut Vec<u8>) {
+        match self.color {
+            ColorOrder::Rgb => out.extend_from_slice(&rgb),
+            ColorOrder::Bgr => out.extend_from_slice(&[rgb[2], rgb[1], rgb[0]]),
+            ColorOrder::Gray => out.push(
+                (0.299 * rgb[0] as f32 + 0.587 * rgb[1] as f32 + 0.114 * rgb[2] as f32).round()
+                    as u8,
+            ),
+        }
+    }
+
+    /// Reorders RGB pixels into the configured color order, one `channels`
+    /// sized group per pixel.
+    pub fn pixels(&self, rgb: &RgbImage, channels: usize) -> Result<Vec<u8>> {
+        self.check_channels(channels)?;
+        if self.color == ColorOrder::Rgb {
+            return Ok(rgb.as_raw().clone());
+        }
+        let mut out = Vec::with_capacity(rgb.width() as usize * rgb.height() as usize * channels);
+        for p in rgb.pixels() {
+            self.push_pixel(p.0, &mut out);
+        }
+        Ok(out)
+    }
+
+    /// Builds the input for `attr` from interleaved (HWC) pixels, in the
+    /// attr's layout: raw u8 when no normalization is configured, otherwise
+    /// normalized f32. Models taking int8 or f16 get these too; the runtime
+    /// converts them to the input's own type.
+    pub fn to_input(&self, hwc: Vec<u8>, attr: &rknn_tensor_attr) -> Result<TensorInput> {
+        let (height, width, channels, nchw) = input_geometry(attr)?;
+        let (h, w, c) = (height as usize, width as usize, channels as usize);
+        let dims = if nchw {
+            vec![1, c, h, w]
+        } else {
+            vec![1, h, w, c]
+        };
+        let reorder = |i: usize| {
+            if nchw {
+                (i % (h * w)) * c + i / (h * w)
+            } else {
+                i
+            }
+        };
+
+        if self.mean.is_empty() && self.std.is_empty() {
+            return Ok(TensorInput {
+                data: (0..hwc.len()).map(|i| hwc[reorder(i)]).collect(),
+                dims,
+                type_: rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8,
+            });
+        }
+
+        let mean = per_channel(&self.mean, c, 0.0, "--mean")?;
+        let std = per_channel(&self.std, c, 1.0, "--std")?;
+        let values: Vec<f32> = (0..hwc.len())
+            .map(|i| {
+                let src = reorder(i);
+                (hwc[src] as f32 - mean[src % c]) / std[src % c]
+            })
+            .collect();
+        // Inputs are set with pass_through off, so the runtime quantizes f32
+        // with the model's own scale and zero point.
+        Ok(TensorInput {
+            data: to_bytes(&values),
+            dims,
+            type_: rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32,
+        })
+    }
+
+    pub fn run(
+        &self,
+        img: &DynamicImage,
+        attr: &rknn_tensor_attr,
+    ) -> Result<(TensorInput, ImageTransform)> {
+        let (height, width, channels, _) = input_geometry(attr)?;
+        let (rgb, transform) = self.resize(img, width, height);
+        let pixels = self.pixels(&rgb, channels as usize)?;
+        Ok((self.to_input(pixels, attr)?, transform))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image_attr(fmt: rknn_api_sys::rknn_tensor_format, dims: [u32; 4]) -> rknn_tensor_attr {
+        let mut attr = rknn_tensor_attr {
+            n_dims: 4,
+            fmt,
+            ..Default::default()
+        };
+        attr.dims[..4].copy_from_slice(&dims);
+        attr
+    }
+
+    fn nhwc(h: u32, w: u32, c: u32) -> rknn_tensor_attr {
+        image_attr(
+            rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC,
+            [1, h, w, c],
+        )
+    }
+
+    fn nchw(h: u32, w: u32, c: u32) -> rknn_tensor_attr {
+        image_attr(
+            rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NCHW,
+            [1, c, h, w],
+        )
+    }
+
+    fn f32_values(input: &TensorInput) -> Vec<f32> {
+        input
+            .data
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn letterboxes_and_pads() {
+        let pre = Preprocessor {
+            resize_filter: ResizeFilter::Nearest,
+            letterbox: true,
+            pad_color: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let (placement, transform) = pre.place(8, 4, 4, 4);
+        assert_eq!(placement.crop, (0, 0, 8, 4));
+        assert_eq!(placement.dest, (0, 1, 4, 2));
+        assert_eq!((transform.scale_x, transform.scale_y), (0.5, 0.5));
+        assert_eq!((transform.offset_x, transform.offset_y), (0.0, 1.0));
+
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 4, Rgb([200, 0, 0])));
+        let (rgb, _) = pre.resize(&img, 4, 4);
+        for (x, y, p) in rgb.enumerate_pixels() {
+            let expected = if (1..3).contains(&y) {
+                [200, 0, 0]
+            } else {
+                [1, 2, 3]
+            };
+            assert_eq!(p.0, expected, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn crops_the_center() {
+        let pre = Preprocessor {
+            center_crop: Some(0.8),
+            ..Default::default()
+        };
+        // The largest 1:1 region is 10x10, 80% of it is 8x8.
+        let (placement, transform) = pre.place(10, 10, 4, 4);
+        assert_eq!(placement.crop, (1, 1, 8, 8));
+        assert_eq!(placement.dest, (0, 0, 4, 4));
+        assert_eq!(transform.to_source(0.0, 0.0), (1.0, 1.0));
+        assert_eq!(transform.to_source(4.0, 4.0), (9.0, 9.0));
+
+        // A wide model takes a wide region.
+        let (placement, _) = pre.place(20, 20, 8, 4);
+        assert_eq!(placement.crop, (2, 6, 16, 8));
+    }
+
+    #[test]
+    fn maps_model_points_back_to_the_source() {
+        let pre = Preprocessor {
+            letterbox: true,
+            center_crop: Some(0.9),
+            ..Default::default()
+        };
+        let (_, transform) = pre.place(640, 360, 320, 320);
+        for (x, y) in [(0.0, 0.0), (123.5, 77.25), (639.0, 359.0)] {
+            let model = (
+                x * transform.scale_x + transform.offset_x,
+                y * transform.scale_y + transform.offset_y,
+            );
+            let (sx, sy) = transform.to_source(model.0, model.1);
+            assert!(
+                (sx - x).abs() < 1e-3 && (sy - y).abs() < 1e-3,
+                "{} {}",
+                sx,
+                sy
+            );
+        }
+    }
+
+    #[test]
+    fn reorders_pixels() {
+        let rgb = RgbImage::from_raw(2, 1, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        let pre = Preprocessor::default();
+        assert_eq!(pre.pixels(&rgb, 3).unwrap(), [1, 2, 3, 4, 5, 6]);
+        assert!(pre.pixels(&rgb, 1).is_err());
+        let bgr = Preprocessor {
+            color: ColorOrder::Bgr,
+            ..Default::default()
+        };
+        assert_eq!(bgr.pixels(&rgb, 3).unwrap(), [3, 2, 1, 6, 5, 4]);
+
+        let hwc = vec![1, 2, 3, 4, 5, 6];
+        let input = pre.to_input(hwc.clone(), &nhwc(1, 2, 3)).unwrap();
+        assert_eq!(input.dims, [1, 1, 2, 3]);
+        assert_eq!(input.data, hwc);
+        assert_eq!(
+            input.type_,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8
+        );
+        let input = pre.to_input(hwc, &nchw(1, 2, 3)).unwrap();
+        assert_eq!(input.dims, [1, 3, 1, 2]);
+        assert_eq!(input.data, [1, 4, 2, 5, 3, 6]);
+    }
+
+    #[test]
+    fn normalizes_per_channel() {
+        let pre = Preprocessor {
+            mean: vec![1.0, 2.0, 3.0],
+            std: vec![2.0],
+            ..Default::default()
+        };
+        let hwc = vec![3, 4, 5, 7, 8, 9];
+        let input = pre.to_input(hwc.clone(), &nhwc(1, 2, 3)).unwrap();
+        assert_eq!(
+            input.type_,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32
+        );
+        assert_eq!(f32_values(&input), [1.0, 1.0, 1.0, 3.0, 3.0, 3.0]);
+        let input = pre.to_input(hwc.clone(), &nchw(1, 2, 3)).unwrap();
+        assert_eq!(f32_values(&input), [1.0, 3.0, 1.0, 3.0, 1.0, 3.0]);
+
+        let wrong = Preprocessor {
+            mean: vec![1.0, 2.0],
+            ..Default::default()
+        };
+        let Err(err) = wrong.to_input(hwc, &nhwc(1, 2, 3)) else {
+            panic!("two means for three channels");
+        };
+        assert_eq!(
+            err.to_string(),
+            "--mean has 2 values, the model has 3 channels"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rknn_api_sys::rknn_tensor_attr;
+
+use crate::examples::{
+    common::OutputView,
+    npy::save_output,
+    utils::{get_format_string, get_type_string, safe_string},
+};
+
+/// How long each stage of one inference took, in the order they ran.
+#[derive(Clone, Debug, Default)]
+pub struct Timings(pub Vec<(&'static str, Duration)>);
+
+impl Timings {
+    pub fn record(&mut self, stage: &'static str, duration: Duration) {
+        self.0.push((stage, duration));
+    }
+
+    /// Runs `f`, recording how long it took as `stage`.
+    pub fn time<T>(&mut self, stage: &'static str, f: impl FnOnce() -> T) -> T {
+        let now = Instant::now();
+        let result = f();
+        self.record(stage, now.elapsed());
+        result
+    }
+
+    /// Microseconds per stage.
+    pub fn to_json(&self) -> Value {
+        Value::Object(
+            self.0
+                .iter()
+                .map(|(stage, d)| (stage.to_string(), json!(d.as_micros() as u64)))
+                .collect(),
+        )
+    }
+}
+
+/// Name, shape, layout, type and quantization of a tensor.
+pub fn tensor_json(attr: &rknn_tensor_attr) -> Value {
+    json!({
+        "index": attr.index,
+        "name": safe_string(&attr.name).unwrap_or_default(),
+        "dims": &attr.dims[..attr.n_dims as usize],
+        "fmt": get_format_string(attr.fmt),
+        "type": get_type_string(attr.type_),
+        "zp": attr.zp,
+        "scale": attr.scale,
+    })
+}
+
+/// The part of a result record every example shares; examples add their
+/// own results (`top_k`, `detections`, ...) to it.
+pub fn run_record(
+    input: &str,
+    model: &str,
+    input_attrs: &[rknn_tensor_attr],
+    output_attrs: &[rknn_tensor_attr],
+    timings: &Timings,
+) -> Value {
+    json!({
+        "input": input,
+        "model": model,
+        "inputs": input_attrs.iter().map(tensor_json).collect::<Vec<_>>(),
+        "outputs": output_attrs.iter().map(tensor_json).collect::<Vec<_>>(),
+        "timings_us": timings.to_json(),
+    })
+}
+
+/// The file stem results for the input at `path` are named after.
+pub fn input_stem(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Writes the results of each input to a directory: `<name>.json` and the
+/// raw outputs as `<name>_output<i>.npy`, so runs can be diffed.
+pub struct ResultWriter {
+    dir: PathBuf,
+    names: Mutex<HashSet<String>>,
+}
+
+impl ResultWriter {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(ResultWriter {
+            dir: dir.as_ref().to_path_buf(),
+            names: Mutex::default(),
+        })
+    }
+
+    /// The name the results for the input at `path` are written under: its
+    /// file stem, suffixed with `_<n>` if an earlier input already has it
+    /// (e.g. `a/img.jpg` and `b/img.jpg`).
+    pub fn name(&self, path: &str) -> String {
+        let stem = input_stem(path);
+        let mut names = self.names.lock().unwrap();
+        let mut name = stem.clone();
+        for n in 1.. {
+            if !names.contains(&name) {
+                break;
+            }
+            name = format!("{}_{}", stem, n);
+        }
+        names.insert(name.clone());
+        name
+    }
+
+    pub fn path(&self, file_name: &str) -> PathBuf {
+        self.dir.join(file_name)
+    }
+
+    pub fn write_outputs(&self, name: &str, outputs: &[OutputView]) -> Result<()> {
+        for (i, output) in outputs.iter().enumerate() {
+            save_output(self.path(&format!("{}_output{}.npy", name, i)), output)?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, name: &str, record: &Value, outputs: &[OutputView]) -> Result<()> {
+        self.write_outputs(name, outputs)?;
+        let json_path = self.path(&format!("{}.json", name));
+        std::fs::write(&json_path, serde_json::to_string_pretty(record)?)?;
+        println!(
+            "\x1b[34;4m results written to {}\x1b[0m",
+            json_path.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_inputs_uniquely() {
+        let dir = std::env::temp_dir().join(format!("rknn-report-{}", std::process::id()));
+        let writer = ResultWriter::new(&dir).unwrap();
+        let names = [
+            "a/img.jpg",
+            "b/img.jpg",
+            "img_1.png",
+            "c/img.png",
+            "other.jpg",
+        ]
+        .map(|path| writer.name(path));
+        assert_eq!(names, ["img", "img_1", "img_1_1", "img_2", "other"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use serde_json::json;
+
+use crate::examples::{
+    common::*,
+    preprocess::{input_geometry, Preprocessor},
+    report::{run_record, ResultWriter, Timings},
+    semseg::{LabelMap, Palette},
+};
+
+/// Segment images with a static shape model (e.g. DeepLab) and print the
+/// pixels of each class, optionally writing label maps and overlays.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The path to the model file (*.rknn)
+    #[arg(short, long)]
+    model_path: String,
+
+    /// The images to segment
+    #[arg(short, long, value_parser, required = true)]
+    input_paths: Vec<String>,
+
+    /// The directory a JSON record, the raw outputs (.npy), the label map
+    /// and an overlay of each input are written to
+    #[arg(short, long)]
+    output_dir: Option<String>,
+
+    /// A palette file, one r,g,b color per class, defaults to Pascal VOC's
+    #[arg(long)]
+    palette: Option<String>,
+
+    /// The weight of the class colors in the overlays, from 0 to 1
+    #[arg(long, default_value_t = 0.5)]
+    alpha: f32,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+impl Example {
+    pub fn execute(&self) -> Result<()> {
+        let ctx = RKNNContext::load_model(&self.model_path)?;
+        if ctx.n_input != 1 {
+            bail!("{} has {} inputs, expected 1", self.model_path, ctx.n_input);
+        }
+        ctx.set_core_mask(&self.core_mask)?;
+        let input_attrs = ctx.use_default_shapes()?;
+        let (in_h, in_w, _, _) = input_geometry(&input_attrs[0])?;
+        let palette = match &self.palette {
+            Some(path) => Palette::load(path)?,
+            None => Palette::pascal_voc(256),
+        };
+        let writer = self
+            .output_dir
+            .as_ref()
+            .map(ResultWriter::new)
+            .transpose()?;
+
+        let mut results = Vec::with_capacity(self.input_paths.len());
+        for path in &self.input_paths {
+            let img = image::ImageReader::open(path)?.decode()?;
+            let mut timings = Timings::default();
+            let transforms = timings.time("set_inputs", || {
+                ctx.set_inputs(&input_attrs, std::slice::from_ref(&img), &self.preprocess)
+            })?;
+            timings.time("run", || ctx.run())?;
+            let outputs = timings.time("get_outputs", || ctx.fetch_outputs(true))?;
+            let map = timings.time("postprocess", || LabelMap::argmax(&outputs.view(0)?))?;
+            let histogram = map.histogram();
+            let classes = histogram
+                .iter()
+                .map(|(class, count)| json!({ "class": class, "pixels": count }))
+                .collect::<Vec<_>>();
+
+            if let Some(writer) = &writer {
+                let stem = writer.name(path);
+                let source = map.to_source((in_w, in_h), &transforms[0]);
+                source
+                    .colorize(&palette)
+                    .save(writer.path(&format!("{}_labels.png", stem)))?;
+                source
+                    .overlay(&img.to_rgb8(), &palette, self.alpha)?
+                    .save(writer.path(&format!("{}_overlay.png", stem)))?;
+                let mut record = run_record(
+                    path,
+                    &self.model_path,
+                    &input_attrs,
+                    outputs.attrs(),
+                    &timings,
+                );
+                record["classes"] = json!(classes);
+                let views = (0..outputs.len())
+                    .map(|i| outputs.view(i))
+                    .collect::<Result<Vec<_>>>()?;
+                writer.write(&stem, &record, &views)?;
+            }
+            if self.json {
+                results.push(json!({ "input": path, "classes": classes }));
+            } else {
+                println!("\x1b[34;4m {}\x1b[0m", path);
+                for (class, count) in &histogram {
+                    println!("  {:>5}: {} pixels", class, count);
+                }
+            }
+        }
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&results)?);
+        }
+        Ok(())
+    }
+}
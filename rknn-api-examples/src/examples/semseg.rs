@@ -0,0 +1,322 @@
+use anyhow::{bail, Context, Result};
+use image::{Rgb, RgbImage};
+use std::path::Path;
+
+use crate::examples::{common::OutputView, preprocess::ImageTransform};
+
+/// The class of every pixel of a segmentation output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelMap {
+    pub width: u32,
+    pub height: u32,
+    /// Row major, `width * height` class indices.
+    pub labels: Vec<u16>,
+}
+
+/// A color per class index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette(pub Vec<Rgb<u8>>);
+
+impl Palette {
+    /// The Pascal VOC colormap, which spreads the bits of the class index
+    /// over the high bits of the three channels.
+    pub fn pascal_voc(n: usize) -> Self {
+        Palette(
+            (0..n)
+                .map(|class| {
+                    let mut rgb = [0u8; 3];
+                    let mut c = class;
+                    for shift in (0..8).rev() {
+                        for (channel, value) in rgb.iter_mut().enumerate() {
+                            *value |= (((c >> channel) & 1) as u8) << shift;
+                        }
+                        c >>= 3;
+                    }
+                    Rgb(rgb)
+                })
+                .collect(),
+        )
+    }
+
+    /// Reads one `r,g,b` (or `r g b`) color per line.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read palette {}", path.display()))?;
+        let colors = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let values = line
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.parse::<u8>())
+                    .collect::<Result<Vec<_>, _>>()?;
+                match values[..] {
+                    [r, g, b] => Ok(Rgb([r, g, b])),
+                    _ => bail!("'{}' is not an r,g,b color", line),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if colors.is_empty() {
+            bail!("palette {} has no colors", path.display());
+        }
+        Ok(Palette(colors))
+    }
+
+    /// The color of `class`, cycling through the palette; black when it is
+    /// empty.
+    pub fn color(&self, class: u16) -> Rgb<u8> {
+        self.0
+            .get(class as usize % self.0.len().max(1))
+            .copied()
+            .unwrap_or(Rgb([0, 0, 0]))
+    }
+}
+
+impl LabelMap {
+    /// Takes the highest scoring class of each pixel of a `[1, C, H, W]` or
+    /// `[1, H, W, C]` output. Single channel outputs already hold the class.
+    pub fn argmax(output: &OutputView) -> Result<Self> {
+        if output.dims().len() != 4 {
+            bail!("expected a 4-D output, got {:?}", output.dims());
+        }
+        let (channels, height, width) = output.chw();
+        let mut labels = Vec::with_capacity(height * width);
+        for y in 0..height {
+            for x in 0..width {
+                let label = if channels == 1 {
+                    output.at(0, y, x).round().max(0.0) as usize
+                } else {
+                    (0..channels)
+                        .map(|c| (c, output.at(c, y, x)))
+                        .fold((0, f32::NEG_INFINITY), |best, cur| {
+                            if cur.1 > best.1 {
+                                cur
+                            } else {
+                                best
+                            }
+                        })
+                        .0
+                };
+                labels.push(label as u16);
+            }
+        }
+        Ok(LabelMap {
+            width: width as u32,
+            height: height as u32,
+            labels,
+        })
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> u16 {
+        self.labels[(y * self.width + x) as usize]
+    }
+
+    /// Resamples the map (nearest) onto the source image the model input was
+    /// made from. `input_size` is the model's (width, height).
+    pub fn to_source(&self, (in_w, in_h): (u32, u32), transform: &ImageTransform) -> LabelMap {
+        let (width, height) = (transform.source_width, transform.source_height);
+        let mut labels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mx = (x as f32 + 0.5) * transform.scale_x + transform.offset_x;
+                let my = (y as f32 + 0.5) * transform.scale_y + transform.offset_y;
+                let lx = (mx * self.width as f32 / in_w as f32).floor();
+                let ly = (my * self.height as f32 / in_h as f32).floor();
+                let lx = (lx.max(0.0) as u32).min(self.width - 1);
+                let ly = (ly.max(0.0) as u32).min(self.height - 1);
+                labels.push(self.get(lx, ly));
+            }
+        }
+        LabelMap {
+            width,
+            height,
+            labels,
+        }
+    }
+
+    /// The number of pixels of each class present, by class.
+    pub fn histogram(&self) -> Vec<(u16, usize)> {
+        let mut counts = std::collections::BTreeMap::new();
+        for label in &self.labels {
+            *counts.entry(*label).or_insert(0) += 1;
+        }
+        counts.into_iter().collect()
+    }
+
+    pub fn colorize(&self, palette: &Palette) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            palette.color(self.get(x, y))
+        })
+    }
+
+    /// Blends the class colors over `img`, which must be the map's size,
+    /// with weight `alpha` in `0..=1`. Class 0 (background) is left
+    /// untouched.
+    pub fn overlay(&self, img: &RgbImage, palette: &Palette, alpha: f32) -> Result<RgbImage> {
+        if !(0.0..=1.0).contains(&alpha) {
+            bail!("overlay alpha {} is not between 0 and 1", alpha);
+        }
+        if img.dimensions() != (self.width, self.height) {
+            bail!(
+                "{}x{} image for a {}x{} label map",
+                img.width(),
+                img.height(),
+                self.width,
+                self.height
+            );
+        }
+        let mut out = img.clone();
+        for (x, y, p) in out.enumerate_pixels_mut() {
+            let label = self.get(x, y);
+            if label == 0 {
+                continue;
+            }
+            let color = palette.color(label);
+            for c in 0..3 {
+                p[c] = (p[c] as f32 * (1.0 - alpha) + color[c] as f32 * alpha).round() as u8;
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::common::OutputData;
+    use rknn_api_sys::{
+        _rknn_tensor_format_RKNN_TENSOR_NCHW, _rknn_tensor_format_RKNN_TENSOR_NHWC,
+        _rknn_tensor_qnt_type_RKNN_TENSOR_QNT_AFFINE_ASYMMETRIC, rknn_tensor_attr,
+        rknn_tensor_format,
+    };
+
+    #[test]
+    fn loads_palettes() {
+        let path = std::env::temp_dir().join(format!("palette-{}.txt", std::process::id()));
+        std::fs::write(&path, "0,0,0\n\n128 64 32\n").unwrap();
+        let palette = Palette::load(&path).unwrap();
+        assert_eq!(palette.0, [Rgb([0, 0, 0]), Rgb([128, 64, 32])]);
+        assert_eq!(palette.color(3), Rgb([128, 64, 32]));
+
+        std::fs::write(&path, "\n  \n").unwrap();
+        assert!(Palette::load(&path).is_err());
+        std::fs::write(&path, "1,2\n").unwrap();
+        assert!(Palette::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn attr(fmt: rknn_tensor_format, dims: &[u32]) -> rknn_tensor_attr {
+        let mut attr = rknn_tensor_attr {
+            n_dims: dims.len() as u32,
+            n_elems: dims.iter().product(),
+            fmt,
+            ..Default::default()
+        };
+        attr.dims[..dims.len()].copy_from_slice(dims);
+        attr
+    }
+
+    fn quantized(attr: rknn_tensor_attr, zp: i32, scale: f32) -> rknn_tensor_attr {
+        rknn_tensor_attr {
+            qnt_type: _rknn_tensor_qnt_type_RKNN_TENSOR_QNT_AFFINE_ASYMMETRIC,
+            zp,
+            scale,
+            ..attr
+        }
+    }
+
+    #[test]
+    fn takes_the_best_class_in_either_layout() {
+        // 3 classes over 2x2 pixels, NCHW.
+        let scores = [
+            0.9, 0.1, 0.2, 0.3, //
+            0.0, 0.8, 0.2, 0.1, //
+            0.1, 0.0, 0.7, 0.2,
+        ];
+        let expected = LabelMap {
+            width: 2,
+            height: 2,
+            labels: vec![0, 1, 2, 0],
+        };
+        let nchw = attr(_rknn_tensor_format_RKNN_TENSOR_NCHW, &[1, 3, 2, 2]);
+        let map = LabelMap::argmax(&OutputView::from_f32(&scores, nchw)).unwrap();
+        assert_eq!(map, expected);
+
+        let nhwc_scores: Vec<f32> = (0..12).map(|i| scores[(i % 3) * 4 + i / 3]).collect();
+        let nhwc = attr(_rknn_tensor_format_RKNN_TENSOR_NHWC, &[1, 2, 2, 3]);
+        let map = LabelMap::argmax(&OutputView::from_f32(&nhwc_scores, nhwc)).unwrap();
+        assert_eq!(map, expected);
+
+        // The same scores quantized with zp -10 and scale 0.01.
+        let raw: Vec<i8> = scores.iter().map(|s| (s * 100.0) as i8 - 10).collect();
+        let view = OutputView::new(OutputData::I8(&raw), quantized(nchw, -10, 0.01));
+        assert_eq!(LabelMap::argmax(&view).unwrap(), expected);
+
+        let flat = attr(_rknn_tensor_format_RKNN_TENSOR_NCHW, &[1, 12]);
+        assert!(LabelMap::argmax(&OutputView::from_f32(&scores, flat)).is_err());
+    }
+
+    #[test]
+    fn reads_class_indices_from_one_channel() {
+        let nchw = attr(_rknn_tensor_format_RKNN_TENSOR_NCHW, &[1, 1, 2, 2]);
+        let classes = [0.0, 2.2, 0.9, -1.0];
+        let map = LabelMap::argmax(&OutputView::from_f32(&classes, nchw)).unwrap();
+        assert_eq!(map.labels, [0, 2, 1, 0]);
+
+        // Dequantized with scale 0.5 first.
+        let raw = [0i8, 4, 2, 6];
+        let view = OutputView::new(OutputData::I8(&raw), quantized(nchw, 0, 0.5));
+        assert_eq!(LabelMap::argmax(&view).unwrap().labels, [0, 2, 1, 3]);
+    }
+
+    #[test]
+    fn maps_labels_through_a_letterbox() {
+        // A 2x2 map of a 4x4 input holding an 8x4 source in rows 1 and 2.
+        let map = LabelMap {
+            width: 2,
+            height: 2,
+            labels: vec![1, 2, 3, 4],
+        };
+        let transform = ImageTransform {
+            scale_x: 0.5,
+            scale_y: 0.5,
+            offset_x: 0.0,
+            offset_y: 1.0,
+            source_width: 8,
+            source_height: 4,
+        };
+        let source = map.to_source((4, 4), &transform);
+        assert_eq!((source.width, source.height), (8, 4));
+        let rows: Vec<&[u16]> = source.labels.chunks(8).collect();
+        assert_eq!(rows[0], [1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(rows[1], rows[0]);
+        assert_eq!(rows[2], [3, 3, 3, 3, 4, 4, 4, 4]);
+        assert_eq!(rows[3], rows[2]);
+        assert_eq!(source.histogram(), [(1, 8), (2, 8), (3, 8), (4, 8)]);
+    }
+
+    #[test]
+    fn blends_class_colors() {
+        let map = LabelMap {
+            width: 2,
+            height: 1,
+            labels: vec![0, 1],
+        };
+        let palette = Palette(vec![Rgb([0, 0, 0]), Rgb([200, 0, 50])]);
+        let img = RgbImage::from_pixel(2, 1, Rgb([100, 100, 100]));
+        let blend = |alpha| {
+            let out = map.overlay(&img, &palette, alpha).unwrap();
+            (out.get_pixel(0, 0).0, out.get_pixel(1, 0).0)
+        };
+        assert_eq!(blend(0.5), ([100; 3], [150, 50, 75]));
+        assert_eq!(blend(0.0), ([100; 3], [100; 3]));
+        assert_eq!(blend(1.0), ([100; 3], [200, 0, 50]));
+        for alpha in [-0.1, 1.5, f32::NAN] {
+            assert!(map.overlay(&img, &palette, alpha).is_err(), "{}", alpha);
+        }
+        let small = RgbImage::new(1, 1);
+        assert!(map.overlay(&small, &palette, 0.5).is_err());
+    }
+}
@@ -0,0 +1,1086 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use half::f16;
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    io::{Cursor, Read},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tiny_http::{Header, Method, Request, Response};
+
+use rknn_api_sys::rknn_tensor_attr;
+
+use crate::examples::{
+    common::*,
+    info::ModelInfo,
+    npy::{element_count, read_header, write_npz, NpyArray},
+    pool::{Pool, Runtime, SubmitError},
+    preprocess::Preprocessor,
+    report::{input_stem, tensor_json},
+    utils::safe_string,
+};
+
+/// Serve models over HTTP, for programs that can't link the runtime.
+///
+/// `GET /v1/models` lists the models, `GET /v1/models/{name}` describes one
+/// and `POST /v1/models/{name}/infer` runs it on the body: JSON tensors
+/// (`{"inputs": [{"data": [...], "shape": [...], "dtype": "float32"}]}`, or
+/// keyed by input name), a `.npy` array, an image, or a multipart form of
+/// those, one part per input. The outputs come back as JSON, or as an `.npz`
+/// archive with `Accept: application/x-npz`. Ctrl-C stops taking requests and
+/// finishes the ones in flight.
+#[derive(Debug, Parser)]
+#[command(name = "rknn-serve", version)]
+pub struct Example {
+    /// A model to serve, as NAME=PATH or PATH to name it after the file;
+    /// repeat for more
+    #[arg(short, long = "model", required = true)]
+    models: Vec<String>,
+
+    /// The address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// Contexts per model, sharing the weights of the first
+    #[arg(short = 'j', long, default_value_t = 1)]
+    contexts: usize,
+
+    /// The core masks of each model's contexts, assigned in turn, e.g.
+    /// `npu0,npu1,npu2`
+    #[arg(short, long, value_enum, value_delimiter = ',', default_value = "auto")]
+    core_masks: Vec<RknnCoreMask>,
+
+    /// Requests each model queues before answering 503
+    #[arg(short, long, default_value_t = 32)]
+    queue_depth: usize,
+
+    /// How long a free context waits for more requests to fill the batch of
+    /// a model with a batch dimension, in milliseconds
+    #[arg(long, default_value_t = 5)]
+    batch_wait_ms: u64,
+
+    /// Requests handled at once
+    #[arg(long, default_value_t = 16)]
+    threads: usize,
+
+    /// The largest request body accepted, in MiB
+    #[arg(long, default_value_t = 64)]
+    max_body_mib: usize,
+
+    /// How images are turned into inputs
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+/// An error answered with its own status instead of a 500.
+#[derive(Debug)]
+struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+fn http_error(status: u16, message: impl Into<String>) -> anyhow::Error {
+    HttpError {
+        status,
+        message: message.into(),
+    }
+    .into()
+}
+
+fn bad_request(err: anyhow::Error) -> anyhow::Error {
+    http_error(400, format!("{:#}", err))
+}
+
+fn status_of(err: &anyhow::Error) -> u16 {
+    if let Some(err) = err.downcast_ref::<HttpError>() {
+        err.status
+    } else if err.downcast_ref::<SubmitError>().is_some() {
+        503
+    } else {
+        500
+    }
+}
+
+/// The batch a model runs: the leading dim shared by all its inputs and
+/// outputs, or 1 when they don't share one.
+pub fn batch_size(inputs: &[rknn_tensor_attr], outputs: &[rknn_tensor_attr]) -> usize {
+    let Some(first) = inputs.first() else {
+        return 1;
+    };
+    let batch = first.dims[0] as usize;
+    let shared = inputs
+        .iter()
+        .chain(outputs)
+        .all(|attr| attr.n_dims >= 2 && attr.dims[0] as usize == batch);
+    if shared {
+        batch.max(1)
+    } else {
+        1
+    }
+}
+
+fn attr_dims(attr: &rknn_tensor_attr) -> Vec<usize> {
+    attr.dims[..attr.n_dims as usize]
+        .iter()
+        .map(|d| *d as usize)
+        .collect()
+}
+
+/// A model being served and the contexts it runs on.
+pub struct Model {
+    pub name: String,
+    pub inputs: Vec<rknn_tensor_attr>,
+    pub outputs: Vec<rknn_tensor_attr>,
+    /// What the runtime reported about the model at startup.
+    pub metadata: Value,
+    pub contexts: usize,
+    pub pool: Pool,
+}
+
+impl Model {
+    pub fn new(
+        name: &str,
+        inputs: Vec<rknn_tensor_attr>,
+        outputs: Vec<rknn_tensor_attr>,
+        metadata: Value,
+        runtimes: Vec<Box<dyn Runtime>>,
+        queue_depth: usize,
+        batch_wait: Duration,
+    ) -> Self {
+        let batch = batch_size(&inputs, &outputs);
+        Model {
+            name: name.to_string(),
+            contexts: runtimes.len(),
+            pool: Pool::new(runtimes, batch, queue_depth, batch_wait),
+            inputs,
+            outputs,
+            metadata,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let stats = self.pool.stats();
+        let mut value = self.metadata.clone();
+        value["name"] = json!(self.name);
+        value["inputs"] = json!(self.inputs.iter().map(tensor_json).collect::<Vec<_>>());
+        value["outputs"] = json!(self.outputs.iter().map(tensor_json).collect::<Vec<_>>());
+        value["batch_size"] = json!(self.pool.batch_size());
+        value["contexts"] = json!(self.contexts);
+        value["stats"] = json!({
+            "requests": stats.requests.load(Ordering::Relaxed),
+            "batches": stats.batches.load(Ordering::Relaxed),
+        });
+        value
+    }
+
+    /// Checks the size of a `dims` shaped tensor for input `index`, which
+    /// may be one sample of a batched model. Types and layouts are left to
+    /// the runtime.
+    fn check_shape(&self, index: usize, dims: &[usize]) -> Result<()> {
+        let attr = &self.inputs[index];
+        let expected = attr_dims(attr);
+        let mut elems: usize = expected.iter().product();
+        let batch = self.pool.batch_size();
+        if batch > 1 && dims.first() == Some(&1) {
+            elems /= batch;
+        }
+        if element_count(dims).ok() != Some(elems) {
+            bail!(
+                "input {} has shape {:?}, the model expects {:?}",
+                attr.index,
+                dims,
+                expected
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks the number and sizes of `inputs`.
+    fn check_inputs(&self, inputs: &[TensorInput]) -> Result<()> {
+        if inputs.len() != self.inputs.len() {
+            bail!(
+                "{} inputs given, the model has {}",
+                inputs.len(),
+                self.inputs.len()
+            );
+        }
+        for (index, input) in inputs.iter().enumerate() {
+            self.check_shape(index, &input.dims)?;
+        }
+        Ok(())
+    }
+}
+
+/// The value of `key` in a `value; key=value; ...` header, unquoted.
+fn header_param(value: &str, key: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (k, v) = param.trim().split_once('=')?;
+        k.eq_ignore_ascii_case(key)
+            .then(|| v.trim().trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| from + i)
+}
+
+/// A `multipart/form-data` part: the form field it is for and its content.
+struct Part {
+    name: Option<String>,
+    data: Vec<u8>,
+}
+
+fn multipart_parts(body: &[u8], boundary: &str) -> Result<Vec<Part>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut pos = find(body, &delimiter, 0).context("no multipart boundary in the body")?;
+    let mut parts = Vec::new();
+    loop {
+        pos += delimiter.len();
+        if body[pos..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        let headers_end = find(body, b"\r\n\r\n", pos).context("truncated multipart headers")?;
+        let headers = String::from_utf8_lossy(&body[pos..headers_end]);
+        let name = headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(field, _)| field.trim().eq_ignore_ascii_case("content-disposition"))
+            .and_then(|(_, value)| header_param(value, "name"));
+        let start = headers_end + 4;
+        let mut end_marker = b"\r\n".to_vec();
+        end_marker.extend_from_slice(&delimiter);
+        let end = find(body, &end_marker, start).context("truncated multipart part")?;
+        parts.push(Part {
+            name,
+            data: body[start..end].to_vec(),
+        });
+        pos = end + 2;
+    }
+}
+
+/// Appends the numbers of nested arrays to `values`, working out their
+/// shape on the way.
+fn flatten(
+    value: &Value,
+    depth: usize,
+    shape: &mut Vec<usize>,
+    values: &mut Vec<f64>,
+) -> Result<()> {
+    match value {
+        Value::Number(n) if depth == shape.len() => {
+            values.push(n.as_f64().context("bad number")?);
+        }
+        Value::Array(items) => {
+            if depth == shape.len() {
+                // Numbers seen already fixed the rank.
+                if !values.is_empty() {
+                    bail!("ragged tensor data");
+                }
+                shape.push(items.len());
+            } else if shape[depth] != items.len() {
+                bail!("ragged tensor data");
+            }
+            for item in items {
+                flatten(item, depth + 1, shape, values)?;
+            }
+        }
+        Value::Number(_) => bail!("ragged tensor data"),
+        other => bail!("tensor data holds {}, not numbers", other),
+    }
+    Ok(())
+}
+
+/// A tensor given as `{"data": [...], "shape": [...], "dtype": "float32"}`,
+/// or as its data alone. The data may be flat or nested; the shape defaults
+/// to the nesting and the dtype to float32.
+fn json_tensor(value: &Value) -> Result<TensorInput> {
+    let (data, shape, dtype) = match value {
+        Value::Object(tensor) => (
+            tensor.get("data").context("a tensor without data")?,
+            tensor.get("shape"),
+            tensor.get("dtype").and_then(Value::as_str),
+        ),
+        data => (data, None, None),
+    };
+    let (mut nested, mut values) = (Vec::new(), Vec::new());
+    flatten(data, 0, &mut nested, &mut values)?;
+    let shape: Vec<usize> = match shape {
+        Some(shape) => serde_json::from_value(shape.clone()).context("bad tensor shape")?,
+        None => nested,
+    };
+    let array = match dtype.unwrap_or("float32") {
+        "float32" => NpyArray::new(
+            &shape,
+            &values.iter().map(|v| *v as f32).collect::<Vec<_>>(),
+        ),
+        "float16" => NpyArray::new(
+            &shape,
+            &values.iter().map(|v| f16::from_f64(*v)).collect::<Vec<_>>(),
+        ),
+        "int8" => NpyArray::new(&shape, &values.iter().map(|v| *v as i8).collect::<Vec<_>>()),
+        "uint8" => NpyArray::new(&shape, &values.iter().map(|v| *v as u8).collect::<Vec<_>>()),
+        "int32" => NpyArray::new(
+            &shape,
+            &values.iter().map(|v| *v as i32).collect::<Vec<_>>(),
+        ),
+        "int64" => NpyArray::new(
+            &shape,
+            &values.iter().map(|v| *v as i64).collect::<Vec<_>>(),
+        ),
+        other => bail!("unsupported dtype {}", other),
+    }?;
+    Ok(array.into_input())
+}
+
+/// A `.npy` array, or an image preprocessed for input `index`. The array's
+/// shape is checked before its data is read.
+fn bytes_input(
+    data: &[u8],
+    model: &Model,
+    index: usize,
+    preprocess: &Preprocessor,
+) -> Result<TensorInput> {
+    if data.starts_with(b"\x93NUMPY") {
+        let mut reader = Cursor::new(data);
+        let (dtype, shape) = read_header(&mut reader)?;
+        model.check_shape(index, &shape)?;
+        return Ok(NpyArray::read_data(&mut reader, dtype, shape)?.into_input());
+    }
+    let img = image::load_from_memory(data).context("neither a .npy array nor an image")?;
+    Ok(preprocess.run(&img, &model.inputs[index])?.0)
+}
+
+fn input_names(attrs: &[rknn_tensor_attr]) -> Vec<String> {
+    attrs
+        .iter()
+        .map(|attr| safe_string(&attr.name).unwrap_or_default())
+        .collect()
+}
+
+/// Which model input each of `names` is for: when all of them name
+/// different inputs, those, otherwise the inputs in order.
+fn input_indices(names: &[Option<&str>], attrs: &[rknn_tensor_attr]) -> Result<Vec<usize>> {
+    if names.len() != attrs.len() {
+        bail!(
+            "{} inputs given, the model has {}",
+            names.len(),
+            attrs.len()
+        );
+    }
+    let attr_names = input_names(attrs);
+    let by_name: Option<Vec<usize>> = names
+        .iter()
+        .map(|name| attr_names.iter().position(|a| Some(a.as_str()) == *name))
+        .collect();
+    if let Some(indices) = by_name {
+        let mut distinct = indices.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        if distinct.len() == indices.len() {
+            return Ok(indices);
+        }
+    }
+    Ok((0..names.len()).collect())
+}
+
+/// Puts each input where `input_indices` says it goes.
+fn arrange(
+    names: &[Option<&str>],
+    inputs: Vec<TensorInput>,
+    attrs: &[rknn_tensor_attr],
+) -> Result<Vec<TensorInput>> {
+    let indices = input_indices(names, attrs)?;
+    let mut arranged: Vec<Option<TensorInput>> = (0..attrs.len()).map(|_| None).collect();
+    for (index, input) in indices.into_iter().zip(inputs) {
+        arranged[index] = Some(input);
+    }
+    Ok(arranged.into_iter().flatten().collect())
+}
+
+/// The model inputs in a request body of `content_type`.
+fn decode_inputs(
+    content_type: &str,
+    body: &[u8],
+    model: &Model,
+    preprocess: &Preprocessor,
+) -> Result<Vec<TensorInput>> {
+    let attrs = &model.inputs;
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match mime.as_str() {
+        "application/json" => {
+            let request: Value = serde_json::from_slice(body).context("bad JSON")?;
+            match request.get("inputs") {
+                Some(Value::Array(tensors)) => {
+                    let inputs = tensors
+                        .iter()
+                        .map(json_tensor)
+                        .collect::<Result<Vec<_>>>()?;
+                    arrange(&vec![None; inputs.len()], inputs, attrs)
+                }
+                Some(Value::Object(tensors)) => {
+                    let input_names = input_names(attrs);
+                    if let Some(unknown) = tensors.keys().find(|k| !input_names.contains(k)) {
+                        bail!("the model has no input named {}", unknown);
+                    }
+                    let names: Vec<Option<&str>> =
+                        tensors.keys().map(|k| Some(k.as_str())).collect();
+                    let inputs = tensors
+                        .values()
+                        .map(json_tensor)
+                        .collect::<Result<Vec<_>>>()?;
+                    arrange(&names, inputs, attrs)
+                }
+                _ => bail!("expected {{\"inputs\": [...]}}"),
+            }
+        }
+        "multipart/form-data" => {
+            let boundary = header_param(content_type, "boundary")
+                .context("a multipart body without a boundary")?;
+            let parts = multipart_parts(body, &boundary)?;
+            let names: Vec<Option<&str>> = parts.iter().map(|p| p.name.as_deref()).collect();
+            let indices = input_indices(&names, attrs)?;
+            let mut inputs: Vec<(usize, TensorInput)> = parts
+                .iter()
+                .zip(indices)
+                .map(|(part, i)| {
+                    let input = bytes_input(&part.data, model, i, preprocess)
+                        .with_context(|| format!("input {}", i))?;
+                    Ok((i, input))
+                })
+                .collect::<Result<_>>()?;
+            inputs.sort_by_key(|(i, _)| *i);
+            Ok(inputs.into_iter().map(|(_, input)| input).collect())
+        }
+        // .npy, images and anything else sent as is: sniffed.
+        _ => {
+            if attrs.len() != 1 {
+                bail!(
+                    "the model has {} inputs, send them as JSON or a multipart form",
+                    attrs.len()
+                );
+            }
+            Ok(vec![bytes_input(body, model, 0, preprocess)?])
+        }
+    }
+}
+
+fn header(request: &Request, field: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(field))
+        .map(|h| h.value.as_str().to_string())
+}
+
+fn content_type(mime: &str) -> Header {
+    Header::from_bytes("Content-Type", mime).expect("an ASCII header")
+}
+
+fn json_response(status: u16, value: &Value) -> Response<Cursor<Vec<u8>>> {
+    Response::from_data(value.to_string())
+        .with_status_code(status)
+        .with_header(content_type("application/json"))
+}
+
+fn read_body(request: &mut Request, max: usize) -> Result<Vec<u8>> {
+    let too_large = || http_error(413, format!("the body is over {} bytes", max));
+    if request.body_length().is_some_and(|n| n > max) {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(max as u64 + 1)
+        .read_to_end(&mut body)?;
+    if body.len() > max {
+        return Err(too_large());
+    }
+    Ok(body)
+}
+
+/// Serves a set of models until its stop flag is set.
+pub struct Server {
+    http: tiny_http::Server,
+    models: Vec<Model>,
+    preprocess: Preprocessor,
+    max_body: usize,
+    stop: Arc<AtomicBool>,
+}
+
+impl Server {
+    pub fn bind(
+        addr: &str,
+        models: Vec<Model>,
+        preprocess: Preprocessor,
+        max_body: usize,
+    ) -> Result<Self> {
+        for (i, model) in models.iter().enumerate() {
+            if models[..i].iter().any(|m| m.name == model.name) {
+                bail!("two models are named {}", model.name);
+            }
+        }
+        let http = tiny_http::Server::http(addr)
+            .map_err(|err| anyhow::anyhow!("failed to listen on {}: {}", addr, err))?;
+        Ok(Server {
+            http,
+            models,
+            preprocess,
+            max_body,
+            stop: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    /// Setting it makes [`Server::run`] return once the requests in flight
+    /// are answered.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Handles requests on `threads` threads until stopped, then answers
+    /// the requests already accepted and lets the pools drain.
+    pub fn run(self, threads: usize) -> Result<()> {
+        thread::scope(|s| {
+            for _ in 0..threads.max(1) {
+                s.spawn(|| loop {
+                    let request = if self.stop.load(Ordering::Relaxed) {
+                        self.http.try_recv()
+                    } else {
+                        self.http.recv_timeout(Duration::from_millis(100))
+                    };
+                    match request {
+                        Ok(Some(request)) => self.respond(request),
+                        Ok(None) if self.stop.load(Ordering::Relaxed) => break,
+                        Ok(None) => {}
+                        Err(err) => {
+                            eprintln!("failed to accept a request: {}", err);
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    fn model(&self, name: &str) -> Result<&Model> {
+        self.models
+            .iter()
+            .find(|m| m.name == name)
+            .ok_or_else(|| http_error(404, format!("no model named {}", name)))
+    }
+
+    fn respond(&self, mut request: Request) {
+        let start = Instant::now();
+        let response = self.handle(&mut request).unwrap_or_else(|err| {
+            json_response(status_of(&err), &json!({ "error": format!("{:#}", err) }))
+        });
+        println!(
+            "{} {} {} {:.1} ms",
+            request.method(),
+            request.url(),
+            response.status_code().0,
+            start.elapsed().as_secs_f64() * 1e3
+        );
+        if let Err(err) = request.respond(response) {
+            eprintln!("failed to respond: {}", err);
+        }
+    }
+
+    fn handle(&self, request: &mut Request) -> Result<Response<Cursor<Vec<u8>>>> {
+        let path = request
+            .url()
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let method = request.method().clone();
+        match (&method, segments.as_slice()) {
+            (Method::Get, ["healthz"]) => Ok(json_response(200, &json!({ "status": "ok" }))),
+            (Method::Get, ["v1", "models"]) => {
+                let models: Vec<Value> = self.models.iter().map(Model::to_json).collect();
+                Ok(json_response(200, &json!({ "models": models })))
+            }
+            (Method::Get, ["v1", "models", name]) => {
+                Ok(json_response(200, &self.model(name)?.to_json()))
+            }
+            (Method::Post, ["v1", "models", name, "infer"]) => {
+                self.infer(self.model(name)?, request)
+            }
+            (
+                _,
+                ["healthz"] | ["v1", "models"] | ["v1", "models", _] | ["v1", "models", _, "infer"],
+            ) => Err(http_error(
+                405,
+                format!("{} is not allowed on {}", method, path),
+            )),
+            _ => Err(http_error(404, format!("no route for {}", path))),
+        }
+    }
+
+    fn infer(&self, model: &Model, request: &mut Request) -> Result<Response<Cursor<Vec<u8>>>> {
+        let mime = header(request, "Content-Type").unwrap_or_default();
+        let npz = header(request, "Accept").is_some_and(|a| a.contains("application/x-npz"));
+        let body = read_body(request, self.max_body)?;
+        let inputs = decode_inputs(&mime, &body, model, &self.preprocess)
+            .and_then(|inputs| model.check_inputs(&inputs).map(|_| inputs))
+            .map_err(bad_request)?;
+        let sample =
+            model.pool.batch_size() > 1 && inputs.iter().all(|i| i.dims.first() == Some(&1));
+        let reply = model
+            .pool
+            .submit(inputs)?
+            .recv()
+            .context("the runtime stopped")??;
+
+        let outputs: Vec<(String, Vec<usize>, &[f32])> = model
+            .outputs
+            .iter()
+            .zip(&reply.outputs)
+            .map(|(attr, data)| {
+                let mut shape = attr_dims(attr);
+                if sample {
+                    shape[0] = 1;
+                }
+                // The runtime's buffer may be padded past the last element.
+                let n = shape.iter().product::<usize>().min(data.len());
+                (
+                    safe_string(&attr.name).unwrap_or_default(),
+                    shape,
+                    &data[..n],
+                )
+            })
+            .collect();
+        if npz {
+            let arrays = outputs
+                .iter()
+                .map(|(name, shape, data)| Ok((name.clone(), NpyArray::new(shape, data)?)))
+                .collect::<Result<Vec<_>>>()?;
+            let mut bytes = Vec::new();
+            write_npz(Cursor::new(&mut bytes), &arrays)?;
+            return Ok(Response::from_data(bytes).with_header(content_type("application/x-npz")));
+        }
+        let outputs: Vec<Value> = outputs
+            .iter()
+            .map(|(name, shape, data)| {
+                json!({ "name": name, "shape": shape, "dtype": "float32", "data": data })
+            })
+            .collect();
+        Ok(json_response(
+            200,
+            &json!({
+                "model": model.name,
+                "outputs": outputs,
+                "batch": reply.batch,
+                "timings_us": {
+                    "queue": reply.queued.as_micros() as u64,
+                    "infer": reply.infer.as_micros() as u64,
+                },
+            }),
+        ))
+    }
+}
+
+impl Example {
+    /// Loads a `NAME=PATH` or `PATH` model with its contexts.
+    fn load(&self, spec: &str) -> Result<Model> {
+        let (name, path) = match spec.split_once('=') {
+            Some((name, path)) => (name.to_string(), path),
+            None => (input_stem(spec), spec),
+        };
+        let mut contexts: Vec<RKNNContext> = Vec::with_capacity(self.contexts);
+        for i in 0..self.contexts {
+            let ctx = match contexts.first() {
+                Some(first) => first.dup()?,
+                None => RKNNContext::load_model(path)?,
+            };
+            ctx.set_core_mask(&self.core_masks[i % self.core_masks.len()])?;
+            ctx.use_default_shapes()?;
+            contexts.push(ctx);
+        }
+        let inputs = contexts[0].get_input_attrs()?;
+        let outputs = contexts[0].get_output_attrs()?;
+        let mut metadata = ModelInfo::query(&contexts[0])?.to_json();
+        metadata["path"] = json!(path);
+        metadata["platform"] = json!(PLATFORM);
+        let runtimes = contexts
+            .into_iter()
+            .map(|ctx| Box::new(ctx) as Box<dyn Runtime>)
+            .collect();
+        Ok(Model::new(
+            &name,
+            inputs,
+            outputs,
+            metadata,
+            runtimes,
+            self.queue_depth,
+            Duration::from_millis(self.batch_wait_ms),
+        ))
+    }
+
+    pub fn execute(&self) -> Result<()> {
+        if self.contexts == 0 || self.core_masks.is_empty() {
+            bail!("at least one context and one core mask are needed");
+        }
+        let models = self
+            .models
+            .iter()
+            .map(|spec| {
+                self.load(spec)
+                    .with_context(|| format!("failed to load {}", spec))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for model in &models {
+            println!(
+                "\x1b[34;4m {}: {} contexts, batch size {}\x1b[0m",
+                model.name,
+                model.contexts,
+                model.pool.batch_size()
+            );
+        }
+        let server = Server::bind(
+            &self.listen,
+            models,
+            self.preprocess.clone(),
+            self.max_body_mib << 20,
+        )?;
+        let stop = server.stop_flag();
+        ctrlc::set_handler(move || stop.store(true, Ordering::Relaxed))?;
+        println!("\x1b[34;4m listening on http://{}\x1b[0m", self.listen);
+        server.run(self.threads)?;
+        println!("\x1b[34;4m stopped, all requests answered\x1b[0m");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::Write,
+        net::TcpStream,
+        sync::{Mutex, OnceLock},
+    };
+
+    /// Negates its inputs, read as f32, or u8 for images.
+    struct Negate;
+
+    impl Runtime for Negate {
+        fn infer(&mut self, inputs: &[TensorInput]) -> Result<Vec<Vec<f32>>> {
+            Ok(inputs
+                .iter()
+                .map(|input| match input.type_ {
+                    rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8 => {
+                        input.data.iter().map(|v| -(*v as f32)).collect()
+                    }
+                    _ => input
+                        .data
+                        .chunks_exact(4)
+                        .map(|b| -f32::from_le_bytes(b.try_into().unwrap()))
+                        .collect(),
+                })
+                .collect())
+        }
+    }
+
+    fn attr(name: &str, dims: &[u32], fmt: u32, type_: u32) -> rknn_tensor_attr {
+        let mut attr = rknn_tensor_attr {
+            n_dims: dims.len() as u32,
+            fmt,
+            type_,
+            ..Default::default()
+        };
+        attr.dims[..dims.len()].copy_from_slice(dims);
+        for (dst, src) in attr.name.iter_mut().zip(name.bytes()) {
+            *dst = src as _;
+        }
+        attr
+    }
+
+    fn negate_model(name: &str, input: rknn_tensor_attr, output: rknn_tensor_attr) -> Model {
+        let runtimes: Vec<Box<dyn Runtime>> = vec![Box::new(Negate), Box::new(Negate)];
+        Model::new(
+            name,
+            vec![input],
+            vec![output],
+            json!({ "custom_string": "stub" }),
+            runtimes,
+            4,
+            Duration::ZERO,
+        )
+    }
+
+    /// A server with a `vector` model of 4 floats and an `image` model of
+    /// 2x2 RGB, started once for all tests.
+    fn server() -> SocketAddr {
+        static ADDR: OnceLock<SocketAddr> = OnceLock::new();
+        *ADDR.get_or_init(|| {
+            let undefined = rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_UNDEFINED;
+            let nhwc = rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC;
+            let f32_ = rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32;
+            let u8_ = rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8;
+            let models = vec![
+                negate_model(
+                    "vector",
+                    attr("x", &[1, 4], undefined, f32_),
+                    attr("y", &[1, 4], undefined, f32_),
+                ),
+                negate_model(
+                    "image",
+                    attr("images", &[1, 2, 2, 3], nhwc, u8_),
+                    attr("pixels", &[1, 12], undefined, f32_),
+                ),
+            ];
+            let server =
+                Server::bind("127.0.0.1:0", models, Preprocessor::default(), 1 << 20).unwrap();
+            let addr = server.addr().unwrap();
+            thread::spawn(move || server.run(2));
+            addr
+        })
+    }
+
+    fn request(method: &str, path: &str, mime: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(server()).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            method,
+            path,
+            mime,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let status = String::from_utf8_lossy(&response[9..12]).parse().unwrap();
+        let body_start = find(&response, b"\r\n\r\n", 0).unwrap() + 4;
+        (status, response[body_start..].to_vec())
+    }
+
+    fn json_request(method: &str, path: &str, mime: &str, body: &[u8]) -> (u16, Value) {
+        let (status, body) = request(method, path, mime, body);
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn output(response: &Value) -> Vec<f32> {
+        serde_json::from_value(response["outputs"][0]["data"].clone()).unwrap()
+    }
+
+    #[test]
+    fn describes_models() {
+        let (status, model) = json_request("GET", "/v1/models/vector", "text/plain", b"");
+        assert_eq!(status, 200);
+        assert_eq!(model["name"], "vector");
+        assert_eq!(model["custom_string"], "stub");
+        assert_eq!(model["inputs"][0]["name"], "x");
+        assert_eq!(model["inputs"][0]["dims"], json!([1, 4]));
+        assert_eq!(model["contexts"], 2);
+
+        let (status, list) = json_request("GET", "/v1/models", "text/plain", b"");
+        assert_eq!(status, 200);
+        assert_eq!(list["models"].as_array().unwrap().len(), 2);
+
+        let (status, _) = json_request("GET", "/v1/models/missing", "text/plain", b"");
+        assert_eq!(status, 404);
+        let (status, _) = json_request("DELETE", "/v1/models/vector", "text/plain", b"");
+        assert_eq!(status, 405);
+    }
+
+    #[test]
+    fn infers_json_tensors() {
+        let body = br#"{"inputs": [[[1, 2, 3, 4]]]}"#;
+        let (status, response) =
+            json_request("POST", "/v1/models/vector/infer", "application/json", body);
+        assert_eq!(status, 200, "{}", response);
+        assert_eq!(output(&response), [-1.0, -2.0, -3.0, -4.0]);
+        assert_eq!(response["outputs"][0]["shape"], json!([1, 4]));
+
+        let body =
+            br#"{"inputs": {"x": {"data": [1, 2, 3, 4], "shape": [1, 4], "dtype": "int32"}}}"#;
+        let (status, response) =
+            json_request("POST", "/v1/models/vector/infer", "application/json", body);
+        assert_eq!(status, 200, "{}", response);
+
+        let body = br#"{"inputs": [[1, 2, 3]]}"#;
+        let (status, response) =
+            json_request("POST", "/v1/models/vector/infer", "application/json", body);
+        assert_eq!(status, 400);
+        assert!(response["error"].as_str().unwrap().contains("shape"));
+
+        let (status, _) = json_request(
+            "POST",
+            "/v1/models/vector/infer",
+            "application/json",
+            b"[[1, 2]",
+        );
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn rejects_npy_shapes_before_reading_them() {
+        let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (1099511627776,), }";
+        let mut npy = b"\x93NUMPY\x01\x00".to_vec();
+        npy.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        npy.extend_from_slice(header.as_bytes());
+        let (status, response) =
+            json_request("POST", "/v1/models/vector/infer", "application/x-npy", &npy);
+        assert_eq!(status, 400);
+        assert!(response["error"].as_str().unwrap().contains("shape"));
+    }
+
+    #[test]
+    fn infers_npy_and_returns_npz() {
+        let mut npy = Vec::new();
+        NpyArray::new(&[1, 4], &[0.5f32, 1.0, 1.5, 2.0])
+            .unwrap()
+            .write(&mut npy)
+            .unwrap();
+        let (status, response) =
+            json_request("POST", "/v1/models/vector/infer", "application/x-npy", &npy);
+        assert_eq!(status, 200, "{}", response);
+        assert_eq!(output(&response), [-0.5, -1.0, -1.5, -2.0]);
+
+        let mut stream = TcpStream::connect(server()).unwrap();
+        write!(
+            stream,
+            "POST /v1/models/vector/infer HTTP/1.1\r\nHost: test\r\nConnection: close\r\nAccept: application/x-npz\r\nContent-Length: {}\r\n\r\n",
+            npy.len()
+        )
+        .unwrap();
+        stream.write_all(&npy).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let body_start = find(&response, b"\r\n\r\n", 0).unwrap() + 4;
+        let arrays = crate::examples::npy::read_npz(Cursor::new(&response[body_start..])).unwrap();
+        assert_eq!(arrays[0].0, "y");
+        assert_eq!(
+            arrays[0].1.to_vec::<f32>().unwrap(),
+            [-0.5, -1.0, -1.5, -2.0]
+        );
+    }
+
+    #[test]
+    fn infers_uploaded_images() {
+        let img = image::RgbImage::from_pixel(2, 2, image::Rgb([10, 20, 30]));
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(img)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let mut body = b"--XyZ\r\nContent-Disposition: form-data; name=\"images\"; filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\n".to_vec();
+        body.extend_from_slice(&png);
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+        let mime = "multipart/form-data; boundary=XyZ";
+        let (status, response) = json_request("POST", "/v1/models/image/infer", mime, &body);
+        assert_eq!(status, 200, "{}", response);
+        assert_eq!(output(&response), [-10.0, -20.0, -30.0].repeat(4));
+
+        let (status, response) = json_request("POST", "/v1/models/image/infer", "image/png", &png);
+        assert_eq!(status, 200, "{}", response);
+        assert_eq!(output(&response), [-10.0, -20.0, -30.0].repeat(4));
+    }
+
+    #[test]
+    fn stops_after_answering_requests_in_flight() {
+        /// Takes long enough for the stop to arrive mid-request.
+        struct Slow(Arc<Mutex<usize>>);
+
+        impl Runtime for Slow {
+            fn infer(&mut self, inputs: &[TensorInput]) -> Result<Vec<Vec<f32>>> {
+                thread::sleep(Duration::from_millis(300));
+                *self.0.lock().unwrap() += 1;
+                Negate.infer(inputs)
+            }
+        }
+
+        let runs = Arc::new(Mutex::new(0));
+        let f32_ = rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32;
+        let undefined = rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_UNDEFINED;
+        let model = Model::new(
+            "slow",
+            vec![attr("x", &[1, 1], undefined, f32_)],
+            vec![attr("y", &[1, 1], undefined, f32_)],
+            json!({}),
+            vec![Box::new(Slow(runs.clone()))],
+            4,
+            Duration::ZERO,
+        );
+        let server =
+            Server::bind("127.0.0.1:0", vec![model], Preprocessor::default(), 1024).unwrap();
+        let (addr, stop) = (server.addr().unwrap(), server.stop_flag());
+        let running = thread::spawn(move || server.run(1));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let body = br#"{"inputs": [[[2]]]}"#;
+        write!(
+            stream,
+            "POST /v1/models/slow/infer HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        stop.store(true, Ordering::Relaxed);
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        running.join().unwrap().unwrap();
+        assert_eq!(*runs.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn parses_multipart_bodies() {
+        let body = b"preamble\r\n--b\r\nContent-Disposition: form-data; name=\"first\"\r\n\r\none\r\n--b\r\ncontent-disposition: form-data; filename=\"x\"\r\n\r\ntwo\r\n--b--\r\n";
+        let parts = multipart_parts(body, "b").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name.as_deref(), Some("first"));
+        assert_eq!(parts[0].data, b"one");
+        assert_eq!(parts[1].name, None);
+        assert_eq!(parts[1].data, b"two");
+        assert_eq!(
+            header_param("multipart/form-data; boundary=\"a b\"", "boundary").as_deref(),
+            Some("a b")
+        );
+    }
+
+    #[test]
+    fn finds_the_batch_dimension() {
+        let (nchw, f32_) = (
+            rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NCHW,
+            rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32,
+        );
+        let inputs = [attr("x", &[8, 3, 4, 4], nchw, f32_)];
+        assert_eq!(batch_size(&inputs, &[attr("y", &[8, 10], nchw, f32_)]), 8);
+        assert_eq!(batch_size(&inputs, &[attr("y", &[10], nchw, f32_)]), 1);
+        assert_eq!(batch_size(&[attr("x", &[1, 4], nchw, f32_)], &[]), 1);
+    }
+}
@@ -0,0 +1,379 @@
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+use serde_json::{json, Value};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, sync_channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::examples::{
+    bench::{bench_inputs, core_mask_name, iteration, parse_seconds, RunLength},
+    common::*,
+    latency::Latencies,
+    npy::is_npy_path,
+    preprocess::Preprocessor,
+};
+
+/// Measure end-to-end throughput with several worker threads, each with its
+/// own context on its own core mask, fed frames through a bounded queue,
+/// e.g. to find how many camera streams a board keeps up with.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The path to the model file (*.rknn)
+    #[arg(short, long)]
+    model_path: String,
+
+    /// The inputs, as for `infer`; zeros when none are given. For a model
+    /// with one input, each image is a frame and they are queued in turn
+    #[arg(short, long, value_parser)]
+    input_paths: Vec<String>,
+
+    /// The number of worker threads
+    #[arg(short = 'j', long, default_value_t = 3)]
+    workers: usize,
+
+    /// The core masks of the workers, assigned in turn, e.g.
+    /// `npu0,npu1,npu2`
+    #[arg(short, long, value_enum, value_delimiter = ',', default_value = "auto")]
+    core_masks: Vec<RknnCoreMask>,
+
+    /// Duplicate the first context instead of loading the model per worker,
+    /// so the workers share one copy of the weights
+    #[arg(long)]
+    share_weights: bool,
+
+    /// Frames the queue holds before the producer waits [default: 2 per
+    /// worker]
+    #[arg(short, long)]
+    queue_depth: Option<usize>,
+
+    /// Iterations each worker runs before measuring
+    #[arg(short, long, default_value_t = 5)]
+    warmup: usize,
+
+    /// Frames to process [default: 1000]
+    #[arg(short = 'n', long, conflicts_with = "duration")]
+    frames: Option<usize>,
+
+    /// Feed frames for this many seconds instead of a number of frames
+    #[arg(short, long, value_parser = parse_seconds)]
+    duration: Option<Duration>,
+
+    /// Fetch the outputs in the model's own types instead of dequantized f32
+    #[arg(long)]
+    raw: bool,
+
+    /// Write the report to this JSON file
+    #[arg(long)]
+    json: Option<String>,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+/// Where the NPU driver reports the load of each core, readable by root
+/// when debugfs is mounted.
+const NPU_LOAD_PATH: &str = "/sys/kernel/debug/rknpu/load";
+
+/// The per core load percentages of a `NPU load:  Core0: 35%, Core1:  0%,`
+/// line, or the single one of `NPU load:  35%`.
+fn parse_npu_load(text: &str) -> Vec<f64> {
+    text.split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+        .filter_map(|token| token.strip_suffix('%')?.parse().ok())
+        .collect()
+}
+
+/// Samples the NPU load every 100 ms until `stop`, returning the mean load
+/// per core, or `None` when the driver doesn't expose it.
+fn sample_npu_load(stop: &AtomicBool) -> Option<Vec<f64>> {
+    let mut sums: Vec<f64> = Vec::new();
+    let mut samples = 0;
+    while !stop.load(Ordering::Relaxed) {
+        let load = parse_npu_load(&std::fs::read_to_string(NPU_LOAD_PATH).ok()?);
+        sums.resize(sums.len().max(load.len()), 0.0);
+        for (sum, load) in sums.iter_mut().zip(load) {
+            *sum += load;
+        }
+        samples += 1;
+        thread::sleep(Duration::from_millis(100));
+    }
+    (samples > 0).then(|| sums.iter().map(|s| s / samples as f64).collect())
+}
+
+/// A frame waiting for a worker, with its own copy of the inputs.
+struct Job {
+    inputs: Vec<TensorInput>,
+    queued: Instant,
+}
+
+#[derive(Default)]
+struct WorkerStats {
+    /// From queueing to outputs fetched.
+    end_to_end: Latencies,
+    /// set-inputs, run and get-outputs only.
+    service: Latencies,
+    busy: Duration,
+}
+
+/// Runs `warmup` iterations on `warmup_inputs`, drops `ready`, then
+/// processes jobs until the queue closes.
+fn worker(
+    ctx: RKNNContext,
+    warmup_inputs: &[TensorInput],
+    want_float: bool,
+    warmup: usize,
+    ready: Sender<()>,
+    jobs: Arc<Mutex<Receiver<Job>>>,
+) -> Result<WorkerStats> {
+    let warmed_up =
+        (0..warmup).try_for_each(|_| iteration(&ctx, warmup_inputs, want_float).map(drop));
+    drop(ready);
+    warmed_up?;
+
+    let mut stats = WorkerStats::default();
+    loop {
+        // The lock is held only while waiting for a frame.
+        let job = match jobs
+            .lock()
+            .map_err(|_| anyhow!("a worker panicked"))?
+            .recv()
+        {
+            Ok(job) => job,
+            Err(_) => break,
+        };
+        let durations = iteration(&ctx, &job.inputs, want_float)?;
+        let total = durations[durations.len() - 1];
+        stats.service.push(total);
+        stats.busy += total;
+        stats.end_to_end.push(job.queued.elapsed());
+    }
+    Ok(stats)
+}
+
+impl Example {
+    fn run_length(&self) -> RunLength {
+        match (self.duration, self.frames) {
+            (Some(duration), _) => RunLength::Duration(duration),
+            (None, frames) => RunLength::Iterations(frames.unwrap_or(1000)),
+        }
+    }
+
+    /// The inputs of each frame: one per image for a model with one input,
+    /// otherwise a single frame of all the inputs.
+    fn frames(
+        &self,
+        input_attrs: &[rknn_api_sys::rknn_tensor_attr],
+    ) -> Result<Vec<Vec<TensorInput>>> {
+        if input_attrs.len() == 1
+            && self.input_paths.len() > 1
+            && !self.input_paths.iter().any(|p| is_npy_path(p))
+        {
+            return self
+                .input_paths
+                .iter()
+                .map(|path| bench_inputs(input_attrs, std::slice::from_ref(path), &self.preprocess))
+                .collect();
+        }
+        Ok(vec![bench_inputs(
+            input_attrs,
+            &self.input_paths,
+            &self.preprocess,
+        )?])
+    }
+
+    /// One context per worker, on the worker's core mask.
+    fn contexts(&self) -> Result<Vec<(RknnCoreMask, RKNNContext)>> {
+        let mut contexts: Vec<(RknnCoreMask, RKNNContext)> = Vec::with_capacity(self.workers);
+        for i in 0..self.workers {
+            let mask = self.core_masks[i % self.core_masks.len()];
+            let ctx = match contexts.first() {
+                Some((_, first)) if self.share_weights => first.dup()?,
+                _ => RKNNContext::load_model(&self.model_path)?,
+            };
+            ctx.set_core_mask(&mask)?;
+            ctx.use_default_shapes()?;
+            contexts.push((mask, ctx));
+        }
+        Ok(contexts)
+    }
+
+    pub fn execute(&self) -> Result<()> {
+        if self.workers == 0 || self.core_masks.is_empty() {
+            bail!("at least one worker and one core mask are needed");
+        }
+        let contexts = self.contexts()?;
+        let frame_inputs = self.frames(&contexts[0].1.get_input_attrs()?)?;
+        let masks: Vec<String> = contexts.iter().map(|(m, _)| core_mask_name(m)).collect();
+        let depth = self.queue_depth.unwrap_or(2 * self.workers).max(1);
+        let length = self.run_length();
+        println!(
+            "\x1b[34;4m {} workers on {}, queue depth {}{}\x1b[0m",
+            self.workers,
+            masks.join(","),
+            depth,
+            if self.share_weights {
+                ", sharing weights"
+            } else {
+                ""
+            }
+        );
+
+        let (sender, receiver) = sync_channel::<Job>(depth);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (ready, warmed_up) = channel::<()>();
+        let stop_sampling = AtomicBool::new(false);
+        let (results, npu_load, frames, elapsed) = thread::scope(|s| {
+            let handles: Vec<_> = contexts
+                .into_iter()
+                .map(|(_, ctx)| {
+                    let (warmup_inputs, ready) = (&frame_inputs[0], ready.clone());
+                    let jobs = receiver.clone();
+                    let (want_float, warmup) = (!self.raw, self.warmup);
+                    s.spawn(move || worker(ctx, warmup_inputs, want_float, warmup, ready, jobs))
+                })
+                .collect();
+            // Only the workers may keep the queue open, so it closes when
+            // they all stop and the producer doesn't wait forever.
+            drop(receiver);
+            // Every worker drops its sender once warmed up, or as it
+            // unwinds from a panic, so this can't wait forever either.
+            drop(ready);
+            let _ = warmed_up.recv();
+
+            let sampler = s.spawn(|| sample_npu_load(&stop_sampling));
+            let start = Instant::now();
+            let mut frames = 0;
+            while !length.done(frames, start.elapsed()) {
+                let job = Job {
+                    inputs: frame_inputs[frames % frame_inputs.len()].clone(),
+                    queued: Instant::now(),
+                };
+                if sender.send(job).is_err() {
+                    break;
+                }
+                frames += 1;
+            }
+            drop(sender);
+            let results: Vec<Result<WorkerStats>> = handles
+                .into_iter()
+                .map(|h| {
+                    h.join()
+                        .unwrap_or_else(|_| Err(anyhow!("a worker panicked")))
+                })
+                .collect();
+            let elapsed = start.elapsed();
+            stop_sampling.store(true, Ordering::Relaxed);
+            let npu_load = sampler.join().ok().flatten();
+            (results, npu_load, frames, elapsed)
+        });
+        let workers = results.into_iter().collect::<Result<Vec<_>>>()?;
+
+        let mut end_to_end = Latencies::default();
+        let mut service = Latencies::default();
+        for stats in &workers {
+            end_to_end.extend(&stats.end_to_end);
+            service.extend(&stats.service);
+        }
+        let fps = frames as f64 / elapsed.as_secs_f64();
+        let (end_to_end, service) = (end_to_end.summary(), service.summary());
+        let worker_json = |(i, stats): (usize, &WorkerStats)| {
+            json!({
+                "worker": i,
+                "core_mask": masks[i],
+                "frames": stats.service.len(),
+                "fps": stats.service.len() as f64 / elapsed.as_secs_f64(),
+                "busy": stats.busy.as_secs_f64() / elapsed.as_secs_f64(),
+                "service": stats.service.summary().to_json(),
+            })
+        };
+
+        println!(
+            "\x1b[34;4m {} frames in {:.2} s: {:.2} FPS\x1b[0m",
+            frames,
+            elapsed.as_secs_f64(),
+            fps
+        );
+        println!("  {:<12}{}", "end_to_end", end_to_end.dump());
+        println!("  {:<12}{}", "service", service.dump());
+        // Host side: the share of the wall time the workers on a mask spent
+        // in the runtime, so above 100% when several share one.
+        let mut mask_busy: Vec<(String, f64)> = Vec::new();
+        for (mask, stats) in masks.iter().zip(&workers) {
+            let busy = stats.busy.as_secs_f64() / elapsed.as_secs_f64();
+            match mask_busy.iter_mut().find(|(m, _)| m == mask) {
+                Some((_, total)) => *total += busy,
+                None => mask_busy.push((mask.clone(), busy)),
+            }
+        }
+        println!("\x1b[34;4m workers:\x1b[0m");
+        for (i, stats) in workers.iter().enumerate() {
+            println!(
+                "  {} ({}): {} frames, {:.2} FPS, busy {:.1}%, p99 {:.1} us",
+                i,
+                masks[i],
+                stats.service.len(),
+                stats.service.len() as f64 / elapsed.as_secs_f64(),
+                100.0 * stats.busy.as_secs_f64() / elapsed.as_secs_f64(),
+                stats.service.summary().p99_us
+            );
+        }
+        for (mask, busy) in &mask_busy {
+            println!("  {}: busy {:.1}%", mask, 100.0 * busy);
+        }
+        match &npu_load {
+            Some(load) => {
+                let cores: Vec<String> = load
+                    .iter()
+                    .enumerate()
+                    .map(|(core, load)| format!("core{} {:.1}%", core, load))
+                    .collect();
+                println!("\x1b[34;4m NPU load: {}\x1b[0m", cores.join(", "));
+            }
+            None => println!("\x1b[34;4m NPU load: unavailable, needs root and debugfs\x1b[0m"),
+        }
+
+        if let Some(path) = &self.json {
+            let report = json!({
+                "model": self.model_path,
+                "platform": PLATFORM,
+                "workers": self.workers,
+                "share_weights": self.share_weights,
+                "queue_depth": depth,
+                "frames": frames,
+                "elapsed_sec": elapsed.as_secs_f64(),
+                "fps": fps,
+                "end_to_end": end_to_end.to_json(),
+                "service": service.to_json(),
+                "core_mask_busy": mask_busy
+                    .iter()
+                    .map(|(mask, busy)| json!({ "core_mask": mask, "busy": busy }))
+                    .collect::<Vec<_>>(),
+                "npu_load": npu_load,
+                "per_worker": workers.iter().enumerate().map(worker_json).collect::<Vec<Value>>(),
+            });
+            std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+            println!("\x1b[34;4m JSON written to {}\x1b[0m", path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_npu_load() {
+        let multi = "NPU load:  Core0: 35%, Core1:  0%, Core2: 100%,\n";
+        assert_eq!(parse_npu_load(multi), [35.0, 0.0, 100.0]);
+        assert_eq!(parse_npu_load("NPU load:  35%\n"), [35.0]);
+        assert_eq!(parse_npu_load("NPU load:Core0:12%,Core1:7%"), [12.0, 7.0]);
+        assert!(parse_npu_load("").is_empty());
+        assert!(parse_npu_load("NPU load: n/a%").is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::BufWriter,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+struct SpanRecord {
+    name: &'static str,
+    ctx: u64,
+    frame: u64,
+    start: Duration,
+    duration: Duration,
+}
+
+/// Collects timing spans from one or more contexts and writes them in the
+/// Chrome trace event format, viewable in Perfetto or `chrome://tracing`.
+///
+/// Cloning is cheap and every clone records into the same timeline, so a
+/// single tracer can be handed to contexts living on different threads.
+#[derive(Clone)]
+pub struct Tracer {
+    origin: Instant,
+    spans: Arc<Mutex<Vec<SpanRecord>>>,
+}
+
+/// Records a span from its creation until it is dropped.
+pub struct Span<'a> {
+    tracer: &'a Tracer,
+    name: &'static str,
+    ctx: u64,
+    frame: u64,
+    start: Instant,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Tracer {
+            origin: Instant::now(),
+            spans: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn span(&self, name: &'static str, ctx: u64, frame: u64) -> Span<'_> {
+        Span {
+            tracer: self,
+            name,
+            ctx,
+            frame,
+            start: Instant::now(),
+        }
+    }
+
+    fn record(&self, span: &Span) {
+        let record = SpanRecord {
+            name: span.name,
+            ctx: span.ctx,
+            frame: span.frame,
+            start: span.start.duration_since(self.origin),
+            duration: span.start.elapsed(),
+        };
+        self.spans.lock().unwrap().push(record);
+    }
+
+    /// Builds the trace document. Each context gets its own track, named
+    /// after its handle, and every span carries the frame it belongs to.
+    pub fn to_chrome_trace(&self) -> Value {
+        let pid = std::process::id();
+        let spans = self.spans.lock().unwrap();
+        let contexts: BTreeSet<u64> = spans.iter().map(|s| s.ctx).collect();
+
+        let mut events: Vec<Value> = contexts
+            .iter()
+            .map(|ctx| {
+                json!({
+                    "name": "thread_name",
+                    "ph": "M",
+                    "pid": pid,
+                    "tid": ctx,
+                    "args": { "name": format!("RKNNContext {}", ctx) },
+                })
+            })
+            .collect();
+        events.extend(spans.iter().map(|s| {
+            json!({
+                "name": s.name,
+                "cat": "rknn",
+                "ph": "X",
+                "ts": s.start.as_secs_f64() * 1e6,
+                "dur": s.duration.as_secs_f64() * 1e6,
+                "pid": pid,
+                "tid": s.ctx,
+                "args": { "frame": s.frame },
+            })
+        }));
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+
+    pub fn write_chrome_trace(&self, path: &str) -> Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(writer, &self.to_chrome_trace())?;
+        Ok(())
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Span<'_> {
+    fn drop(&mut self) {
+        self.tracer.record(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_track_per_context() {
+        let tracer = Tracer::new();
+        drop(tracer.span("run", 7, 1));
+        drop(tracer.clone().span("set_inputs", 3, 2));
+        drop(tracer.span("get_outputs", 7, 2));
+
+        let trace = tracer.to_chrome_trace();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let tracks: Vec<_> = events
+            .iter()
+            .filter(|e| e["ph"] == "M")
+            .map(|e| (e["tid"].as_u64().unwrap(), e["args"]["name"].clone()))
+            .collect();
+        assert_eq!(
+            tracks,
+            [(3, json!("RKNNContext 3")), (7, json!("RKNNContext 7"))]
+        );
+        let spans: Vec<_> = events
+            .iter()
+            .filter(|e| e["ph"] == "X")
+            .map(|e| {
+                assert!(e["ts"].as_f64().unwrap() >= 0.0);
+                assert!(e["dur"].as_f64().unwrap() >= 0.0);
+                assert_eq!(e["pid"], events[0]["pid"]);
+                (
+                    e["name"].as_str().unwrap(),
+                    e["tid"].as_u64().unwrap(),
+                    e["args"]["frame"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            [("run", 7, 1), ("set_inputs", 3, 2), ("get_outputs", 7, 2)]
+        );
+        assert_eq!(trace["displayTimeUnit"], "ms");
+    }
+}
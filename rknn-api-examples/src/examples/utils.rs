@@ -3,7 +3,7 @@ use std::ffi::CStr;
 
 use rknn_api_sys::*;
 
-fn get_type_string(t: rknn_tensor_type) -> &'static str {
+pub fn get_type_string(t: rknn_tensor_type) -> &'static str {
     match t {
         rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT32 => "FP32",
         rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_FLOAT16 => "FP16",
@@ -29,7 +29,7 @@ fn get_qnt_type_string(t: rknn_tensor_qnt_type) -> &'static str {
     }
 }
 
-fn get_format_string(fmt: rknn_tensor_format) -> &'static str {
+pub fn get_format_string(fmt: rknn_tensor_format) -> &'static str {
     match fmt {
         rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NCHW => "NCHW",
         rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC => "NHWC",
@@ -45,6 +45,34 @@ pub fn safe_string(chars: &[::std::os::raw::c_char]) -> anyhow::Result<String> {
     Ok(String::from_utf8_lossy(cstr.to_bytes()).to_string())
 }
 
+/// Maps a quantized output value back to the real number it encodes.
+pub fn dequantize(value: f32, attr: &rknn_tensor_attr) -> f32 {
+    match attr.qnt_type {
+        rknn_api_sys::_rknn_tensor_qnt_type_RKNN_TENSOR_QNT_AFFINE_ASYMMETRIC => {
+            (value - attr.zp as f32) * attr.scale
+        }
+        rknn_api_sys::_rknn_tensor_qnt_type_RKNN_TENSOR_QNT_DFP => {
+            value / 2f32.powi(attr.fl as i32)
+        }
+        _ => value,
+    }
+}
+
+/// Copies `src` into a fixed size C char array, keeping the trailing NUL.
+pub fn write_c_string(dst: &mut [::std::os::raw::c_char], src: &str) -> anyhow::Result<()> {
+    if src.len() >= dst.len() || src.as_bytes().contains(&0) {
+        anyhow::bail!(
+            "'{}' is not a C string of less than {} chars",
+            src,
+            dst.len()
+        );
+    }
+    for (d, s) in dst.iter_mut().zip(src.bytes().chain(std::iter::once(0))) {
+        *d = s as ::std::os::raw::c_char;
+    }
+    Ok(())
+}
+
 pub trait DumpVals {
     fn dump(&self) -> Result<String>;
 }
@@ -59,6 +87,20 @@ impl DumpVals for &rknn_tensor_attr {
     }
 }
 
+impl DumpVals for &rknn_matmul_tensor_attr {
+    fn dump(&self) -> Result<String> {
+        let dims = self.dims[0..self.n_dims as usize].to_vec();
+        Ok(format!(
+            "  name={}, n_dims={}, dims={:?}, size={}, type={}",
+            safe_string(&self.name)?,
+            self.n_dims,
+            dims,
+            self.size,
+            get_type_string(self.type_)
+        ))
+    }
+}
+
 impl DumpVals for &rknn_input_range {
     fn dump(&self) -> Result<String> {
         let dims: Vec<Vec<u32>> = self.dyn_range[0..self.shape_number as usize]
@@ -123,7 +165,7 @@ macro_rules! time_bench {
                     duration,
                     1.0 / duration.as_secs_f32()
                 );
-                observations.push(duration.as_millis() as f64);
+                observations.push(duration.as_secs_f64() * 1000.0);
             }
             println!("{}",observations.dump_stats("ms"));
     };
@@ -0,0 +1,426 @@
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+use serde_json::json;
+use std::{
+    sync::mpsc::{sync_channel, SyncSender},
+    thread,
+    time::Instant,
+};
+
+use rknn_api_sys::rknn_tensor_attr;
+
+use crate::examples::{
+    classifier::load_labels,
+    common::*,
+    detect::{detections_json, DetectTask, COCO_LABELS},
+    draw::{class_color, draw_box},
+    frames::{Frame, FrameSource},
+    latency::Latencies,
+    preprocess::{input_geometry, ImageTransform, Preprocessor},
+    report::{run_record, ResultWriter, Timings},
+    yolo::{YoloDecoder, YoloVersion},
+};
+
+/// Run a YOLO model over a directory of images, a Motion JPEG or a Y4M
+/// file. Reading, preprocessing, inference and postprocessing run on their
+/// own threads joined by bounded queues, so a slow stage holds back the
+/// ones before it instead of frames piling up. Annotated frames and a JSON
+/// record per frame are written to the output directory.
+#[derive(Debug, Parser)]
+pub struct Example {
+    /// The path to the model file (*.rknn)
+    #[arg(short, long)]
+    model_path: String,
+
+    /// A directory of images, or a .mjpeg/.mjpg or .y4m file
+    #[arg(short, long)]
+    input: String,
+
+    /// The directory the annotated frames (PNG) and JSON records are
+    /// written to
+    #[arg(short, long)]
+    output_dir: String,
+
+    #[arg(short = 'y', long, value_enum, default_value_t = YoloVersion::V8)]
+    yolo_version: YoloVersion,
+
+    #[arg(short, long, value_enum, default_value_t = DetectTask::Detect)]
+    task: DetectTask,
+
+    /// A label file, one label per line, defaults to the COCO classes
+    #[arg(long)]
+    labels: Option<String>,
+
+    #[arg(long, default_value_t = 0.25)]
+    conf_threshold: f32,
+
+    #[arg(long, default_value_t = 0.45)]
+    iou_threshold: f32,
+
+    /// Apply sigmoid to the scores, for heads exported without it
+    #[arg(long)]
+    sigmoid: bool,
+
+    /// Only write the JSON records, not the annotated frames
+    #[arg(long)]
+    no_frames: bool,
+
+    /// Frames each queue between two stages holds
+    #[arg(short, long, default_value_t = 4)]
+    queue_depth: usize,
+
+    /// Stop after this many frames
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Run the stages in turn on one thread, e.g. to compare with the
+    /// pipelined run
+    #[arg(long)]
+    sequential: bool,
+
+    /// Create the context with `RKNN_FLAG_ASYNC_MASK`: getting the outputs
+    /// of a frame doesn't wait for its run, it returns the previous frame's
+    /// outputs, so the NPU runs one frame while the next is set
+    #[arg(long = "async")]
+    async_mode: bool,
+
+    #[arg(short, long, value_enum, default_value_t = RknnCoreMask::Npu0)]
+    core_mask: RknnCoreMask,
+
+    #[command(flatten)]
+    preprocess: Preprocessor,
+}
+
+/// A frame ready to be set as the model's input.
+struct Prepared {
+    frame: Frame,
+    input: TensorInput,
+    transform: ImageTransform,
+    timings: Timings,
+}
+
+/// A frame and a dequantized copy of its outputs, so the context can move
+/// on to the next one.
+struct Inferred {
+    frame: Frame,
+    transform: ImageTransform,
+    outputs: Vec<Vec<f32>>,
+    timings: Timings,
+}
+
+fn prepare(frame: Frame, attr: &rknn_tensor_attr, preprocess: &Preprocessor) -> Result<Prepared> {
+    let mut timings = Timings::default();
+    let (input, transform) = timings.time("preprocess", || preprocess.run(&frame.image, attr))?;
+    Ok(Prepared {
+        frame,
+        input,
+        transform,
+        timings,
+    })
+}
+
+/// A frame that has run, waiting for its outputs.
+struct Pending {
+    frame: Frame,
+    transform: ImageTransform,
+    timings: Timings,
+}
+
+/// Runs frames on the context. In async mode the outputs fetched after a
+/// run are the previous run's, so each frame is held back until the next
+/// one has run, and [`Inference::finish`] runs the last input once more.
+struct Inference<'a> {
+    ctx: &'a RKNNContext,
+    async_mode: bool,
+    pending: Option<Pending>,
+}
+
+impl<'a> Inference<'a> {
+    fn new(ctx: &'a RKNNContext, async_mode: bool) -> Self {
+        Inference {
+            ctx,
+            async_mode,
+            pending: None,
+        }
+    }
+
+    /// Runs `prepared`, returning the frame whose outputs that yields.
+    fn infer(&mut self, prepared: Prepared) -> Result<Option<Inferred>> {
+        let Prepared {
+            frame,
+            input,
+            transform,
+            mut timings,
+        } = prepared;
+        timings.time("set_inputs", || {
+            self.ctx.set_tensor_inputs(std::slice::from_ref(&input))
+        })?;
+        timings.time("run", || self.ctx.run())?;
+        let current = Pending {
+            frame,
+            transform,
+            timings,
+        };
+        let done = if self.async_mode {
+            self.pending.replace(current)
+        } else {
+            Some(current)
+        };
+        // The first async run only gives the outputs of no frame.
+        let outputs = self.fetch_outputs()?;
+        Ok(done.map(|done| done.with_outputs(outputs)))
+    }
+
+    /// The last frame of an async run.
+    fn finish(&mut self) -> Result<Option<Inferred>> {
+        let Some(mut pending) = self.pending.take() else {
+            return Ok(None);
+        };
+        pending.timings.time("run", || self.ctx.run())?;
+        let outputs = self.fetch_outputs()?;
+        Ok(Some(pending.with_outputs(outputs)))
+    }
+
+    fn fetch_outputs(&self) -> Result<(Vec<Vec<f32>>, std::time::Duration)> {
+        let start = Instant::now();
+        let outputs = self.ctx.fetch_outputs(true)?;
+        let outputs = (0..outputs.len())
+            .map(|i| Ok(outputs.view(i)?.to_vec()))
+            .collect::<Result<_>>()?;
+        Ok((outputs, start.elapsed()))
+    }
+}
+
+impl Pending {
+    fn with_outputs(self, (outputs, fetched): (Vec<Vec<f32>>, std::time::Duration)) -> Inferred {
+        let mut timings = self.timings;
+        timings.record("get_outputs", fetched);
+        Inferred {
+            frame: self.frame,
+            transform: self.transform,
+            outputs,
+            timings,
+        }
+    }
+}
+
+/// Runs every prepared frame and sends on the inferred ones, stopping
+/// quietly when the next stage has stopped.
+fn infer_stage(
+    inference: &mut Inference,
+    prepared: impl IntoIterator<Item = Prepared>,
+    sender: SyncSender<Inferred>,
+) -> Result<()> {
+    for prepared in prepared {
+        if let Some(inferred) = inference.infer(prepared)? {
+            if sender.send(inferred).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    if let Some(inferred) = inference.finish()? {
+        let _ = sender.send(inferred);
+    }
+    Ok(())
+}
+
+/// Decodes, draws and writes the results of each frame.
+struct Postprocessor<'a> {
+    example: &'a Example,
+    decoder: YoloDecoder,
+    labels: Vec<String>,
+    input_size: (u32, u32),
+    input_attrs: Vec<rknn_tensor_attr>,
+    output_attrs: Vec<rknn_tensor_attr>,
+    writer: ResultWriter,
+}
+
+impl Postprocessor<'_> {
+    fn process(&self, inferred: Inferred) -> Result<Timings> {
+        let Inferred {
+            frame,
+            transform,
+            outputs,
+            mut timings,
+        } = inferred;
+        let views: Vec<OutputView> = outputs
+            .iter()
+            .zip(&self.output_attrs)
+            .map(|(data, attr)| OutputView::from_f32(data, *attr))
+            .collect();
+        let postprocess = Instant::now();
+        let mut canvas = frame.image.to_rgb8();
+        let (detections, extras) = self.example.task.decode(
+            &self.decoder,
+            &views,
+            self.input_size,
+            &transform,
+            &mut canvas,
+        )?;
+        for det in &detections {
+            draw_box(&mut canvas, &det.bbox, class_color(det.class), 2);
+        }
+        timings.record("postprocess", postprocess.elapsed());
+
+        let mut record = run_record(
+            &frame.name,
+            &self.example.model_path,
+            &self.input_attrs,
+            &self.output_attrs,
+            &timings,
+        );
+        record["frame"] = json!(frame.index);
+        record["detections"] = json!(detections_json(&detections, &extras, &self.labels));
+        self.writer.write(&frame.name, &record, &[])?;
+        if !self.example.no_frames {
+            canvas.save(self.writer.path(&format!("{}.png", frame.name)))?;
+        }
+        Ok(timings)
+    }
+}
+
+/// Sends every item of `items` after `f`, stopping quietly when the next
+/// stage has stopped (its error is reported there).
+fn stage<T, U>(
+    items: impl IntoIterator<Item = T>,
+    sender: SyncSender<U>,
+    mut f: impl FnMut(T) -> Result<U>,
+) -> Result<()> {
+    for item in items {
+        if sender.send(f(item)?).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl Example {
+    fn frames(&self) -> Result<impl Iterator<Item = Result<Frame>>> {
+        let frames = FrameSource::open(&self.input)?.into_iter();
+        Ok(frames.take(self.limit.unwrap_or(usize::MAX)))
+    }
+
+    fn run_sequential(
+        &self,
+        ctx: &RKNNContext,
+        attr: &rknn_tensor_attr,
+        preprocess: &Preprocessor,
+        post: &Postprocessor,
+    ) -> Result<Vec<Timings>> {
+        let mut inference = Inference::new(ctx, self.async_mode);
+        let mut timings = Vec::new();
+        for frame in self.frames()? {
+            if let Some(inferred) = inference.infer(prepare(frame?, attr, preprocess)?)? {
+                timings.push(post.process(inferred)?);
+            }
+        }
+        if let Some(inferred) = inference.finish()? {
+            timings.push(post.process(inferred)?);
+        }
+        Ok(timings)
+    }
+
+    fn run_pipelined(
+        &self,
+        ctx: &RKNNContext,
+        attr: &rknn_tensor_attr,
+        preprocess: &Preprocessor,
+        post: &Postprocessor,
+    ) -> Result<Vec<Timings>> {
+        let frames = self.frames()?;
+        let depth = self.queue_depth.max(1);
+        let (read_tx, read_rx) = sync_channel::<Frame>(depth);
+        let (prep_tx, prep_rx) = sync_channel::<Prepared>(depth);
+        let (infer_tx, infer_rx) = sync_channel::<Inferred>(depth);
+        thread::scope(|s| {
+            let reader = s.spawn(move || stage(frames, read_tx, |frame| frame));
+            let preparer =
+                s.spawn(move || stage(read_rx, prep_tx, |frame| prepare(frame, attr, preprocess)));
+            let writer = s.spawn(move || infer_rx.into_iter().map(|i| post.process(i)).collect());
+            // The context stays on this thread.
+            let mut inference = Inference::new(ctx, self.async_mode);
+            let inferred = infer_stage(&mut inference, prep_rx, infer_tx);
+
+            let join = |result: thread::Result<Result<()>>| {
+                result.unwrap_or_else(|_| Err(anyhow!("a pipeline stage panicked")))
+            };
+            let timings: Result<Vec<Timings>> = writer
+                .join()
+                .unwrap_or_else(|_| Err(anyhow!("a pipeline stage panicked")));
+            // The first stage to fail stopped the others, report its error.
+            join(reader.join())?;
+            join(preparer.join())?;
+            inferred?;
+            timings
+        })
+    }
+
+    pub fn execute(&self) -> Result<()> {
+        let flags = if self.async_mode {
+            rknn_api_sys::RKNN_FLAG_ASYNC_MASK
+        } else {
+            0
+        };
+        let ctx = RKNNContext::load_model_with_flags(&self.model_path, flags)?;
+        if ctx.n_input != 1 {
+            bail!("{} has {} inputs, expected 1", self.model_path, ctx.n_input);
+        }
+        ctx.set_core_mask(&self.core_mask)?;
+        let input_attrs = ctx.use_default_shapes()?;
+        let (height, width, _, _) = input_geometry(&input_attrs[0])?;
+        let preprocess = Preprocessor {
+            letterbox: true,
+            ..self.preprocess.clone()
+        };
+        let post = Postprocessor {
+            example: self,
+            decoder: YoloDecoder {
+                conf_threshold: self.conf_threshold,
+                iou_threshold: self.iou_threshold,
+                sigmoid: self.sigmoid,
+                ..YoloDecoder::new(self.yolo_version)
+            },
+            labels: match &self.labels {
+                Some(path) => load_labels(path)?,
+                None => COCO_LABELS.iter().map(|l| l.to_string()).collect(),
+            },
+            input_size: (width, height),
+            output_attrs: ctx.get_output_attrs()?,
+            input_attrs,
+            writer: ResultWriter::new(&self.output_dir)?,
+        };
+
+        let start = Instant::now();
+        let attr = &post.input_attrs[0];
+        let timings = if self.sequential {
+            self.run_sequential(&ctx, attr, &preprocess, &post)?
+        } else {
+            self.run_pipelined(&ctx, attr, &preprocess, &post)?
+        };
+        let elapsed = start.elapsed();
+
+        let mut stages: Vec<(&'static str, Latencies)> = Vec::new();
+        for (stage, duration) in timings.iter().flat_map(|t| &t.0) {
+            match stages.iter_mut().find(|(s, _)| s == stage) {
+                Some((_, latencies)) => latencies.push(*duration),
+                None => stages.push((stage, Latencies(vec![*duration]))),
+            }
+        }
+        println!(
+            "\x1b[34;4m {} frames in {:.2} s: {:.2} FPS{}\x1b[0m",
+            timings.len(),
+            elapsed.as_secs_f64(),
+            timings.len() as f64 / elapsed.as_secs_f64(),
+            match (self.sequential, self.async_mode) {
+                (true, true) => ", sequential, async",
+                (true, false) => ", sequential",
+                (false, true) => ", async",
+                (false, false) => "",
+            }
+        );
+        for (stage, latencies) in &stages {
+            println!("  {:<12}{}", stage, latencies.summary().dump());
+        }
+        Ok(())
+    }
+}
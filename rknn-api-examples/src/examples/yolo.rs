@@ -0,0 +1,668 @@
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use crate::examples::{common::OutputView, preprocess::ImageTransform};
+
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum YoloVersion {
+    /// Anchor based heads
+    V5,
+    /// Anchor free heads with DFL box regression
+    V8,
+    /// v8 heads trained without NMS
+    V10,
+}
+
+/// The YOLOv5 COCO anchors (w, h) of the stride 8, 16 and 32 branches.
+pub const YOLOV5_ANCHORS: [[(f32, f32); 3]; 3] = [
+    [(10.0, 13.0), (16.0, 30.0), (33.0, 23.0)],
+    [(30.0, 61.0), (62.0, 45.0), (59.0, 119.0)],
+    [(116.0, 90.0), (156.0, 198.0), (373.0, 326.0)],
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BBox {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+}
+
+impl BBox {
+    pub fn from_center(cx: f32, cy: f32, w: f32, h: f32) -> Self {
+        BBox {
+            x1: cx - w / 2.0,
+            y1: cy - h / 2.0,
+            x2: cx + w / 2.0,
+            y2: cy + h / 2.0,
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        (self.x2 - self.x1).max(0.0)
+    }
+
+    pub fn height(&self) -> f32 {
+        (self.y2 - self.y1).max(0.0)
+    }
+
+    pub fn area(&self) -> f32 {
+        self.width() * self.height()
+    }
+
+    pub fn intersection(&self, other: &BBox) -> f32 {
+        BBox {
+            x1: self.x1.max(other.x1),
+            y1: self.y1.max(other.y1),
+            x2: self.x2.min(other.x2),
+            y2: self.y2.min(other.y2),
+        }
+        .area()
+    }
+
+    pub fn iou(&self, other: &BBox) -> f32 {
+        let inter = self.intersection(other);
+        let union = self.area() + other.area() - inter;
+        if union > 0.0 {
+            inter / union
+        } else {
+            0.0
+        }
+    }
+
+    /// Maps a box in model input coordinates back to the source image,
+    /// clipped to its bounds.
+    pub fn to_source(&self, transform: &ImageTransform) -> BBox {
+        let (x1, y1) = transform.to_source(self.x1, self.y1);
+        let (x2, y2) = transform.to_source(self.x2, self.y2);
+        let (w, h) = (
+            transform.source_width as f32,
+            transform.source_height as f32,
+        );
+        BBox {
+            x1: x1.clamp(0.0, w),
+            y1: y1.clamp(0.0, h),
+            x2: x2.clamp(0.0, w),
+            y2: y2.clamp(0.0, h),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Detection {
+    pub class: usize,
+    pub score: f32,
+    pub bbox: BBox,
+    /// The grid cell (or row) the box was decoded from, counting through the
+    /// branches in order, to look up per anchor outputs such as mask
+    /// coefficients.
+    pub anchor: usize,
+}
+
+impl Detection {
+    pub fn to_json(&self, labels: &[String]) -> Value {
+        json!({
+            "class": self.class,
+            "label": labels.get(self.class),
+            "score": self.score,
+            "bbox": [self.bbox.x1, self.bbox.y1, self.bbox.x2, self.bbox.y2],
+        })
+    }
+}
+
+/// Keeps the highest scoring boxes, dropping boxes of the same class that
+/// overlap a kept one by more than `iou_threshold`.
+pub fn nms(mut detections: Vec<Detection>, iou_threshold: f32) -> Vec<Detection> {
+    detections.sort_by(|a, b| b.score.total_cmp(&a.score));
+    let mut kept: Vec<Detection> = Vec::new();
+    for det in detections {
+        let suppressed = kept
+            .iter()
+            .any(|k| k.class == det.class && k.bbox.iou(&det.bbox) > iou_threshold);
+        if !suppressed {
+            kept.push(det);
+        }
+    }
+    kept
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Decodes YOLO outputs into boxes in model input coordinates.
+///
+/// The output layout is recognised from the number and shape of outputs:
+/// - v5: 3 branches `[1, 3*(5+nc), H, W]`, or one `[1, N, 5+nc]` of
+///   decoded boxes;
+/// - v8/v10: per branch DFL boxes `[1, 4*reg_max, H, W]`, class scores
+///   `[1, nc, H, W]` and an optional score sum `[1, 1, H, W]` (6 or 9
+///   outputs), 3 branches `[1, 4*reg_max+nc, H, W]`, or one
+///   `[1, 4+nc, N]` of decoded boxes;
+/// - v10: additionally one `[1, N, 6]` of `x1, y1, x2, y2, score, class`.
+#[derive(Clone, Debug)]
+pub struct YoloDecoder {
+    pub version: YoloVersion,
+    pub conf_threshold: f32,
+    pub iou_threshold: f32,
+    /// Apply sigmoid to scores (and v5 box terms), for heads exported
+    /// without it.
+    pub sigmoid: bool,
+    pub reg_max: usize,
+    pub anchors: Vec<[(f32, f32); 3]>,
+    pub max_detections: usize,
+    /// The number of classes, for outputs carrying more than boxes and
+    /// scores (e.g. mask coefficients); inferred from the shapes otherwise.
+    pub num_classes: Option<usize>,
+}
+
+impl YoloDecoder {
+    pub fn new(version: YoloVersion) -> Self {
+        YoloDecoder {
+            version,
+            conf_threshold: 0.25,
+            iou_threshold: 0.45,
+            sigmoid: false,
+            reg_max: 16,
+            anchors: YOLOV5_ANCHORS.to_vec(),
+            max_detections: 300,
+            num_classes: None,
+        }
+    }
+
+    fn activate(&self, x: f32) -> f32 {
+        if self.sigmoid {
+            sigmoid(x)
+        } else {
+            x
+        }
+    }
+
+    /// Decodes, filters by score, runs NMS (except for v10) and keeps at most
+    /// `max_detections` boxes. `input_size` is the model's (width, height).
+    pub fn decode(&self, outputs: &[OutputView], input_size: (u32, u32)) -> Result<Vec<Detection>> {
+        let candidates = match (self.version, outputs.len()) {
+            (YoloVersion::V5, 1) => self.decode_v5_concat(&outputs[0])?,
+            (YoloVersion::V5, _) => self.decode_v5_branches(outputs, input_size)?,
+            (YoloVersion::V10, 1) if outputs[0].dims().last() == Some(&6) => {
+                self.decode_v10_concat(&outputs[0])
+            }
+            (_, 1) => self.decode_v8_concat(&outputs[0])?,
+            (_, _) => self.decode_v8_branches(outputs, input_size)?,
+        };
+        let mut detections = if self.version == YoloVersion::V10 {
+            let mut candidates = candidates;
+            candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+            candidates
+        } else {
+            nms(candidates, self.iou_threshold)
+        };
+        detections.truncate(self.max_detections);
+        Ok(detections)
+    }
+
+    /// The best class and its score among `n` scores read by `score`.
+    fn best_class(&self, n: usize, score: impl Fn(usize) -> f32) -> (usize, f32) {
+        (0..n)
+            .map(|c| (c, self.activate(score(c))))
+            .fold((0, f32::NEG_INFINITY), |best, cur| {
+                if cur.1 > best.1 {
+                    cur
+                } else {
+                    best
+                }
+            })
+    }
+
+    fn decode_v5_branches(
+        &self,
+        outputs: &[OutputView],
+        (in_w, _): (u32, u32),
+    ) -> Result<Vec<Detection>> {
+        if outputs.len() != self.anchors.len() {
+            bail!(
+                "v5 expects {} branches, the model has {} outputs",
+                self.anchors.len(),
+                outputs.len()
+            );
+        }
+        // Finest branch first, matching the anchor order.
+        let mut branches: Vec<&OutputView> = outputs.iter().collect();
+        branches.sort_by_key(|o| std::cmp::Reverse(o.chw().2));
+
+        let mut detections = Vec::new();
+        let mut first_anchor = 0;
+        for (branch, anchors) in branches.into_iter().zip(&self.anchors) {
+            let (channels, height, width) = branch.chw();
+            if channels % 3 != 0 || channels / 3 <= 5 {
+                bail!("v5 branch with {} channels", channels);
+            }
+            let per_anchor = channels / 3;
+            let stride = in_w as f32 / width as f32;
+            for (a, (anchor_w, anchor_h)) in anchors.iter().enumerate() {
+                let base = a * per_anchor;
+                for y in 0..height {
+                    for x in 0..width {
+                        let v = |c: usize| self.activate(branch.at(base + c, y, x));
+                        let objectness = v(4);
+                        if objectness < self.conf_threshold {
+                            continue;
+                        }
+                        let n_classes = self.num_classes.unwrap_or(per_anchor - 5);
+                        let (class, class_score) =
+                            self.best_class(n_classes, |c| branch.at(base + 5 + c, y, x));
+                        let score = objectness * class_score;
+                        if score < self.conf_threshold {
+                            continue;
+                        }
+                        let cx = (v(0) * 2.0 - 0.5 + x as f32) * stride;
+                        let cy = (v(1) * 2.0 - 0.5 + y as f32) * stride;
+                        let w = (v(2) * 2.0).powi(2) * anchor_w;
+                        let h = (v(3) * 2.0).powi(2) * anchor_h;
+                        detections.push(Detection {
+                            class,
+                            score,
+                            bbox: BBox::from_center(cx, cy, w, h),
+                            anchor: first_anchor + (a * height + y) * width + x,
+                        });
+                    }
+                }
+            }
+            first_anchor += 3 * height * width;
+        }
+        Ok(detections)
+    }
+
+    /// Rows and attributes of a `[1, N, C]` or `[1, C, N]` output, and a
+    /// reader of attribute `c` of row `i`. The longer axis holds the rows.
+    pub(crate) fn rows<'v>(
+        output: &'v OutputView,
+    ) -> Result<(usize, usize, impl Fn(usize, usize) -> f32 + 'v)> {
+        let dims = output.dims();
+        let (a, b) = match dims[..] {
+            [1, a, b] | [a, b] => (a, b),
+            _ => bail!("expected a [1, N, C] output, got {:?}", dims),
+        };
+        let rows_first = a >= b;
+        let (rows, attrs) = if rows_first { (a, b) } else { (b, a) };
+        Ok((rows, attrs, move |i: usize, c: usize| {
+            if rows_first {
+                output.get(i * attrs + c)
+            } else {
+                output.get(c * rows + i)
+            }
+        }))
+    }
+
+    fn decode_v5_concat(&self, output: &OutputView) -> Result<Vec<Detection>> {
+        let (rows, attrs, at) = Self::rows(output)?;
+        if attrs <= 5 {
+            bail!("v5 output with {} values per box", attrs);
+        }
+        let mut detections = Vec::new();
+        for i in 0..rows {
+            let objectness = self.activate(at(i, 4));
+            if objectness < self.conf_threshold {
+                continue;
+            }
+            let n_classes = self.num_classes.unwrap_or(attrs - 5);
+            let (class, class_score) = self.best_class(n_classes, |c| at(i, 5 + c));
+            let score = objectness * class_score;
+            if score >= self.conf_threshold {
+                detections.push(Detection {
+                    class,
+                    score,
+                    bbox: BBox::from_center(at(i, 0), at(i, 1), at(i, 2), at(i, 3)),
+                    anchor: i,
+                });
+            }
+        }
+        Ok(detections)
+    }
+
+    fn decode_v8_concat(&self, output: &OutputView) -> Result<Vec<Detection>> {
+        let (rows, attrs, at) = Self::rows(output)?;
+        if attrs <= 4 {
+            bail!("v8 output with {} values per box", attrs);
+        }
+        let mut detections = Vec::new();
+        for i in 0..rows {
+            let n_classes = self.num_classes.unwrap_or(attrs - 4);
+            let (class, score) = self.best_class(n_classes, |c| at(i, 4 + c));
+            if score >= self.conf_threshold {
+                detections.push(Detection {
+                    class,
+                    score,
+                    bbox: BBox::from_center(at(i, 0), at(i, 1), at(i, 2), at(i, 3)),
+                    anchor: i,
+                });
+            }
+        }
+        Ok(detections)
+    }
+
+    fn decode_v10_concat(&self, output: &OutputView) -> Vec<Detection> {
+        let rows = output.len() / 6;
+        (0..rows)
+            .map(|i| (i, move |c: usize| output.get(i * 6 + c)))
+            .filter(|(_, at)| at(4) >= self.conf_threshold)
+            .map(|(i, at)| Detection {
+                class: at(5).round().max(0.0) as usize,
+                score: at(4),
+                bbox: BBox {
+                    x1: at(0),
+                    y1: at(1),
+                    x2: at(2),
+                    y2: at(3),
+                },
+                anchor: i,
+            })
+            .collect()
+    }
+
+    /// The expected distance encoded by `reg_max` DFL bins.
+    fn dfl(&self, bin: impl Fn(usize) -> f32) -> f32 {
+        let bins: Vec<f32> = (0..self.reg_max).map(bin).collect();
+        let max = bins.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let (mut sum, mut expected) = (0.0, 0.0);
+        for (i, b) in bins.iter().enumerate() {
+            let e = (b - max).exp();
+            sum += e;
+            expected += e * i as f32;
+        }
+        expected / sum
+    }
+
+    fn decode_v8_branches(
+        &self,
+        outputs: &[OutputView],
+        (in_w, _): (u32, u32),
+    ) -> Result<Vec<Detection>> {
+        let box_channels = 4 * self.reg_max;
+        // (boxes, class scores, first class channel, score sum) per branch
+        let branches: Vec<(&OutputView, &OutputView, usize, Option<&OutputView>)> =
+            match outputs.len() {
+                3 => outputs.iter().map(|o| (o, o, box_channels, None)).collect(),
+                6 => outputs.chunks(2).map(|b| (&b[0], &b[1], 0, None)).collect(),
+                9 => outputs
+                    .chunks(3)
+                    .map(|b| (&b[0], &b[1], 0, Some(&b[2])))
+                    .collect(),
+                n => bail!("no v8 layout with {} outputs", n),
+            };
+
+        let mut detections = Vec::new();
+        let mut first_anchor = 0;
+        for (boxes, scores, score_offset, score_sum) in branches {
+            let (channels, height, width) = boxes.chw();
+            let n_classes = match score_offset {
+                0 => scores.chw().0,
+                _ => self
+                    .num_classes
+                    .unwrap_or(scores.chw().0.saturating_sub(score_offset)),
+            };
+            if channels < box_channels || n_classes == 0 {
+                bail!(
+                    "v8 branch with {} box and {} class channels",
+                    channels,
+                    n_classes
+                );
+            }
+            let stride = in_w as f32 / width as f32;
+            for y in 0..height {
+                for x in 0..width {
+                    if let Some(sum) = score_sum {
+                        if sum.at(0, y, x) < self.conf_threshold {
+                            continue;
+                        }
+                    }
+                    let (class, score) =
+                        self.best_class(n_classes, |c| scores.at(score_offset + c, y, x));
+                    if score < self.conf_threshold {
+                        continue;
+                    }
+                    let dist: Vec<f32> = (0..4)
+                        .map(|side| self.dfl(|i| boxes.at(side * self.reg_max + i, y, x)))
+                        .collect();
+                    let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+                    detections.push(Detection {
+                        class,
+                        score,
+                        bbox: BBox {
+                            x1: (cx - dist[0]) * stride,
+                            y1: (cy - dist[1]) * stride,
+                            x2: (cx + dist[2]) * stride,
+                            y2: (cy + dist[3]) * stride,
+                        },
+                        anchor: first_anchor + y * width + x,
+                    });
+                }
+            }
+            first_anchor += height * width;
+        }
+        Ok(detections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rknn_api_sys::{_rknn_tensor_format_RKNN_TENSOR_NHWC, rknn_tensor_attr};
+
+    fn attr(dims: &[u32]) -> rknn_tensor_attr {
+        let mut attr = rknn_tensor_attr {
+            n_dims: dims.len() as u32,
+            n_elems: dims.iter().product(),
+            ..Default::default()
+        };
+        attr.dims[..dims.len()].copy_from_slice(dims);
+        attr
+    }
+
+    /// An NCHW `[1, C, H, W]` tensor, zero but for `values` at (c, y, x).
+    fn nchw(c: usize, h: usize, w: usize, values: &[((usize, usize, usize), f32)]) -> Vec<f32> {
+        let mut data = vec![0.0; c * h * w];
+        for ((ci, y, x), v) in values {
+            data[(ci * h + y) * w + x] = *v;
+        }
+        data
+    }
+
+    fn nhwc(data: &[f32], c: usize, h: usize, w: usize) -> Vec<f32> {
+        let mut out = vec![0.0; data.len()];
+        for ci in 0..c {
+            for y in 0..h {
+                for x in 0..w {
+                    out[(y * w + x) * c + ci] = data[(ci * h + y) * w + x];
+                }
+            }
+        }
+        out
+    }
+
+    fn assert_box(actual: &BBox, expected: [f32; 4]) {
+        let actual = [actual.x1, actual.y1, actual.x2, actual.y2];
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (a - e).abs() < 1e-3),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    fn detection(class: usize, score: f32, bbox: BBox) -> Detection {
+        Detection {
+            class,
+            score,
+            bbox,
+            anchor: 0,
+        }
+    }
+
+    #[test]
+    fn computes_iou() {
+        let a = BBox::from_center(1.0, 1.0, 2.0, 2.0);
+        let b = BBox::from_center(2.0, 2.0, 2.0, 2.0);
+        assert_eq!(a.intersection(&b), 1.0);
+        assert_eq!(a.iou(&b), 1.0 / 7.0);
+        assert_eq!(a.iou(&a), 1.0);
+        assert_eq!(a.iou(&BBox::from_center(10.0, 10.0, 2.0, 2.0)), 0.0);
+        let empty = BBox::from_center(1.0, 1.0, 0.0, 0.0);
+        assert_eq!(empty.iou(&empty), 0.0);
+    }
+
+    #[test]
+    fn suppresses_overlaps_within_a_class() {
+        let a = BBox::from_center(10.0, 10.0, 10.0, 10.0);
+        let near = BBox::from_center(11.0, 10.0, 10.0, 10.0);
+        let far = BBox::from_center(40.0, 40.0, 10.0, 10.0);
+        let kept = nms(
+            vec![
+                detection(0, 0.8, near),
+                detection(0, 0.9, a),
+                detection(1, 0.7, a),
+                detection(0, 0.6, far),
+            ],
+            0.5,
+        );
+        let kept: Vec<(usize, f32)> = kept.iter().map(|d| (d.class, d.score)).collect();
+        assert_eq!(kept, [(0, 0.9), (1, 0.7), (0, 0.6)]);
+    }
+
+    #[test]
+    fn decodes_dfl_bins() {
+        let mut decoder = YoloDecoder::new(YoloVersion::V8);
+        decoder.reg_max = 4;
+        assert!((decoder.dfl(|_| 0.0) - 1.5).abs() < 1e-6);
+        assert!((decoder.dfl(|i| if i == 2 { 30.0 } else { 0.0 }) - 2.0).abs() < 1e-6);
+        // Two equal peaks meet halfway.
+        let d = decoder.dfl(|i| if i == 1 || i == 3 { 30.0 } else { 0.0 });
+        assert!((d - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn decodes_v8_branches_in_either_layout() {
+        // reg_max 2, 2 classes: a 2x2 branch (stride 16) with a box at
+        // x=1, y=0 and two 1x1 branches scoring nothing.
+        let boxes = nchw(
+            8,
+            2,
+            2,
+            &[
+                ((1, 0, 1), 30.0),
+                ((2, 0, 1), 30.0),
+                ((5, 0, 1), 30.0),
+                ((7, 0, 1), 30.0),
+            ],
+        );
+        let scores = nchw(2, 2, 2, &[((0, 0, 1), 0.2), ((1, 0, 1), 0.9)]);
+        let empty = (vec![0.0; 8], vec![0.0; 2]);
+        let mut decoder = YoloDecoder::new(YoloVersion::V8);
+        decoder.reg_max = 2;
+
+        let views = [
+            OutputView::from_f32(&boxes, attr(&[1, 8, 2, 2])),
+            OutputView::from_f32(&scores, attr(&[1, 2, 2, 2])),
+            OutputView::from_f32(&empty.0, attr(&[1, 8, 1, 1])),
+            OutputView::from_f32(&empty.1, attr(&[1, 2, 1, 1])),
+            OutputView::from_f32(&empty.0, attr(&[1, 8, 1, 1])),
+            OutputView::from_f32(&empty.1, attr(&[1, 2, 1, 1])),
+        ];
+        let detections = decoder.decode(&views, (32, 32)).unwrap();
+        assert_eq!(detections.len(), 1);
+        assert_eq!((detections[0].class, detections[0].anchor), (1, 1));
+        // Distances of 1, 0, 1 and 1 cells around the center (1.5, 0.5).
+        assert_box(&detections[0].bbox, [8.0, 8.0, 40.0, 24.0]);
+
+        let (boxes, scores) = (nhwc(&boxes, 8, 2, 2), nhwc(&scores, 2, 2, 2));
+        let nhwc_attr = |dims: &[u32]| rknn_tensor_attr {
+            fmt: _rknn_tensor_format_RKNN_TENSOR_NHWC,
+            ..attr(dims)
+        };
+        let mut views = views;
+        views[0] = OutputView::from_f32(&boxes, nhwc_attr(&[1, 2, 2, 8]));
+        views[1] = OutputView::from_f32(&scores, nhwc_attr(&[1, 2, 2, 2]));
+        assert_eq!(views[0].chw(), (8, 2, 2));
+        assert_eq!(decoder.decode(&views, (32, 32)).unwrap(), detections);
+    }
+
+    #[test]
+    fn decodes_v5_branches() {
+        // One class, so 3 anchors of 6 channels. Anchor 1 of the finest
+        // branch (2x2, stride 16) finds a box at x=0, y=1.
+        let fine = nchw(
+            18,
+            2,
+            2,
+            &[
+                ((6, 1, 0), 0.5),
+                ((7, 1, 0), 0.5),
+                ((8, 1, 0), 0.5),
+                ((9, 1, 0), 0.5),
+                ((10, 1, 0), 0.9),
+                ((11, 1, 0), 0.8),
+            ],
+        );
+        let coarse = vec![0.0; 18];
+        let views = [
+            OutputView::from_f32(&coarse, attr(&[1, 18, 1, 1])),
+            OutputView::from_f32(&fine, attr(&[1, 18, 2, 2])),
+            OutputView::from_f32(&coarse, attr(&[1, 18, 1, 1])),
+        ];
+        let detections = YoloDecoder::new(YoloVersion::V5)
+            .decode(&views, (32, 32))
+            .unwrap();
+        assert_eq!(detections.len(), 1);
+        assert!((detections[0].score - 0.72).abs() < 1e-6);
+        assert_eq!(detections[0].anchor, 6);
+        // Center (8, 24), the anchor's 16x30.
+        assert_box(&detections[0].bbox, [0.0, 9.0, 16.0, 39.0]);
+    }
+
+    #[test]
+    fn decodes_concatenated_outputs() {
+        // v5: [1, 6 rows, 5 + 1 class].
+        let mut rows = vec![0.0; 36];
+        rows[12..18].copy_from_slice(&[50.0, 40.0, 20.0, 10.0, 0.9, 0.9]);
+        let view = OutputView::from_f32(&rows, attr(&[1, 6, 6]));
+        let detections = YoloDecoder::new(YoloVersion::V5)
+            .decode(&[view], (64, 64))
+            .unwrap();
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].anchor, 2);
+        assert_box(&detections[0].bbox, [40.0, 35.0, 60.0, 45.0]);
+
+        // v8: [1, 4 + 2 classes, 8 rows], attributes first.
+        let mut columns = vec![0.0; 48];
+        for (c, v) in [10.0, 10.0, 4.0, 4.0, 0.1, 0.7].into_iter().enumerate() {
+            columns[c * 8 + 3] = v;
+        }
+        let view = OutputView::from_f32(&columns, attr(&[1, 6, 8]));
+        let detections = YoloDecoder::new(YoloVersion::V8)
+            .decode(&[view], (64, 64))
+            .unwrap();
+        assert_eq!(detections.len(), 1);
+        assert_eq!((detections[0].class, detections[0].anchor), (1, 3));
+        assert_box(&detections[0].bbox, [8.0, 8.0, 12.0, 12.0]);
+
+        // v10: [1, N, 6] of x1, y1, x2, y2, score, class, not suppressed.
+        let boxes = [
+            1.0, 2.0, 3.0, 4.0, 0.6, 3.0, //
+            1.0, 2.0, 3.0, 4.0, 0.1, 0.0, //
+            1.0, 2.0, 3.0, 4.5, 0.9, 3.0,
+        ];
+        let view = OutputView::from_f32(&boxes, attr(&[1, 3, 6]));
+        let detections = YoloDecoder::new(YoloVersion::V10)
+            .decode(&[view], (64, 64))
+            .unwrap();
+        let found: Vec<(usize, f32, usize)> = detections
+            .iter()
+            .map(|d| (d.class, d.score, d.anchor))
+            .collect();
+        assert_eq!(found, [(3, 0.9, 2), (3, 0.6, 0)]);
+        assert_box(&detections[0].bbox, [1.0, 2.0, 3.0, 4.5]);
+    }
+}
@@ -0,0 +1,478 @@
+use anyhow::{bail, Result};
+use image::RgbImage;
+use rknn_api_sys::rknn_tensor_attr;
+
+use crate::examples::{
+    common::TensorInput,
+    preprocess::{input_geometry, ImageTransform, Preprocessor, ResizeFilter},
+};
+
+/// Raw camera frame layouts.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum YuvFormat {
+    /// Y plane, then interleaved U/V at half resolution
+    Nv12,
+    /// Y plane, then interleaved V/U at half resolution
+    Nv21,
+    /// Y, U and V planes, U and V at half resolution
+    I420,
+    /// Packed Y0 U Y1 V, U and V shared by two pixels of a row
+    Yuyv,
+}
+
+/// One plane of a frame; `stride` is the number of bytes between rows.
+#[derive(Copy, Clone, Debug)]
+pub struct YuvPlane<'a> {
+    pub data: &'a [u8],
+    pub stride: usize,
+}
+
+/// A borrowed YUV frame, BT.601 limited range.
+#[derive(Clone, Debug)]
+pub struct YuvFrame<'a> {
+    format: YuvFormat,
+    width: u32,
+    height: u32,
+    planes: Vec<YuvPlane<'a>>,
+}
+
+impl YuvFormat {
+    /// Rows and bytes per row of each plane.
+    fn plane_sizes(self, width: usize, height: usize) -> Vec<(usize, usize)> {
+        let (half_w, half_h) = (width.div_ceil(2), height.div_ceil(2));
+        match self {
+            YuvFormat::Nv12 | YuvFormat::Nv21 => vec![(height, width), (half_h, half_w * 2)],
+            YuvFormat::I420 => vec![(height, width), (half_h, half_w), (half_h, half_w)],
+            YuvFormat::Yuyv => vec![(height, half_w * 4)],
+        }
+    }
+}
+
+impl<'a> YuvFrame<'a> {
+    /// Checks that every plane the format needs is there and large enough for
+    /// its stride.
+    pub fn new(
+        format: YuvFormat,
+        width: u32,
+        height: u32,
+        planes: Vec<YuvPlane<'a>>,
+    ) -> Result<Self> {
+        if width == 0 || height == 0 {
+            bail!("empty {:?} frame {}x{}", format, width, height);
+        }
+        let sizes = format.plane_sizes(width as usize, height as usize);
+        if planes.len() != sizes.len() {
+            bail!(
+                "{:?} has {} planes, {} given",
+                format,
+                sizes.len(),
+                planes.len()
+            );
+        }
+        for (i, (plane, (rows, row_bytes))) in planes.iter().zip(sizes).enumerate() {
+            let needed = plane.stride * (rows - 1) + row_bytes;
+            if plane.stride < row_bytes || plane.data.len() < needed {
+                bail!(
+                    "{:?} plane {} of a {}x{} frame needs a stride of at least {} and {} bytes, got {} and {}",
+                    format,
+                    i,
+                    width,
+                    height,
+                    row_bytes,
+                    needed,
+                    plane.stride,
+                    plane.data.len()
+                );
+            }
+        }
+        Ok(YuvFrame {
+            format,
+            width,
+            height,
+            planes,
+        })
+    }
+
+    /// Splits a frame whose planes follow each other without row padding.
+    pub fn from_contiguous(
+        format: YuvFormat,
+        width: u32,
+        height: u32,
+        data: &'a [u8],
+    ) -> Result<Self> {
+        let mut planes = Vec::new();
+        let mut rest = data;
+        for (rows, row_bytes) in format.plane_sizes(width as usize, height as usize) {
+            let len = (rows * row_bytes).min(rest.len());
+            let (plane, tail) = rest.split_at(len);
+            planes.push(YuvPlane {
+                data: plane,
+                stride: row_bytes,
+            });
+            rest = tail;
+        }
+        Self::new(format, width, height, planes)
+    }
+
+    pub fn format(&self) -> YuvFormat {
+        self.format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The Y, U and V samples of a pixel.
+    pub fn yuv(&self, x: usize, y: usize) -> [u8; 3] {
+        let p = &self.planes;
+        match self.format {
+            YuvFormat::Nv12 | YuvFormat::Nv21 => {
+                let luma = p[0].data[y * p[0].stride + x];
+                let uv = (y / 2) * p[1].stride + (x / 2) * 2;
+                let (first, second) = (p[1].data[uv], p[1].data[uv + 1]);
+                if self.format == YuvFormat::Nv12 {
+                    [luma, first, second]
+                } else {
+                    [luma, second, first]
+                }
+            }
+            YuvFormat::I420 => {
+                let luma = p[0].data[y * p[0].stride + x];
+                let u = p[1].data[(y / 2) * p[1].stride + x / 2];
+                let v = p[2].data[(y / 2) * p[2].stride + x / 2];
+                [luma, u, v]
+            }
+            YuvFormat::Yuyv => {
+                let pair = y * p[0].stride + (x / 2) * 4;
+                let row = &p[0].data[pair..pair + 4];
+                [row[(x % 2) * 2], row[1], row[3]]
+            }
+        }
+    }
+
+    pub fn rgb(&self, x: usize, y: usize) -> [u8; 3] {
+        let [luma, u, v] = self.yuv(x, y);
+        yuv_to_rgb(luma, u, v)
+    }
+
+    /// Converts the whole frame, mostly useful for saving or drawing on it.
+    pub fn to_rgb(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            image::Rgb(self.rgb(x as usize, y as usize))
+        })
+    }
+}
+
+/// BT.601 limited range YUV to RGB, in 8.8 fixed point.
+pub fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let c = (y as i32 - 16) * 298;
+    let (d, e) = (u as i32 - 128, v as i32 - 128);
+    let clamp = |x: i32| ((x + 128) >> 8).clamp(0, 255) as u8;
+    [
+        clamp(c + 409 * e),
+        clamp(c - 100 * d - 208 * e),
+        clamp(c + 516 * d),
+    ]
+}
+
+impl Preprocessor {
+    /// Crops and resizes a YUV frame straight into the input for `attr`,
+    /// sampling the source per output pixel. `Nearest` picks the closest
+    /// source pixel, every other filter is bilinear.
+    pub fn run_yuv(
+        &self,
+        frame: &YuvFrame,
+        attr: &rknn_tensor_attr,
+    ) -> Result<(TensorInput, ImageTransform)> {
+        let (height, width, channels, _) = input_geometry(attr)?;
+        self.check_channels(channels as usize)?;
+        let (placement, transform) = self.place(frame.width, frame.height, width, height);
+        let (crop_x, crop_y, crop_w, crop_h) = placement.crop;
+        let (dest_x, dest_y, dest_w, dest_h) = placement.dest;
+        let step_x = crop_w as f32 / dest_w as f32;
+        let step_y = crop_h as f32 / dest_h as f32;
+        let (max_x, max_y) = (crop_x + crop_w - 1, crop_y + crop_h - 1);
+        let pad = self.pad_rgb().0;
+
+        let mut hwc = Vec::with_capacity((width * height * channels) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let inside = (dest_x..dest_x + dest_w).contains(&x)
+                    && (dest_y..dest_y + dest_h).contains(&y);
+                if !inside {
+                    self.push_pixel(pad, &mut hwc);
+                    continue;
+                }
+                // The source position of the output pixel's center.
+                let sx = crop_x as f32 + ((x - dest_x) as f32 + 0.5) * step_x - 0.5;
+                let sy = crop_y as f32 + ((y - dest_y) as f32 + 0.5) * step_y - 0.5;
+                let rgb = if self.resize_filter == ResizeFilter::Nearest {
+                    let nx = (sx.round().max(0.0) as u32).clamp(crop_x, max_x);
+                    let ny = (sy.round().max(0.0) as u32).clamp(crop_y, max_y);
+                    frame.rgb(nx as usize, ny as usize)
+                } else {
+                    bilinear(frame, sx, sy, (crop_x, crop_y, max_x, max_y))
+                };
+                self.push_pixel(rgb, &mut hwc);
+            }
+        }
+        Ok((self.to_input(hwc, attr)?, transform))
+    }
+}
+
+fn bilinear(frame: &YuvFrame, sx: f32, sy: f32, (x0, y0, x1, y1): (u32, u32, u32, u32)) -> [u8; 3] {
+    let sx = sx.clamp(x0 as f32, x1 as f32);
+    let sy = sy.clamp(y0 as f32, y1 as f32);
+    let (left, top) = (sx.floor() as usize, sy.floor() as usize);
+    let (right, bottom) = ((left + 1).min(x1 as usize), (top + 1).min(y1 as usize));
+    let (fx, fy) = (sx - left as f32, sy - top as f32);
+    let corners = [
+        (frame.rgb(left, top), (1.0 - fx) * (1.0 - fy)),
+        (frame.rgb(right, top), fx * (1.0 - fy)),
+        (frame.rgb(left, bottom), (1.0 - fx) * fy),
+        (frame.rgb(right, bottom), fx * fy),
+    ];
+    let mut rgb = [0u8; 3];
+    for (c, out) in rgb.iter_mut().enumerate() {
+        let value: f32 = corners.iter().map(|(p, w)| p[c] as f32 * w).sum();
+        *out = value.round().clamp(0.0, 255.0) as u8;
+    }
+    rgb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::preprocess::ColorOrder;
+
+    /// BT.601 limited range, straight from the standard's coefficients.
+    fn rgb_to_yuv([r, g, b]: [u8; 3]) -> [u8; 3] {
+        let (r, g, b) = (r as f32, g as f32, b as f32);
+        let y = 16.0 + 0.257 * r + 0.504 * g + 0.098 * b;
+        let u = 128.0 - 0.148 * r - 0.291 * g + 0.439 * b;
+        let v = 128.0 + 0.439 * r - 0.368 * g - 0.071 * b;
+        [y.round() as u8, u.round() as u8, v.round() as u8]
+    }
+
+    fn reference_yuv_to_rgb([y, u, v]: [u8; 3]) -> [u8; 3] {
+        let (y, u, v) = (y as f32 - 16.0, u as f32 - 128.0, v as f32 - 128.0);
+        let clamp = |x: f32| x.round().clamp(0.0, 255.0) as u8;
+        [
+            clamp(1.164 * y + 1.596 * v),
+            clamp(1.164 * y - 0.392 * u - 0.813 * v),
+            clamp(1.164 * y + 2.017 * u),
+        ]
+    }
+
+    const COLORS: [[u8; 3]; 6] = [
+        [255, 0, 0],
+        [0, 255, 0],
+        [0, 0, 255],
+        [255, 255, 255],
+        [0, 0, 0],
+        [200, 120, 40],
+    ];
+
+    /// A `width` x `height` image of 2x2 blocks, each block one color, so
+    /// chroma subsampling loses nothing.
+    fn block_color(x: usize, y: usize, width: usize) -> [u8; 3] {
+        COLORS[((y / 2) * width.div_ceil(2) + x / 2) % COLORS.len()]
+    }
+
+    /// Encodes the block image in `format`, each row padded by `padding`
+    /// bytes, and returns the planes' bytes and strides.
+    fn encode(
+        format: YuvFormat,
+        width: usize,
+        height: usize,
+        padding: usize,
+    ) -> Vec<(Vec<u8>, usize)> {
+        let sizes = format.plane_sizes(width, height);
+        let mut planes: Vec<(Vec<u8>, usize)> = sizes
+            .iter()
+            .map(|(rows, row_bytes)| {
+                (
+                    vec![0xAA; rows * (row_bytes + padding)],
+                    row_bytes + padding,
+                )
+            })
+            .collect();
+        for y in 0..height {
+            for x in 0..width {
+                let [luma, u, v] = rgb_to_yuv(block_color(x, y, width));
+                let (cx, cy) = (x / 2, y / 2);
+                let strides: Vec<usize> = planes.iter().map(|(_, stride)| *stride).collect();
+                match format {
+                    YuvFormat::Nv12 | YuvFormat::Nv21 => {
+                        planes[0].0[y * strides[0] + x] = luma;
+                        let uv = cy * strides[1] + cx * 2;
+                        let (first, second) = if format == YuvFormat::Nv12 {
+                            (u, v)
+                        } else {
+                            (v, u)
+                        };
+                        planes[1].0[uv] = first;
+                        planes[1].0[uv + 1] = second;
+                    }
+                    YuvFormat::I420 => {
+                        planes[0].0[y * strides[0] + x] = luma;
+                        planes[1].0[cy * strides[1] + cx] = u;
+                        planes[2].0[cy * strides[2] + cx] = v;
+                    }
+                    YuvFormat::Yuyv => {
+                        let pair = y * strides[0] + cx * 4;
+                        planes[0].0[pair + (x % 2) * 2] = luma;
+                        planes[0].0[pair + 1] = u;
+                        planes[0].0[pair + 3] = v;
+                    }
+                }
+            }
+        }
+        planes
+    }
+
+    fn frame(planes: &[(Vec<u8>, usize)], format: YuvFormat, w: u32, h: u32) -> YuvFrame<'_> {
+        let planes = planes
+            .iter()
+            .map(|(data, stride)| YuvPlane {
+                data,
+                stride: *stride,
+            })
+            .collect();
+        YuvFrame::new(format, w, h, planes).unwrap()
+    }
+
+    fn assert_close(actual: [u8; 3], expected: [u8; 3], context: &str) {
+        for c in 0..3 {
+            assert!(
+                (actual[c] as i32 - expected[c] as i32).abs() <= 2,
+                "{}: got {:?}, expected {:?}",
+                context,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_point_matches_reference() {
+        for y in (16..=235).step_by(7) {
+            for u in (16..=240).step_by(8) {
+                for v in (16..=240).step_by(8) {
+                    assert_close(
+                        yuv_to_rgb(y, u, v),
+                        reference_yuv_to_rgb([y, u, v]),
+                        &format!("yuv {} {} {}", y, u, v),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn formats_decode_with_strides() {
+        let (width, height) = (6, 4);
+        for format in [
+            YuvFormat::Nv12,
+            YuvFormat::Nv21,
+            YuvFormat::I420,
+            YuvFormat::Yuyv,
+        ] {
+            for padding in [0, 5] {
+                let planes = encode(format, width, height, padding);
+                let frame = frame(&planes, format, width as u32, height as u32);
+                for y in 0..height {
+                    for x in 0..width {
+                        assert_close(
+                            frame.rgb(x, y),
+                            block_color(x, y, width),
+                            &format!("{:?} padding {} at ({}, {})", format, padding, x, y),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_short_planes() {
+        let data = vec![0u8; 6 * 4];
+        assert!(YuvFrame::from_contiguous(YuvFormat::Nv12, 6, 4, &data).is_err());
+        let data = vec![0u8; 6 * 4 * 3 / 2];
+        assert!(YuvFrame::from_contiguous(YuvFormat::Nv12, 6, 4, &data).is_ok());
+    }
+
+    #[test]
+    fn resizes_into_nchw_bgr() {
+        // 12x8 of 2x2 blocks halved with nearest sampling: each output pixel
+        // lands inside one block.
+        let (width, height) = (12, 8);
+        let planes = encode(YuvFormat::Nv12, width, height, 3);
+        let frame = frame(&planes, YuvFormat::Nv12, width as u32, height as u32);
+
+        let mut attr = rknn_tensor_attr {
+            n_dims: 4,
+            fmt: rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NCHW,
+            type_: rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8,
+            ..Default::default()
+        };
+        attr.dims[..4].copy_from_slice(&[1, 3, 4, 6]);
+        let preprocessor = Preprocessor {
+            resize_filter: ResizeFilter::Nearest,
+            color: ColorOrder::Bgr,
+            ..Default::default()
+        };
+        let (input, transform) = preprocessor.run_yuv(&frame, &attr).unwrap();
+
+        assert_eq!(input.dims, vec![1, 3, 4, 6]);
+        assert_eq!(transform.to_source(3.0, 2.0), (6.0, 4.0));
+        let plane = 4 * 6;
+        for y in 0..4 {
+            for x in 0..6 {
+                let [r, g, b] = block_color(x * 2 + 1, y * 2 + 1, width);
+                let i = y * 6 + x;
+                let actual = [
+                    input.data[2 * plane + i],
+                    input.data[plane + i],
+                    input.data[i],
+                ];
+                assert_close(actual, [r, g, b], &format!("({}, {})", x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn letterbox_pads_outside_the_frame() {
+        let (width, height) = (8, 4);
+        let planes = encode(YuvFormat::I420, width, height, 0);
+        let frame = frame(&planes, YuvFormat::I420, width as u32, height as u32);
+
+        let mut attr = rknn_tensor_attr {
+            n_dims: 4,
+            fmt: rknn_api_sys::_rknn_tensor_format_RKNN_TENSOR_NHWC,
+            type_: rknn_api_sys::_rknn_tensor_type_RKNN_TENSOR_UINT8,
+            ..Default::default()
+        };
+        attr.dims[..4].copy_from_slice(&[1, 8, 8, 3]);
+        let preprocessor = Preprocessor {
+            letterbox: true,
+            pad_color: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let (input, transform) = preprocessor.run_yuv(&frame, &attr).unwrap();
+
+        // 8x4 fills the width and sits in rows 2..6.
+        assert_eq!(transform.offset_y, 2.0);
+        assert_eq!(&input.data[..3], &[1, 2, 3]);
+        assert_eq!(&input.data[(7 * 8) * 3..(7 * 8) * 3 + 3], &[1, 2, 3]);
+        assert_close(
+            [input.data[48], input.data[49], input.data[50]],
+            block_color(0, 0, width),
+            "first frame pixel",
+        );
+    }
+}
@@ -1,17 +1,43 @@
-pub mod examples;
 use anyhow::Result;
 use clap::Parser;
+use rknn_api_examples::examples;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 enum CLIOptions {
+    Bench(examples::bench::Example),
+    Classify(examples::classify::Example),
+    Compare(examples::compare::Example),
+    Detect(examples::detect::Example),
     DynshapeInference(examples::dynshape_inference::Example),
+    Eval(examples::eval::Example),
+    EvalDetect(examples::eval_detect::Example),
+    Infer(examples::infer::Example),
+    Info(examples::info::Example),
+    MatmulApiDemo(examples::matmul_api_demo::Example),
+    Ocr(examples::ocr_demo::Example),
+    Segment(examples::segment::Example),
+    Throughput(examples::throughput::Example),
+    Video(examples::video::Example),
 }
 
 impl CLIOptions {
     fn execute(&self) -> Result<()> {
         match self {
+            Self::Bench(example) => example.execute(),
+            Self::Classify(example) => example.execute(),
+            Self::Compare(example) => example.execute(),
+            Self::Detect(example) => example.execute(),
             Self::DynshapeInference(example) => example.execute(),
+            Self::Eval(example) => example.execute(),
+            Self::EvalDetect(example) => example.execute(),
+            Self::Infer(example) => example.execute(),
+            Self::Info(example) => example.execute(),
+            Self::MatmulApiDemo(example) => example.execute(),
+            Self::Ocr(example) => example.execute(),
+            Self::Segment(example) => example.execute(),
+            Self::Throughput(example) => example.execute(),
+            Self::Video(example) => example.execute(),
         }
     }
 }
@@ -20,6 +46,10 @@ fn main() {
     let options = CLIOptions::parse();
     match options.execute() {
         Ok(_) => println!("Done!"),
-        Err(_) => println!("Ooops!"),
+        Err(err) => {
+            println!("Ooops!");
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
     }
 }
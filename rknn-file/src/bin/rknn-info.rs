@@ -0,0 +1,38 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use rknn_file::RknnFile;
+
+/// Print what a `.rknn` file says about itself, without the runtime or an
+/// NPU. The same as `rknn-api-examples info --offline`, but it builds on
+/// any host.
+#[derive(Debug, Parser)]
+struct Options {
+    /// The path to the model file (*.rknn)
+    #[arg(short, long)]
+    model_path: String,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Fail when the file says the model is built for another platform,
+    /// e.g. `rk3588` or `rk3566_rk3568`
+    #[arg(long)]
+    platform: Option<String>,
+}
+
+fn run(options: &Options) -> Result<()> {
+    let file = RknnFile::load(&options.model_path)?;
+    if let Some(platform) = &options.platform {
+        file.check_platform(platform)
+            .with_context(|| format!("{} can't run on {}", options.model_path, platform))?;
+    }
+    file.print(&options.model_path, options.json)
+}
+
+fn main() {
+    if let Err(err) = run(&Options::parse()) {
+        eprintln!("{:#}", err);
+        std::process::exit(1);
+    }
+}
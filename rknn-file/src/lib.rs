@@ -0,0 +1,494 @@
+//! Reads what a `.rknn` file says about itself without the runtime, so
+//! models can be checked on machines without an NPU.
+//!
+//! The container is undocumented. What is relied on: the file starts with
+//! `RKNN`, four reserved bytes, the format version and the size of the
+//! compiled model (both `u64`), and ends with a JSON document prefixed by
+//! its `u64` length, which the toolkit fills with the target platform and
+//! its own version. Files without the JSON still give their header, and
+//! their platform when its name appears in the file. Only the header and
+//! the last MiB of a file are read, the compiled model in between is not.
+//!
+//! This crate doesn't depend on `rknn-api-sys`, so it builds and is tested
+//! on any host, and the examples' `build.rs` uses it too.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"RKNN";
+const HEADER_LEN: usize = 24;
+/// How much of the end of a file is searched for the metadata.
+const TAIL_LEN: u64 = 1 << 20;
+
+/// The chips the toolkit can build for, as they appear in `.rknn` files.
+const KNOWN_PLATFORMS: [&str; 12] = [
+    "rk3588",
+    "rk3576",
+    "rk3568",
+    "rk3566",
+    "rk3562",
+    "rv1106",
+    "rv1103",
+    "rv1106b",
+    "rv1103b",
+    "rk2118",
+    "rv1126b",
+    "rk3399pro",
+];
+
+/// Chips sharing an NPU, which run each other's models.
+const NPU_FAMILIES: [&[&str]; 3] = [
+    &["rk3566", "rk3568"],
+    &["rv1103", "rv1106"],
+    &["rv1103b", "rv1106b"],
+];
+
+/// A model input or output, as far as the metadata describes it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TensorDesc {
+    pub name: String,
+    pub dims: Vec<i64>,
+    pub dtype: Option<String>,
+    pub layout: Option<String>,
+}
+
+impl TensorDesc {
+    fn from_json(value: &Value) -> Option<Self> {
+        let text = |keys: &[&str]| text_of(value, keys);
+        let dims = ["dims", "shape", "size"]
+            .iter()
+            .find_map(|k| value.get(*k).and_then(Value::as_array))
+            .map(|dims| dims.iter().filter_map(Value::as_i64).collect())
+            .unwrap_or_default();
+        Some(TensorDesc {
+            name: text(&["name", "url"])?,
+            dims,
+            dtype: text(&["dtype", "type"])
+                .or_else(|| text_of(value.get("dtype")?, &["vx_type", "qnt_type"])),
+            layout: text(&["layout", "fmt"]),
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "dims": self.dims,
+            "dtype": self.dtype,
+            "layout": self.layout,
+        })
+    }
+}
+
+fn text_of(value: &Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|k| value.get(*k).and_then(Value::as_str))
+        .map(str::to_string)
+}
+
+/// The header and metadata of a `.rknn` file.
+#[derive(Clone, Debug)]
+pub struct RknnFile {
+    pub format_version: u64,
+    /// The size of the compiled model following the header.
+    pub model_size: u64,
+    /// Lower case chip names, e.g. `["rk3566", "rk3568"]`. Empty when the
+    /// file doesn't say.
+    pub target_platforms: Vec<String>,
+    /// Whether `target_platforms` were guessed from chip names appearing in
+    /// the file rather than read from the metadata.
+    pub platforms_scanned: bool,
+    /// The version of the toolkit that built the model.
+    pub toolkit_version: Option<String>,
+    pub custom_string: Option<String>,
+    /// Empty unless the metadata lists them.
+    pub inputs: Vec<TensorDesc>,
+    pub outputs: Vec<TensorDesc>,
+    /// The trailing JSON document, when there is one.
+    pub metadata: Option<Value>,
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// The JSON document the file ends with: the last `{` that is preceded by
+/// its own length and runs to the end of the file, give or take padding.
+/// `bytes` is the end of the file, after the header.
+fn trailing_json(bytes: &[u8]) -> Option<Value> {
+    let end = bytes
+        .iter()
+        .rposition(|b| !matches!(b, 0 | b' ' | b'\n' | b'\r' | b'\t'))?
+        + 1;
+    (8..end)
+        .rev()
+        .filter(|start| bytes[*start] == b'{')
+        .find_map(|start| {
+            let len = read_u64(bytes, start - 8)? as usize;
+            let json = bytes.get(start..start.checked_add(len)?)?;
+            if start + len < end {
+                return None;
+            }
+            serde_json::from_slice(json).ok()
+        })
+}
+
+/// `target_platform` may be a name or a list of them.
+fn platforms_of(value: &Value) -> Vec<String> {
+    let names = match value {
+        Value::String(name) => vec![name.as_str()],
+        Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    names.into_iter().map(str::to_lowercase).collect()
+}
+
+/// The known chip names appearing in `bytes`, in [`KNOWN_PLATFORMS`] order.
+fn scan_platforms(bytes: &[u8]) -> Vec<String> {
+    KNOWN_PLATFORMS
+        .iter()
+        .filter(|name| {
+            let name = name.as_bytes();
+            bytes.windows(name.len()).enumerate().any(|(i, w)| {
+                // Not part of a longer name, e.g. rv1106 in rv1106b.
+                w.eq_ignore_ascii_case(name)
+                    && !bytes
+                        .get(i + name.len())
+                        .is_some_and(|b| b.is_ascii_alphanumeric())
+            })
+        })
+        .map(|name| name.to_string())
+        .collect()
+}
+
+fn tensors_of(metadata: &Value, keys: &[&str]) -> Vec<TensorDesc> {
+    keys.iter()
+        .find_map(|k| metadata.get(*k).and_then(Value::as_array))
+        .map(|tensors| tensors.iter().filter_map(TensorDesc::from_json).collect())
+        .unwrap_or_default()
+}
+
+/// The chip names in a platform name as used by this crate's features and
+/// model files, e.g. `rk3566_rk3568`.
+pub fn platform_chips(platform: &str) -> Vec<String> {
+    platform
+        .split(['_', ','])
+        .filter(|chip| !chip.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The NPU `chip` has, named like the platforms of this crate's features:
+/// `rk3568` and `rk3566` are `rk3566_rk3568`, and variants like `rk3588s`
+/// are their base chip.
+pub fn npu_family(chip: &str) -> String {
+    let chip = chip.to_lowercase();
+    let chip = match chip.strip_suffix('s') {
+        Some(base) if base.len() == 6 => base,
+        _ => &chip,
+    };
+    NPU_FAMILIES
+        .iter()
+        .find(|family| family.contains(&chip))
+        .map(|family| family.join("_"))
+        .unwrap_or_else(|| chip.to_string())
+}
+
+/// The first `HEADER_LEN` bytes of `file`, and what follows them up to the
+/// last [`TAIL_LEN`] bytes.
+fn read_ends(file: &mut File) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    file.by_ref()
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(
+        len.saturating_sub(TAIL_LEN).max(header.len() as u64),
+    ))?;
+    let mut tail = Vec::new();
+    file.take(TAIL_LEN).read_to_end(&mut tail)?;
+    Ok((header, tail))
+}
+
+fn print_descs(title: &str, descs: &[TensorDesc]) {
+    println!("\x1b[34;4m {}:\x1b[0m", title);
+    if descs.is_empty() {
+        println!("  not in the metadata");
+    }
+    for desc in descs {
+        println!(
+            "  name={}, dims={:?}, type={}, fmt={}",
+            desc.name,
+            desc.dims,
+            desc.dtype.as_deref().unwrap_or("-"),
+            desc.layout.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+impl RknnFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let split = HEADER_LEN.min(bytes.len());
+        Self::from_ends(&bytes[..split], &bytes[split..])
+    }
+
+    /// Parses a file from its `header` and the `tail` after it, which may
+    /// leave out the middle of the file.
+    fn from_ends(header: &[u8], tail: &[u8]) -> Result<Self> {
+        if header.len() < HEADER_LEN || &header[..4] != MAGIC {
+            bail!("not an RKNN model, the file doesn't start with 'RKNN'");
+        }
+        let format_version = read_u64(header, 8).unwrap_or_default();
+        let model_size = read_u64(header, 16).unwrap_or_default();
+
+        let metadata = trailing_json(tail);
+        let field = |keys: &[&str]| {
+            metadata
+                .as_ref()
+                .and_then(|m| keys.iter().find_map(|k| m.get(*k)))
+        };
+        let mut target_platforms = field(&["target_platform", "platform"])
+            .map(platforms_of)
+            .unwrap_or_default();
+        let mut platforms_scanned = false;
+        if target_platforms.is_empty() {
+            target_platforms = scan_platforms(tail);
+            platforms_scanned = !target_platforms.is_empty();
+        }
+        let toolkit_version = field(&["version", "toolkit_version"])
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let custom_string = field(&["custom_string"])
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let (inputs, outputs) = match &metadata {
+            Some(m) => (
+                tensors_of(m, &["inputs", "input"]),
+                tensors_of(m, &["outputs", "output"]),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        Ok(RknnFile {
+            format_version,
+            model_size,
+            target_platforms,
+            platforms_scanned,
+            toolkit_version,
+            custom_string,
+            inputs,
+            outputs,
+            metadata,
+        })
+    }
+
+    /// Reads the header and the end of the file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let (header, tail) = File::open(path)
+            .and_then(|mut file| read_ends(&mut file))
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_ends(&header, &tail)
+            .with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    /// Whether the model runs on `platform` (e.g. `rk3588`, `rk3568` or
+    /// `rk3566_rk3568`), `None` when the file doesn't name its platform.
+    /// Chips sharing an NPU support each other's models.
+    pub fn supports(&self, platform: &str) -> Option<bool> {
+        if self.target_platforms.is_empty() {
+            return None;
+        }
+        let families = platform_chips(platform)
+            .iter()
+            .map(|chip| npu_family(chip))
+            .collect::<Vec<_>>();
+        Some(
+            self.target_platforms
+                .iter()
+                .any(|p| families.contains(&npu_family(p))),
+        )
+    }
+
+    /// Fails when the model names its platform and it isn't `platform`.
+    pub fn check_platform(&self, platform: &str) -> Result<()> {
+        if self.supports(platform) == Some(false) {
+            bail!(
+                "the model is built for {}, not {}",
+                self.target_platforms.join(","),
+                platform
+            );
+        }
+        Ok(())
+    }
+
+    /// The target platforms for reports, e.g. `rk3588` or `rk3588 (guessed)`.
+    pub fn target_platforms_text(&self) -> String {
+        if self.target_platforms.is_empty() {
+            return "unknown".to_string();
+        }
+        let guessed = if self.platforms_scanned {
+            " (guessed)"
+        } else {
+            ""
+        };
+        format!("{}{}", self.target_platforms.join(","), guessed)
+    }
+
+    /// Prints what the file says about the model at `model_path`, as JSON
+    /// with `json`.
+    pub fn print(&self, model_path: &str, json: bool) -> Result<()> {
+        if json {
+            let mut report = self.to_json();
+            report["model"] = json!(model_path);
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+        let or_unknown = |s: &Option<String>| s.clone().unwrap_or_else(|| "unknown".to_string());
+        println!("\x1b[34;4m model: {}\x1b[0m", model_path);
+        println!(
+            "\x1b[34;4m format version: {}, model size: {:.2} MiB\x1b[0m",
+            self.format_version,
+            self.model_size as f64 / (1024.0 * 1024.0)
+        );
+        println!(
+            "\x1b[34;4m target platform: {}\x1b[0m",
+            self.target_platforms_text()
+        );
+        println!(
+            "\x1b[34;4m toolkit version: {}\x1b[0m",
+            or_unknown(&self.toolkit_version)
+        );
+        println!(
+            "\x1b[34;4m custom string: {}\x1b[0m",
+            or_unknown(&self.custom_string)
+        );
+        print_descs("input tensors", &self.inputs);
+        print_descs("output tensors", &self.outputs);
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "format_version": self.format_version,
+            "model_size": self.model_size,
+            "target_platforms": self.target_platforms,
+            "platforms_scanned": self.platforms_scanned,
+            "toolkit_version": self.toolkit_version,
+            "custom_string": self.custom_string,
+            "inputs": self.inputs.iter().map(TensorDesc::to_json).collect::<Vec<_>>(),
+            "outputs": self.outputs.iter().map(TensorDesc::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(model: &[u8], metadata: Option<&str>) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&6u64.to_le_bytes());
+        bytes.extend_from_slice(&(model.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(model);
+        if let Some(metadata) = metadata {
+            bytes.extend_from_slice(&(metadata.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(metadata.as_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_header_and_metadata() {
+        let bytes = model(
+            b"{not json} rk3588",
+            Some(
+                r#"{"target_platform":["RK3566","rk3568"],"version":"2.3.0",
+                "custom_string":"resnet18","inputs":[{"name":"x","dims":[1,3,224,224],
+                "dtype":{"vx_type":"int8"}}]}"#,
+            ),
+        );
+        let file = RknnFile::parse(&bytes).unwrap();
+        assert_eq!(file.format_version, 6);
+        assert_eq!(file.model_size, 17);
+        assert_eq!(file.target_platforms, ["rk3566", "rk3568"]);
+        assert!(!file.platforms_scanned);
+        assert_eq!(file.target_platforms_text(), "rk3566,rk3568");
+        assert_eq!(file.toolkit_version.as_deref(), Some("2.3.0"));
+        assert_eq!(file.custom_string.as_deref(), Some("resnet18"));
+        assert_eq!(file.inputs[0].name, "x");
+        assert_eq!(file.inputs[0].dims, [1, 3, 224, 224]);
+        assert_eq!(file.inputs[0].dtype.as_deref(), Some("int8"));
+        assert!(file.outputs.is_empty());
+    }
+
+    #[test]
+    fn checks_platform() {
+        let bytes = model(b"", Some(r#"{"target_platform":"rk3566"}"#));
+        let file = RknnFile::parse(&bytes).unwrap();
+        assert_eq!(file.supports("rk3566_rk3568"), Some(true));
+        assert!(file.check_platform("rk3566_rk3568").is_ok());
+        let err = file.check_platform("rk3588").unwrap_err();
+        assert_eq!(err.to_string(), "the model is built for rk3566, not rk3588");
+    }
+
+    #[test]
+    fn scans_for_platform_without_metadata() {
+        let file = RknnFile::parse(&model(b"\0rv1106b\0rk3588\0", None)).unwrap();
+        assert!(file.metadata.is_none());
+        assert!(file.platforms_scanned);
+        assert_eq!(file.target_platforms, ["rk3588", "rv1106b"]);
+        assert_eq!(file.target_platforms_text(), "rk3588,rv1106b (guessed)");
+        let unknown = RknnFile::parse(&model(b"weights", None)).unwrap();
+        assert_eq!(unknown.target_platforms_text(), "unknown");
+        assert_eq!(unknown.supports("rk3588"), None);
+        assert!(unknown.check_platform("rk3588").is_ok());
+    }
+
+    #[test]
+    fn maps_chips_to_npu_families() {
+        assert_eq!(npu_family("rk3568"), "rk3566_rk3568");
+        assert_eq!(npu_family("RK3566"), "rk3566_rk3568");
+        assert_eq!(npu_family("rk3588s"), "rk3588");
+        assert_eq!(npu_family("rv1103"), "rv1103_rv1106");
+        assert_eq!(npu_family("rv1106b"), "rv1103b_rv1106b");
+        assert_eq!(npu_family("rk3399pro"), "rk3399pro");
+
+        let file = RknnFile::parse(&model(b"", Some(r#"{"target_platform":"rk3568"}"#))).unwrap();
+        assert_eq!(file.supports("rk3566"), Some(true));
+        assert_eq!(file.supports("rk3566_rk3568"), Some(true));
+        assert_eq!(file.supports("rk3562"), Some(false));
+        let file = RknnFile::parse(&model(b"", Some(r#"{"target_platform":"rv1106"}"#))).unwrap();
+        assert_eq!(file.supports("rv1103"), Some(true));
+        assert_eq!(file.supports("rv1103b"), Some(false));
+    }
+
+    #[test]
+    fn loads_only_the_ends_of_large_files() {
+        let weights = vec![b'w'; 3 * TAIL_LEN as usize];
+        let bytes = model(&weights, Some(r#"{"target_platform":"rk3576"}"#));
+        let path = std::env::temp_dir().join(format!("large-{}.rknn", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let file = RknnFile::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file.model_size, weights.len() as u64);
+        assert_eq!(file.target_platforms, ["rk3576"]);
+
+        let path = std::env::temp_dir().join(format!("short-{}.rknn", std::process::id()));
+        std::fs::write(&path, b"RKNN").unwrap();
+        assert!(RknnFile::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(RknnFile::parse(b"\x93NUMPY").is_err());
+        assert!(RknnFile::parse(b"ONNX and more than twenty four bytes").is_err());
+    }
+}